@@ -0,0 +1,68 @@
+/* C interface of the rs-riscv-sc emulator, for co-simulation with RTL testbenches.
+ * Link against the cdylib built by `cargo build` (librs_riscv_sc.so). */
+#ifndef RS_RISCV_SC_H
+#define RS_RISCV_SC_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct RvscSim RvscSim;
+
+/* What one rvsc_step did. */
+typedef struct {
+    uint64_t pc;
+    uint64_t next_pc;
+    uint64_t rd_value;
+    uint64_t mem_addr;
+    uint64_t mem_data;
+    uint64_t trap_cause;
+    uint32_t insn;
+    /* 2 or 4, or 0 when an interrupt was taken instead of executing an instruction */
+    uint8_t len;
+    uint8_t privilege;
+    /* destination register, 0 if none was written */
+    uint8_t rd;
+    /* 1 if the instruction trapped instead of retiring, with the cause in trap_cause */
+    uint8_t trapped;
+    /* size of the data access in bytes, 0 if there was none */
+    uint8_t mem_len;
+    uint8_t mem_write;
+    uint8_t reserved[6];
+} RvscRetire;
+
+/* A machine-mode hart with mem_size bytes of zeroed memory at mem_base, starting at mem_base. */
+RvscSim *rvsc_init(uint64_t mem_base, uint64_t mem_size);
+void rvsc_free(RvscSim *sim);
+
+/* Functions returning int return 0 on success and -1 on failure, described by rvsc_last_error,
+ * which includes an internal error of the emulator. The message stays valid until the next
+ * call. */
+const char *rvsc_last_error(const RvscSim *sim);
+
+/* Copies the loadable segments of an ELF file into memory and jumps to its entry point. */
+int rvsc_load_elf(RvscSim *sim, const char *path);
+
+/* Executes one instruction, or takes a pending interrupt, and describes it in retire unless it
+ * is NULL. Fails once the emulator has stopped. */
+int rvsc_step(RvscSim *sim, RvscRetire *retire);
+
+uint64_t rvsc_get_pc(const RvscSim *sim);
+/* Value of xn, or 0 if there is no such register. */
+uint64_t rvsc_get_reg(RvscSim *sim, uint32_t n);
+
+int rvsc_read_mem(RvscSim *sim, uint64_t addr, uint8_t *buf, size_t len);
+int rvsc_write_mem(RvscSim *sim, uint64_t addr, const uint8_t *buf, size_t len);
+
+/* Makes machine interrupt cause pending; it is taken by a later rvsc_step once enabled by mie
+ * and mstatus.MIE. */
+int rvsc_raise_interrupt(RvscSim *sim, uint32_t cause);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,74 @@
+"""Shadow stack experiments through the Python bindings.
+
+    cargo build --features python
+    cp target/debug/librs_riscv_sc.so python/rs_riscv_sc.so
+    python3 -m unittest discover python
+"""
+import os
+import struct
+import unittest
+
+from rs_riscv_sc import Machine
+
+BASE = 0x1000
+PROGRAM = [
+    0x00C000EF,  # 0x1000: jal ra, f
+    0x00150513,  # 0x1004: addi a0, a0, 1
+    0x0000006F,  # 0x1008: j .
+    0x00500513,  # 0x100c: f: li a0, 5
+    0x00008067,  # 0x1010: ret
+]
+RISCV_TESTS = os.path.join(os.path.dirname(__file__), "..", "riscv-tests")
+
+
+def machine():
+    m = Machine(BASE, 0x100)
+    m.write_mem(BASE, struct.pack("<%dI" % len(PROGRAM), *PROGRAM))
+    events = []
+    m.on_sstack(lambda m, e: events.append((e.kind, e.pc, e.target, e.expected)))
+    return m, events
+
+
+class ShadowStackTest(unittest.TestCase):
+    def test_call_and_return(self):
+        m, events = machine()
+        self.assertEqual(m.run(max_steps=4), "limit")
+        self.assertEqual(m.read_reg("a0"), 6)
+        self.assertEqual(events, [("push", 0x1000, 0x1004, None), ("match", 0x1010, 0x1004, None)])
+        self.assertEqual(m.shadow_stack, [])
+
+    def test_overwritten_return_address(self):
+        m, events = machine()
+        # redirect the return to skip the increment, as a stack smash would
+        m.add_breakpoint(0x1010, lambda m: m.write_reg("ra", 0x1008))
+        self.assertEqual(m.run(), "stopped")
+        self.assertIn("shadow stack mismatch", m.stop_reason)
+        self.assertEqual(events[-1], ("mismatch", 0x1010, 0x1008, 0x1004))
+        self.assertEqual(m.pc, 0x1010)
+        self.assertEqual(m.shadow_stack, [0x1004])
+
+    def test_breakpoint_and_state(self):
+        m, _ = machine()
+        m.add_breakpoint(0x100C)
+        self.assertEqual(m.run(), "breakpoint")
+        self.assertEqual(m.pc, 0x100C)
+        self.assertEqual(m.shadow_stack, [0x1004])
+        r = m.step()
+        self.assertEqual((r.pc, r.insn, r.rd, r.next_pc), (0x100C, 0x00500513, (10, 5), 0x1010))
+        self.assertTrue(m.remove_breakpoint(0x100C))
+        m.write_csr("mscratch", 7)
+        self.assertEqual(m.read_csr(0x340), 7)
+        self.assertEqual(m.read_mem(BASE + 4, 4), struct.pack("<I", PROGRAM[1]))
+        with self.assertRaises(ValueError):
+            m.read_reg("x32")
+        with self.assertRaises(ValueError):
+            m.read_mem(BASE + 0xFE, 4)
+
+    def test_load_elf(self):
+        m = Machine(0x80000000, 0x4000)
+        self.assertEqual(m.load_elf(os.path.join(RISCV_TESTS, "rv32ui-p-add")), 0x80000000)
+        self.assertEqual(m.step().next_pc, 0x80000048)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,159 @@
+use crate::elf::Elf;
+use std::fmt::Write;
+
+/// A return whose target did not match the top of the shadow stack.
+#[derive(Clone, Copy)]
+pub struct Violation {
+    pub target: u64,
+    pub expected: u64,
+}
+
+pub struct Frame {
+    pub addr: u64,
+    pub symbol: Option<(String, u64)>,
+    pub location: Option<(String, u64)>,
+    pub mismatch: bool,
+}
+
+impl Frame {
+    fn new(addr: u64, line_addr: u64, elf: Option<&Elf>) -> Self {
+        Self {
+            addr,
+            symbol: elf
+                .and_then(|e| e.symbolize(addr))
+                .map(|(s, off)| (s.name.clone(), off)),
+            location: elf
+                .and_then(|e| e.line(line_addr))
+                .map(|(f, l)| (f.to_string(), l)),
+            mismatch: false,
+        }
+    }
+}
+
+/// Why execution stopped, with the shadow stack rendered as a symbolized backtrace.
+pub struct Report {
+    pub reason: String,
+    pub pc: u64,
+    pub violation: Option<Violation>,
+    pub frames: Vec<Frame>,
+}
+
+impl Report {
+    /// `sstack` is ordered outermost first, as stored. Frame #0 is the stopping pc and the
+    /// shadow stack follows innermost first; its top frame is marked when `violation` is set.
+    pub fn new(
+        reason: String,
+        pc: u64,
+        sstack: &[u64],
+        violation: Option<Violation>,
+        elf: Option<&Elf>,
+    ) -> Self {
+        let mut frames = vec![Frame::new(pc, pc, elf)];
+        for (i, &ra) in sstack.iter().rev().enumerate() {
+            // the line of the call site, not of the instruction after it
+            let mut frame = Frame::new(ra, ra.wrapping_sub(1), elf);
+            frame.mismatch = i == 0 && violation.is_some();
+            frames.push(frame);
+        }
+        Self {
+            reason,
+            pc,
+            violation,
+            frames,
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "stopped at pc {:#x}: {}", self.pc, self.reason).unwrap();
+        if let Some(v) = &self.violation {
+            writeln!(
+                out,
+                "shadow stack violation: ret to {:#x}, expected {:#x}",
+                v.target, v.expected
+            )
+            .unwrap();
+        }
+        writeln!(out, "backtrace:").unwrap();
+        for (i, f) in self.frames.iter().enumerate() {
+            write!(out, "  #{:<2} {:#010x}", i, f.addr).unwrap();
+            if let Some((name, off)) = &f.symbol {
+                write!(out, " in {}+{:#x}", name, off).unwrap();
+            }
+            if let Some((file, line)) = &f.location {
+                write!(out, " at {}:{}", file, line).unwrap();
+            }
+            if f.mismatch {
+                write!(out, "  <== mismatch, ret to {:#x}", self.violation.as_ref().unwrap().target).unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        write!(out, "{{\"reason\":{},\"pc\":{}", json_str(&self.reason), self.pc).unwrap();
+        match &self.violation {
+            Some(v) => write!(
+                out,
+                ",\"violation\":{{\"target\":{},\"expected\":{}}}",
+                v.target, v.expected
+            )
+            .unwrap(),
+            None => out.push_str(",\"violation\":null"),
+        }
+        out.push_str(",\"frames\":[");
+        for (i, f) in self.frames.iter().enumerate() {
+            if i != 0 {
+                out.push(',');
+            }
+            write!(out, "{{\"addr\":{}", f.addr).unwrap();
+            match &f.symbol {
+                Some((name, off)) => write!(out, ",\"function\":{},\"offset\":{}", json_str(name), off).unwrap(),
+                None => out.push_str(",\"function\":null,\"offset\":null"),
+            }
+            match &f.location {
+                Some((file, line)) => write!(out, ",\"file\":{},\"line\":{}", json_str(file), line).unwrap(),
+                None => out.push_str(",\"file\":null,\"line\":null"),
+            }
+            write!(out, ",\"mismatch\":{}}}", f.mismatch).unwrap();
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[test]
+fn test_report() {
+    let report = Report::new(
+        String::from("shadow stack mismatch\n"),
+        0x100,
+        &[0x10, 0x20],
+        Some(Violation {
+            target: 0x30,
+            expected: 0x20,
+        }),
+        None,
+    );
+    assert!(report.frames.len() == 3);
+    assert!(report.frames[1].addr == 0x20 && report.frames[1].mismatch);
+    assert!(!report.frames[2].mismatch);
+    assert!(report.to_text().contains("<== mismatch, ret to 0x30"));
+    assert!(report.to_json().starts_with("{\"reason\":\"shadow stack mismatch\\n\",\"pc\":256,"));
+}
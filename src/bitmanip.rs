@@ -0,0 +1,120 @@
+use crate::{bitutils::Bits, decode::BitOp};
+
+/// Result of `op` on `a` and `b`, the second register or the immediate, on an `xlen`-bit hart.
+/// Operands are already truncated to `xlen` bits, and so is the result. `word` selects the RV64
+/// forms on the low 32 bits.
+pub fn exec(op: BitOp, a: u64, b: u64, xlen: u32, word: bool) -> Result<u64, String> {
+    if word && xlen != 64 {
+        return Err(String::from("RV64 only instruction"));
+    }
+    let mask = u64::MAX >> (64 - xlen);
+    let signed = |x: u64| ((x << (64 - xlen)) as i64) >> (64 - xlen);
+    let index = b & (xlen as u64 - 1);
+    let result = match (op, word) {
+        (BitOp::Andn, _) => a & !b,
+        (BitOp::Orn, _) => a | !b,
+        (BitOp::Xnor, _) => !(a ^ b),
+        (BitOp::Min, _) => signed(a).min(signed(b)) as u64,
+        (BitOp::Minu, _) => a.min(b),
+        (BitOp::Max, _) => signed(a).max(signed(b)) as u64,
+        (BitOp::Maxu, _) => a.max(b),
+        (BitOp::Rol, false) => a << index | a >> ((xlen as u64 - index) % xlen as u64),
+        (BitOp::Ror, false) => a >> index | a << ((xlen as u64 - index) % xlen as u64),
+        (BitOp::Rol, true) => Bits::new((a as u32).rotate_left(b as u32 & 31) as u64, 32).extend_u64(),
+        (BitOp::Ror, true) => Bits::new((a as u32).rotate_right(b as u32 & 31) as u64, 32).extend_u64(),
+        (BitOp::Clz, false) => (a.leading_zeros() - (64 - xlen)) as u64,
+        (BitOp::Ctz, false) => a.trailing_zeros().min(xlen) as u64,
+        (BitOp::Clz, true) => (a as u32).leading_zeros() as u64,
+        (BitOp::Ctz, true) => (a as u32).trailing_zeros() as u64,
+        (BitOp::Cpop, false) => a.count_ones() as u64,
+        (BitOp::Cpop, true) => (a as u32).count_ones() as u64,
+        (BitOp::SextB, _) => Bits::new(a, 8).extend_u64(),
+        (BitOp::SextH, _) => Bits::new(a, 16).extend_u64(),
+        // packw and pack with x0, a zext.w on RV64
+        (BitOp::ZextH, true) => Bits::new(a, 16).to_u64(),
+        (BitOp::ZextH, false) => a & (mask >> (xlen / 2)),
+        (BitOp::OrcB, _) => (0..8).map(|i| (a >> (8 * i) & 0xff != 0) as u64 * (0xff << (8 * i))).sum(),
+        (BitOp::Rev8, _) if b == xlen as u64 - 8 => a.swap_bytes() >> (64 - xlen),
+        (BitOp::Clmul, _) => clmul(a, b) as u64,
+        (BitOp::Clmulh, _) => (clmul(a, b) >> xlen) as u64,
+        (BitOp::Clmulr, _) => (clmul(a, b) >> (xlen - 1)) as u64,
+        (BitOp::Shadd(n), false) => (a << n).wrapping_add(b),
+        (BitOp::Shadd(n), true) => (Bits::new(a, 32).to_u64() << n).wrapping_add(b),
+        (BitOp::SlliUw, true) => Bits::new(a, 32).to_u64() << (b & 63),
+        (BitOp::Bclr, _) => a & !(1 << index),
+        (BitOp::Bext, _) => a >> index & 1,
+        (BitOp::Binv, _) => a ^ (1 << index),
+        (BitOp::Bset, _) => a | (1 << index),
+        (BitOp::Pack, false) => (a & (mask >> (xlen / 2))) | b << (xlen / 2),
+        (BitOp::Pack, true) => Bits::new(b << 16 | (a & 0xffff), 32).extend_u64(),
+        (BitOp::Packh, _) => (a & 0xff) | (b & 0xff) << 8,
+        (BitOp::Brev8, _) => a.reverse_bits().swap_bytes(),
+        // bit i of each half to bits 2i and 2i + 1, and back
+        (BitOp::Zip, _) if xlen == 32 => (0..16)
+            .map(|i| (a >> i & 1) << (2 * i) | (a >> (i + 16) & 1) << (2 * i + 1))
+            .sum(),
+        (BitOp::Unzip, _) if xlen == 32 => (0..16)
+            .map(|i| (a >> (2 * i) & 1) << i | (a >> (2 * i + 1) & 1) << (i + 16))
+            .sum(),
+        (BitOp::Xperm4, _) => xperm(a, b, 4, xlen),
+        (BitOp::Xperm8, _) => xperm(a, b, 8, xlen),
+        _ => return Err(String::from("No such bit-manipulation instruction for this XLEN")),
+    };
+    Ok(result & mask)
+}
+
+/// Each `width`-bit element of `b` replaced by the element of `a` it indexes, 0 if out of range.
+fn xperm(a: u64, b: u64, width: u32, xlen: u32) -> u64 {
+    let element = (1 << width) - 1;
+    (0..xlen / width)
+        .map(|i| {
+            let index = (b >> (i * width) & element) as u32;
+            match index < xlen / width {
+                true => (a >> (index * width) & element) << (i * width),
+                false => 0,
+            }
+        })
+        .sum()
+}
+
+/// Carry-less product of `a` and `b`.
+fn clmul(a: u64, b: u64) -> u128 {
+    (0..64)
+        .filter(|i| b >> i & 1 == 1)
+        .fold(0, |p, i| p ^ (a as u128) << i)
+}
+
+#[test]
+fn test_exec() {
+    let rv32 = |op, a, b| exec(op, a, b, 32, false).unwrap();
+    assert!(rv32(BitOp::Andn, 0xff, 0x0f) == 0xf0 && rv32(BitOp::Xnor, 0, 0) == 0xffffffff);
+    assert!(rv32(BitOp::Min, 0xffffffff, 1) == 0xffffffff && rv32(BitOp::Minu, 0xffffffff, 1) == 1);
+    assert!(rv32(BitOp::Rol, 0x80000001, 1) == 3 && rv32(BitOp::Ror, 1, 33) == 0x80000000);
+    assert!(rv32(BitOp::Ror, 0x1234, 0) == 0x1234);
+    assert!(rv32(BitOp::Clz, 0, 0) == 32 && rv32(BitOp::Clz, 1, 0) == 31 && rv32(BitOp::Ctz, 0, 0) == 32);
+    assert!(rv32(BitOp::Cpop, 0xf0f0, 0) == 8 && rv32(BitOp::SextB, 0x80, 0) == 0xffffff80);
+    assert!(rv32(BitOp::ZextH, 0xffff8000, 0) == 0x8000 && rv32(BitOp::OrcB, 0x01000200, 0) == 0xff00ff00);
+    assert!(rv32(BitOp::Rev8, 0x12345678, 24) == 0x78563412);
+    assert!(rv32(BitOp::Clmul, 0x80000003, 3) == 0x80000005 && rv32(BitOp::Clmulh, 0x80000003, 3) == 1);
+    assert!(rv32(BitOp::Clmulr, 0x80000000, 0x80000000) == 0x80000000);
+    assert!(rv32(BitOp::Shadd(3), 2, 1) == 17 && rv32(BitOp::Bext, 0x10, 36) == 1);
+    assert!(rv32(BitOp::Bclr, 0xff, 0) == 0xfe && rv32(BitOp::Binv, 0, 31) == 0x80000000);
+    assert!(rv32(BitOp::Pack, 0x12345678, 0x9abc) == 0x9abc5678 && rv32(BitOp::Packh, 0x1234, 0x5678) == 0x7834);
+    assert!(rv32(BitOp::Brev8, 0x01020380, 0) == 0x8040c001);
+    assert!(rv32(BitOp::Zip, 0xffff0000, 0) == 0xaaaaaaaa && rv32(BitOp::Unzip, 0xaaaaaaaa, 0) == 0xffff0000);
+    assert!(rv32(BitOp::Xperm8, 0x44332211, 0x00ff0103) == 0x11002244);
+    assert!(rv32(BitOp::Xperm4, 0x76543210, 0x89abcdef) == 0);
+    assert!(rv32(BitOp::Xperm4, 0xfedcba98, 0x01234567) == 0x89abcdef);
+    assert!(exec(BitOp::Rev8, 1, 56, 32, false).is_err() && exec(BitOp::Shadd(0), 1, 1, 32, true).is_err());
+    let rv64 = |op, a, b, word| exec(op, a, b, 64, word).unwrap();
+    assert!(rv64(BitOp::Rev8, 0x0102030405060708, 56, false) == 0x0807060504030201);
+    assert!(rv64(BitOp::Clz, 1, 0, false) == 63 && rv64(BitOp::Clz, 1, 0, true) == 31);
+    assert!(rv64(BitOp::Rol, 0x40000000, 1, true) == 0xffffffff80000000);
+    assert!(rv64(BitOp::Shadd(0), 0xffffffff_ffffffff, 1, true) == 0x1_00000000);
+    assert!(rv64(BitOp::SlliUw, 0xffffffff_80000000, 4, true) == 0x8_00000000);
+    assert!(rv64(BitOp::Clmulh, u64::MAX, u64::MAX, false) == 0x5555555555555555);
+    assert!(rv64(BitOp::ZextH, u64::MAX, 0, false) == 0xffffffff && rv64(BitOp::Pack, 1, 2, false) == 0x2_00000001);
+    assert!(rv64(BitOp::Pack, 0x8000, 0x8000, true) == 0xffffffff80008000);
+    assert!(rv64(BitOp::Brev8, 0x0100000000000080, 0, false) == 0x8000000000000001);
+    assert!(exec(BitOp::Zip, 0, 0, 64, false).is_err());
+}
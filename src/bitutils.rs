@@ -19,10 +19,10 @@ macro_rules! bitcat {
 }
 impl Bits {
     pub fn blank() -> Self {
-        return Self { data: 0, len: 0 }.fit();
+        Self { data: 0, len: 0 }.fit()
     }
     pub fn new(data: u64, len: usize) -> Self {
-        return Self { data, len }.fit();
+        Self { data, len }.fit()
     }
     pub fn shiftadd(&mut self, s: Self) -> Self {
         Self{data:(self.data<<s.len)+s.data,len:self.len+s.len}.fit()
@@ -31,9 +31,9 @@ impl Bits {
         Bits{data: ((data >> lsb) & ((1 << (msb - lsb + 1)) - 1)) as u64, len: msb-lsb+1}.fit()
     }
     pub fn cut(&self, msb: usize, lsb: usize) -> Self{
-        Bits{data: ((self.data >> lsb) & ((1 << (msb - lsb + 1)) - 1)) as u64, len: msb-lsb+1}.fit()
+        Bits{data: ((self.data >> lsb) & ((1 << (msb - lsb + 1)) - 1)), len: msb-lsb+1}.fit()
     }
-    pub fn to_u32(&self) -> u32{
+    pub fn to_u32(self) -> u32{
         self.data as u32
     }
     pub fn extend(&self) -> u32{
@@ -44,17 +44,27 @@ impl Bits {
             self.data as u32
         }
     }
+    /// Sign extended to 64 bits.
+    pub fn extend_u64(&self) -> u64 {
+        let shift = 64 - self.len;
+        ((self.data << shift) as i64 >> shift) as u64
+    }
+    pub fn to_u64(self) -> u64 {
+        self.data
+    }
     pub fn expand(&self, len: usize) -> Self{
         Self{data: self.data, len}.fit()
     }
+    #[allow(dead_code)]
     pub fn get_len(&self) -> usize{
         self.len
     }
+    #[allow(dead_code)]
     fn get_data(&self) -> u64 {
         self.data
     }
     fn fit(&self) -> Self{
-        return Self{data:self.data&((1<<self.len)-1),len:self.len}
+        Self{data:self.data&((1<<self.len)-1),len:self.len}
     }
 }
 
@@ -71,6 +81,13 @@ fn test_shiftadd(){
     assert!(b.to_u32()==63);
 }
 
+#[test]
+fn test_extend_u64() {
+    assert!(Bits::new(0x80, 8).extend_u64() == 0xffffffffffffff80);
+    assert!(Bits::new(0x1234_5678_9abc, 16).extend_u64() == 0xffffffffffff9abc);
+    assert!(Bits::new(0x7fff, 16).extend_u64() == 0x7fff);
+}
+
 #[test]
 fn test_expand(){
     let b = Bits::new(7,3).expand(5).shiftadd(Bits::new(7,3));
@@ -88,6 +105,6 @@ impl ops::Add<Bits> for Bits {
             _rhs.len
         };
         let data = (self.data + _rhs.data) & ((1 << len) - 1);
-        return Bits { data, len };
+        Bits { data, len }
     }
 }
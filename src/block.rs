@@ -0,0 +1,256 @@
+use crate::{
+    decode::{Decoded, Instruction, PushPop},
+    mmu::PAGE_SHIFT,
+};
+use std::{collections::HashMap, fmt::Write, ops::RangeInclusive, rc::Rc};
+
+/// Longest block translated, in instructions.
+pub const MAX_INSTS: usize = 64;
+
+/// Whether a block ends after `inst`: it may change the pc, the privilege or what is
+/// enabled, or it synchronizes the instruction stream.
+pub fn ends_block(inst: &Instruction) -> bool {
+    matches!(
+        inst,
+        Instruction::Jal { .. }
+            | Instruction::Jalr { .. }
+            | Instruction::Branch { .. }
+            | Instruction::Ecall
+            | Instruction::Sret
+            | Instruction::Mret
+            | Instruction::SfenceVma { .. }
+            | Instruction::Hfence { .. }
+            | Instruction::Csr { .. }
+            | Instruction::FenceI
+            | Instruction::PushPop { op: PushPop::Popret | PushPop::Popretz, .. }
+            | Instruction::TableJump { .. }
+            | Instruction::Custom { .. }
+    )
+}
+
+/// Straight-line run of decoded instructions, executed back to back without fetching.
+pub struct Block {
+    pub pc: u64,
+    pub insts: Rc<[Decoded]>,
+    /// address after the last instruction
+    pub end: u64,
+    /// blocks that followed this one, by where the direct branch ending it went: taken, and
+    /// fall through
+    links: [Option<(u64, usize)>; 2],
+    /// times run, until compiled
+    #[cfg(feature = "jit")]
+    pub runs: u32,
+    #[cfg(feature = "jit")]
+    pub native: Option<crate::jit::Code>,
+}
+
+impl Block {
+    pub fn new(pc: u64, insts: Vec<Decoded>) -> Self {
+        let end = pc + insts.iter().map(|d| d.len).sum::<u64>();
+        Block {
+            pc,
+            insts: insts.into(),
+            end,
+            links: [None; 2],
+            #[cfg(feature = "jit")]
+            runs: 0,
+            #[cfg(feature = "jit")]
+            native: None,
+        }
+    }
+    /// Whether the block ends in a direct branch or falls through into the next, so it goes on
+    /// to one of two blocks.
+    fn chains(&self) -> bool {
+        matches!(
+            self.insts.last().map(|d| d.inst),
+            Some(Instruction::Jal { .. }) | Some(Instruction::Branch { .. })
+        ) || self.insts.last().is_some_and(|d| !ends_block(&d.inst))
+    }
+    /// Pages the block's code is on.
+    fn pages(&self) -> RangeInclusive<u64> {
+        self.pc >> PAGE_SHIFT..=(self.end - 1) >> PAGE_SHIFT
+    }
+}
+
+/// How well the block cache does, for `--block-stats`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockStats {
+    /// blocks executed
+    pub run: u64,
+    /// blocks found through the link of the block before
+    pub chained: u64,
+    /// blocks found by looking up their pc
+    pub hits: u64,
+    pub translated: u64,
+    /// blocks dropped because their code was written, or by fence.i
+    pub invalidated: u64,
+    pub instructions: u64,
+    #[cfg(feature = "jit")]
+    pub compiled: u64,
+    /// instructions run as compiled code
+    #[cfg(feature = "jit")]
+    pub native: u64,
+}
+
+impl BlockStats {
+    /// Share of executed blocks that did not have to be translated first.
+    pub fn hit_rate(&self) -> f64 {
+        match self.run {
+            0 => 0.0,
+            run => (self.chained + self.hits) as f64 / run as f64,
+        }
+    }
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        writeln!(
+            out,
+            "blocks: {} run, {:.2}% hit ({} chained, {} looked up), {} translated, {} invalidated",
+            self.run,
+            100.0 * self.hit_rate(),
+            self.chained,
+            self.hits,
+            self.translated,
+            self.invalidated
+        )
+        .unwrap();
+        if self.run != 0 {
+            writeln!(
+                out,
+                "instructions: {}, {:.1} per block",
+                self.instructions,
+                self.instructions as f64 / self.run as f64
+            )
+            .unwrap();
+        }
+        #[cfg(feature = "jit")]
+        writeln!(
+            out,
+            "jit: {} blocks compiled, {} instructions native",
+            self.compiled, self.native
+        )
+        .unwrap();
+        out
+    }
+}
+
+/// Translated blocks by start address. Blocks are never moved, but the slot of a dropped one is
+/// reused, so following a link checks the block in its slot still starts at the link's pc.
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: Vec<Option<Block>>,
+    /// slots of dropped blocks
+    free: Vec<usize>,
+    index: HashMap<u64, usize>,
+    /// slots of the blocks on each page
+    pages: HashMap<u64, Vec<usize>>,
+    pub stats: BlockStats,
+}
+
+impl BlockCache {
+    /// The block at `pc` following block `from`, through its link if it has one.
+    pub fn find(&mut self, from: Option<usize>, pc: u64) -> Option<usize> {
+        let slot = |l: Option<(u64, usize)>| l.filter(|&(target, _)| target == pc).map(|l| l.1);
+        if let Some(Some(prev)) = from.and_then(|i| self.blocks.get(i)) {
+            if let Some(i) = slot(prev.links[0]).or_else(|| slot(prev.links[1])) {
+                if self.blocks[i].as_ref().is_some_and(|b| b.pc == pc) {
+                    self.stats.chained += 1;
+                    return Some(i);
+                }
+            }
+        }
+        let i = *self.index.get(&pc)?;
+        self.stats.hits += 1;
+        self.link(from, pc, i);
+        Some(i)
+    }
+    pub fn insert(&mut self, from: Option<usize>, block: Block) -> usize {
+        let pc = block.pc;
+        let i = match self.free.pop() {
+            Some(i) => i,
+            None => {
+                self.blocks.push(None);
+                self.blocks.len() - 1
+            }
+        };
+        for page in block.pages() {
+            self.pages.entry(page).or_default().push(i);
+        }
+        self.index.insert(pc, i);
+        self.blocks[i] = Some(block);
+        self.stats.translated += 1;
+        self.link(from, pc, i);
+        i
+    }
+    pub fn get(&self, i: usize) -> &Block {
+        self.blocks[i].as_ref().unwrap()
+    }
+    pub fn get_mut(&mut self, i: usize) -> &mut Block {
+        self.blocks[i].as_mut().unwrap()
+    }
+    fn link(&mut self, from: Option<usize>, pc: u64, i: usize) {
+        if let Some(Some(prev)) = from.and_then(|f| self.blocks.get_mut(f)) {
+            if prev.chains() {
+                let n = (pc == prev.end) as usize;
+                prev.links[n] = Some((pc, i));
+            }
+        }
+    }
+    /// Drops the blocks that may overlap `page`, including one straddling into it from the
+    /// page before.
+    pub fn invalidate(&mut self, page: u64) {
+        for i in self.pages.remove(&page).unwrap_or_default() {
+            let b = self.blocks[i].take().unwrap();
+            // a block straddling two pages is listed on the other one too
+            for other in b.pages().filter(|&p| p != page) {
+                if let Some(slots) = self.pages.get_mut(&other) {
+                    slots.retain(|&j| j != i);
+                    if slots.is_empty() {
+                        self.pages.remove(&other);
+                    }
+                }
+            }
+            self.index.remove(&b.pc);
+            self.free.push(i);
+            self.stats.invalidated += 1;
+        }
+    }
+    pub fn clear(&mut self) {
+        self.stats.invalidated += self.index.len() as u64;
+        self.blocks.clear();
+        self.free.clear();
+        self.index.clear();
+        self.pages.clear();
+    }
+}
+
+#[test]
+fn test_block_cache() {
+    let d = |inst| Decoded {
+        raw: 0,
+        len: 4,
+        expanded: 0,
+        inst,
+    };
+    let nop = Instruction::Addi { rd: 0, rs1: 0, imm: 0 };
+    let jal = Instruction::Jal { rd: 0, offset: 0xfff8 };
+    let mut cache = BlockCache::default();
+    // a loop of two blocks, 0x1ffc..0x2004 and 0x2004..0x2008, jumping back
+    let a = cache.insert(None, Block::new(0x1ffc, vec![d(nop), d(Instruction::Ecall)]));
+    assert!(cache.get(a).end == 0x2004);
+    assert!(cache.find(Some(a), 0x2004).is_none());
+    let b = cache.insert(Some(a), Block::new(0x2004, vec![d(jal)]));
+    assert!(cache.find(Some(b), 0x1ffc) == Some(a));
+    // ecall does not chain, jal does
+    assert!(cache.find(Some(a), 0x2004) == Some(b) && cache.find(Some(b), 0x1ffc) == Some(a));
+    assert!(cache.stats.chained == 1 && cache.stats.hits == 2);
+    cache.invalidate(1);
+    assert!(cache.find(Some(b), 0x1ffc).is_none() && cache.find(None, 0x2004) == Some(b));
+    assert!(cache.stats.invalidated == 1);
+    // the slot is reused, and the old link to it is not followed to the new block
+    let c = cache.insert(None, Block::new(0x3000, vec![d(nop)]));
+    assert!(c == a && cache.find(Some(b), 0x1ffc).is_none() && cache.find(None, 0x3000) == Some(c));
+    // the block straddling pages 1 and 2 is gone from page 2's list as well
+    cache.invalidate(2);
+    assert!(cache.find(None, 0x2004).is_none() && cache.stats.invalidated == 2);
+    assert!(cache.find(None, 0x3000) == Some(c));
+}
@@ -0,0 +1,268 @@
+//! C ABI for linking the emulator into RTL testbenches, declared in `include/rs_riscv_sc.h`.
+//!
+//! Every function taking a `RvscSim` pointer requires one returned by `rvsc_init` that has not
+//! been passed to `rvsc_free`, and buffers must be valid for the given length.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::{
+    cpu::{Cpu, Retired},
+    csr::Csr,
+    elf::Elf,
+    mmu::{Access, Mmu},
+    register::Register,
+    shadowstack::ShadowStacks,
+};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::{fs, ptr, slice};
+
+/// One hart with its memory, and the message of the last failed call.
+pub struct RvscSim {
+    cpu: Cpu,
+    error: CString,
+}
+
+impl RvscSim {
+    fn fail(&mut self, e: String) -> c_int {
+        self.error = CString::new(e.replace('\0', " ")).unwrap_or_default();
+        -1
+    }
+}
+
+/// What a call to `rvsc_step` did, mirroring `Retired`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct RvscRetire {
+    pub pc: u64,
+    pub next_pc: u64,
+    pub rd_value: u64,
+    pub mem_addr: u64,
+    pub mem_data: u64,
+    pub trap_cause: u64,
+    pub insn: u32,
+    /// 2 or 4, or 0 when an interrupt was taken instead of executing an instruction
+    pub len: u8,
+    pub privilege: u8,
+    /// destination register, 0 if none was written
+    pub rd: u8,
+    /// 1 if the instruction trapped instead of retiring, with the cause in `trap_cause`
+    pub trapped: u8,
+    /// size of the data access in bytes, 0 if there was none
+    pub mem_len: u8,
+    pub mem_write: u8,
+    pub reserved: [u8; 6],
+}
+
+impl From<&Retired> for RvscRetire {
+    fn from(r: &Retired) -> Self {
+        let mut out = RvscRetire {
+            pc: r.pc,
+            next_pc: r.next_pc,
+            insn: r.inst as u32,
+            len: r.len as u8,
+            privilege: r.privilege,
+            ..RvscRetire::default()
+        };
+        if let Some((rd, v)) = r.rd {
+            out.rd = rd as u8;
+            out.rd_value = v;
+        }
+        if let Some(cause) = r.trap {
+            out.trapped = 1;
+            out.trap_cause = cause;
+        }
+        if let Some(a) = r.mem.first() {
+            out.mem_addr = a.addr;
+            out.mem_data = a.data;
+            out.mem_len = a.len as u8;
+            out.mem_write = (a.access == Access::Write) as u8;
+        }
+        out
+    }
+}
+
+/// Runs `f`, returning `fallback` if it panics rather than unwinding into C, which aborts.
+fn catch<T>(fallback: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(fallback)
+}
+
+/// Runs `f` on `sim`, failing with the panic message if it panics.
+unsafe fn guard(sim: *mut RvscSim, f: impl FnOnce(&mut RvscSim) -> c_int) -> c_int {
+    match panic::catch_unwind(AssertUnwindSafe(|| f(&mut *sim))) {
+        Ok(r) => r,
+        Err(payload) => {
+            let message = match payload.downcast_ref::<&str>() {
+                Some(s) => s.to_string(),
+                None => payload.downcast_ref::<String>().cloned().unwrap_or_default(),
+            };
+            (*sim).fail(format!("panic: {}", message))
+        }
+    }
+}
+
+/// Creates a machine-mode hart with `mem_size` bytes of zeroed memory at `mem_base`, starting
+/// at `mem_base`.
+#[no_mangle]
+pub extern "C" fn rvsc_init(mem_base: u64, mem_size: u64) -> *mut RvscSim {
+    catch(ptr::null_mut(), || {
+        let cpu = Cpu::new(
+            mem_base,
+            Csr::new([0; 4096]),
+            Register::new([0; 32]),
+            0b11,
+            Mmu::with_base(vec![0; mem_size as usize], mem_base, false),
+            ShadowStacks::new(0, 0b11, false),
+        );
+        Box::into_raw(Box::new(RvscSim {
+            cpu,
+            error: CString::default(),
+        }))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rvsc_free(sim: *mut RvscSim) {
+    if !sim.is_null() {
+        catch((), || drop(Box::from_raw(sim)));
+    }
+}
+
+/// Message of the last call that returned -1, valid until the next call.
+#[no_mangle]
+pub unsafe extern "C" fn rvsc_last_error(sim: *const RvscSim) -> *const c_char {
+    (*sim).error.as_ptr()
+}
+
+/// Copies the loadable segments of an ELF file into memory and jumps to its entry point.
+#[no_mangle]
+pub unsafe extern "C" fn rvsc_load_elf(sim: *mut RvscSim, path: *const c_char) -> c_int {
+    guard(sim, |sim| {
+        let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+        let buf = match fs::read(&path) {
+            Ok(buf) => buf,
+            Err(e) => return sim.fail(format!("{}: {}", path, e)),
+        };
+        let elf = match Elf::parse(&buf) {
+            Ok(elf) => elf,
+            Err(e) => return sim.fail(format!("{}: {}", path, e)),
+        };
+        if let Err(e) = elf.load(sim.cpu.mmu()) {
+            return sim.fail(e);
+        }
+        sim.cpu.set_pc(elf.entry);
+        0
+    })
+}
+
+/// Executes one instruction, or takes a pending interrupt, and describes it in `retire` if that
+/// is not null. Returns -1 if the emulator stopped, see `rvsc_last_error`.
+#[no_mangle]
+pub unsafe extern "C" fn rvsc_step(sim: *mut RvscSim, retire: *mut RvscRetire) -> c_int {
+    guard(sim, |sim| {
+        if let Err(e) = sim.cpu.step() {
+            return sim.fail(e);
+        }
+        if !retire.is_null() {
+            ptr::write(retire, RvscRetire::from(sim.cpu.retired()));
+        }
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rvsc_get_pc(sim: *const RvscSim) -> u64 {
+    (*sim).cpu.get_pc()
+}
+
+/// Value of x`n`, or 0 if there is no such register.
+#[no_mangle]
+pub unsafe extern "C" fn rvsc_get_reg(sim: *mut RvscSim, n: u32) -> u64 {
+    catch(0, || {
+        let cpu = &mut (*sim).cpu;
+        let len = cpu.get_xlen_bytes();
+        cpu.register().read(n as usize, len).unwrap_or(0)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rvsc_read_mem(sim: *mut RvscSim, addr: u64, buf: *mut u8, len: usize) -> c_int {
+    guard(sim, |sim| {
+        if !sim.cpu.mmu().contains(addr, len as u64) {
+            return sim.fail(format!("{:#x} of {:#x} bytes is outside memory", addr, len));
+        }
+        let out = slice::from_raw_parts_mut(buf, len);
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = sim.cpu.mmu().peek(addr + i as u64, 1) as u8;
+        }
+        0
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rvsc_write_mem(sim: *mut RvscSim, addr: u64, buf: *const u8, len: usize) -> c_int {
+    guard(sim, |sim| {
+        if !sim.cpu.mmu().contains(addr, len as u64) {
+            return sim.fail(format!("{:#x} of {:#x} bytes is outside memory", addr, len));
+        }
+        sim.cpu.mmu().poke(addr, slice::from_raw_parts(buf, len));
+        0
+    })
+}
+
+/// Makes machine interrupt `cause` pending; it is taken by a later `rvsc_step` once enabled.
+#[no_mangle]
+pub unsafe extern "C" fn rvsc_raise_interrupt(sim: *mut RvscSim, cause: u32) -> c_int {
+    guard(sim, |sim| match sim.cpu.raise_interrupt(cause as u64) {
+        Ok(()) => 0,
+        Err(e) => sim.fail(e),
+    })
+}
+
+#[test]
+fn test_capi() {
+    unsafe {
+        let sim = rvsc_init(0x1000, 0x100);
+        let program: [u32; 7] = [
+            0x000012b7, // lui t0, 0x1
+            0x08028293, // addi t0, t0, 0x80
+            0x30529073, // csrw mtvec, t0
+            0x30046073, // csrsi mstatus, 8
+            0x00001337, // lui t1, 0x1
+            0x80030313, // addi t1, t1, -0x800
+            0x30431073, // csrw mie, t1
+        ];
+        let bytes: Vec<u8> = program.iter().flat_map(|i| i.to_le_bytes().to_vec()).collect();
+        assert!(rvsc_write_mem(sim, 0x1000, bytes.as_ptr(), bytes.len()) == 0);
+        assert!(rvsc_write_mem(sim, 0x10fe, bytes.as_ptr(), 4) == -1);
+        assert!(CStr::from_ptr(rvsc_last_error(sim)).to_str().unwrap().contains("outside memory"));
+        let mut r = RvscRetire::default();
+        assert!(rvsc_step(sim, &mut r) == 0);
+        assert!(r.pc == 0x1000 && r.insn == 0x000012b7 && r.len == 4 && r.rd == 5 && r.rd_value == 0x1000);
+        for _ in 0..6 {
+            assert!(rvsc_step(sim, &mut r) == 0);
+        }
+        assert!(rvsc_get_pc(sim) == 0x101c);
+        assert!(rvsc_get_reg(sim, 6) == 0x800);
+        assert!(rvsc_raise_interrupt(sim, 11) == 0);
+        assert!(rvsc_step(sim, &mut r) == 0);
+        assert!(r.trapped == 1 && r.trap_cause == 0x8000000b && r.len == 0);
+        assert!(r.pc == 0x101c && r.next_pc == 0x1080 && rvsc_get_pc(sim) == 0x1080);
+        let mut word = [0u8; 4];
+        assert!(rvsc_read_mem(sim, 0x1004, word.as_mut_ptr(), 4) == 0);
+        assert!(u32::from_le_bytes(word) == 0x08028293);
+        // lw a0, 0(zero) has no memory to load from, and traps
+        assert!(rvsc_write_mem(sim, 0x1080, 0x00002503u32.to_le_bytes().as_ptr(), 4) == 0);
+        assert!(rvsc_step(sim, &mut r) == 0);
+        assert!(r.trapped == 1 && r.trap_cause == 5 && r.next_pc == 0x1080);
+        rvsc_free(sim);
+
+        let sim = rvsc_init(0x80000000, 0x4000);
+        let path = CString::new(concat!(env!("CARGO_MANIFEST_DIR"), "/riscv-tests/rv32ui-p-add")).unwrap();
+        assert!(rvsc_load_elf(sim, path.as_ptr()) == 0);
+        assert!(rvsc_get_pc(sim) == 0x80000000);
+        assert!(rvsc_step(sim, ptr::null_mut()) == 0);
+        assert!(rvsc_get_pc(sim) == 0x80000048);
+        rvsc_free(sim);
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{
+    cpu::Retired,
+    csr,
+    mmu::Access,
+};
+use std::io::Write;
+
+/// Writes retired instructions in the format of Spike's `--log-commits`, so runs can be diffed.
+pub struct CommitLog {
+    out: Box<dyn Write>,
+    hart: u64,
+}
+
+impl CommitLog {
+    pub fn new(out: Box<dyn Write>, hart: u64) -> Self {
+        CommitLog { out, hart }
+    }
+    pub fn log(&mut self, retired: &Retired, xlen: u32) {
+        if let Some(line) = format_commit(retired, xlen, self.hart) {
+            // the log must never stop the guest, so a closed sink is ignored
+            let _ = writeln!(self.out, "{}", line);
+        }
+    }
+}
+
+fn value(bits: u32, v: u64) -> String {
+    let v = if bits >= 64 { v } else { v & ((1 << bits) - 1) };
+    format!("0x{:0width$x}", v, width = bits as usize / 4)
+}
+
+/// One commit log line, or `None` for an instruction that trapped, which Spike does not log.
+///
+/// Register writes are ordered like Spike's, by `(number << 4) | kind` with kind 0 for x
+/// registers, 1 for f registers and 4 for CSRs, followed by loads and then stores.
+pub fn format_commit(r: &Retired, xlen: u32, hart: u64) -> Option<String> {
+    if r.trap.is_some() {
+        return None;
+    }
+    let mut line = format!(
+        "core{:4}: {} {} ({})",
+        hart,
+        r.privilege,
+        value(xlen, r.pc),
+        value(8 * r.len as u32, r.inst)
+    );
+    let mut writes: Vec<(usize, String)> = Vec::new();
+    if let Some((rd, v)) = r.rd {
+        writes.push((rd << 4, format!(" x{:<2} {}", rd, value(xlen, v))));
+    }
+    if let Some((rd, v)) = r.frd {
+        writes.push(((rd << 4) | 1, format!(" f{:<2} {}", rd, value(64, v))));
+    }
+    for &(n, v) in r.csr_writes.iter() {
+        let key = (n << 4) | 4;
+        let text = format!(" c{}_{} {}", n, csr::name(n), value(xlen, v));
+        // a CSR written twice is logged once, with its final value
+        match writes.iter_mut().find(|(k, _)| *k == key) {
+            Some(w) => w.1 = text,
+            None => writes.push((key, text)),
+        }
+    }
+    writes.sort_by_key(|&(key, _)| key);
+    for (_, text) in writes.iter() {
+        line += text;
+    }
+    for a in r.mem.iter().filter(|a| a.access == Access::Read) {
+        line += &format!(" mem {}", value(xlen, a.addr));
+    }
+    for a in r.mem.iter().filter(|a| a.access == Access::Write) {
+        line += &format!(" mem {} {}", value(xlen, a.addr), value(8 * a.len as u32, a.data));
+    }
+    Some(line)
+}
+
+#[test]
+fn test_format_commit() {
+    use crate::mmu::MemAccess;
+    let auipc = Retired {
+        pc: 0x80000000,
+        inst: 0x00000297,
+        len: 4,
+        privilege: 3,
+        rd: Some((5, 0x80000000)),
+        ..Retired::default()
+    };
+    assert!(format_commit(&auipc, 32, 0).unwrap() == "core   0: 3 0x80000000 (0x00000297) x5  0x80000000");
+    let csrrw = Retired {
+        pc: 0x80000004,
+        inst: 0x34029373,
+        len: 4,
+        privilege: 3,
+        rd: Some((6, 0)),
+        csr_writes: vec![(0x340, 1), (0x340, 2)],
+        ..Retired::default()
+    };
+    assert!(format_commit(&csrrw, 32, 0).unwrap().ends_with(" x6  0x00000000 c832_mscratch 0x00000002"));
+    let sb = Retired {
+        pc: 0x80000008,
+        inst: 0x00a10023,
+        len: 4,
+        privilege: 0,
+        mem: vec![MemAccess {
+            addr: 0x80001000,
+            len: 1,
+            access: Access::Write,
+            data: 0x5,
+        }],
+        ..Retired::default()
+    };
+    assert!(format_commit(&sb, 32, 1).unwrap() == "core   1: 0 0x80000008 (0x00a10023) mem 0x80001000 0x05");
+    let c_li = Retired {
+        inst: 0x4515,
+        len: 2,
+        rd: Some((10, 5)),
+        ..Retired::default()
+    };
+    assert!(format_commit(&c_li, 32, 0).unwrap() == "core   0: 0 0x00000000 (0x4515) x10 0x00000005");
+    let fadd = Retired {
+        inst: 0x00c5f553,
+        len: 4,
+        frd: Some((10, 0xffffffff3fc00000)),
+        csr_writes: vec![(0x300, 0x80006000), (0x001, 1)],
+        ..Retired::default()
+    };
+    assert!(format_commit(&fadd, 32, 0).unwrap().ends_with(
+        " c1_fflags 0x00000001 f10 0xffffffff3fc00000 c768_mstatus 0x80006000"
+    ));
+    let ecall = Retired {
+        trap: Some(11),
+        ..Retired::default()
+    };
+    assert!(format_commit(&ecall, 32, 0).is_none());
+}
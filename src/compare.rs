@@ -0,0 +1,243 @@
+use crate::{commitlog, cpu::Retired};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::io::BufRead;
+
+/// One parsed commit log line.
+#[derive(Debug, PartialEq)]
+pub struct Commit {
+    pub pc: u64,
+    pub inst: u64,
+    /// register and CSR writes as (name, value), e.g. ("x5", 0x80000000) or ("c833_mepc", 0)
+    pub writes: Vec<(String, u64)>,
+    pub loads: Vec<u64>,
+    pub stores: Vec<(u64, u64)>,
+}
+
+fn hex(token: &str) -> Option<u64> {
+    u64::from_str_radix(token.strip_prefix("0x")?, 16).ok()
+}
+
+/// Parses a line of Spike's `--log-commits` output, or `None` if it is not a commit line.
+pub fn parse_commit(line: &str) -> Option<Commit> {
+    let rest = line.trim().strip_prefix("core")?;
+    let mut tokens = rest.split_once(':')?.1.split_whitespace().peekable();
+    let _privilege = tokens.next()?;
+    let pc = hex(tokens.next()?)?;
+    let inst = hex(tokens.next()?.strip_prefix('(')?.strip_suffix(')')?)?;
+    let mut commit = Commit {
+        pc,
+        inst,
+        writes: Vec::new(),
+        loads: Vec::new(),
+        stores: Vec::new(),
+    };
+    while let Some(name) = tokens.next() {
+        let v = hex(tokens.next()?)?;
+        if name != "mem" {
+            commit.writes.push((name.to_string(), v));
+            continue;
+        }
+        // a store carries its data after the address, a load does not
+        match tokens.peek().and_then(|t| hex(t)) {
+            Some(data) => {
+                tokens.next();
+                commit.stores.push((v, data));
+            }
+            None => commit.loads.push(v),
+        }
+    }
+    Some(commit)
+}
+
+/// First difference between our commit and the reference's, in order of importance.
+fn diverges(ours: &Commit, reference: &Commit) -> Option<String> {
+    if ours.pc != reference.pc {
+        return Some(format!("pc {:#x}, reference {:#x}", ours.pc, reference.pc));
+    }
+    if ours.inst != reference.inst {
+        return Some(format!(
+            "instruction word {:#x}, reference {:#x}",
+            ours.inst, reference.inst
+        ));
+    }
+    if ours.writes != reference.writes {
+        let show = |w: &[(String, u64)]| match w.len() {
+            0 => String::from("none"),
+            _ => w
+                .iter()
+                .map(|(n, v)| format!("{} = {:#x}", n, v))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        return Some(format!(
+            "register writeback {}, reference {}",
+            show(&ours.writes),
+            show(&reference.writes)
+        ));
+    }
+    if ours.stores != reference.stores {
+        let show = |s: &[(u64, u64)]| match s.len() {
+            0 => String::from("none"),
+            _ => s
+                .iter()
+                .map(|(a, d)| format!("[{:#x}] = {:#x}", a, d))
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        return Some(format!(
+            "memory write {}, reference {}",
+            show(&ours.stores),
+            show(&reference.stores)
+        ));
+    }
+    None
+}
+
+/// Replays a reference commit log in lockstep with execution and reports the first divergence.
+pub struct Comparator {
+    reference: Box<dyn BufRead>,
+    /// line number of the last reference line read
+    line: u64,
+    retired: u64,
+    context: usize,
+    ours: VecDeque<String>,
+    theirs: VecDeque<String>,
+}
+
+impl Comparator {
+    /// `context` is how many of the preceding instructions are shown on a divergence.
+    pub fn new(reference: Box<dyn BufRead>, context: usize) -> Self {
+        Comparator {
+            reference,
+            line: 0,
+            retired: 0,
+            context,
+            ours: VecDeque::new(),
+            theirs: VecDeque::new(),
+        }
+    }
+
+    fn next_reference(&mut self) -> Result<Option<(String, Commit)>, String> {
+        loop {
+            let mut text = String::new();
+            let n = self
+                .reference
+                .read_line(&mut text)
+                .map_err(|e| format!("reading reference trace: {}", e))?;
+            if n == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            // other log output interleaved with the commits is skipped
+            if let Some(commit) = parse_commit(&text) {
+                return Ok(Some((text.trim_end().to_string(), commit)));
+            }
+        }
+    }
+
+    /// Checks one retired instruction against the next reference commit. Trapped instructions
+    /// are not in Spike's log and are skipped. On a divergence returns the report as the error.
+    pub fn check(&mut self, retired: &Retired, xlen: u32, hart: u64) -> Result<(), String> {
+        let ours_text = match commitlog::format_commit(retired, xlen, hart) {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+        let ours = parse_commit(&ours_text).expect("commit log lines parse");
+        let (reason, theirs_text) = match self.next_reference()? {
+            Some((text, theirs)) => match diverges(&ours, &theirs) {
+                Some(reason) => (reason, text),
+                None => {
+                    self.push(ours_text, text);
+                    return Ok(());
+                }
+            },
+            None => (String::from("reference trace ended"), String::from("<end of trace>")),
+        };
+        let mut report = format!(
+            "divergence from reference at instruction {} (reference line {}): {}\n",
+            self.retired, self.line, reason
+        );
+        writeln!(report, "ours:").unwrap();
+        for l in self.ours.iter() {
+            writeln!(report, "    {}", l).unwrap();
+        }
+        writeln!(report, "  > {}", ours_text).unwrap();
+        writeln!(report, "reference:").unwrap();
+        for l in self.theirs.iter() {
+            writeln!(report, "    {}", l).unwrap();
+        }
+        write!(report, "  > {}", theirs_text).unwrap();
+        Err(report)
+    }
+
+    fn push(&mut self, ours: String, theirs: String) {
+        self.retired += 1;
+        self.ours.push_back(ours);
+        self.theirs.push_back(theirs);
+        if self.ours.len() > self.context {
+            self.ours.pop_front();
+            self.theirs.pop_front();
+        }
+    }
+}
+
+#[test]
+fn test_parse_commit() {
+    let c = parse_commit("core   0: 3 0x80000008 (0x00a10023) x5  0x00000001 c833_mepc 0x00000004 mem 0x80001000 mem 0x80001004 0x05").unwrap();
+    assert!(c.pc == 0x80000008 && c.inst == 0xa10023);
+    assert!(c.writes == vec![(String::from("x5"), 1), (String::from("c833_mepc"), 4)]);
+    assert!(c.loads == vec![0x80001000]);
+    assert!(c.stores == vec![(0x80001004, 5)]);
+    assert!(parse_commit("core   0: exception trap_user_ecall, epc 0x80000008").is_none());
+    assert!(parse_commit("bbl loader").is_none());
+}
+
+#[test]
+fn test_check() {
+    let reference = "core   0: 3 0x00000000 (0x00500513) x10 0x00000005\n\
+                     core   0: 3 0x00000004 (0x00a02823) mem 0x00000010 0x00000005\n\
+                     core   0: 3 0x00000008 (0x00150513) x10 0x00000007\n";
+    let mut cmp = Comparator::new(Box::new(std::io::Cursor::new(reference)), 1);
+    let mut r = Retired {
+        pc: 0,
+        inst: 0x00500513,
+        len: 4,
+        privilege: 3,
+        rd: Some((10, 5)),
+        ..Retired::default()
+    };
+    cmp.check(&r, 32, 0).unwrap();
+    r = Retired {
+        pc: 4,
+        inst: 0x00a02823,
+        len: 4,
+        privilege: 3,
+        mem: vec![crate::mmu::MemAccess {
+            addr: 0x10,
+            len: 4,
+            access: crate::mmu::Access::Write,
+            data: 5,
+        }],
+        ..Retired::default()
+    };
+    cmp.check(&r, 32, 0).unwrap();
+    r = Retired {
+        trap: Some(11),
+        ..Retired::default()
+    };
+    cmp.check(&r, 32, 0).unwrap();
+    r = Retired {
+        pc: 8,
+        inst: 0x00150513,
+        len: 4,
+        privilege: 3,
+        rd: Some((10, 6)),
+        ..Retired::default()
+    };
+    let report = cmp.check(&r, 32, 0).unwrap_err();
+    assert!(report.starts_with("divergence from reference at instruction 2 (reference line 3): register writeback x10 = 0x6, reference x10 = 0x7\n"));
+    assert!(!report.contains("(0x00500513)"));
+    assert!(report.contains("    core   0: 3 0x00000004 (0x00a02823) mem 0x00000010 0x00000005\n"));
+    assert!(report.ends_with("  > core   0: 3 0x00000008 (0x00150513) x10 0x00000007"));
+}
@@ -0,0 +1,77 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/// A local socket connection to a debugger or verification tool.
+pub enum Conn {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Conn {
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Conn::Tcp(s) => s.set_nonblocking(nonblocking),
+            Conn::Unix(s) => s.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Conn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Conn::Tcp(s) => s.read(buf),
+            Conn::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Conn {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Conn::Tcp(s) => s.write(buf),
+            Conn::Unix(s) => s.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Conn::Tcp(s) => s.flush(),
+            Conn::Unix(s) => s.flush(),
+        }
+    }
+}
+
+/// Waits for one connection from `peer` on a TCP port (`1234`, `host:1234`) or a Unix socket path.
+pub fn listen(addr: &str, peer: &str) -> io::Result<Conn> {
+    if let Ok(port) = addr.parse::<u16>() {
+        println!("waiting for {} on port {}", peer, port);
+        return Ok(Conn::Tcp(TcpListener::bind(("127.0.0.1", port))?.accept()?.0));
+    }
+    if addr.contains(':') {
+        println!("waiting for {} on {}", peer, addr);
+        return Ok(Conn::Tcp(TcpListener::bind(addr)?.accept()?.0));
+    }
+    // a socket left by an earlier run is replaced, but nothing else is
+    match std::fs::symlink_metadata(addr) {
+        Ok(m) if m.file_type().is_socket() => std::fs::remove_file(addr)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", addr),
+            ))
+        }
+        Err(_) => {}
+    }
+    println!("waiting for {} on {}", peer, addr);
+    Ok(Conn::Unix(UnixListener::bind(addr)?.accept()?.0))
+}
+
+#[test]
+fn test_listen_keeps_files() {
+    let path = std::env::temp_dir().join(format!("rs-riscv-sc-listen-{}", std::process::id()));
+    std::fs::write(&path, "data").unwrap();
+    assert!(listen(path.to_str().unwrap(), "test").is_err());
+    assert!(std::fs::read(&path).unwrap() == b"data");
+    std::fs::remove_file(&path).unwrap();
+}
@@ -1,15 +1,33 @@
 use crate::{
+    backtrace::{Report, Violation},
     bitcat,
+    bitmanip,
+    commitlog::CommitLog,
+    compare::Comparator,
+    custom::{self, Custom, Hart},
+    decode::{Cond, CsrOp, DecodeCache, Decoded, FmaOp, FpOp, Instruction, PushPop, VMode, VOp, VSrc, ZCMP_REGS},
     bitutils::{self, Bits},
-    cpu::rv32::get_bits,
-    csr::Csr,
-    mmu::Mmu,
-    register::Register,
-    shadowstack::ShadowStack,
+    block::{self, Block, BlockCache, BlockStats},
+    crypto::{self, Entropy},
+    csr::{self, Csr},
+    disasm,
+    elf::Elf,
+    float::{Env, Format, Rounding, F16, F32, F64},
+    isa::Isa,
+    mmu::{Access, MemAccess, Mmu, PAGE_SHIFT},
+    paging::{self, Fault, Kind, Walk},
+    plugin::{self, Plugin},
+    register::{FRegister, Register, ABI_NAMES, FP_ABI_NAMES},
+    shadowstack::{self, Return, ShadowStacks},
+    trace,
+    trace::{category, Tracer},
+    vector::{self, Context, Operand, VRegister, VectorConfig, Vtype},
 };
-use std::{io, ops::Add, str::FromStr};
+#[cfg(feature = "jit")]
+use crate::jit;
+use std::{io, ops::Add};
 
-mod op {
+pub mod op {
     pub const LUDI: u32 = 0b01101;
     pub const AUIPC: u32 = 0b00101;
     pub const JAL: u32 = 0b11011;
@@ -21,10 +39,38 @@ mod op {
     pub const AREG: u32 = 0b01100;
     pub const CSR: u32 = 0b11100;
     pub const FENCE: u32 = 0b00011;
+    pub const LOAD_FP: u32 = 0b00001;
+    pub const STORE_FP: u32 = 0b01001;
+    pub const MADD: u32 = 0b10000;
+    pub const MSUB: u32 = 0b10001;
+    pub const NMSUB: u32 = 0b10010;
+    pub const NMADD: u32 = 0b10011;
+    pub const OP_FP: u32 = 0b10100;
+    pub const OP_V: u32 = 0b10101;
+    /// OP-IMM-32 and OP-32, RV64 only
+    pub const AIMM32: u32 = 0b00110;
+    pub const AREG32: u32 = 0b01110;
+}
+
+//funct5 for OP-FP
+pub mod f5fp {
+    pub const ADD: u32 = 0b00000;
+    pub const SUB: u32 = 0b00001;
+    pub const MUL: u32 = 0b00010;
+    pub const DIV: u32 = 0b00011;
+    pub const SQRT: u32 = 0b01011;
+    pub const SGNJ: u32 = 0b00100;
+    pub const MIN_MAX: u32 = 0b00101;
+    pub const CVT_FMT: u32 = 0b01000;
+    pub const CMP: u32 = 0b10100;
+    pub const CVT_TO_INT: u32 = 0b11000;
+    pub const CVT_FROM_INT: u32 = 0b11010;
+    pub const MV_TO_INT_CLASS: u32 = 0b11100;
+    pub const MV_FROM_INT: u32 = 0b11110;
 }
 
 //funct3 for branch
-mod f3b {
+pub mod f3b {
     pub const BEQ: u32 = 0b000;
     pub const BNE: u32 = 0b001;
     pub const BLT: u32 = 0b100;
@@ -34,7 +80,7 @@ mod f3b {
 }
 
 //funct3 for load
-mod f3l {
+pub mod f3l {
     pub const LB: u32 = 0b000;
     pub const LH: u32 = 0b001;
     pub const LW: u32 = 0b010;
@@ -43,14 +89,14 @@ mod f3l {
 }
 
 //funct3 for store
-mod f3s {
+pub mod f3s {
     pub const SB: u32 = 0b000;
     pub const SH: u32 = 0b001;
     pub const SW: u32 = 0b010;
 }
 
 //funct3 for arithmatic immediate
-mod f3i {
+pub mod f3i {
     pub const ADDI: u32 = 0b000;
     pub const SLTI: u32 = 0b010;
     pub const SLTIU: u32 = 0b011;
@@ -62,7 +108,8 @@ mod f3i {
 }
 
 //funct3 for arithmatic register
-mod f3r {
+#[allow(dead_code)]
+pub mod f3r {
     pub const ADD_SUB: u32 = 0b000;
     pub const SLL: u32 = 0b001;
     pub const SLT: u32 = 0b010;
@@ -73,7 +120,7 @@ mod f3r {
     pub const AND: u32 = 0b111;
 }
 
-mod f3c {
+pub mod f3c {
     pub const EXCEPT: u32 = 0b000;
     pub const CSRRW: u32 = 0b001;
     pub const CSRRS: u32 = 0b010;
@@ -81,53 +128,109 @@ mod f3c {
     pub const CSRRWI: u32 = 0b101;
     pub const CSRRSI: u32 = 0b110;
     pub const CSRRCI: u32 = 0b111;
+    /// the hypervisor loads and stores
+    pub const HLSV: u32 = 0b100;
+}
+
+/// OP-V funct3, the operand kinds
+pub mod f3v {
+    pub const OPIVV: u32 = 0b000;
+    pub const OPFVV: u32 = 0b001;
+    pub const OPMVV: u32 = 0b010;
+    pub const OPIVI: u32 = 0b011;
+    pub const OPIVX: u32 = 0b100;
+    pub const OPFVF: u32 = 0b101;
+    pub const OPMVX: u32 = 0b110;
+    pub const OPCFG: u32 = 0b111;
 }
 
 mod privilege {
-    pub const user: u8 = 0b00;
-    pub const supervisor: u8 = 0b01;
-    pub const hypervisor: u8 = 0b10;
-    pub const machine: u8 = 0b11;
+    pub const USER: u8 = 0b00;
+    pub const SUPERVISOR: u8 = 0b01;
+    pub const HYPERVISOR: u8 = 0b10;
+    pub const MACHINE: u8 = 0b11;
+}
+
+/// Exception causes raised other than by memory accesses, whose causes are in `paging::Fault`.
+mod cause {
+    pub const ILLEGAL_INSTRUCTION: u64 = 2;
+    pub const ECALL_U: u64 = 8;
+    pub const ECALL_S: u64 = 9;
+    pub const ECALL_VS: u64 = 10;
+    pub const ECALL_M: u64 = 11;
+    pub const VIRTUAL_INSTRUCTION: u64 = 22;
+}
+
+/// An exception raised by an instruction, which traps instead of stopping the cpu.
+#[derive(Clone, Copy, Debug)]
+struct Exception {
+    cause: u64,
+    tval: u64,
+    /// the guest physical address of a guest-page fault
+    gpa: u64,
+    /// whether `tval` is a guest virtual address
+    gva: bool,
 }
 
-mod exception {
-    pub const ecall: u32 = 0;
-    pub const mret: u32 = 0b001100000010;
+pub mod exception {
+    pub const ECALL: u32 = 0;
+    pub const SRET: u32 = 0b000100000010;
+    pub const MRET: u32 = 0b001100000010;
+    /// funct7 of the fences, which take rs1 and rs2
+    pub const SFENCE_VMA: u32 = 0b0001001;
+    pub const HFENCE_VVMA: u32 = 0b0010001;
+    pub const HFENCE_GVMA: u32 = 0b0110001;
 }
 
+mod f3co_0 {
+    pub const FLD: u32 = 0b001;
+    pub const FLW: u32 = 0b011;
+    /// the Zcb byte and half loads and stores
+    pub const ZCB: u32 = 0b100;
+    pub const FSD: u32 = 0b101;
+    pub const FSW: u32 = 0b111;
+}
+
+#[allow(dead_code)]
 mod f3co_1 {
-    pub const li: u32 = 0b010;
-    pub const lui: u32 = 0b011;
-    pub const beqz: u32 = 0b110;
+    pub const JAL: u32 = 0b001;
+    pub const LI: u32 = 0b010;
+    pub const LUI: u32 = 0b011;
+    pub const MISC_ALU: u32 = 0b100;
+    pub const BEQZ: u32 = 0b110;
 }
 
+#[allow(dead_code)]
 mod f3co_2 {
-    pub const slli: u32 = 0b000;
-    pub const jal: u32 = 0b001;
-    pub const lwsp: u32 = 0b010;
-    pub const mv_ja: u32 = 0b100;
-    pub const ldsp: u32 = 0b011;
-    pub const swsp: u32 = 0b110;
-    pub const sdsp: u32 = 0b111;
+    pub const SLLI: u32 = 0b000;
+    pub const FLDSP: u32 = 0b001;
+    pub const LWSP: u32 = 0b010;
+    pub const MV_JA: u32 = 0b100;
+    pub const FLWSP: u32 = 0b011;
+    pub const FSDSP: u32 = 0b101;
+    pub const SWSP: u32 = 0b110;
+    pub const FSWSP: u32 = 0b111;
 }
 
+/// A register-to-register instruction, which returns the register file it leaves behind.
+/// Custom instructions can be registered as one, see `Cpu::add_custom`.
 pub trait R2R {
     fn exec_register(&self, state: State, reg: &Register) -> Result<Register, String>;
 }
 
-struct RegisterInst {
-    exec: fn(&Self, State, &Register) -> Result<Register, String>,
+pub struct RegisterInst {
+    pub exec: fn(&Self, State, &Register) -> Result<Register, String>,
 }
 
 impl R2R for RegisterInst {
     fn exec_register(&self, state: State, reg: &Register) -> Result<Register, String> {
-        (self.exec)(&self, state, reg)
+        (self.exec)(self, state, reg)
     }
 }
 
-const add: RegisterInst = RegisterInst {
-    exec: |sel, state, reg| {
-        let mut r = reg.clone();
+const ADD: RegisterInst = RegisterInst {
+    exec: |_sel, state, reg| {
+        let mut r = *reg;
         if rv32::get_bits(state.imm as u32, 10, 10) != 1 {
             r.write(
                 state.rd,
@@ -150,6 +253,7 @@ pub struct State {
     rs2: usize,
     imm: u64,
 }
+#[allow(dead_code)]
 impl State {
     pub fn new(rd: usize, rs1: usize, rs2: usize, imm: u64) -> Self {
         Self { rd, rs1, rs2, imm }
@@ -168,14 +272,64 @@ impl State {
     }
 }
 
+/// Architectural effects of the last retired instruction, for commit logs and co-simulation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Retired {
+    pub pc: u64,
+    /// instruction bits as fetched, 16 bits wide if compressed
+    pub inst: u64,
+    pub len: u64,
+    /// privilege the instruction executed in
+    pub privilege: u8,
+    /// source registers and the values read, if the format has them
+    pub rs1: Option<(usize, u64)>,
+    pub rs2: Option<(usize, u64)>,
+    /// destination register and the value written, unless rd is x0 or there is none
+    pub rd: Option<(usize, u64)>,
+    /// floating-point destination register and the value written
+    pub frd: Option<(usize, u64)>,
+    pub csr_writes: Vec<(usize, u64)>,
+    pub mem: Vec<MemAccess>,
+    /// CSR read by a Zicsr instruction and its value before the instruction
+    pub csr_read: Option<(usize, u64)>,
+    /// cause of the trap the instruction took instead of completing
+    pub trap: Option<u64>,
+    /// shadow stack push or checked return, if the instruction was a call or return
+    pub sstack: Option<shadowstack::Event>,
+    pub next_pc: u64,
+}
+
 pub struct Cpu {
     pc: u64,
     len: u8,
     csr: Csr,
     register: Register,
+    fregister: FRegister,
+    vregister: VRegister,
     privilege: u8,
+    /// in a virtual machine, VS or VU mode
+    virt: bool,
     mmu: Mmu,
-    sstack: ShadowStack,
+    sstack: ShadowStacks,
+    stop: Option<(u64, String)>,
+    violation: Option<Violation>,
+    exception: Option<Exception>,
+    /// bits of the instruction executing, the trap value of an illegal-instruction exception
+    inst: u64,
+    trap: Option<u64>,
+    sstack_event: Option<shadowstack::Event>,
+    tracer: Tracer,
+    retired: Retired,
+    commit_log: Option<CommitLog>,
+    compare: Option<Comparator>,
+    plugins: Vec<Box<dyn Plugin>>,
+    customs: Vec<custom::Entry>,
+    isa: Isa,
+    entropy: Entropy,
+    decode_cache: DecodeCache,
+    blocks: BlockCache,
+    #[cfg(feature = "jit")]
+    jit: bool,
 }
 
 impl Cpu {
@@ -185,136 +339,988 @@ impl Cpu {
         register: Register,
         privilege: u8,
         mmu: Mmu,
-        sstack: ShadowStack,
+        sstack: ShadowStacks,
     ) -> Cpu {
-        Cpu {
+        let mut cpu = Cpu {
             pc,
             len: 4,
             csr,
             register,
+            fregister: FRegister::new([0; 32]),
+            vregister: VRegister::new(0),
             privilege,
+            virt: false,
             mmu,
             sstack,
-        }
+            stop: None,
+            violation: None,
+            exception: None,
+            inst: 0,
+            trap: None,
+            sstack_event: None,
+            tracer: Tracer::default(),
+            retired: Retired::default(),
+            commit_log: None,
+            compare: None,
+            plugins: Vec::new(),
+            customs: Vec::new(),
+            isa: Isa::default(),
+            entropy: Entropy::default(),
+            decode_cache: DecodeCache::default(),
+            blocks: BlockCache::default(),
+            #[cfg(feature = "jit")]
+            jit: true,
+        };
+        cpu.apply_isa();
+        cpu
     }
+    /// A machine mode cpu at pc 0 with `mem` at address 0, as tests use.
+    #[cfg(test)]
+    pub fn with_memory(mem: Vec<u8>) -> Cpu {
+        Cpu::new(
+            0,
+            Csr::new([0; 4096]),
+            Register::new([0; 32]),
+            privilege::MACHINE,
+            Mmu::new(mem, false),
+            ShadowStacks::new(0, privilege::MACHINE, false),
+        )
+    }
+    /// Runs until the cpu stops. Unless something observes every instruction, whole basic
+    /// blocks are run at a time, see `run_blocks`.
     pub fn execute(&mut self) -> io::Result<()> {
+        if self.tracer.is_off()
+            && self.commit_log.is_none()
+            && self.compare.is_none()
+            && self.plugins.is_empty()
+        {
+            let _ = self.run_blocks();
+        } else {
+            while self.step().is_ok() {}
+        }
+        Ok(())
+    }
+
+    /// Executes translated basic blocks, following the links between them, until the cpu
+    /// stops. Interrupts are only taken between blocks, and `retired` is not updated. While
+    /// addresses are translated the cpu steps instead, as the page tables may map code and
+    /// data anywhere.
+    pub fn run_blocks(&mut self) -> Result<(), String> {
+        let mut prev = None;
         loop {
-            let old_pc = self.pc;
-            let (inst, op_len) = self.fetch();
-            let mut backup_register: [u64; 32] = [0; 32];
-            for i in 0..32 {
-                backup_register[i] = match self.register.read(i, self.len) {
-                    Ok(t) => t,
-                    Err(s) => {
-                        println!("{:?}", s);
-                        0
-                    }
-                };
+            if self.take_interrupt()? {
+                prev = None;
+                continue;
+            }
+            if self.walk(self.privilege, self.virt)?.is_some() || self.data_walk()?.is_some() {
+                self.step()?;
+                prev = None;
+                continue;
             }
-            match self.exec(inst) {
-                Ok(()) => {}
-                Err(e) => {
-                    println!("{}", e);
+            self.invalidate_code();
+            let i = match self.blocks.find(prev, self.pc) {
+                Some(i) => i,
+                None => match self.translate(self.pc) {
+                    Some(block) => self.blocks.insert(prev, block),
+                    None => {
+                        // does not decode; stop the usual way
+                        self.step()?;
+                        prev = None;
+                        continue;
+                    }
+                },
+            };
+            self.run_block(i)?;
+            prev = Some(i);
+        }
+    }
+
+    fn run_block(&mut self, i: usize) -> Result<(), String> {
+        let insts = self.blocks.get(i).insts.clone();
+        self.blocks.stats.run += 1;
+        #[cfg(feature = "jit")]
+        let start = self.run_native(i);
+        #[cfg(not(feature = "jit"))]
+        let start = 0;
+        for d in insts[start..].iter() {
+            let old_pc = self.pc;
+            self.inst = d.raw;
+            if let Err(e) = self.exec(d.inst) {
+                if self.exception.is_some() {
+                    self.take_exception()?;
                     break;
                 }
+                self.stop = Some((old_pc, e.clone()));
+                return Err(e);
             }
-            for i in 0..32 {
-                let k = match self.register.read(i, self.len) {
-                    Ok(t) => t,
-                    Err(s) => {
-                        println!("{:?}", s);
-                        0
-                    }
+            self.blocks.stats.instructions += 1;
+            if old_pc == self.pc {
+                self.pc += d.len;
+            }
+            if self.mmu.code_written() {
+                // the rest of the block may have been overwritten
+                break;
+            }
+        }
+        self.mmu.take_accesses();
+        self.csr.take_writes();
+        Ok(())
+    }
+
+    /// Runs block `i` as native code once it is hot, and returns how many of its instructions
+    /// are done; the interpreter executes the rest.
+    #[cfg(feature = "jit")]
+    fn run_native(&mut self, i: usize) -> usize {
+        if !self.jit {
+            return 0;
+        }
+        let block = self.blocks.get_mut(i);
+        block.runs = block.runs.saturating_add(1);
+        if block.runs == jit::HOT {
+            block.native = jit::compile(block.pc, &block.insts);
+            self.blocks.stats.compiled += block.native.is_some() as u64;
+        }
+        let block = self.blocks.get(i);
+        let len = block.insts.len();
+        let code = match &block.native {
+            Some(code) => code,
+            None => return 0,
+        };
+        let mut ctx = jit::Context::new(self.register.raw(), self.pc, &mut self.mmu);
+        let n = code.run(&mut ctx);
+        let embedded = self.register.count() < 32;
+        self.register = Register::new(ctx.regs);
+        self.register.set_embedded(embedded);
+        self.pc = ctx.pc;
+        self.blocks.stats.instructions += n as u64;
+        self.blocks.stats.native += n as u64;
+        match self.mmu.code_written() {
+            // a store changed code, so leave the block as the interpreter would
+            true => len,
+            false => n,
+        }
+    }
+
+    /// Whether `run_blocks` compiles hot blocks to native code, as it does by default. The
+    /// interpreter is the reference it is checked against.
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, on: bool) {
+        self.jit = on;
+    }
+
+    /// Decodes the block starting at `pc`, or `None` if its first instruction does not decode.
+    fn translate(&mut self, pc: u64) -> Option<Block> {
+        let mut insts = Vec::new();
+        let mut next = pc;
+        while let (_, Ok(d)) = self.fetch_at(next) {
+            // left to `step`, which raises the exception
+            if self.check_legal(&d).is_err() {
+                break;
+            }
+            insts.push(d);
+            next += d.len;
+            if block::ends_block(&d.inst)
+                || insts.len() == block::MAX_INSTS
+                || next & ((1 << PAGE_SHIFT) - 1) == 0
+            {
+                break;
+            }
+        }
+        // the block ends before a fetch that faults, which is raised when it is stepped to
+        self.exception = None;
+        match insts.is_empty() {
+            true => None,
+            false => Some(Block::new(pc, insts)),
+        }
+    }
+
+    /// Statistics of the blocks run by `run_blocks`.
+    pub fn block_stats(&self) -> &BlockStats {
+        &self.blocks.stats
+    }
+
+    /// Executes a single instruction. On error the cpu records why it stopped, see `report`.
+    /// An enabled pending interrupt is taken instead, without executing an instruction.
+    pub fn step(&mut self) -> Result<(), String> {
+        if self.take_interrupt()? {
+            if let Err(e) = self.notify_retired() {
+                self.stop = Some((self.retired.pc, e.clone()));
+                return Err(e);
+            }
+            return Ok(());
+        }
+        let (inst, decoded) = self.fetch();
+        self.run(inst, decoded)
+    }
+
+    /// Makes machine interrupt `cause` pending in mip. It is taken before the next instruction
+    /// once enabled by mie and mstatus.MIE, which clears it again.
+    pub fn raise_interrupt(&mut self, cause: u64) -> Result<(), String> {
+        if cause >= 8 * self.len as u64 {
+            return Err(format!("no interrupt {}", cause));
+        }
+        //mip
+        let mip = self.csr.read(0x344)?;
+        self.csr.write(0x344, mip | (1 << cause))
+    }
+
+    /// Takes the highest priority pending interrupt enabled for where it goes, M mode, or HS or
+    /// VS mode as mideleg and hideleg delegate it: below that mode, or in it with its
+    /// interrupts enabled.
+    fn take_interrupt(&mut self) -> Result<bool, String> {
+        let pending = self.csr.read(csr::MIP)? & self.csr.read(csr::MIE)?;
+        if pending == 0 {
+            return Ok(false);
+        }
+        // the VS interrupts always go to HS mode at least
+        let vs = if self.isa.h { csr::VS_INTS } else { 0 };
+        let mideleg = self.csr.read(csr::MIDELEG)? | vs;
+        let hideleg = self.csr.read(csr::HIDELEG)? & vs;
+        let mstatus = self.csr.read(csr::MSTATUS)?;
+        let vsstatus = self.csr.read(csr::VSSTATUS)?;
+        let (m, s) = (self.privilege == privilege::MACHINE, self.privilege == privilege::SUPERVISOR);
+        let levels = [
+            (!mideleg, !m || mstatus & csr::MSTATUS_MIE != 0),
+            (mideleg & !hideleg, !m && (self.virt || !s || mstatus & csr::MSTATUS_SIE != 0)),
+            (mideleg & hideleg, self.virt && (!s || vsstatus & csr::MSTATUS_SIE != 0)),
+        ];
+        let pending = match levels.iter().find(|&&(level, on)| on && pending & level != 0) {
+            Some(&(level, _)) => pending & level,
+            None => return Ok(false),
+        };
+        // MEI, MSI, MTI, SEI, SSI, STI, SGEI, VSEI, VSSI, VSTI, then platform interrupts
+        let cause = [11, 3, 7, 9, 1, 5, 12, 10, 2, 6]
+            .iter()
+            .copied()
+            .find(|c| pending & (1 << c) != 0)
+            .unwrap_or_else(|| pending.trailing_zeros() as u64);
+        let old_pc = self.pc;
+        self.tracer.begin(old_pc);
+        self.csr.take_writes();
+        let mip = self.csr.read(csr::MIP)?;
+        self.csr.write(csr::MIP, mip & !(1 << cause))?;
+        let privilege = self.privilege;
+        self.enter_trap(cause, true, 0, 0, false)?;
+        self.retired = Retired {
+            pc: old_pc,
+            privilege,
+            csr_writes: self.csr.take_writes(),
+            trap: self.trap,
+            next_pc: self.pc,
+            ..Retired::default()
+        };
+        trace!(self.tracer, category::TRAP, 1, "interrupt {}, to {:#x}", cause, self.pc);
+        Ok(true)
+    }
+
+    /// Traps to the handler of `cause`, an interrupt if `interrupt`: in M mode, or in HS mode
+    /// if medeleg or mideleg delegates it from below M, or in VS mode if hedeleg or hideleg
+    /// delegates it further from a virtual machine. `tval` is the trap value, `gpa` the guest
+    /// physical address of a guest-page fault, and `gva` whether `tval` is a guest virtual
+    /// address.
+    fn enter_trap(&mut self, cause: u64, interrupt: bool, tval: u64, gpa: u64, gva: bool) -> Result<(), String> {
+        let (medeleg, hedeleg) = match interrupt {
+            true => (csr::MIDELEG, csr::HIDELEG),
+            false => (csr::MEDELEG, csr::HEDELEG),
+        };
+        let vs = if interrupt && self.isa.h { csr::VS_INTS } else { 0 };
+        let to_hs = self.privilege != privilege::MACHINE && (self.csr.read(medeleg)? | vs) & (1 << cause) != 0;
+        let to_vs = to_hs && self.virt && self.csr.read(hedeleg)? & (1 << cause) != 0;
+        let interrupt_bit = (interrupt as u64) << (8 * self.len as u64 - 1);
+        let (privilege, virt, pc) = (self.privilege, self.virt, self.pc);
+        let (tvec, cause) = if to_vs {
+            // VS mode sees its interrupts where S mode has them
+            let cause = if interrupt { cause - 1 } else { cause };
+            self.csr.write(csr::VSEPC, pc)?;
+            self.csr.write(csr::VSCAUSE, interrupt_bit | cause)?;
+            self.csr.write(csr::VSTVAL, tval)?;
+            let vsstatus = self.csr.read(csr::VSSTATUS)?;
+            self.csr.write(csr::VSSTATUS, s_trap(vsstatus, privilege))?;
+            self.privilege = privilege::SUPERVISOR;
+            (self.csr.read(csr::VSTVEC)?, cause)
+        } else if to_hs {
+            self.csr.write(csr::SEPC, pc)?;
+            self.csr.write(csr::SCAUSE, interrupt_bit | cause)?;
+            self.csr.write(csr::STVAL, tval)?;
+            let mstatus = self.csr.read(csr::MSTATUS)?;
+            self.csr.write(csr::MSTATUS, s_trap(mstatus, privilege))?;
+            if self.isa.h {
+                self.csr.write(csr::HTVAL, gpa >> 2)?;
+                self.csr.write(csr::HTINST, 0)?;
+                let hstatus = self.csr.read(csr::HSTATUS)? & !(csr::HSTATUS_SPV | csr::HSTATUS_GVA);
+                let spvp = match virt {
+                    true => (privilege as u64) << 8,
+                    false => hstatus & csr::HSTATUS_SPVP,
                 };
-                if k != backup_register[i] {
-                    print!("\x1b[31m{:x?}\x1b[0m\t", k);
-                } else {
-                    print!("{:x?}\t", k);
-                }
-                if i % 8 == 7 {
-                    println!("");
-                }
+                let hstatus = (hstatus & !csr::HSTATUS_SPVP) | spvp;
+                self.csr
+                    .write(csr::HSTATUS, hstatus | (virt as u64) << 7 | (gva as u64) << 6)?;
+            }
+            self.privilege = privilege::SUPERVISOR;
+            self.virt = false;
+            (self.csr.read(csr::STVEC)?, cause)
+        } else {
+            self.csr.write(csr::MEPC, pc)?;
+            self.csr.write(csr::MCAUSE, interrupt_bit | cause)?;
+            self.csr.write(csr::MTVAL, tval)?;
+            if self.isa.h {
+                self.csr.write(csr::MTVAL2, gpa >> 2)?;
+                self.csr.write(csr::MTINST, 0)?;
+                let mstatush = self.csr.read(csr::MSTATUSH)? & !(csr::MSTATUSH_MPV | csr::MSTATUSH_GVA);
+                self.csr
+                    .write(csr::MSTATUSH, mstatush | (virt as u64) << 7 | (gva as u64) << 6)?;
+            }
+            //MPP = privilege, MPIE = MIE, MIE = 0
+            let mstatus = self.csr.read(csr::MSTATUS)?;
+            let mie = (mstatus & csr::MSTATUS_MIE) << 4;
+            let mstatus = mstatus & !(csr::MSTATUS_MPP | csr::MSTATUS_MPIE | csr::MSTATUS_MIE);
+            self.csr
+                .write(csr::MSTATUS, mstatus | ((privilege as u64) << 11) | mie)?;
+            self.privilege = privilege::MACHINE;
+            self.virt = false;
+            (self.csr.read(csr::MTVEC)?, cause)
+        };
+        self.switch_sstack()?;
+        // vectored when mode is 1
+        self.pc = (tvec & !0b11) + if interrupt && tvec & 0b11 == 1 { 4 * cause } else { 0 };
+        self.trap = Some(interrupt_bit | cause);
+        Ok(())
+    }
+
+    /// Raises exception `cause`, returning the error that abandons the instruction.
+    fn raise(&mut self, cause: u64, tval: u64, gpa: u64, gva: bool) -> String {
+        self.exception = Some(Exception { cause, tval, gpa, gva });
+        format!("exception {}", cause)
+    }
+    fn illegal_instruction(&mut self) -> String {
+        self.raise(cause::ILLEGAL_INSTRUCTION, self.inst, 0, false)
+    }
+    fn virtual_instruction(&mut self) -> String {
+        self.raise(cause::VIRTUAL_INSTRUCTION, 0, 0, false)
+    }
+    /// Traps for the exception raised by the instruction just abandoned.
+    fn take_exception(&mut self) -> Result<(), String> {
+        let e = match self.exception.take() {
+            Some(e) => e,
+            None => return Ok(()),
+        };
+        let privilege = self.privilege;
+        self.enter_trap(e.cause, false, e.tval, e.gpa, e.gva)?;
+        trace!(
+            self.tracer,
+            category::TRAP,
+            1,
+            "exception {} from privilege {}, tval {:#x}, to {:#x}",
+            e.cause,
+            privilege,
+            e.tval,
+            self.pc
+        );
+        Ok(())
+    }
+
+    /// Executes `inst` at the current pc as if it had been fetched from there, for instruction
+    /// injection by verification tools.
+    pub fn step_injected(&mut self, inst: u32) -> Result<(), String> {
+        let decoded = self.decode(inst as u64);
+        self.exception = None;
+        self.run(inst as u64, decoded)
+    }
+
+    fn run(&mut self, inst: u64, decoded: Result<Decoded, String>) -> Result<(), String> {
+        let old_pc = self.pc;
+        let op_len = parse_inst_length(inst);
+        self.tracer.begin(old_pc);
+        trace!(
+            self.tracer,
+            category::FETCH,
+            1,
+            "{:#010x}: {:0width$x}  {}",
+            self.pc,
+            bitcat!(Bits::new(inst, 8 * op_len as usize)).to_u32(),
+            match self.is_zcm(inst) {
+                true => disasm::disassemble_zcm(inst as u16),
+                false => disasm::disassemble_raw(inst, self.pc).0,
+            },
+            width = 2 * op_len as usize
+        );
+        if let Err(e) = plugin::notify(&mut self.plugins, |p| p.fetch(old_pc, inst)) {
+            self.stop = Some((old_pc, e.clone()));
+            return Err(e);
+        }
+        let backup_register = self.register;
+        let backup_fregister = self.fregister;
+        let privilege = self.privilege;
+        let pending_trap = self.trap.take();
+        let expanded = disasm::expand(inst).unwrap_or(0);
+        let csr_read = csr_read(expanded).map(|n| (n, self.csr.read(n).unwrap_or(0)));
+        self.mmu.take_accesses();
+        self.csr.take_writes();
+        self.sstack_event = None;
+        self.violation = None;
+        let result = decoded.and_then(|d| {
+            // Zcmp and Zcmt have nothing to expand to
+            if d.len == 2 && d.expanded != 0 {
+                trace!(self.tracer, category::DECODE, 1, "expands to {:#010x}", d.expanded);
+            }
+            let (rs1, rs2) = (rv32::get_rs1(d.expanded), rv32::get_rs2(d.expanded));
+            trace!(
+                self.tracer,
+                category::DECODE,
+                2,
+                "rs1 {} = {:#x}, rs2 {} = {:#x}",
+                ABI_NAMES[rs1],
+                self.register.read(rs1, self.len)?,
+                ABI_NAMES[rs2],
+                self.register.read(rs2, self.len)?
+            );
+            self.inst = d.raw;
+            if let Err(e) = self.check_legal(&d) {
+                trace!(self.tracer, category::DECODE, 1, "illegal: {}", e);
+                return Err(self.illegal_instruction());
+            }
+            self.exec(d.inst)
+        });
+        // a raised exception traps, and the instruction writes no register
+        let raised = self.exception.is_some();
+        let result = match result {
+            Err(_) if raised => self.take_exception(),
+            result => result,
+        };
+        if let Err(e) = result {
+            if let Some(v) = self.violation {
+                let event = shadowstack::Event::Return(v.target, Return::Mismatch(v.expected));
+                // the mismatch stops the cpu anyway, so plugin errors add nothing
+                let _ = plugin::notify(&mut self.plugins, |p| p.sstack(old_pc, event));
+            }
+            self.stop = Some((old_pc, e.clone()));
+            return Err(e);
+        }
+        let trap = self.trap.or(pending_trap);
+        // a trap goes to its handler even if that is where it came from
+        if old_pc == self.pc && self.trap.is_none() {
+            self.pc += op_len;
+        } else {
+            trace!(self.tracer, category::DECODE, 1, "next pc {:#x}", self.pc);
+        }
+        let (rs1, rs2) = rs_read(expanded);
+        let source = |rs: Option<usize>| rs.map(|n| (n, backup_register.read(n, self.len).unwrap_or(0)));
+        self.retired = Retired {
+            pc: old_pc,
+            inst: inst & ((1 << (8 * op_len)) - 1),
+            len: op_len,
+            privilege,
+            rs1: source(rs1),
+            rs2: source(rs2),
+            rd: rd_written(expanded)
+                .or_else(|| frd_written(expanded).filter(|&rd| self.isa.zfinx && rd != 0))
+                // custom instructions are not decoded here, so look for the register they wrote
+                .or_else(|| {
+                    (1..32).find(|&n| {
+                        self.register.read(n, self.len) != backup_register.read(n, self.len)
+                    })
+                })
+                .filter(|_| !raised)
+                .map(|rd| (rd, self.register.read(rd, self.len).unwrap_or(0))),
+            frd: frd_written(expanded)
+                .filter(|_| !self.isa.zfinx && !raised)
+                .map(|rd| (rd, self.fregister.read(rd).unwrap_or(0))),
+            csr_writes: self.csr.take_writes(),
+            csr_read,
+            mem: self.mmu.take_accesses(),
+            trap: self.trap,
+            sstack: self.sstack_event,
+            next_pc: self.pc,
+        };
+        self.trap = trap;
+        self.trace_retire(&backup_register, &backup_fregister);
+        if let Some(log) = self.commit_log.as_mut() {
+            log.log(&self.retired, 8 * self.len as u32);
+        }
+        if let Some(compare) = self.compare.as_mut() {
+            let hart = self.csr.read(0xf14)?;
+            if let Err(e) = compare.check(&self.retired, 8 * self.len as u32, hart) {
+                self.stop = Some((old_pc, e.clone()));
+                return Err(e);
+            }
+        }
+        if let Err(e) = self.notify_retired() {
+            self.stop = Some((old_pc, e.clone()));
+            return Err(e);
+        }
+        self.tracer.end();
+        Ok(())
+    }
+
+    /// Reports the register, memory and CSR effects of the instruction that just executed.
+    fn trace_retire(&mut self, before: &Register, fbefore: &FRegister) {
+        if self.tracer.is_off() {
+            return;
+        }
+        for (i, name) in ABI_NAMES.iter().enumerate().skip(1) {
+            let old = before.read(i, self.len).unwrap_or(0);
+            let new = self.register.read(i, self.len).unwrap_or(0);
+            if old != new {
+                trace!(self.tracer, category::REGS, 1, "{} {:#x} -> {:#x}", name, old, new);
+            }
+        }
+        for (i, name) in FP_ABI_NAMES.iter().enumerate() {
+            let (old, new) = (fbefore.read(i).unwrap_or(0), self.fregister.read(i).unwrap_or(0));
+            if old != new {
+                trace!(self.tracer, category::REGS, 1, "{} {:#x} -> {:#x}", name, old, new);
             }
-            let sp = match self.register.read(2,self.len) {
-                Ok(t) => t,
-                Err(s) => {
-                    println!("{:?}", s);
-                    0
+        }
+        if self.tracer.enabled(category::REGS, 2) {
+            for row in 0..4 {
+                let mut line = String::new();
+                for i in row * 8..row * 8 + 8 {
+                    let k = self.register.read(i, self.len).unwrap_or(0);
+                    if k != before.read(i, self.len).unwrap_or(0) {
+                        line += &format!("\x1b[31m{:x?}\x1b[0m\t", k);
+                    } else {
+                        line += &format!("{:x?}\t", k);
+                    }
                 }
+                self.tracer.emit(category::REGS, format_args!("{}", line));
+            }
+        }
+        for a in self.retired.mem.iter() {
+            let dir = match a.access {
+                Access::Read => "load ",
+                Access::Write => "store",
             };
-            /*
-            println!("---stack---");
-            if sp > 4 {
-            for i in (sp/4-64)..(sp/4+64) {
-                print!(
-                    "{:#x}: {:>02x}{:>02x}{:>02x}{:>02x}",
-                    i*4,
-                    self.mmu.read_nbytes(i*4, 1),
-                    self.mmu.read_nbytes(i*4 + 1, 1),
-                    self.mmu.read_nbytes(i*4 + 2, 1),
-                    self.mmu.read_nbytes(i*4 + 3, 1)
-                );
-                if i==sp{
-                    print!("<- sp")
-                }
-                println!();
-            }}
-            println!("---stack---");
-            */
-            if old_pc == self.pc {
-                self.pc += op_len;
+            trace!(self.tracer, category::MEMORY, 1, "{} {:#x} [{}] = {:#x}", dir, a.addr, a.len, a.data);
+        }
+        for &(csr, value) in self.retired.csr_writes.iter() {
+            trace!(self.tracer, category::CSR, 1, "{} <- {:#x}", csr::name(csr), value);
+        }
+    }
+
+    /// Delivers the events of the last retired instruction to the plugins.
+    fn notify_retired(&mut self) -> Result<(), String> {
+        if self.plugins.is_empty() {
+            return Ok(());
+        }
+        let r = &self.retired;
+        let plugins = &mut self.plugins;
+        let mut result = Ok(());
+        let mut keep = |res: Result<(), String>| {
+            if result.is_ok() {
+                result = res;
             }
+        };
+        for a in r.mem.iter() {
+            keep(plugin::notify(plugins, |p| match a.access {
+                Access::Read => p.mem_read(a.addr, a.len, a.data),
+                Access::Write => p.mem_write(a.addr, a.len, a.data),
+            }));
+        }
+        if let Some((n, v)) = r.csr_read {
+            keep(plugin::notify(plugins, |p| p.csr_read(n, v)));
+        }
+        for &(n, v) in r.csr_writes.iter() {
+            keep(plugin::notify(plugins, |p| p.csr_write(n, v)));
+        }
+        if let Some(event) = r.sstack {
+            keep(plugin::notify(plugins, |p| p.sstack(r.pc, event)));
+        }
+        if let Some(cause) = r.trap {
+            keep(plugin::notify(plugins, |p| p.trap_enter(r.pc, cause, r.next_pc)));
+        }
+        let inst = disasm::expand(r.inst).unwrap_or(0);
+        if rv32::get_op(inst) == op::CSR
+            && rv32::get_funct3(inst) == f3c::EXCEPT
+            && matches!(rv32::get_bits(inst, 31, 20), exception::MRET | exception::SRET)
+            && r.trap.is_none()
+        {
+            keep(plugin::notify(plugins, |p| p.trap_exit(r.pc, r.next_pc)));
         }
+        keep(plugin::notify(plugins, |p| p.retire(r)));
+        result
+    }
+
+    /// What the last instruction that completed did.
+    pub fn retired(&self) -> &Retired {
+        &self.retired
+    }
+    /// Writes a Spike-style commit log line for every retired instruction.
+    pub fn set_commit_log(&mut self, log: CommitLog) {
+        self.commit_log = Some(log);
+    }
+
+    /// Checks every retired instruction against a reference commit log, stopping at the first
+    /// divergence.
+    pub fn set_compare(&mut self, compare: Comparator) {
+        self.compare = Some(compare);
+    }
+
+    /// Registers a plugin, which sees events after those registered before it.
+    pub fn add_plugin(&mut self, plugin: Box<dyn Plugin>) {
+        self.plugins.push(plugin);
+    }
+
+    /// Executes 32-bit instructions with `inst & mask == pattern` with `custom` instead of the
+    /// built-in decoder. Earlier registrations are tried first.
+    pub fn add_custom(&mut self, pattern: u32, mask: u32, custom: Box<dyn Custom>) -> Result<(), String> {
+        self.customs.push(custom::Entry::new(pattern, mask, custom)?);
+        self.decode_cache.clear();
+        self.blocks.clear();
+        Ok(())
+    }
+
+    /// Executes the whole custom-`n` opcode space with `custom`.
+    pub fn add_custom_space(&mut self, n: usize, custom: Box<dyn Custom>) -> Result<(), String> {
+        let opcode = *custom::space::ALL
+            .get(n)
+            .ok_or_else(|| format!("no custom-{} opcode space", n))?;
+        self.add_custom(opcode, 0x7f, custom)
+    }
+
+    fn exec_custom(&mut self, i: usize, inst: u32) -> Result<(), String> {
+        // taken out while one runs, since it gets the whole cpu
+        let mut customs = std::mem::take(&mut self.customs);
+        let mut hart = Hart::new(self.pc, self.len, self);
+        let result = customs[i].custom.exec(inst, &mut hart);
+        let next_pc = hart.next_pc;
+        self.customs = customs;
+        result?;
+        if let Some(pc) = next_pc {
+            self.pc = pc;
+        }
+        Ok(())
+    }
+
+    /// Selects the optional extensions implemented, all but Zfinx by default.
+    pub fn set_isa(&mut self, isa: Isa) {
+        self.isa = isa;
+        self.decode_cache.clear();
+        self.blocks.clear();
+        self.apply_isa();
+    }
+    /// Sets misa and the x registers for the ISA, and sizes the vector registers for it, zeroed,
+    /// with vtype illegal and vl 0 until the first vset.
+    fn apply_isa(&mut self) {
+        self.register.set_embedded(self.isa.e);
+        let xlen = 8 * self.len as u32;
+        // MXL 1 for 32 bits
+        let _ = self.csr.write(csr::MISA, 1 << (xlen - 2) | self.isa.misa());
+        let vlen = self.isa.vector.map_or(0, |v| v.vlen);
+        self.vregister = VRegister::new(vlen);
+        let vtype = Vtype::ILLEGAL.bits(xlen);
+        let _ = self.csr.write(csr::VLENB, vlen as u64 / 8);
+        let _ = self.csr.write(csr::VTYPE, vtype);
+        let _ = self.csr.write(csr::VL, 0);
+        // configuration, not writes by an instruction
+        self.csr.take_writes();
+    }
+    pub fn isa(&self) -> Isa {
+        self.isa
+    }
+    /// Replaces where the Zkr seed CSR gets its entropy, a fixed sequence by default.
+    pub fn set_entropy(&mut self, entropy: Entropy) {
+        self.entropy = entropy;
+    }
+
+    /// Replaces the tracer, which by default reports nothing.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = tracer;
+    }
+
+    pub fn get_pc(&self) -> u64 {
+        self.pc
+    }
+    pub fn set_pc(&mut self, pc: u64) {
+        self.pc = pc;
+    }
+    pub fn get_xlen_bytes(&self) -> u8 {
+        self.len
+    }
+    pub fn register(&mut self) -> &mut Register {
+        &mut self.register
+    }
+    pub fn vregister(&mut self) -> &mut VRegister {
+        &mut self.vregister
+    }
+    pub fn fregister(&mut self) -> &mut FRegister {
+        &mut self.fregister
+    }
+    pub fn csr(&mut self) -> &mut Csr {
+        &mut self.csr
+    }
+    pub fn mmu(&mut self) -> &mut Mmu {
+        &mut self.mmu
+    }
+    pub fn sstack(&self) -> &ShadowStacks {
+        &self.sstack
+    }
+    pub fn get_privilege(&self) -> u8 {
+        self.privilege
+    }
+    pub fn set_privilege(&mut self, privilege: u8) -> Result<(), String> {
+        self.privilege = privilege;
+        self.switch_sstack()
+    }
+    /// Returns and clears the cause of the last trap taken, if any.
+    pub fn take_trap(&mut self) -> Option<u64> {
+        self.trap.take()
+    }
+
+    /// Symbolized report of why `execute` stopped, or `None` if it has not stopped on an error.
+    pub fn report(&self, elf: Option<&Elf>) -> Option<Report> {
+        let (pc, reason) = self.stop.as_ref()?;
+        Some(Report::new(
+            reason.clone(),
+            *pc,
+            self.sstack.frames(),
+            self.violation,
+            elf,
+        ))
+    }
+
+    /// Switches to the shadow stack of the current mode, VS and VU modes having their own.
+    fn switch_sstack(&mut self) -> Result<(), String> {
+        //mhartid
+        let hart = self.csr.read(0xf14)?;
+        self.sstack.switch(hart, self.privilege | (self.virt as u8) << 2);
         Ok(())
     }
 
-    fn fetch(&mut self) -> (u64, u64) {
-        let inst = match self.len {
-            2 => self.mmu.read_nbytes(self.pc as u32 as u64, 2),
-            4 => self.mmu.read_nbytes(self.pc as u32 as u64, 4),
-            _ => 999999999999,
+    /// Raw instruction at pc and its decoding, from the decode cache unless its page was
+    /// written since it was decoded.
+    fn fetch(&mut self) -> (u64, Result<Decoded, String>) {
+        self.invalidate_code();
+        // a fetch that faults raises the exception `run` takes
+        self.exception = None;
+        self.fetch_at(self.pc)
+    }
+    fn fetch_at(&mut self, pc: u64) -> (u64, Result<Decoded, String>) {
+        let pc = pc as u32 as u64;
+        match self.walk(self.privilege, self.virt) {
+            Ok(Some(walk)) => return self.fetch_translated(pc, walk),
+            Ok(None) => {}
+            Err(e) => return (0, Err(e)),
+        }
+        if let Some(d) = self.decode_cache.get(pc) {
+            return (d.raw, Ok(d));
+        }
+        let low = match self.translate_address(pc, Kind::Fetch, 2, None) {
+            Ok(pa) => self.mmu.peek(pa, 2),
+            Err(e) => return (0, Err(e)),
+        };
+        let inst = match parse_inst_length(low) {
+            2 => low,
+            _ => match self.translate_address(pc, Kind::Fetch, 4, None) {
+                Ok(pa) => self.mmu.peek(pa, 4),
+                Err(e) => return (low, Err(e)),
+            },
+        };
+        let decoded = self.decode(inst);
+        if let Ok(d) = decoded {
+            self.decode_cache.insert(pc, d);
+            self.mmu.watch_code(pc >> PAGE_SHIFT);
+            self.mmu.watch_code((pc + d.len - 1) >> PAGE_SHIFT);
+        }
+        (inst, decoded)
+    }
+    /// Fetches the instruction at virtual address `pc`, each half on its page, and decodes it
+    /// without the decode cache, which is by physical address.
+    fn fetch_translated(&mut self, pc: u64, walk: Walk) -> (u64, Result<Decoded, String>) {
+        let low = match self.translate_address(pc, Kind::Fetch, 2, Some(walk)) {
+            Ok(pa) => self.mmu.peek(pa, 2),
+            Err(e) => return (0, Err(e)),
         };
-        let op_length = parse_inst_length(inst);
-        let op_visible = bitcat!(Bits::new(inst, 8 * op_length as usize));
-        println!("\ninst: {:#x}_{:}", op_visible.to_u32(), op_length);
-        println!("pc  : {:#x}", self.pc);
-        (inst, op_length)
+        if parse_inst_length(low) == 2 {
+            return (low, self.decode(low));
+        }
+        let pc = (pc + 2) as u32 as u64;
+        match self.translate_address(pc, Kind::Fetch, 2, Some(walk)) {
+            Ok(pa) => {
+                let inst = low | self.mmu.peek(pa, 2) << 16;
+                (inst, self.decode(inst))
+            }
+            Err(e) => (low, Err(e)),
+        }
+    }
+    /// Drops decoded instructions and blocks whose code was written.
+    fn invalidate_code(&mut self) {
+        for page in self.mmu.take_code_writes() {
+            self.decode_cache.invalidate(page);
+            self.blocks.invalidate(page);
+        }
+    }
+    /// Whether `inst` is Zcmp or Zcmt rather than c.fsdsp, as it is if the hart has either.
+    pub fn is_zcm(&self, inst: u64) -> bool {
+        let inst = inst as u32;
+        (self.isa.zcmp || self.isa.zcmt)
+            && parse_inst_length(inst as u64) == 2
+            && rv32::get_bits(inst, 1, 0) == 2
+            && rv32::get_bits(inst, 15, 13) == f3co_2::FSDSP
+    }
+    fn decode(&self, inst: u64) -> Result<Decoded, String> {
+        let len = parse_inst_length(inst);
+        if self.is_zcm(inst) {
+            return Ok(Decoded {
+                raw: inst & 0xffff,
+                len,
+                expanded: 0,
+                inst: Instruction::decode_zcm(inst as u16)?,
+            });
+        }
+        let expanded = match len {
+            //TODO: implement compressed op
+            2 => Cpu::uncompress(inst as u32)?,
+            4 => inst as u32,
+            8 => return Err(String::from("Not implemented rv64")),
+            _ => return Err(String::from("Invalid xlen")),
+        };
+        let decoded = match self.customs.iter().position(|c| c.matches(expanded)) {
+            Some(index) => Instruction::Custom {
+                index,
+                inst: expanded,
+            },
+            None => Instruction::decode(expanded)?,
+        };
+        Ok(Decoded {
+            raw: inst & ((1 << (8 * len)) - 1),
+            len,
+            expanded,
+            inst: decoded,
+        })
     }
-    fn exec(&mut self, inst: u64) -> Result<(), String> {
-        let op_length = parse_inst_length(inst);
-        match op_length {
-            2 => {
-                //TODO: implement compressed op
-                println!("pc={:x},inst={:x}", self.pc, inst);
-                self.exec_rv32(self.uncompress(inst as u32)?)
+    /// Checks that decoded `d` is an instruction of the hart: in extensions it has, and in RV32E
+    /// naming only x0 to x15. One that is not raises an illegal-instruction exception when
+    /// executed.
+    fn check_legal(&self, d: &Decoded) -> Result<(), String> {
+        self.isa.check(&d.inst)?;
+        if d.len == 2 {
+            if !self.isa.zcb && zcb(d.raw as u32) {
+                return Err(String::from("Zcb not implemented"));
             }
-            4 => self.exec_rv32(inst as u32),
-            8 => Err(String::from("Not implemented rv64")),
-            _ => Err(String::from("Invalid xlen")),
+            self.isa.check_compressed(&d.inst)?;
+        }
+        // Zcmp expands to nothing, so its registers come from the decoded instruction
+        let zcmp_high = match d.inst {
+            Instruction::PushPop { regs, .. } => regs > 3,
+            Instruction::Mv01s { r1s, r2s, .. } => r1s.max(r2s) > 15,
+            _ => false,
+        };
+        if self.isa.e && (zcmp_high || !embedded(d.expanded)) {
+            return Err(String::from("No register above x15 in RV32E"));
         }
+        Ok(())
     }
-    fn uncompress(&self, inst: u32) -> Result<u32, String> {
+    /// Expands a 16-bit compressed instruction into its 32-bit equivalent.
+    pub fn uncompress(inst: u32) -> Result<u32, String> {
         let rd = Bits::cut_new(inst, 11, 7);
-        let rs = Bits::cut_new(inst, 6, 2);
+        let _rs = Bits::cut_new(inst, 6, 2);
         match rv32::get_bits(inst, 1, 0) {
             0 => {
-                return Err(String::from("No such inst 0"));
+                // rd' or rs2', and rs1', are x8 to x15 or f8 to f15
+                let rs1 = Bits::cut_new(inst, 9, 7).expand(5).add(Bits::new(8, 5));
+                let rd = Bits::cut_new(inst, 4, 2).expand(5).add(Bits::new(8, 5));
+                if rv32::get_bits(inst, 15, 13) == f3co_0::ZCB {
+                    // offsets 0 to 3, the bits swapped in the encoding, and 0 or 2 for halves
+                    let byte = bitcat!(Bits::cut_new(inst, 5, 5), Bits::cut_new(inst, 6, 6)).expand(12);
+                    let half = bitcat!(Bits::cut_new(inst, 5, 5), Bits::new(0, 1)).expand(12);
+                    return match (rv32::get_bits(inst, 12, 10), rv32::get_bits(inst, 6, 6)) {
+                        (0b000, _) => Ok(load(op::LD, byte, rs1, f3l::LBU as u64, rd)),
+                        (0b001, 0) => Ok(load(op::LD, half, rs1, f3l::LHU as u64, rd)),
+                        (0b001, _) => Ok(load(op::LD, half, rs1, f3l::LH as u64, rd)),
+                        (0b010, _) => Ok(store(op::STORE, byte, rd, rs1, f3s::SB as u64)),
+                        (0b011, 0) => Ok(store(op::STORE, half, rd, rs1, f3s::SH as u64)),
+                        _ => Err(String::from("No such inst 0")),
+                    };
+                }
+                let (imm, width) = match rv32::get_bits(inst, 15, 13) {
+                    f3co_0::FLD | f3co_0::FSD => (
+                        bitcat!(Bits::cut_new(inst, 6, 5), Bits::cut_new(inst, 12, 10), Bits::new(0, 3)),
+                        0b011,
+                    ),
+                    f3co_0::FLW | f3co_0::FSW => (
+                        bitcat!(
+                            Bits::cut_new(inst, 5, 5),
+                            Bits::cut_new(inst, 12, 10),
+                            Bits::cut_new(inst, 6, 6),
+                            Bits::new(0, 2)
+                        ),
+                        0b010,
+                    ),
+                    _ => return Err(String::from("No such inst 0")),
+                };
+                match rv32::get_bits(inst, 15, 15) {
+                    0 => Ok(load(op::LOAD_FP, imm.expand(12), rs1, width, rd)),
+                    _ => Ok(store(op::STORE_FP, imm.expand(12), rd, rs1, width)),
+                }
             }
             1 => match rv32::get_bits(inst, 15, 13) {
-                f3co_1::li => {
-                    let imm = bitcat!(Bits::cut_new(inst, 12, 12), Bits::cut_new(inst, 6, 2));
+                f3co_1::LI => {
+                    // addi rd, zero, imm
+                    let imm = bitcat!(Bits::cut_new(inst, 12, 12), Bits::cut_new(inst, 6, 2)).extend();
                     let ret = bitcat!(
-                        imm,
-                        Bits::new(0, 8),
+                        Bits::new(imm as u64, 12),
+                        Bits::new(0, 5),
+                        Bits::new(f3i::ADDI as u64, 3),
                         rd,
-                        Bits::new(f3i::ADDI as u64, 5),
+                        Bits::new(op::AIMM as u64, 5),
+                        Bits::new(0b11, 2)
+                    );
+                    Ok(ret.to_u32())
+                }
+                f3co_1::JAL => {
+                    let imm = Bits::new(
+                        bitcat!(
+                            Bits::cut_new(inst, 12, 12),
+                            Bits::cut_new(inst, 8, 8),
+                            Bits::cut_new(inst, 10, 9),
+                            Bits::cut_new(inst, 6, 6),
+                            Bits::cut_new(inst, 7, 7),
+                            Bits::cut_new(inst, 2, 2),
+                            Bits::cut_new(inst, 11, 11),
+                            Bits::cut_new(inst, 5, 3),
+                            Bits::new(0, 1)
+                        )
+                        .extend() as u64,
+                        32,
+                    );
+                    let ret = bitcat!(
+                        imm.cut(20, 20),
+                        imm.cut(10, 1),
+                        imm.cut(11, 11),
+                        imm.cut(19, 12),
+                        Bits::new(1, 5),
+                        Bits::new(op::JAL as u64, 5),
                         Bits::new(0b11, 2)
                     );
-                    println!("{:b}", ret.to_u32());
-                    return Ok(ret.to_u32());
+                    Ok(ret.to_u32())
                 }
-                f3co_1::beqz => {
-                    println!("c.beqz");
+                f3co_1::MISC_ALU => {
+                    // only the Zcb instructions, where RV64 has c.subw and c.addw
+                    let rd = Bits::cut_new(inst, 9, 7).expand(5).add(Bits::new(8, 5));
+                    let unary = |funct7: u64, rs2: u64, funct3: u32, opcode: u32| {
+                        bitcat!(
+                            Bits::new(funct7, 7),
+                            Bits::new(rs2, 5),
+                            rd,
+                            Bits::new(funct3 as u64, 3),
+                            rd,
+                            Bits::new(opcode as u64, 5),
+                            Bits::new(0b11, 2)
+                        )
+                        .to_u32()
+                    };
+                    match (rv32::get_bits(inst, 12, 10), rv32::get_bits(inst, 6, 5), rv32::get_bits(inst, 4, 2)) {
+                        // mul is not there without M or Zmmul
+                        (0b111, 0b10, _) => Err(String::from("c.mul needs M, which is not implemented")),
+                        // andi 255, sext.b, zext.h, sext.h and xori -1
+                        (0b111, 0b11, 0b000) => Ok(unary(0x07, 0x1f, f3i::ANDI, op::AIMM)),
+                        (0b111, 0b11, 0b001) => Ok(unary(0x30, 0x04, f3i::SLLI, op::AIMM)),
+                        (0b111, 0b11, 0b010) => Ok(unary(0x04, 0, 0b100, op::AREG)),
+                        (0b111, 0b11, 0b011) => Ok(unary(0x30, 0x05, f3i::SLLI, op::AIMM)),
+                        (0b111, 0b11, 0b101) => Ok(unary(0x7f, 0x1f, f3i::XORI, op::AIMM)),
+                        _ => Err(String::from("no such inst")),
+                    }
+                }
+                f3co_1::BEQZ => {
                     let imm = bitcat!(
                         Bits::cut_new(inst, 12, 12),
                         Bits::cut_new(inst, 6, 5),
@@ -323,9 +1329,7 @@ impl Cpu {
                         Bits::cut_new(inst, 4, 3),
                         Bits::new(0, 1)
                     );
-                    println!("imm={}", imm.to_u32());
                     let rs = Bits::cut_new(inst, 9, 7).expand(5).add(Bits::new(8, 5));
-                    println!("{}", rs.to_u32());
                     let ret = bitcat!(
                         imm.cut(12, 12),
                         imm.cut(10, 5),
@@ -337,16 +1341,14 @@ impl Cpu {
                         Bits::new(op::BRANCH as u64, 5),
                         Bits::new(0b11, 2)
                     );
-                    println!("uncompress={:x}", rs.to_u32());
                     Ok(ret.to_u32())
                 }
                 _ => {
-                    println!("{}", rv32::get_bits(inst, 15, 13));
-                    return Err(String::from("no such inst"));
+                    Err(String::from("no such inst"))
                 }
             },
             2 => match rv32::get_bits(inst, 15, 13) {
-                f3co_2::slli => {
+                f3co_2::SLLI => {
                     let rd = bitutils::Bits::cut_new(inst, 11, 7);
                     let shamt = bitutils::Bits::cut_new(inst, 12, 12)
                         .shiftadd(bitutils::Bits::cut_new(inst, 6, 2));
@@ -358,35 +1360,9 @@ impl Cpu {
                         Bits::new(op::AIMM as u64, 5),
                         Bits::new(0b11, 2)
                     );
-                    return Ok(bits.to_u32());
-                }
-                f3co_2::jal => {
-                    let imm = Bits::new(
-                        bitcat!(
-                            Bits::cut_new(inst, 12, 12),
-                            Bits::cut_new(inst, 8, 8),
-                            Bits::cut_new(inst, 10, 9),
-                            Bits::cut_new(inst, 6, 6),
-                            Bits::cut_new(inst, 7, 7),
-                            Bits::cut_new(inst, 2, 2),
-                            Bits::cut_new(inst, 11, 11),
-                            Bits::cut_new(inst, 5, 3)
-                        )
-                        .extend() as u64,
-                        32,
-                    );
-                    let ret = bitcat!(
-                        imm.cut(20, 20),
-                        imm.cut(10, 1),
-                        imm.cut(11, 11),
-                        imm.cut(19, 12),
-                        Bits::new(1, 5),
-                        Bits::new(op::JAL as u64, 5),
-                        Bits::new(0b11, 2)
-                    );
-                    return Ok(ret.to_u32());
+                    Ok(bits.to_u32())
                 }
-                f3co_2::lwsp => {
+                f3co_2::LWSP => {
                     let rd = bitutils::Bits::cut_new(inst, 11, 7);
                     let imm = bitcat!(
                         Bits::cut_new(inst, 3, 2),
@@ -402,9 +1378,9 @@ impl Cpu {
                         Bits::new(op::LD as u64, 5),
                         Bits::new(0b11, 2)
                     );
-                    return Ok(ret.to_u32());
+                    Ok(ret.to_u32())
                 }
-                f3co_2::mv_ja => {
+                f3co_2::MV_JA => {
                     let rd = Bits::cut_new(inst, 11, 7);
                     let rs = Bits::cut_new(inst, 6, 2);
                     match rv32::get_bits(inst, 12, 12) {
@@ -417,7 +1393,7 @@ impl Cpu {
                                     Bits::new(op::JALR as u64, 5),
                                     Bits::new(0b11, 2)
                                 );
-                                return Ok(ret.to_u32());
+                                Ok(ret.to_u32())
                             }
                             _ => {
                                 let ret = bitcat!(
@@ -428,7 +1404,7 @@ impl Cpu {
                                     Bits::new(op::AREG as u64, 5),
                                     Bits::new(0b11, 2)
                                 );
-                                return Ok(ret.to_u32());
+                                Ok(ret.to_u32())
                             }
                         },
                         1 => match rd.to_u32() {
@@ -441,461 +1417,1132 @@ impl Cpu {
                                     Bits::new(op::JALR as u64, 5),
                                     Bits::new(0b11, 2)
                                 );
-                                return Ok(ret.to_u32());
+                                Ok(ret.to_u32())
                             }
                             _ => {
-                                return Err(String::from("No such compressed op"));
+                                Err(String::from("No such compressed op"))
                             }
                         },
                         _ => {
-                            return Err(String::from("No such compressed op"));
+                            Err(String::from("No such compressed op"))
                         }
                     }
                 }
-                f3co_2::ldsp => {
-                    return Err(String::from("C.LDSP,RV64C is not implemeted."));
-                }
-                _ => {
-                    return Err(String::from("No such compressed op"));
-                }
+                f3co_2::FLDSP => {
+                    let imm = bitcat!(
+                        Bits::cut_new(inst, 4, 2),
+                        Bits::cut_new(inst, 12, 12),
+                        Bits::cut_new(inst, 6, 5),
+                        Bits::new(0, 3)
+                    );
+                    Ok(load(op::LOAD_FP, imm.expand(12), Bits::new(2, 5), 0b011, rd))
+                }
+                f3co_2::FLWSP => {
+                    let imm = bitcat!(
+                        Bits::cut_new(inst, 3, 2),
+                        Bits::cut_new(inst, 12, 12),
+                        Bits::cut_new(inst, 6, 4),
+                        Bits::new(0, 2)
+                    );
+                    Ok(load(op::LOAD_FP, imm.expand(12), Bits::new(2, 5), 0b010, rd))
+                }
+                f3co_2::FSDSP => {
+                    let imm = bitcat!(Bits::cut_new(inst, 9, 7), Bits::cut_new(inst, 12, 10), Bits::new(0, 3));
+                    Ok(store(op::STORE_FP, imm.expand(12), Bits::cut_new(inst, 6, 2), Bits::new(2, 5), 0b011))
+                }
+                f3co_2::FSWSP => {
+                    let imm = bitcat!(Bits::cut_new(inst, 8, 7), Bits::cut_new(inst, 12, 9), Bits::new(0, 2));
+                    Ok(store(op::STORE_FP, imm.expand(12), Bits::cut_new(inst, 6, 2), Bits::new(2, 5), 0b010))
+                }
+                _ => {
+                    Err(String::from("No such compressed op"))
+                }
             },
             _ => {
-                return Err(String::from("No such compressed op"));
+                Err(String::from("No such compressed op"))
             }
         }
     }
-    fn exec_rv32(&mut self, inst: u32) -> Result<(), String> {
-        match rv32::get_op(inst) {
-            op::LUDI => {
-                self.register.write(
-                    rv32::get_rd(inst),
-                    (rv32::get_bits_extended(inst, 31, 12) << 12) as u64,
-                    self.len,
-                )?;
+    fn exec(&mut self, inst: Instruction) -> Result<(), String> {
+        match inst {
+            Instruction::Lui { rd, imm } => {
+                self.register.write(rd, imm, self.len)?;
             }
-            op::AUIPC => {
-                self.register.write(
-                    rv32::get_rd(inst),
-                    self.pc + rv32::get_bits_extended(inst, 31, 12) as u64,
-                    self.len,
-                )?;
+            Instruction::Auipc { rd, imm } => {
+                self.register.write(rd, self.pc.wrapping_add(imm) as u32 as u64, self.len)?;
             }
-            op::JAL => {
-                self.register
-                    .write(rv32::get_rd(inst), self.pc + 4, self.len)?;
-                if rv32::get_rd(inst) == 1 {
+            Instruction::Jal { rd, offset } => {
+                self.register.write(rd, self.pc + 4, self.len)?;
+                if rd == 1 {
                     //this is subroutine call
-                    println!("@subroutine call! push {:x} to shadow stack!@", self.pc + 4);
-                    self.sstack.push(self.pc + 4)?;
+                    self.sstack_call(self.pc + 4)?;
                 }
-                self.pc += rv32::get_imm_jal(inst) as u64;
-                println!(
-                    "JAL x{:x}, 0x{:x}",
-                    rv32::get_rd(inst),
-                    rv32::get_imm_jal(inst)
-                )
+                self.pc += offset;
             }
-            op::JALR => {
-                self.register
-                    .write(rv32::get_rd(inst), self.pc + 4, self.len)?;
-                let target = (self.register.read(rv32::get_rs1(inst), self.len)?
-                    + rv32::get_bits_extended(inst, 31, 20) as u64)
-                    & !1;
-                if rv32::get_rd(inst) == 0
-                    && rv32::get_rs1(inst) == 1
-                    && rv32::get_bits(inst, 31, 20) == 0
-                {
+            Instruction::Jalr { rd, rs1, imm } => {
+                let target = (self.register.read(rs1, self.len)? + imm) & !1;
+                self.register.write(rd, self.pc + 4, self.len)?;
+                if rd == 1 {
+                    //indirect subroutine call
+                    self.sstack_call(self.pc + 4)?;
+                } else if rd == 0 && rs1 == 1 && imm == 0 {
                     //ret
-                    let sv = self.sstack.pop()?;
-                    if target == sv {
-                        self.pc = (self.register.read(rv32::get_rs1(inst), self.len)?
-                            + rv32::get_bits_extended(inst, 31, 20) as u64)
-                            & !1;
-                        println!("@@@ shadow stack match! @@@\n ret to {:#x}", target);
-                    } else {
-                        return Err(String::from(format!("@@@ shadow stack mismatch! @@@\ntrying to ret to 0x{:x}, however, shadow stack value is {:#x}",target,sv)));
-                    }
+                    self.sstack_return(target)?;
                 }
+                self.pc = target;
             }
-            op::BRANCH => match rv32::get_funct3(inst) {
-                f3b::BEQ => {
-                    println!(
-                        "BEQ {}, {}, {:x}",
-                        rv32::get_rs1(inst),
-                        rv32::get_rs2(inst),
-                        rv32::get_imm_branch(inst)
-                    );
-                    if self.register.read(rv32::get_rs1(inst), self.len)?
-                        == self.register.read(rv32::get_rs2(inst), self.len)?
-                    {
-                        self.pc += rv32::sign_extend(rv32::get_imm_branch(inst), 12) as u64;
-                    }
-                }
-                f3b::BNE => {
-                    println!("rsv{}",self.register.read(rv32::get_rs1(inst), self.len)?);
-                    println!("rsv{}",self.register.read(rv32::get_rs2(inst), self.len)?);
-                    if self.register.read(rv32::get_rs1(inst), self.len)?
-                        != self.register.read(rv32::get_rs2(inst), self.len)?
-                    {
-                        println!(
-                            "bne_pc={0}",
-                            rv32::sign_extend(rv32::get_imm_branch(inst), 12)
-                        );
-                        self.pc += rv32::sign_extend(rv32::get_imm_branch(inst), 12) as u64;
-                        self.pc = self.pc & 0xffffffff;
-                    }
-                }
-                f3b::BLT => {
-                    if (self.register.read(rv32::get_rs1(inst), self.len)? as i32)
-                        < (self.register.read(rv32::get_rs2(inst), self.len)? as i32)
-                    {
-                        self.pc += rv32::sign_extend(rv32::get_imm_branch(inst), 12) as u64;
-                    }
-                }
-                f3b::BGE => {
-                    if (self.register.read(rv32::get_rs1(inst), self.len)? as i32)
-                        >= (self.register.read(rv32::get_rs2(inst), self.len)? as i32)
-                    {
-                        self.pc += rv32::sign_extend(rv32::get_imm_branch(inst), 12) as u64;
+            Instruction::Branch {
+                cond,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let a = self.register.read(rs1, self.len)?;
+                let b = self.register.read(rs2, self.len)?;
+                let taken = match cond {
+                    Cond::Eq => a == b,
+                    Cond::Ne => a != b,
+                    Cond::Lt => (a as i32) < (b as i32),
+                    Cond::Ge => (a as i32) >= (b as i32),
+                    Cond::Ltu => a < b,
+                    Cond::Geu => a >= b,
+                };
+                if taken {
+                    self.pc += offset;
+                    if cond == Cond::Ne {
+                        self.pc &= 0xffffffff;
                     }
                 }
-                f3b::BLTU => {
-                    if self.register.read(rv32::get_rs1(inst), self.len)?
-                        < self.register.read(rv32::get_rs2(inst), self.len)?
-                    {
-                        self.pc += rv32::sign_extend(rv32::get_imm_branch(inst), 12) as u64;
-                    }
+            }
+            Instruction::Lb { rd, rs1, offset } => {
+                let address = (self.register.read(rs1, self.len)? as u32).wrapping_add(offset);
+                let data = rv32::sign_extend(self.read_memory(address as u64, 1)? as u32, 7);
+                self.register.write(rd, data as u64, self.len)?;
+            }
+            Instruction::Lh { rd, rs1, offset } => {
+                let address = (self.register.read(rs1, self.len)? as u32).wrapping_add(offset);
+                let data = rv32::sign_extend(self.read_memory(address as u64, 2)? as u32, 15);
+                self.register.write(rd, data as u64, self.len)?;
+            }
+            Instruction::Lw { rd, rs1, offset } => {
+                let address = (self.register.read(rs1, self.len)? as u32).wrapping_add(offset);
+                let data = rv32::sign_extend(self.read_memory(address as u64, 4)? as u32, 31);
+                self.register.write(rd, data as u64, self.len)?;
+            }
+            Instruction::Lbu { rd, rs1, offset } => {
+                let address = (self.register.read(rs1, self.len)? as u32).wrapping_add(offset);
+                let data = self.read_memory(address as u64, 1)? as u32;
+                self.register.write(rd, data as u64, self.len)?;
+            }
+            Instruction::Lhu { rd, rs1, offset } => {
+                let address = (self.register.read(rs1, self.len)? as u32).wrapping_add(offset);
+                let data = self.read_memory(address as u64, 2)? as u32;
+                self.register.write(rd, data as u64, self.len)?;
+            }
+            Instruction::Sb { rs1, rs2, offset } => {
+                self.write_memory(
+                    (self.register.read(rs1, self.len)? + offset) as u32 as u64,
+                    1,
+                    self.register.read(rs2, self.len)? as u8 as u64,
+                )?;
+            }
+            Instruction::Sh { rs1, rs2, offset } => {
+                self.write_memory(
+                    (self.register.read(rs1, self.len)? + offset) as u32 as u64,
+                    2,
+                    self.register.read(rs2, self.len)? as u16 as u64,
+                )?;
+            }
+            Instruction::Sw { rs1, rs2, offset } => {
+                self.write_memory(
+                    (self.register.read(rs1, self.len)? + offset) as u32 as u64,
+                    4,
+                    self.register.read(rs2, self.len)? as u32 as u64,
+                )?;
+            }
+            Instruction::Addi { rd, rs1, imm } => {
+                // TODO: 32bitと64bit
+                self.register
+                    .write(rd, self.register.read(rs1, self.len)? + imm, self.len)?;
+            }
+            // TODO: 32bitと64bit
+            Instruction::Slti { rd, rs1, imm } => {
+                let a = self.register.read(rs1, self.len)?;
+                self.register.write(rd, ((a as i32) < (imm as i32)) as u64, self.len)?;
+            }
+            Instruction::Sltiu { rd, rs1, imm } => {
+                let a = self.register.read(rs1, self.len)?;
+                self.register.write(rd, ((a as u32) < (imm as u32)) as u64, self.len)?;
+            }
+            Instruction::Xori { rd, rs1, imm } => {
+                self.register.write(
+                    rd,
+                    ((self.register.read(rs1, self.len)? as u32) ^ imm) as u64,
+                    self.len,
+                )?;
+            }
+            Instruction::Ori { rd, rs1, imm } => {
+                self.register.write(
+                    rd,
+                    ((self.register.read(rs1, self.len)? as u32) | imm) as u64,
+                    self.len,
+                )?;
+            }
+            Instruction::Andi { rd, rs1, imm } => {
+                self.register.write(
+                    rd,
+                    ((self.register.read(rs1, self.len)? as u32) & imm) as u64,
+                    self.len,
+                )?;
+            }
+            Instruction::Slli { rd, rs1, shamt } => {
+                self.register.write(
+                    rd,
+                    ((self.register.read(rs1, self.len)? as u32) << shamt) as u64,
+                    self.len,
+                )?;
+            }
+            Instruction::Srli { rd, rs1, shamt } => {
+                self.register.write(
+                    rd,
+                    ((self.register.read(rs1, self.len)? as u32) >> shamt) as u64,
+                    self.len,
+                )?;
+            }
+            Instruction::Srai { rd, rs1, shamt } => {
+                self.register.write(
+                    rd,
+                    ((self.register.read(rs1, self.len)? as i32) >> shamt) as u32 as u64,
+                    self.len,
+                )?;
+            }
+            Instruction::AddSub {
+                rd,
+                rs1,
+                rs2,
+                funct7,
+            } => {
+                let state = State {
+                    rd,
+                    rs1,
+                    rs2,
+                    imm: funct7,
+                };
+                let areg = ADD.exec_register(state, &self.register)?;
+                for i in 0..32 {
+                    self.register.write(i, areg.read(i, 4)?, 4)?;
                 }
-                f3b::BGEU => {
-                    if self.register.read(rv32::get_rs1(inst), self.len)?
-                        >= self.register.read(rv32::get_rs2(inst), self.len)?
-                    {
-                        self.pc += rv32::sign_extend(rv32::get_imm_branch(inst), 12) as u64;
+            }
+            Instruction::Ecall => {
+                let cause = match (self.privilege, self.virt) {
+                    (privilege::USER, _) => cause::ECALL_U,
+                    (privilege::SUPERVISOR, false) => cause::ECALL_S,
+                    (privilege::SUPERVISOR, true) => cause::ECALL_VS,
+                    _ => cause::ECALL_M,
+                };
+                let current_priv = self.privilege;
+                self.enter_trap(cause, false, 0, 0, false)?;
+                trace!(
+                    self.tracer,
+                    category::TRAP,
+                    1,
+                    "ecall from privilege {}, cause {}, to {:#x}",
+                    current_priv,
+                    cause,
+                    self.pc
+                );
+            }
+            Instruction::Sret => {
+                self.supervisor_only(csr::MSTATUS_TSR, csr::HSTATUS_VTSR)?;
+                // VS mode returns with its own registers, HS mode perhaps into a virtual machine
+                let (status, epc) = match self.virt {
+                    true => (csr::VSSTATUS, csr::VSEPC),
+                    false => (csr::MSTATUS, csr::SEPC),
+                };
+                self.pc = self.csr.read(epc)?;
+                let sstatus = self.csr.read(status)?;
+                self.privilege = ((sstatus & csr::MSTATUS_SPP) >> 8) as u8;
+                //SPP = 0, SIE = SPIE, SPIE = 1
+                let sie = (sstatus & csr::MSTATUS_SPIE) >> 4;
+                let mut sstatus = (sstatus & !(csr::MSTATUS_SPP | csr::MSTATUS_SIE)) | sie | csr::MSTATUS_SPIE;
+                if !self.virt {
+                    sstatus &= !csr::MSTATUS_MPRV;
+                    if self.isa.h {
+                        let hstatus = self.csr.read(csr::HSTATUS)?;
+                        self.virt = hstatus & csr::HSTATUS_SPV != 0;
+                        self.csr.write(csr::HSTATUS, hstatus & !csr::HSTATUS_SPV)?;
                     }
                 }
-                _ => {
-                    return Err(String::from("No inst on branch"));
-                }
-            },
-            op::LD => match rv32::get_funct3(inst) {
-                f3l::LB => {
-                    let offset = rv32::get_bits_extended(inst, 31, 20);
-                    let address =
-                        (self.register.read(rv32::get_rs1(inst), self.len)? as u32) + offset;
-                    let data = rv32::sign_extend(self.mmu.read_nbytes(address as u64, 1) as u32, 7);
-                    self.register
-                        .write(rv32::get_rs1(inst), data as u64, self.len)?;
-                }
-                f3l::LH => {
-                    let offset = rv32::get_bits_extended(inst, 31, 20);
-                    let address =
-                        (self.register.read(rv32::get_rs1(inst), self.len)? as u32) + offset;
-                    let data =
-                        rv32::sign_extend(self.mmu.read_nbytes(address as u64, 2) as u32, 15);
-                    self.register
-                        .write(rv32::get_rs1(inst), data as u64, self.len)?;
-                }
-                f3l::LW => {
-                    let offset = rv32::get_bits_extended(inst, 31, 20);
-                    let address =
-                        (self.register.read(rv32::get_rs1(inst), self.len)? as u32) + offset;
-                    let data =
-                        rv32::sign_extend(self.mmu.read_nbytes(address as u64, 4) as u32, 31);
-                    println!("lw x{} x{} = 0x{:x} = {}", rv32::get_rd(inst), rv32::get_rs1(inst),address, data);
-                    self.register
-                        .write(rv32::get_rd(inst), data as u64, self.len)?;
-                }
-                f3l::LBU => {
-                    let offset = rv32::get_bits_extended(inst, 31, 20);
-                    let address =
-                        (self.register.read(rv32::get_rs1(inst), self.len)? as u32) + offset;
-                    println!("lbu rd={:}, rs1={:}, adr=0x{:}, offset={:}",rv32::get_rd(inst),rv32::get_rs1(inst), address, offset);
-                    let data = self.mmu.read_nbytes(address as u64, 1) as u32;
-                    self.register
-                        .write(rv32::get_rd(inst), data as u64, self.len)?;
-                }
-                f3l::LHU => {
-                    let offset = rv32::get_bits_extended(inst, 31, 20);
-                    let address =
-                        (self.register.read(rv32::get_rs1(inst), self.len)? as u32) + offset;
-                    let data = self.mmu.read_nbytes(address as u64, 2) as u32;
-                    self.register
-                        .write(rv32::get_rs1(inst), data as u64, self.len)?;
-                }
-                _ => {
-                    return Err(String::from("No inst on load"));
+                self.csr.write(status, sstatus)?;
+                self.switch_sstack()?;
+                trace!(
+                    self.tracer,
+                    category::TRAP,
+                    1,
+                    "sret to {:#x}, privilege {}{}",
+                    self.pc,
+                    self.privilege,
+                    if self.virt { ", virtual" } else { "" }
+                );
+            }
+            Instruction::Mret => {
+                if self.privilege != privilege::MACHINE {
+                    return Err(self.illegal_instruction());
                 }
-            },
-            op::STORE => match rv32::get_funct3(inst) {
-                f3s::SB => {
-                    self.mmu.write_byte(
-                        (self.register.read(rv32::get_rs1(inst), self.len)?
-                            + rv32::sign_extend(rv32::get_imm_st(inst), 11) as u64)
-                            as u32 as u64,
-                        self.register.read(rv32::get_rs2(inst), self.len)? as u8,
-                    );
+                self.pc = self.csr.read(0x341)?;
+                let mstatus = self.csr.read(0x300)?;
+                self.privilege = ((mstatus >> 11) & 0b11) as u8;
+                //MPP = 0, MIE = MPIE, MPIE = 1
+                let mie = (mstatus >> 7) & 1;
+                let mut mstatus = (mstatus & !(0b11 << 11) & !(1 << 3)) | (mie << 3) | (1 << 7);
+                if self.privilege != privilege::MACHINE {
+                    mstatus &= !csr::MSTATUS_MPRV;
                 }
-                f3s::SH => {
-                    self.mmu.write_2byte(
-                        (self.register.read(rv32::get_rs1(inst), self.len)?
-                            + rv32::sign_extend(rv32::get_imm_st(inst), 11) as u64)
-                            as u32 as u64,
-                        self.register.read(rv32::get_rs2(inst), self.len)? as u16,
-                    );
+                self.csr.write(0x300, mstatus)?;
+                // back into the virtual machine trapped from
+                if self.isa.h {
+                    let mstatush = self.csr.read(csr::MSTATUSH)?;
+                    self.virt = self.privilege != privilege::MACHINE && mstatush & csr::MSTATUSH_MPV != 0;
+                    self.csr.write(csr::MSTATUSH, mstatush & !csr::MSTATUSH_MPV)?;
                 }
-                f3s::SW => {
-                    println!("SW x{}, {}", rv32::get_rs2(inst),((
-                    self.register.read(rv32::get_rs1(inst), self.len)? + rv32::sign_extend(rv32::get_imm_st(inst), 11) as u64) as u32 as u64));
-                    self.mmu.write_4byte(
-                        (self.register.read(rv32::get_rs1(inst), self.len)?
-                            + rv32::sign_extend(rv32::get_imm_st(inst), 11) as u64)
-                            as u32 as u64,
-                        self.register.read(rv32::get_rs2(inst), self.len)? as u32,
-                    );
+                self.switch_sstack()?;
+                trace!(
+                    self.tracer,
+                    category::TRAP,
+                    1,
+                    "mret to {:#x}, privilege {}{}",
+                    self.pc,
+                    self.privilege,
+                    if self.virt { ", virtual" } else { "" }
+                );
+            }
+            Instruction::SfenceVma { .. } => {
+                self.supervisor_only(csr::MSTATUS_TVM, csr::HSTATUS_VTVM)?;
+            }
+            Instruction::Hfence { gvma, .. } => {
+                self.hypervisor_only(false)?;
+                let tvm = self.csr.read(csr::MSTATUS)? & csr::MSTATUS_TVM != 0;
+                if gvma && tvm && self.privilege == privilege::SUPERVISOR {
+                    return Err(self.illegal_instruction());
                 }
-                _ => {
-                    return Err(String::from("No inst on store"));
+            }
+            Instruction::Hlv { rd, rs1, width, signed, exec } => {
+                let hu = self.csr.read(csr::HSTATUS)? & csr::HSTATUS_HU != 0;
+                self.hypervisor_only(hu)?;
+                let walk = self.guest_walk(exec)?;
+                let address = self.register.read(rs1, self.len)?;
+                let data = self.read_memory_as(address, width, Kind::Load, walk)?;
+                let data = match signed {
+                    true => rv32::sign_extend(data as u32, 8 * width as u32 - 1) as u64,
+                    false => data,
+                };
+                self.register.write(rd, data, self.len)?;
+            }
+            Instruction::Hsv { rs1, rs2, width } => {
+                let hu = self.csr.read(csr::HSTATUS)? & csr::HSTATUS_HU != 0;
+                self.hypervisor_only(hu)?;
+                let walk = self.guest_walk(false)?;
+                let address = self.register.read(rs1, self.len)?;
+                let data = self.register.read(rs2, self.len)? & (u64::MAX >> (64 - 8 * width));
+                self.write_memory_as(address, width, data, walk)?;
+            }
+            Instruction::Csr { op, rd, rs1, csr } if csr == csr::SEED => {
+                let value = self.read_seed(op, rs1)?;
+                self.register.write(rd, value, self.len)?;
+            }
+            Instruction::Csr { op, rd, rs1, csr } => {
+                let csr = self.csr_access(csr)?;
+                if csr == csr::JVT && !self.isa.zcmt {
+                    return Err(self.illegal_instruction());
                 }
-            },
-            op::AIMM => match rv32::get_funct3(inst) {
-                f3i::ADDI => {
-                    // TODO: 32bitと64bit
-                    println!(
-                        "ADDI x{}, x{}, 0x{:x}",
-                        rv32::get_rd(inst),
-                        rv32::get_rs1(inst),
-                        rv32::get_bits_extended(inst, 31, 20)
-                    );
-                    self.register.write(
-                        rv32::get_rd(inst),
-                        self.register.read(rv32::get_rs1(inst), self.len)?
-                            + rv32::get_bits_extended(inst, 31, 20) as u64,
-                        self.len,
-                    )?;
-                }
-                f3i::SLTI => {
-                    // TODO: 32bitと64bit
-                    self.register.write(
-                        rv32::get_rd(inst),
-                        if self.register.read(rv32::get_rs1(inst), self.len)?
-                            < rv32::get_bits_extended(inst, 31, 20) as u64
-                        {
-                            1
-                        } else {
-                            0
-                        },
-                        self.len,
-                    )?;
-                }
-                f3i::SLTIU => {
-                    self.register.write(
-                        rv32::get_rd(inst),
-                        if self.register.read(rv32::get_rs1(inst), self.len)?
-                            < rv32::get_bits(inst, 31, 20) as u64
-                        {
-                            1
-                        } else {
-                            0
-                        },
-                        self.len,
-                    )?;
-                }
-                f3i::XORI => {
-                    self.register.write(
-                        rv32::get_rd(inst),
-                        ((self.register.read(rv32::get_rs1(inst), self.len)? as u32)
-                            ^ (rv32::get_bits(inst, 31, 20))) as u64,
-                        self.len,
-                    )?;
-                }
-                f3i::ORI => {
-                    self.register.write(
-                        rv32::get_rd(inst),
-                        ((self.register.read(rv32::get_rs1(inst), self.len)? as u32)
-                            | (rv32::get_bits(inst, 31, 20))) as u64,
-                        self.len,
-                    )?;
-                }
-                f3i::ANDI => {
-                    self.register.write(
-                        rv32::get_rd(inst),
-                        ((self.register.read(rv32::get_rs1(inst), self.len)? as u32)
-                            & (rv32::get_bits(inst, 31, 20))) as u64,
-                        self.len,
-                    )?;
-                }
-                f3i::SLLI => {
-                    println!("slli");
-                    let shamt = rv32::get_bits(inst, 31, 20);
-                    println!("{} {}",shamt, ((self.register.read(rv32::get_rs1(inst), self.len)? as u32)&((1<<(32-shamt))-1)));
-                    self.register.write(
-                        rv32::get_rd(inst),
-                        (((self.register.read(rv32::get_rs1(inst), self.len)? as u32)&((1<<(32-shamt))-1))
-                            << shamt) as u64,
-                        self.len,
-                    )?;
-                }
-                f3i::SRLI_SRAI => match rv32::get_bits(inst, 30, 30) {
-                    0 => {
-                        self.register.write(
-                            rv32::get_rd(inst),
-                            ((self.register.read(rv32::get_rs1(inst), self.len)? as u32)
-                                >> (rv32::get_bits(inst, 31, 20)))
-                                as u64,
-                            self.len,
-                        )?;
-                    }
-                    1 => {
-                        self.register.write(
-                            rv32::get_rd(inst),
-                            if rv32::get_bits(inst, 31, 31) == 1 {
-                                self.register.read(rv32::get_rs1(inst), self.len)?
-                                    >> get_bits(inst, 25, 20)
-                            } else {
-                                rv32::get_bits_extended(
-                                    (self.register.read(rv32::get_rs1(inst), self.len)?
-                                        >> get_bits(inst, 25, 20))
-                                        as u32,
-                                    31 - get_bits(inst, 25, 20) as usize,
-                                    0,
-                                ) as u64
-                            },
-                            self.len,
-                        )?;
-                    }
-                    _ => {
-                        return Err(String::from("No inst on SRLI_SRAI"));
-                    }
-                },
-                _ => {
-                    return Err(String::from("No inst on arithmatic immediate"));
+                let fp = (csr::FFLAGS..=csr::FCSR).contains(&csr);
+                if fp {
+                    self.fp_enabled()?;
                 }
-            },
-            op::AREG => match rv32::get_funct3(inst) {
-                f3r::ADD_SUB => {
-                    println!("ADD_SUB");
-                    let state = State {
-                        rd: rv32::get_rd(inst),
-                        rs1: rv32::get_rs1(inst),
-                        rs2: rv32::get_rs2(inst),
-                        imm: rv32::get_bits(inst, 31, 25) as u64,
+                let vector = matches!(
+                    csr,
+                    csr::VSTART | csr::VXSAT | csr::VXRM | csr::VCSR | csr::VL | csr::VTYPE | csr::VLENB
+                );
+                let config = match vector {
+                    true => Some(self.vector_enabled()?),
+                    false => None,
+                };
+                let t = self.csr.read(csr)?;
+                let write = match op {
+                    CsrOp::Rw => Some(self.register.read(rs1, self.len)?),
+                    CsrOp::Rs if rs1 != 0 => Some(t | self.register.read(rs1, self.len)?),
+                    CsrOp::Rc if rs1 != 0 => Some(t & !self.register.read(rs1, self.len)?),
+                    CsrOp::Rwi => Some(rs1 as u64),
+                    CsrOp::Rsi if rs1 != 0 => Some(t | rs1 as u64),
+                    CsrOp::Rci if rs1 != 0 => Some(t & !(rs1 as u32) as u64),
+                    _ => None,
+                };
+                if let Some(value) = write {
+                    let value = match (csr, config) {
+                        (csr::VL | csr::VTYPE | csr::VLENB, _) => {
+                            return Err(self.illegal_instruction());
+                        }
+                        // enough bits for any element index
+                        (csr::VSTART, Some(c)) => value & (c.vlen as u64 - 1),
+                        // only the jump table mode
+                        (csr::JVT, _) => value & !csr::JVT_MODE,
+                        _ => value,
                     };
-                    let areg = add.exec_register(state, &self.register)?;
-                    for i in 0..32 {
-                        self.register.write(i, areg.read(i, 4)?, 4)?;
+                    self.csr.write(csr, value)?;
+                    if fp {
+                        self.fp_dirty()?;
+                    }
+                    if vector {
+                        self.vector_dirty()?;
                     }
-                    /*
-                    self.register.write(
-                        rv32::get_rd(inst),
-                        self.register.read(rv32::get_rs1(inst), self.len)?
-                            + self.register.read(rv32::get_rs2(inst), self.len)?,
-                        self.len,
-                    )?;
-                    */
-                }
-                _ => {
-                    return Err(String::from("No inst on arithmatic register"));
                 }
-            },
-            op::CSR => match rv32::get_funct3(inst) {
-                f3c::EXCEPT => {
-                    let exception = rv32::get_bits(inst, 31, 20);
-                    match exception {
-                        exception::ecall => {
-                            let current_pc = self.pc;
-                            self.csr.write(0x341, current_pc)?;
-                            let current_priv = self.privilege;
-                            match current_priv {
-                                privilege::user => {
-                                    //ほんとう？
-                                    self.csr.write(0x142, 0)?;
-                                    self.privilege = privilege::supervisor;
-                                }
-                                privilege::machine => {
-                                    println!("ECALL {:?}", self.csr.read(0x305)? as u32);
-                                    self.csr.write(0x142, 0)?;
-                                    self.csr.write(0x341, self.pc)?;
-                                    self.csr.write(0x342, 11)?;
-                                    self.privilege = privilege::machine;
-                                    self.pc = self.csr.read(0x305)? as u32 as u64;
-                                }
-                                _ => {
-                                    return Err(String::from("Unknown privilege level"));
-                                }
-                            }
-                        }
-                        exception::mret => {
-                            self.pc = self.csr.read(0x341)?;
-                        }
+                self.register.write(rd, t, self.len)?;
+            }
+            Instruction::Fence => {}
+            Instruction::FenceI => {
+                self.decode_cache.clear();
+                self.blocks.clear();
+            }
+            Instruction::Fload { fmt, rd, rs1, offset } => {
+                self.fp_enabled()?;
+                let address = (self.register.read(rs1, self.len)? + offset) as u32 as u64;
+                let data = self.read_memory(address, fmt.width() as u64 / 8)?;
+                self.fwrite(fmt, rd, data)?;
+            }
+            Instruction::Fstore { fmt, rs1, rs2, offset } => {
+                self.fp_enabled()?;
+                let address = (self.register.read(rs1, self.len)? + offset) as u32 as u64;
+                // the bits as held, NaN-boxed or not
+                let data = self.fregister.read(rs2)?;
+                let width = fmt.width() as u64 / 8;
+                self.write_memory(address, width, data & (u64::MAX >> (64 - 8 * width)))?;
+            }
+            Instruction::Fma {
+                op,
+                fmt,
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => {
+                self.fp_enabled()?;
+                let mut env = Env::new(self.rounding(rm)?);
+                let (a, b, c) = (self.fread(fmt, rs1)?, self.fread(fmt, rs2)?, self.fread(fmt, rs3)?);
+                let (a, c) = match op {
+                    FmaOp::Madd => (a, c),
+                    FmaOp::Msub => (a, fmt.negate(c)),
+                    FmaOp::Nmsub => (fmt.negate(a), c),
+                    FmaOp::Nmadd => (fmt.negate(a), fmt.negate(c)),
+                };
+                let result = fmt.fma(a, b, c, &mut env);
+                self.fwrite(fmt, rd, result)?;
+                self.accrue(env.flags)?;
+            }
+            Instruction::Fp {
+                op,
+                fmt,
+                rd,
+                rs1,
+                rs2,
+                rm,
+            } => {
+                self.exec_fp(op, fmt, rd, rs1, rs2, rm)?;
+            }
+            Instruction::Bitmanip {
+                op,
+                rd,
+                rs1,
+                rs2,
+                word,
+            } => {
+                let (a, b) = (self.register.read(rs1, self.len)?, self.register.read(rs2, self.len)?);
+                let result = bitmanip::exec(op, a, b, 8 * self.len as u32, word)?;
+                self.register.write(rd, result, self.len)?;
+            }
+            Instruction::BitmanipImm {
+                op,
+                rd,
+                rs1,
+                imm,
+                word,
+            } => {
+                let a = self.register.read(rs1, self.len)?;
+                let result = bitmanip::exec(op, a, imm as u64, 8 * self.len as u32, word)?;
+                self.register.write(rd, result, self.len)?;
+            }
+            Instruction::Crypto { op, rd, rs1, rs2, imm } => {
+                let (a, b) = (self.register.read(rs1, self.len)?, self.register.read(rs2, self.len)?);
+                let result = crypto::exec(op, a, b, imm, 8 * self.len as u32)?;
+                self.register.write(rd, result, self.len)?;
+            }
+            Instruction::Vset { rd, avl, vtype } => self.exec_vset(rd, avl, vtype)?,
+            Instruction::Vload {
+                mode,
+                eew,
+                nf,
+                vd,
+                rs1,
+                rs2,
+                vm,
+            } => self.exec_vmem(false, mode, eew, nf, vd, rs1, rs2, vm)?,
+            Instruction::Vstore {
+                mode,
+                eew,
+                nf,
+                vs3,
+                rs1,
+                rs2,
+                vm,
+            } => self.exec_vmem(true, mode, eew, nf, vs3, rs1, rs2, vm)?,
+            Instruction::Vector { op, vd, vs2, src, vm } => self.exec_vector(op, vd, vs2, src, vm)?,
+            Instruction::PushPop { op, regs, stack_adj } => {
+                let sp = self.register.read(2, self.len)?;
+                let top = match op {
+                    PushPop::Push => sp,
+                    _ => (sp + stack_adj) as u32 as u64,
+                };
+                // the last register of the list goes right below the top
+                for (i, &r) in ZCMP_REGS[..regs].iter().rev().enumerate() {
+                    let address = top.wrapping_sub(self.len as u64 * (i as u64 + 1)) as u32 as u64;
+                    match op {
+                        PushPop::Push => self.write_memory(address, 4, self.register.read(r, self.len)?)?,
                         _ => {
-                            return Err(String::from("No inst on CSR EXCEPTION"));
+                            let data = self.read_memory(address, 4)?;
+                            self.register.write(r, data, self.len)?
                         }
                     }
                 }
-                f3c::CSRRW => {
-                    println!("x5={:?}", self.register.read(5, self.len)?);
-                    println!(
-                        "CSRRW x{:?}, 0x{:x}, x{:?}",
-                        rv32::get_rd(inst),
-                        rv32::get_bits(inst, 31, 20,),
-                        rv32::get_rs1(inst)
-                    );
-                    let csr = rv32::get_bits(inst, 31, 20) as usize;
-                    let t = self.csr.read(csr)?;
-                    self.csr
-                        .write(csr, self.register.read(rv32::get_rs1(inst), self.len)?)?;
-                    self.register.write(rv32::get_rd(inst), t, self.len)?;
-                }
-                f3c::CSRRS => {
-                    let csr = rv32::get_bits(inst, 31, 20) as usize;
-                    let t = self.csr.read(csr)?;
-                    self.csr
-                        .write(csr, t | self.register.read(rv32::get_rs1(inst), self.len)?)?;
-                    self.register.write(rv32::get_rd(inst), t, self.len)?;
-                }
-                f3c::CSRRC => {
-                    let csr = rv32::get_bits(inst, 31, 20) as usize;
-                    let t = self.csr.read(csr)?;
-                    self.csr
-                        .write(csr, t & !self.register.read(rv32::get_rs1(inst), self.len)?)?;
-                    self.register.write(rv32::get_rd(inst), t, self.len)?;
-                }
-                f3c::CSRRWI => {
-                    let csr = rv32::get_bits(inst, 31, 20) as usize;
-                    self.register
-                        .write(rv32::get_rd(inst), self.csr.read(csr)?, self.len)?;
-                    self.csr.write(csr, rv32::get_bits(inst, 19, 15) as u64)?;
-                }
-                f3c::CSRRSI => {
-                    let csr = rv32::get_bits(inst, 31, 20) as usize;
-                    let t = self.csr.read(csr)?;
-                    self.csr
-                        .write(csr, t | rv32::get_bits(inst, 19, 15) as u64)?;
-                    self.register.write(rv32::get_rd(inst), t, self.len)?;
-                }
-                f3c::CSRRCI => {
-                    let csr = rv32::get_bits(inst, 31, 20) as usize;
-                    let t = self.csr.read(csr)?;
-                    self.csr
-                        .write(csr, t & (!rv32::get_bits(inst, 19, 15)) as u64)?;
-                    self.register.write(rv32::get_rd(inst), t, self.len)?;
+                let sp = match op {
+                    PushPop::Push => sp.wrapping_sub(stack_adj) as u32 as u64,
+                    _ => top,
+                };
+                self.register.write(2, sp, self.len)?;
+                if op == PushPop::Popretz {
+                    self.register.write(10, 0, self.len)?;
                 }
-                _ => {
-                    return Err(String::from("No inst on CSR"));
+                if op == PushPop::Popret || op == PushPop::Popretz {
+                    let target = self.register.read(1, self.len)? & !1;
+                    self.sstack_return(target)?;
+                    self.pc = target;
                 }
-            },
-            op::FENCE => {
-                return Ok(());
             }
-            _ => {
-                return Err(String::from("No instruction"));
+            Instruction::Mv01s { r1s, r2s, to_a } => {
+                let (to, from) = match to_a {
+                    true => ([10, 11], [r1s, r2s]),
+                    false => ([r1s, r2s], [10, 11]),
+                };
+                let values = [self.register.read(from[0], self.len)?, self.register.read(from[1], self.len)?];
+                self.register.write(to[0], values[0], self.len)?;
+                self.register.write(to[1], values[1], self.len)?;
+            }
+            Instruction::TableJump { index } => {
+                let entry = (self.csr.read(csr::JVT)? & !csr::JVT_MODE) + index * self.len as u64;
+                // the table is fetched from, like code
+                let walk = self.walk(self.privilege, self.virt)?;
+                let target = self.read_memory_as(entry as u32 as u64, self.len as u64, Kind::Fetch, walk)? & !1;
+                // cm.jalt
+                if index >= 32 {
+                    self.register.write(1, self.pc + 2, self.len)?;
+                    self.sstack_call(self.pc + 2)?;
+                }
+                self.pc = target;
+            }
+            Instruction::Custom { index, inst } => {
+                return self.exec_custom(index, inst);
+            }
+        }
+        Ok(())
+    }
+
+    fn exec_fp(&mut self, op: FpOp, fmt: Format, rd: usize, rs1: usize, rs2: usize, rm: u32) -> Result<(), String> {
+        self.fp_enabled()?;
+        // only what rounds looks at the rounding mode, elsewhere the field selects the operation
+        let rounds = matches!(
+            op,
+            FpOp::Add
+                | FpOp::Sub
+                | FpOp::Mul
+                | FpOp::Div
+                | FpOp::Sqrt
+                | FpOp::Cvt { .. }
+                | FpOp::ToInt { .. }
+                | FpOp::FromInt { .. }
+        );
+        let mut env = Env::new(match rounds {
+            true => self.rounding(rm)?,
+            false => Rounding::Rne,
+        });
+        let (a, b) = (self.fread(fmt, rs1)?, self.fread(fmt, rs2)?);
+        let x = self.register.read(rs1, self.len)?;
+        let bits = 8 * self.len as u32;
+        // result, and whether it goes to an x register
+        let (result, to_x) = match op {
+            FpOp::Add => (fmt.add(a, b, &mut env), false),
+            FpOp::Sub => (fmt.sub(a, b, &mut env), false),
+            FpOp::Mul => (fmt.mul(a, b, &mut env), false),
+            FpOp::Div => (fmt.div(a, b, &mut env), false),
+            FpOp::Sqrt => (fmt.sqrt(a, &mut env), false),
+            FpOp::Sgnj => (fmt.sign_inject(a, b, 0), false),
+            FpOp::Sgnjn => (fmt.sign_inject(a, b, 1), false),
+            FpOp::Sgnjx => (fmt.sign_inject(a, b, 2), false),
+            FpOp::Min => (fmt.min(a, b, &mut env), false),
+            FpOp::Max => (fmt.max(a, b, &mut env), false),
+            FpOp::Cvt { from } => (fmt.convert(from, self.fread(from, rs1)?, &mut env), false),
+            FpOp::Eq => (fmt.eq(a, b, &mut env) as u64, true),
+            FpOp::Lt => (fmt.lt(a, b, &mut env) as u64, true),
+            FpOp::Le => (fmt.le(a, b, &mut env) as u64, true),
+            FpOp::ToInt { signed } => (fmt.to_int(a, signed, 32, &mut env), true),
+            FpOp::FromInt { signed } => (fmt.from_int(x, signed, 32, &mut env), false),
+            // the bits as held, sign extended
+            FpOp::MoveToInt => {
+                let w = 64 - fmt.width();
+                (((self.fregister.read(rs1)? << w) as i64 >> w) as u64, true)
+            }
+            FpOp::Class => (fmt.classify(a), true),
+            FpOp::MoveFromInt => (x, false),
+        };
+        match to_x {
+            true => self.register.write(rd, result & (u64::MAX >> (64 - bits)), self.len)?,
+            false => self.fwrite(fmt, rd, result)?,
+        }
+        self.accrue(env.flags)
+    }
+
+    /// Pushes `ret` on the shadow stack for a call.
+    fn sstack_call(&mut self, ret: u64) -> Result<(), String> {
+        trace!(self.tracer, category::SSTACK, 1, "push {:#x}", ret);
+        self.sstack.push(ret)?;
+        self.sstack_event = Some(shadowstack::Event::Push(ret));
+        Ok(())
+    }
+
+    /// Checks a return to `target` against the shadow stack, stopping on a mismatch.
+    fn sstack_return(&mut self, target: u64) -> Result<(), String> {
+        let ret = self.sstack.ret(target)?;
+        self.sstack_event = Some(shadowstack::Event::Return(target, ret));
+        match ret {
+            Return::Match => {
+                trace!(self.tracer, category::SSTACK, 1, "match, ret to {:#x}", target);
+            }
+            Return::Resync(skipped) => {
+                trace!(
+                    self.tracer,
+                    category::SSTACK,
+                    1,
+                    "resync, ret to {:#x}, skipped {} frames",
+                    target,
+                    skipped
+                );
+            }
+            Return::Mismatch(sv) => {
+                trace!(self.tracer, category::SSTACK, 1, "mismatch, ret to {:#x}, expected {:#x}", target, sv);
+                self.violation = Some(Violation {
+                    target,
+                    expected: sv,
+                });
+                return Err(format!("@@@ shadow stack mismatch! @@@\ntrying to ret to 0x{:x}, however, shadow stack value is {:#x}",target,sv));
+            }
+        }
+        Ok(())
+    }
+
+    /// How `privilege`, in a virtual machine if `virt`, translates addresses: through satp, or
+    /// vsatp and hgatp, or not at all.
+    fn walk(&mut self, privilege: u8, virt: bool) -> Result<Option<Walk>, String> {
+        if privilege == privilege::MACHINE {
+            return Ok(None);
+        }
+        let mstatus = self.csr.read(csr::MSTATUS)?;
+        let (satp, status, hgatp) = match virt {
+            true => (self.csr.read(csr::VSATP)?, self.csr.read(csr::VSSTATUS)?, Some(self.csr.read(csr::HGATP)?)),
+            false => (self.csr.read(csr::SATP)?, mstatus, None),
+        };
+        if satp & paging::MODE_SV32 == 0 && !virt {
+            return Ok(None);
+        }
+        Ok(Some(Walk {
+            satp,
+            hgatp,
+            user: privilege == privilege::USER,
+            sum: status & csr::MSTATUS_SUM != 0,
+            mxr: mstatus & csr::MSTATUS_MXR != 0,
+            vmxr: virt && status & csr::MSTATUS_MXR != 0,
+            hlvx: false,
+        }))
+    }
+    /// How loads and stores translate: as the current mode, or in M mode with mstatus.MPRV set
+    /// as mstatus.MPP, and mstatush.MPV, say.
+    fn data_walk(&mut self) -> Result<Option<Walk>, String> {
+        let mstatus = self.csr.read(csr::MSTATUS)?;
+        if self.privilege == privilege::MACHINE && mstatus & csr::MSTATUS_MPRV != 0 {
+            let mpp = ((mstatus & csr::MSTATUS_MPP) >> 11) as u8;
+            let mpv = self.isa.h && mpp != privilege::MACHINE && self.csr.read(csr::MSTATUSH)? & csr::MSTATUSH_MPV != 0;
+            return self.walk(mpp, mpv);
+        }
+        self.walk(self.privilege, self.virt)
+    }
+    /// How hlv and hsv translate: as VS mode would, or VU mode if hstatus.SPVP is clear.
+    fn guest_walk(&mut self, hlvx: bool) -> Result<Option<Walk>, String> {
+        let privilege = match self.csr.read(csr::HSTATUS)? & csr::HSTATUS_SPVP {
+            0 => privilege::USER,
+            _ => privilege::SUPERVISOR,
+        };
+        Ok(self.walk(privilege, true)?.map(|w| Walk { hlvx, ..w }))
+    }
+    /// Physical address of the `n` bytes at virtual address `va` for a `kind` access through
+    /// `walk`, raising a page fault, or an access fault if there is no memory there.
+    fn translate_address(&mut self, va: u64, kind: Kind, n: u64, walk: Option<Walk>) -> Result<u64, String> {
+        let walk = match walk {
+            Some(walk) => walk,
+            None if self.mmu.contains(va, n) => return Ok(va),
+            None => return Err(self.raise(Fault::Access.cause(kind), va, 0, false)),
+        };
+        let fault = match paging::translate(&self.mmu, va, kind, &walk) {
+            Ok(pa) if self.mmu.contains(pa, n) => return Ok(pa),
+            Ok(_) => Fault::Access,
+            Err(fault) => fault,
+        };
+        let gpa = match fault {
+            Fault::GuestPage { gpa } => gpa,
+            _ => 0,
+        };
+        Err(self.raise(fault.cause(kind), va, gpa, walk.hgatp.is_some()))
+    }
+    /// Physical addresses of the `n` bytes at `va`: the first `split` of them are on one page,
+    /// the rest on the next.
+    fn translate_span(&mut self, va: u64, n: u64, kind: Kind, walk: Option<Walk>) -> Result<([u64; 2], u64), String> {
+        let split = n.min(paging::PAGE_SIZE - (va & (paging::PAGE_SIZE - 1)));
+        let first = self.translate_address(va, kind, split, walk)?;
+        let second = match split < n {
+            true => self.translate_address((va + split) as u32 as u64, kind, n - split, walk)?,
+            false => 0,
+        };
+        Ok(([first, second], split))
+    }
+    /// Loads `n` bytes from `va`.
+    pub(crate) fn read_memory(&mut self, va: u64, n: u64) -> Result<u64, String> {
+        let walk = self.data_walk()?;
+        self.read_memory_as(va, n, Kind::Load, walk)
+    }
+    /// Reads `n` bytes from `va` for a `kind` access through `walk`.
+    fn read_memory_as(&mut self, va: u64, n: u64, kind: Kind, walk: Option<Walk>) -> Result<u64, String> {
+        if walk.is_none() {
+            let pa = self.translate_address(va, kind, n, None)?;
+            return Ok(self.mmu.read_nbytes(pa, n));
+        }
+        let ([first, second], split) = self.translate_span(va, n, kind, walk)?;
+        let data = self.mmu.read_nbytes(first, split);
+        Ok(match split < n {
+            true => data | self.mmu.read_nbytes(second, n - split) << (8 * split),
+            false => data,
+        })
+    }
+    /// Stores the low `n` bytes of `data` to `va`.
+    pub(crate) fn write_memory(&mut self, va: u64, n: u64, data: u64) -> Result<(), String> {
+        let walk = self.data_walk()?;
+        self.write_memory_as(va, n, data, walk)
+    }
+    /// Stores the low `n` bytes of `data` to `va` through `walk`, once both pages it may span
+    /// are known to be writable.
+    fn write_memory_as(&mut self, va: u64, n: u64, data: u64, walk: Option<Walk>) -> Result<(), String> {
+        let ([first, second], split) = match walk {
+            Some(_) => self.translate_span(va, n, Kind::Store, walk)?,
+            None => ([self.translate_address(va, Kind::Store, n, None)?, 0], n),
+        };
+        self.write_nbytes(first, split, data);
+        if split < n {
+            self.write_nbytes(second, n - split, data >> (8 * split));
+        }
+        Ok(())
+    }
+    fn write_nbytes(&mut self, pa: u64, n: u64, data: u64) {
+        match n {
+            1 => self.mmu.write_byte(pa, data as u8),
+            2 => self.mmu.write_2byte(pa, data as u16),
+            4 => self.mmu.write_4byte(pa, data as u32),
+            8 => self.mmu.write_8byte(pa, data),
+            _ => (0..n).for_each(|i| self.mmu.write_byte(pa + i, (data >> (8 * i)) as u8)),
+        }
+    }
+
+    /// Checks a supervisor instruction may execute. It raises an illegal-instruction exception in
+    /// U mode, and in S mode while mstatus has `trap` set; in VU mode, or VS mode while hstatus
+    /// has `vtrap` set, a virtual-instruction exception.
+    fn supervisor_only(&mut self, trap: u64, vtrap: u64) -> Result<(), String> {
+        let (mstatus, hstatus) = (self.csr.read(csr::MSTATUS)?, self.csr.read(csr::HSTATUS)?);
+        match (self.privilege, self.virt) {
+            (privilege::USER, false) => Err(self.illegal_instruction()),
+            (privilege::USER, true) => Err(self.virtual_instruction()),
+            (privilege::SUPERVISOR, true) if hstatus & vtrap != 0 => Err(self.virtual_instruction()),
+            (privilege::SUPERVISOR, false) if mstatus & trap != 0 => Err(self.illegal_instruction()),
+            _ => Ok(()),
+        }
+    }
+    /// Checks a hypervisor instruction may execute: in M and HS mode, in U mode only if `user`,
+    /// otherwise raising an illegal-instruction exception, and in a virtual machine never,
+    /// raising a virtual-instruction exception.
+    fn hypervisor_only(&mut self, user: bool) -> Result<(), String> {
+        match (self.privilege, self.virt) {
+            (_, true) => Err(self.virtual_instruction()),
+            (privilege::USER, false) if !user => Err(self.illegal_instruction()),
+            _ => Ok(()),
+        }
+    }
+    /// Checks CSR `n` may be accessed in the current mode, and returns the CSR accessed, which in
+    /// VS mode is the VS copy of an S mode one. HS mode has the hypervisor CSRs too. A CSR of a
+    /// higher privilege raises an illegal-instruction exception, but a virtual-instruction one in
+    /// a virtual machine if HS mode could access it; so does satp with hstatus.VTVM set in VS
+    /// mode.
+    fn csr_access(&mut self, n: usize) -> Result<usize, String> {
+        let required = ((n >> 8) & 0b11) as u8;
+        if required == privilege::HYPERVISOR && !self.isa.h {
+            return Err(self.illegal_instruction());
+        }
+        let level = match (self.privilege, self.virt) {
+            (privilege::SUPERVISOR, false) => privilege::HYPERVISOR,
+            (privilege, _) => privilege,
+        };
+        if required > level {
+            return match self.virt && required <= privilege::HYPERVISOR {
+                true => Err(self.virtual_instruction()),
+                false => Err(self.illegal_instruction()),
+            };
+        }
+        let (mstatus, hstatus) = (self.csr.read(csr::MSTATUS)?, self.csr.read(csr::HSTATUS)?);
+        match n {
+            csr::SATP if self.virt && hstatus & csr::HSTATUS_VTVM != 0 => Err(self.virtual_instruction()),
+            csr::SATP | csr::HGATP if level == privilege::HYPERVISOR && mstatus & csr::MSTATUS_TVM != 0 => {
+                Err(self.illegal_instruction())
+            }
+            csr::SSTATUS | csr::SIE | csr::STVEC | csr::SSCRATCH | csr::SEPC | csr::SCAUSE | csr::STVAL
+            | csr::SIP | csr::SATP
+                if self.virt =>
+            {
+                Ok(n + 0x100)
+            }
+            _ => Ok(n),
+        }
+    }
+
+    /// Reads 16 bits of entropy from the Zkr seed CSR, which must be written as well, and below M
+    /// mode be allowed by mseccfg; any other access is illegal. What is written is ignored.
+    fn read_seed(&mut self, op: CsrOp, rs1: usize) -> Result<u64, String> {
+        let read_only = matches!(op, CsrOp::Rs | CsrOp::Rc | CsrOp::Rsi | CsrOp::Rci) && rs1 == 0;
+        let allowed = self.isa.zkr && !read_only && match self.privilege {
+            privilege::MACHINE => true,
+            privilege::SUPERVISOR => self.csr.read(csr::MSECCFG)? & csr::MSECCFG_SSEED != 0,
+            _ => self.csr.read(csr::MSECCFG)? & csr::MSECCFG_USEED != 0,
+        };
+        if !allowed {
+            return Err(self.illegal_instruction());
+        }
+        // OPST is ES16, entropy is always ready
+        Ok(0b10 << 30 | self.entropy.draw()? as u64)
+    }
+    /// Floating-point instructions and CSRs are illegal without F, and while mstatus.FS is off,
+    /// or in a virtual machine vsstatus.FS. With Zfinx there is no FP state to switch off.
+    fn fp_enabled(&mut self) -> Result<(), String> {
+        if !self.isa.f {
+            return Err(self.illegal_instruction());
+        }
+        if self.isa.zfinx {
+            return Ok(());
+        }
+        match self.status_enabled(csr::MSTATUS_FS)? {
+            false => Err(self.illegal_instruction()),
+            true => Ok(()),
+        }
+    }
+    /// Marks the floating-point state dirty in mstatus.FS, unless it already is.
+    fn fp_dirty(&mut self) -> Result<(), String> {
+        if self.isa.zfinx {
+            return Ok(());
+        }
+        self.status_dirty(csr::MSTATUS_FS)
+    }
+    /// Whether the state of an extension is on in mstatus field `field`, and in vsstatus too in a
+    /// virtual machine.
+    fn status_enabled(&mut self, field: u64) -> Result<bool, String> {
+        let vsstatus = match self.virt {
+            true => self.csr.read(csr::VSSTATUS)?,
+            false => field,
+        };
+        Ok(self.csr.read(csr::MSTATUS)? & field != 0 && vsstatus & field != 0)
+    }
+    /// Marks the state of an extension dirty in mstatus field `field`, and in vsstatus too in a
+    /// virtual machine.
+    fn status_dirty(&mut self, field: u64) -> Result<(), String> {
+        for &status in [csr::MSTATUS, csr::VSSTATUS][..1 + self.virt as usize].iter() {
+            let value = self.csr.read(status)?;
+            if value & field != field {
+                self.csr.write(status, value | field)?;
+            }
+        }
+        Ok(())
+    }
+    /// Rounding mode of an instruction's `rm` field, frm if it is 7. A reserved mode in either
+    /// makes the instruction illegal.
+    fn rounding(&mut self, rm: u32) -> Result<Rounding, String> {
+        let rm = match rm {
+            7 => self.csr.read(csr::FRM)? as u32,
+            _ => rm,
+        };
+        match Rounding::from_bits(rm) {
+            Some(rounding) => Ok(rounding),
+            None => Err(self.illegal_instruction()),
+        }
+    }
+    /// Value of `fmt` in f register `n`, the canonical NaN if it is not NaN-boxed. With Zfinx
+    /// it is x register `n`, whose upper bits are ignored, or the pair from `n` if wider.
+    fn fread(&self, fmt: Format, n: usize) -> Result<u64, String> {
+        if !self.isa.zfinx {
+            return Ok(fmt.unbox(self.fregister.read(n)?));
+        }
+        let bits = 8 * self.len as u32;
+        let x = self.register.read(n, self.len)?;
+        Ok(match fmt.width() > bits {
+            // the pair from x0 reads as zero
+            true if n == 0 => 0,
+            true => x | self.register.read(n + 1, self.len)? << bits,
+            false => x & (u64::MAX >> (64 - fmt.width())),
+        })
+    }
+    /// Writes `fmt` value `value` NaN-boxed to f register `n`, or with Zfinx sign extended to x
+    /// register `n`, or split low half first over the pair from `n` if wider.
+    fn fwrite(&mut self, fmt: Format, n: usize, value: u64) -> Result<(), String> {
+        if !self.isa.zfinx {
+            self.fregister.write(n, fmt.nan_box(value))?;
+            return self.fp_dirty();
+        }
+        let bits = 8 * self.len as u32;
+        let mask = u64::MAX >> (64 - bits);
+        if fmt.width() <= bits {
+            let w = 64 - fmt.width();
+            return self.register.write(n, ((value << w) as i64 >> w) as u64 & mask, self.len);
+        }
+        // writes to the pair from x0 are dropped
+        if n != 0 {
+            self.register.write(n, value & mask, self.len)?;
+            self.register.write(n + 1, value >> bits, self.len)?;
+        }
+        Ok(())
+    }
+    /// Vector instructions and CSRs are illegal while mstatus.VS is off.
+    fn vector_enabled(&mut self) -> Result<VectorConfig, String> {
+        let config = match self.isa.vector {
+            Some(config) => config,
+            None => return Err(self.illegal_instruction()),
+        };
+        match self.status_enabled(csr::MSTATUS_VS)? {
+            false => Err(self.illegal_instruction()),
+            true => Ok(config),
+        }
+    }
+    /// Raises an illegal-instruction exception for a vector instruction the vector unit
+    /// rejected, such as a misaligned register group, tracing why.
+    fn vector_illegal(&mut self, reason: String) -> String {
+        trace!(self.tracer, category::DECODE, 1, "illegal: {}", reason);
+        self.illegal_instruction()
+    }
+    /// Marks the vector state dirty in mstatus.VS, unless it already is.
+    fn vector_dirty(&mut self) -> Result<(), String> {
+        self.status_dirty(csr::MSTATUS_VS)
+    }
+    /// The vector CSRs as a vector instruction sees them. Unless `any_vtype`, the instruction is
+    /// illegal while vtype.vill is set.
+    fn vector_context(&mut self, any_vtype: bool) -> Result<Context, String> {
+        let config = self.vector_enabled()?;
+        let xlen = 8 * self.len as u32;
+        let vtype = Vtype::parse(self.csr.read(csr::VTYPE)?, &config);
+        if vtype.vill && !any_vtype {
+            return Err(self.illegal_instruction());
+        }
+        Ok(Context {
+            config,
+            vtype,
+            vl: self.csr.read(csr::VL)?,
+            vstart: self.csr.read(csr::VSTART)?,
+            vxrm: self.csr.read(csr::VXRM)? as u32,
+            frm: Rounding::from_bits(self.csr.read(csr::FRM)? as u32),
+            xlen,
+        })
+    }
+    /// vsetvli, vsetivli and vsetvl. An AVL of x0 asks for VLMAX, or keeps vl if rd is x0 too.
+    fn exec_vset(&mut self, rd: usize, avl: VSrc, vtype: VSrc) -> Result<(), String> {
+        let config = self.vector_enabled()?;
+        let xlen = 8 * self.len as u32;
+        let bits = match vtype {
+            VSrc::X(n) => self.register.read(n, self.len)?,
+            VSrc::Imm(x) => x,
+            _ => return Err(String::from("Invalid vtype operand")),
+        };
+        let vtype = Vtype::parse(bits, &config);
+        let vlmax = vtype.vlmax(config.vlen);
+        let vl = match avl {
+            VSrc::X(0) if rd != 0 => vlmax,
+            VSrc::X(0) => self.csr.read(csr::VL)?.min(vlmax),
+            VSrc::X(n) => self.register.read(n, self.len)?.min(vlmax),
+            VSrc::Imm(x) => x.min(vlmax),
+            _ => return Err(String::from("Invalid AVL operand")),
+        };
+        let vl = if vtype.vill { 0 } else { vl };
+        self.csr.write(csr::VTYPE, vtype.bits(xlen))?;
+        self.csr.write(csr::VL, vl)?;
+        self.csr.write(csr::VSTART, 0)?;
+        self.vector_dirty()?;
+        self.register.write(rd, vl, self.len)
+    }
+    /// A vector load or store; `vd` is the data register, vs3 for stores.
+    #[allow(clippy::too_many_arguments)]
+    fn exec_vmem(
+        &mut self,
+        store: bool,
+        mode: VMode,
+        eew: u32,
+        nf: u32,
+        vd: usize,
+        rs1: usize,
+        rs2: usize,
+        vm: bool,
+    ) -> Result<(), String> {
+        let ctx = self.vector_context(mode == VMode::Whole)?;
+        let walk = self.data_walk()?;
+        let base = self.register.read(rs1, self.len)?;
+        let stride = self.register.read(rs2, self.len)?;
+        // taken out while the access runs, since the memory accesses need the whole cpu
+        let mut v = std::mem::replace(&mut self.vregister, VRegister::new(0));
+        let mut fault = None;
+        let result = vector::access(
+            &mut v,
+            &mut |i, addr, n, data| {
+                let result = match data {
+                    Some(x) => self.write_memory_as(addr, n, x, walk).map(|_| 0),
+                    None => self.read_memory_as(addr, n, Kind::Load, walk),
+                };
+                if result.is_err() {
+                    fault = Some(i);
+                }
+                result
+            },
+            store,
+            mode,
+            eew,
+            nf,
+            vd,
+            base,
+            stride,
+            rs2,
+            vm,
+            &ctx,
+        );
+        self.vregister = v;
+        let effects = match (result, fault) {
+            (Ok(effects), _) => effects,
+            // the trap resumes at the faulting element
+            (Err(e), Some(i)) => {
+                self.csr.write(csr::VSTART, i)?;
+                return Err(e);
             }
+            (Err(e), None) => return Err(self.vector_illegal(e)),
+        };
+        if let Some(vl) = effects.vl {
+            // the fault past the first element is not taken
+            self.exception = None;
+            self.csr.write(csr::VL, vl)?;
+        }
+        if ctx.vstart != 0 {
+            self.csr.write(csr::VSTART, 0)?;
+        }
+        match store {
+            true => Ok(()),
+            false => self.vector_dirty(),
         }
-        return Ok(());
+    }
+    /// An OP-V arithmetic instruction. x operands are sign extended, f operands are of SEW.
+    fn exec_vector(&mut self, op: VOp, vd: usize, vs2: usize, src: VSrc, vm: bool) -> Result<(), String> {
+        let ctx = self.vector_context(matches!(op, VOp::MvWhole(_)))?;
+        if vector::is_float(op) {
+            self.fp_enabled()?;
+        }
+        let fmt = match ctx.vtype.sew {
+            64 => F64,
+            32 => F32,
+            _ => F16,
+        };
+        let w = 64 - ctx.xlen;
+        let operand = match src {
+            VSrc::V(n) => Operand::V(n),
+            VSrc::X(n) => Operand::Scalar(((self.register.read(n, self.len)? << w) as i64 >> w) as u64),
+            VSrc::F(n) => Operand::Scalar(self.fread(fmt, n)?),
+            VSrc::Imm(x) => Operand::Scalar(x),
+        };
+        let effects = match vector::exec(&mut self.vregister, op, vd, vs2, operand, vm, &ctx) {
+            Ok(effects) => effects,
+            Err(e) => return Err(self.vector_illegal(e)),
+        };
+        match effects.scalar {
+            Some(x) if op == VOp::FmvFS => self.fwrite(fmt, vd, x)?,
+            Some(x) => self.register.write(vd, x & (u64::MAX >> w), self.len)?,
+            None => self.vector_dirty()?,
+        }
+        self.accrue(effects.fflags)?;
+        if effects.vxsat {
+            self.csr.write(csr::VXSAT, 1)?;
+        }
+        if ctx.vstart != 0 {
+            self.csr.write(csr::VSTART, 0)?;
+        }
+        Ok(())
+    }
+    /// Accrues exception flags in fflags.
+    fn accrue(&mut self, flags: u32) -> Result<(), String> {
+        if flags == 0 {
+            return Ok(());
+        }
+        let fflags = self.csr.read(csr::FFLAGS)?;
+        self.csr.write(csr::FFLAGS, fflags | flags as u64)?;
+        self.fp_dirty()
     }
 }
 
-fn parse_inst_length(inst: u64) -> u64 {
+/// mstatus or vsstatus on a trap to S mode from `privilege`: SPP = privilege, SPIE = SIE, SIE = 0.
+fn s_trap(status: u64, privilege: u8) -> u64 {
+    let sie = (status & csr::MSTATUS_SIE) << 4;
+    let status = status & !(csr::MSTATUS_SPP | csr::MSTATUS_SPIE | csr::MSTATUS_SIE);
+    status | ((privilege as u64 & 1) << 8) | sie
+}
+
+/// A load of `width` into `rd` from `imm(rs1)` with major opcode `opcode`, LOAD or LOAD-FP, as a
+/// 32-bit instruction.
+fn load(opcode: u32, imm: Bits, rs1: Bits, width: u64, rd: Bits) -> u32 {
+    bitcat!(
+        imm,
+        rs1,
+        Bits::new(width, 3),
+        rd,
+        Bits::new(opcode as u64, 5),
+        Bits::new(0b11, 2)
+    )
+    .to_u32()
+}
+
+/// A store of `width` from `rs2` to `imm(rs1)` with major opcode `opcode`, STORE or STORE-FP, as
+/// a 32-bit instruction.
+fn store(opcode: u32, imm: Bits, rs2: Bits, rs1: Bits, width: u64) -> u32 {
+    bitcat!(
+        imm.cut(11, 5),
+        rs2,
+        rs1,
+        Bits::new(width, 3),
+        imm.cut(4, 0),
+        Bits::new(opcode as u64, 5),
+        Bits::new(0b11, 2)
+    )
+    .to_u32()
+}
+
+pub fn parse_inst_length(inst: u64) -> u64 {
     if inst & ((1 << 7) - 1) == 0b0111111 {
         //64bit
         8
@@ -908,7 +2555,629 @@ fn parse_inst_length(inst: u64) -> u64 {
     }
 }
 
-mod rv32 {
+#[test]
+fn test_trace() {
+    use crate::trace::SharedBuf;
+    // li a0, 5; sw a0, 16(zero); csrw mscratch, a0
+    let mut mem = Vec::new();
+    for inst in [0x00500513u32, 0x00a02823, 0x34051073].iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(32, 0);
+    let mut cpu = Cpu::with_memory(mem);
+    let buf = SharedBuf::default();
+    let categories = category::ALL & !category::DECODE;
+    cpu.set_tracer(Tracer::new(categories, 1, Box::new(buf.clone())).window(1, None));
+    for _ in 0..3 {
+        cpu.step().unwrap();
+    }
+    let out = String::from_utf8(buf.0.borrow().clone()).unwrap();
+    assert!(!out.contains("li a0, 5"));
+    assert!(out.contains("       1 fetch  0x00000004: 00a02823  sw a0, 16(zero)\n"));
+    assert!(out.contains("       1 mem    store 0x10 [4] = 0x5\n"));
+    assert!(out.contains("       2 csr    mscratch <- 0x5\n"));
+}
+
+#[test]
+fn test_self_modifying() {
+    // li a0, 1; sw a1, 0(zero); j -8
+    let mut mem = Vec::new();
+    for inst in [0x00100513u32, 0x00b02023, 0xff9ff06f].iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    let mut cpu = Cpu::with_memory(mem);
+    // li a0, 2
+    cpu.register().write(11, 0x00200513, 4).unwrap();
+    for _ in 0..4 {
+        cpu.step().unwrap();
+    }
+    assert!(cpu.register().read(10, 4) == Ok(2));
+    // code written by a debugger is decoded again too
+    cpu.mmu().poke(0, &0x00300513u32.to_le_bytes());
+    cpu.set_pc(0);
+    cpu.step().unwrap();
+    assert!(cpu.register().read(10, 4) == Ok(3));
+}
+
+#[test]
+fn test_run_blocks() {
+    // sw a1, 8(zero); addi a0, a0, 1; addi a0, a0, 16, overwritten by the store
+    let mut mem = Vec::new();
+    for inst in [0x00b02423u32, 0x00150513, 0x01050513].iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(32, 0);
+    let mut cpu = Cpu::with_memory(mem);
+    cpu.execute().unwrap();
+    let report = cpu.report(None).unwrap();
+    assert!(report.pc == 8 && cpu.register().read(10, 4) == Ok(1));
+    let stats = cpu.block_stats();
+    // the store ends its block, and what follows is translated again
+    assert!(stats.translated == 2 && stats.invalidated == 1 && stats.instructions == 2);
+}
+
+#[test]
+fn test_float() {
+    // li t0, 0x2000; csrs mstatus, t0 (FS initial); fa0 = 3.0; fa1 = 1.0; fa2 = fa1 / fa0;
+    // fa3 = fa2 as double; fa4 = fa3 * fa3 + fa3; fsd fa4, 256(zero); fld fa5, 256(zero);
+    // feq.d a2, fa4, fa5; fcvt.w.s a3, fa0, rtz; fclass.s a4, fa1; fmv.x.w a5, fa2;
+    // csrr a6, fflags
+    let program = [
+        0x000022b7u32, 0x3002a073, 0x00300513, 0xd0057553, 0x00100593, 0xd005f5d3, 0x18a5f653,
+        0x420606d3, 0x6ad6f743, 0x10e03027, 0x10003787, 0xa2f72653, 0xc00516d3, 0xe0059753,
+        0xe00607d3, 0x00102873,
+    ];
+    let mut mem = Vec::new();
+    for inst in program.iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(0x200, 0);
+    let mut cpu = Cpu::with_memory(mem.clone());
+    for _ in 0..program.len() {
+        cpu.step().unwrap();
+    }
+    assert!(cpu.fregister().read(12) == Ok(0xffffffff3eaaaaab));
+    assert!(cpu.fregister().read(14) == Ok(0x3fdc71c72e38e390));
+    let x: Vec<u64> = (12..17).map(|n| cpu.register().read(n, 4).unwrap()).collect();
+    assert!(x == [1, 3, 1 << 6, 0x3eaaaaab, 1]);
+    // the division was inexact, and FP state was written
+    assert!(cpu.csr().read(0x300) == Ok(0x80006000));
+    assert!(cpu.retired().rd == Some((16, 1)));
+    // without enabling FS first, the first FP instruction is illegal
+    let trap = |cpu: &mut Cpu| [csr::MCAUSE, csr::MEPC, csr::MTVAL].map(|n| cpu.csr().read(n).unwrap());
+    let mut cpu = Cpu::with_memory(mem.clone());
+    cpu.set_pc(8);
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 12, 0xd0057553]);
+    // so are a reserved dynamic rounding mode in frm, and a reserved rm in the instruction
+    let mut cpu = Cpu::with_memory(mem.clone());
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    cpu.csr().write(csr::FRM, 5).unwrap();
+    cpu.set_pc(12);
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 12, 0xd0057553]);
+    mem[12..16].copy_from_slice(&0xd0055553u32.to_le_bytes());
+    let mut cpu = Cpu::with_memory(mem);
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    cpu.set_pc(12);
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 12, 0xd0055553]);
+}
+
+#[test]
+fn test_zfinx() {
+    // li a0, 3; li a1, 1; fcvt.s.w a0, a0; fcvt.s.w a1, a1; fdiv.s a2, a1, a0;
+    // fcvt.d.s a4, a2; fadd.d a4, a4, a4; fcvt.h.s a6, a2; fneg.h a7, a6; fmv.x.w a0, a0
+    let program = [
+        0x00300513u32, 0x00100593, 0xd0057553, 0xd005f5d3, 0x18a5f653, 0x42060753, 0x02e77753,
+        0x44067853, 0x250818d3, 0xe0050553,
+    ];
+    let mut mem = Vec::new();
+    for inst in program.iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(0x100, 0);
+    let mut cpu = Cpu::with_memory(mem);
+    cpu.set_isa(Isa {
+        zfinx: true,
+        ..Isa::default()
+    });
+    // no mstatus.FS to turn on first
+    for _ in 0..program.len() - 1 {
+        cpu.step().unwrap();
+    }
+    let x: Vec<u64> = (12..18).map(|n| cpu.register().read(n, 4).unwrap()).collect();
+    // the double in the a4/a5 pair, low half first, and halves sign extended
+    assert!(x == [0x3eaaaaab, 0, 0x60000000, 0x3fe55555, 0x3555, 0xffffb555]);
+    assert!(cpu.retired().rd == Some((17, 0xffffb555)) && cpu.retired().frd.is_none());
+    assert!(cpu.fregister().raw() == [0; 32] && cpu.csr().read(0x300) == Ok(0));
+    assert!(cpu.csr().read(1) == Ok(1));
+    // fmv.x.w does not exist
+    cpu.step().unwrap();
+    let trap = [csr::MCAUSE, csr::MEPC, csr::MTVAL].map(|n| cpu.csr().read(n).unwrap());
+    assert!(trap == [2, 0x24, 0xe0050553] && cpu.register().read(10, 4) == Ok(0x40400000));
+}
+
+#[test]
+fn test_bitmanip() {
+    // lui a1, 0x12345; rev8 a0, a1; sh2add a2, a0, a1; binvi a3, zero, 31; clzw a0, a1;
+    // rev8 a0, a1 for RV64; bclri a0, a1, 32
+    let program = [0x123455b7u32, 0x6985d513, 0x20b54633, 0x69f01693, 0x6005951b, 0x6b85d513, 0x4a059513];
+    let mut mem = Vec::new();
+    for inst in program.iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(0x40, 0);
+    let mut cpu = Cpu::with_memory(mem);
+    for _ in 0..4 {
+        cpu.step().unwrap();
+    }
+    let x: Vec<u64> = (10..14).map(|n| cpu.register().read(n, 4).unwrap()).collect();
+    assert!(x == [0x00503412, 0x12345000, 0x13752048, 0x80000000]);
+    // clzw, the RV64 rev8 and bit 32 are RV64 only, and illegal
+    let trap = |cpu: &mut Cpu| [csr::MCAUSE, csr::MEPC, csr::MTVAL].map(|n| cpu.csr().read(n).unwrap());
+    for (pc, inst) in [(0x10, 0x6005951b), (0x14, 0x6b85d513), (0x18, 0x4a059513)].iter() {
+        cpu.set_pc(*pc);
+        cpu.step().unwrap();
+        assert!(trap(&mut cpu) == [2, *pc, *inst]);
+    }
+}
+
+#[test]
+fn test_seed() {
+    // csrrw a0, seed, zero; csrrs a0, seed, zero; mret; csrrw a0, seed, zero
+    let program = [0x01501573u32, 0x01502573, 0x30200073, 0x01501573];
+    let mut mem = Vec::new();
+    for inst in program.iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(0x40, 0);
+    let mut cpu = Cpu::with_memory(mem);
+    cpu.set_entropy(Entropy::Deterministic(7));
+    cpu.step().unwrap();
+    let expected = Entropy::Deterministic(7).draw().unwrap() as u64;
+    assert!(cpu.register().read(10, 4) == Ok(0x80000000 | expected));
+    // reading without writing is illegal
+    let trap = |cpu: &mut Cpu| [csr::MCAUSE, csr::MEPC, csr::MTVAL].map(|n| cpu.csr().read(n).unwrap());
+    cpu.csr().write(csr::MTVEC, 0x20).unwrap();
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 4, 0x01502573] && cpu.get_pc() == 0x20);
+    // so is S mode access unless mseccfg.SSEED allows it
+    let mret_to_s = |cpu: &mut Cpu| {
+        cpu.csr().write(csr::MSTATUS, 0x800).unwrap();
+        cpu.csr().write(csr::MEPC, 0xc).unwrap();
+        cpu.set_pc(8);
+        cpu.step().unwrap();
+        assert!(cpu.get_privilege() == privilege::SUPERVISOR);
+        cpu.step().unwrap();
+    };
+    mret_to_s(&mut cpu);
+    assert!(trap(&mut cpu) == [2, 0xc, 0x01501573] && cpu.get_privilege() == privilege::MACHINE);
+    cpu.csr().write(csr::MSECCFG, csr::MSECCFG_SSEED).unwrap();
+    mret_to_s(&mut cpu);
+    assert!(cpu.retired().trap.is_none() && cpu.get_pc() == 0x10);
+    assert!(cpu.register().read(10, 4).unwrap() >> 30 == 0b10);
+}
+
+#[test]
+fn test_vector() {
+    // li t0, 0x200; csrs mstatus, t0 (VS initial); vsetivli a0, 4, e32, m1, ta, ma;
+    // li a1, 0x100; vle32.v v1, (a1); vadd.vv v2, v1, v1; vredsum.vs v3, v2, v4;
+    // vse32.v v2, (a1); vmv.x.s a2, v3; csrr a3, vl
+    let program = [
+        0x20000293u32, 0x3002a073, 0xcd027557, 0x10000593, 0x0205e087, 0x02108157, 0x022221d7,
+        0x0205e127, 0x42302657, 0xc20026f3,
+    ];
+    let mut mem = Vec::new();
+    for inst in program.iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(0x100, 0);
+    for x in 1..5u32 {
+        mem.extend_from_slice(&x.to_le_bytes());
+    }
+    let mut cpu = Cpu::with_memory(mem.clone());
+    for _ in 0..program.len() {
+        cpu.step().unwrap();
+    }
+    let x: Vec<u64> = (10..14).map(|n| cpu.register().read(n, 4).unwrap()).collect();
+    assert!(x == [4, 0x100, 20, 4]);
+    let words: Vec<u64> = (0..4).map(|i| cpu.mmu.peek(0x100 + 4 * i, 4)).collect();
+    assert!(words == [2, 4, 6, 8] && cpu.vregister().get(2, 3, 32) == 8);
+    // the vector state was written
+    assert!(cpu.csr().read(0x300) == Ok(0x80000600) && cpu.csr().read(csr::VLENB) == Ok(16));
+    assert!(cpu.retired().rd == Some((13, 4)));
+    // without enabling VS first, vsetivli is illegal
+    let trap = |cpu: &mut Cpu| [csr::MCAUSE, csr::MEPC, csr::MTVAL].map(|n| cpu.csr().read(n).unwrap());
+    let mut cpu = Cpu::with_memory(mem.clone());
+    cpu.set_pc(8);
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 8, 0xcd027557]);
+    // so are vadd.vv before vtype is set, csrw vl, zero, and vadd.vv v3 on groups of two
+    mem[0x30..0x34].copy_from_slice(&0xc2001073u32.to_le_bytes());
+    mem[0x34..0x38].copy_from_slice(&0xcd127557u32.to_le_bytes());
+    mem[0x38..0x3c].copy_from_slice(&0x021081d7u32.to_le_bytes());
+    let mut cpu = Cpu::with_memory(mem);
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    cpu.set_pc(0x14);
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 0x14, 0x02108157]);
+    cpu.set_pc(0x30);
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 0x30, 0xc2001073]);
+    cpu.set_pc(0x34);
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 0x38, 0x021081d7]);
+    // a load running off the end of memory faults at its third element, where it resumes
+    cpu.set_pc(8);
+    cpu.step().unwrap();
+    cpu.register().write(11, 0x108, 4).unwrap();
+    cpu.set_pc(0x10);
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [5, 0x10, 0x110] && cpu.csr().read(csr::VSTART) == Ok(2));
+    assert!(cpu.vregister().get(1, 1, 32) == 4);
+}
+
+#[test]
+fn test_rv32e() {
+    // li a0, 5; c.li a0, 5; c.li a6, 1; li a6, 1; cm.push {ra, s0-s2}, -32; cm.mvsa01 s2, s1
+    let mut mem = Vec::new();
+    mem.extend_from_slice(&0x00500513u32.to_le_bytes());
+    mem.extend_from_slice(&0x4515u16.to_le_bytes());
+    mem.extend_from_slice(&0x4805u16.to_le_bytes());
+    mem.extend_from_slice(&0x00100813u32.to_le_bytes());
+    mem.extend_from_slice(&0xb872u16.to_le_bytes());
+    mem.extend_from_slice(&0xad26u16.to_le_bytes());
+    mem.resize(0x40, 0);
+    let mut cpu = Cpu::with_memory(mem);
+    cpu.set_isa(Isa {
+        e: true,
+        zcmp: true,
+        ..Isa::default()
+    });
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert!(cpu.register().read(10, 4) == Ok(5) && cpu.register().read(16, 4).is_err());
+    // E and not I in misa
+    assert!(cpu.csr().read(csr::MISA) == Ok(0x4020003c));
+    // x16 does not exist, compressed or not, and naming it is illegal
+    let trap = |cpu: &mut Cpu| [csr::MCAUSE, csr::MEPC, csr::MTVAL].map(|n| cpu.csr().read(n).unwrap());
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 6, 0x4805] && cpu.get_pc() == 0);
+    cpu.set_pc(8);
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 8, 0x00100813] && cpu.retired().rd.is_none());
+    // nor do the Zcmp register lists and moves reaching s2, which is x18
+    cpu.register().write(2, 0x40, 4).unwrap();
+    for &(pc, inst) in [(0xc, 0xb872), (0xe, 0xad26)].iter() {
+        cpu.set_pc(pc);
+        cpu.step().unwrap();
+        assert!(trap(&mut cpu) == [2, pc, inst]);
+    }
+}
+
+#[test]
+fn test_zc() {
+    // cm.jalt 32; c.lbu a1, 0(s1); c.sext.b a1; cm.mvsa01 s0, s1, and at 0x40, through the
+    // table at 0x100: cm.push {ra, s0}, -16; c.li s0, 9; cm.popret {ra, s0}, 16; and at 0x1c0
+    // csrr a0, jvt
+    let mut mem = Vec::new();
+    for inst in [0xa082u16, 0x808c, 0x9de5, 0xac26].iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(0x40, 0);
+    for inst in [0xb852u16, 0x4425, 0xbe52].iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(0x180, 0);
+    mem.extend_from_slice(&0x40u32.to_le_bytes());
+    mem.resize(0x200, 0);
+    mem[0x1c0..0x1c4].copy_from_slice(&0x01702573u32.to_le_bytes());
+    let new_cpu = |mem: Vec<u8>| {
+        let mut cpu = Cpu::with_memory(mem);
+        cpu.set_isa(Isa {
+            zcmp: true,
+            zcmt: true,
+            ..Isa::default()
+        });
+        cpu.csr().write(csr::JVT, 0x100).unwrap();
+        for &(n, v) in [(2, 0x200), (8, 7), (10, 5)].iter() {
+            cpu.register().write(n, v, 4).unwrap();
+        }
+        cpu
+    };
+    let mut cpu = new_cpu(mem.clone());
+    cpu.step().unwrap();
+    assert!(cpu.get_pc() == 0x40 && cpu.register().read(1, 4) == Ok(2));
+    cpu.step().unwrap();
+    assert!(cpu.register().read(2, 4) == Ok(0x1f0) && cpu.mmu().read_nbytes(0x1f8, 8) == 7 << 32 | 2);
+    // the saved s0 comes back, and the return matches the shadow stack
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert!(cpu.get_pc() == 2 && cpu.register().read(8, 4) == Ok(7) && cpu.register().read(2, 4) == Ok(0x200));
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert!(cpu.register().read(8, 4) == Ok(5) && cpu.register().read(9, 4) == Ok(0xffffff82));
+    // a return address overwritten on the stack
+    let mut cpu = new_cpu(mem.clone());
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    cpu.mmu().write_4byte(0x1f8, 0x20);
+    cpu.step().unwrap();
+    assert!(cpu.step().unwrap_err().contains("shadow stack mismatch"));
+    // c.fsdsp again without Zcmp and Zcmt, and c.lbu without Zcb and jvt without Zcmt illegal
+    let mut cpu = new_cpu(mem);
+    cpu.set_isa(Isa {
+        zcb: false,
+        ..Isa::default()
+    });
+    assert!(!cpu.is_zcm(0xa082));
+    let trap = |cpu: &mut Cpu| [csr::MCAUSE, csr::MEPC, csr::MTVAL].map(|n| cpu.csr().read(n).unwrap());
+    cpu.set_pc(2);
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 2, 0x808c] && cpu.register().read(11, 4) == Ok(0));
+    cpu.set_pc(0x1c0);
+    cpu.step().unwrap();
+    assert!(trap(&mut cpu) == [2, 0x1c0, 0x01702573] && cpu.register().read(10, 4) == Ok(5));
+}
+
+#[test]
+fn test_hypervisor() {
+    use crate::paging::{pte, MODE_SV32};
+    // mret at 0x0, sret at the HS mode handler at 0x100, and at the M mode one at 0x200
+    // hlv.w a0, (a1); hsv.w a0, (a3). The guest runs lw a0, 16(a1); lw a0, 0(a2); csrr a0,
+    // hstatus from 0x400000, which its page tables map to 0x1000
+    let mut mem = vec![0; 0xa000];
+    let mut put = |address: usize, words: &[u32]| {
+        for (i, w) in words.iter().enumerate() {
+            mem[address + 4 * i..address + 4 * i + 4].copy_from_slice(&w.to_le_bytes());
+        }
+    };
+    put(0, &[0x30200073]);
+    put(0x100, &[0x10200073]);
+    put(0x200, &[0x6805c573, 0x6aa6c073]);
+    put(0x1000, &[0x0105a503, 0x00062503, 0x60002573]);
+    put(0x2010, &[0x1234]);
+    // the G-stage maps guest pages 0 to 2 onto 0x1000 to 0x3000 through the table at 0x8000,
+    // and the VS-stage root at guest 0x2000 the megapage at 0x400000 onto guest 0
+    let leaf = pte::V | pte::R | pte::W | pte::X | pte::A | pte::D;
+    put(0x4000, &[(0x8 << 10 | pte::V) as u32]);
+    put(0x8000, &[(1 << 10 | leaf | pte::U) as u32, (2 << 10 | leaf | pte::U) as u32, (3 << 10 | leaf | pte::U) as u32]);
+    put(0x3004, &[leaf as u32]);
+    let mut cpu = Cpu::with_memory(mem);
+    cpu.set_isa(Isa { h: true, ..Isa::default() });
+    let csrs = [
+        (csr::MSTATUS, 1 << 11),
+        (csr::MSTATUSH, csr::MSTATUSH_MPV),
+        (csr::MEPC, 0x400000),
+        (csr::MTVEC, 0x200),
+        (csr::STVEC, 0x100),
+        (csr::MEDELEG, 1 << 21),
+        (csr::HGATP, MODE_SV32 | 4),
+        (csr::VSATP, MODE_SV32 | 2),
+    ];
+    for &(n, v) in csrs.iter() {
+        cpu.csr().write(n, v).unwrap();
+    }
+    for &(n, v) in [(11, 0x401000), (12, 0x403000), (13, 0x401020)].iter() {
+        cpu.register().write(n, v, 4).unwrap();
+    }
+    // into VS mode, where loads go through both stages
+    cpu.step().unwrap();
+    assert!(cpu.get_pc() == 0x400000 && cpu.get_privilege() == privilege::SUPERVISOR);
+    cpu.step().unwrap();
+    assert!(cpu.register().read(10, 4) == Ok(0x1234));
+    // a guest-page fault, delegated to HS mode
+    cpu.step().unwrap();
+    assert!(cpu.get_pc() == 0x100 && cpu.retired().trap == Some(21) && cpu.retired().rd.is_none());
+    assert!(cpu.csr().read(csr::SEPC) == Ok(0x400004) && cpu.csr().read(csr::STVAL) == Ok(0x403000));
+    assert!(cpu.csr().read(csr::HTVAL) == Ok(0x3000 >> 2));
+    let hstatus = csr::HSTATUS_SPV | csr::HSTATUS_SPVP | csr::HSTATUS_GVA;
+    assert!(cpu.csr().read(csr::HSTATUS) == Ok(hstatus));
+    // back into the guest past the fault, where hstatus is out of reach
+    cpu.csr().write(csr::SEPC, 0x400008).unwrap();
+    cpu.step().unwrap();
+    assert!(cpu.get_pc() == 0x400008 && cpu.get_privilege() == privilege::SUPERVISOR);
+    cpu.step().unwrap();
+    assert!(cpu.get_pc() == 0x200 && cpu.csr().read(csr::MCAUSE) == Ok(22));
+    assert!(cpu.csr().read(csr::MSTATUSH) == Ok(csr::MSTATUSH_MPV) && cpu.get_privilege() == privilege::MACHINE);
+    // M mode loads and stores as the guest would
+    cpu.register().write(11, 0x401010, 4).unwrap();
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert!(cpu.register().read(10, 4) == Ok(0x1234) && cpu.mmu().peek(0x2020, 4) == 0x1234);
+}
+
+#[test]
+fn test_vector_paging() {
+    use crate::paging::{pte, MODE_SV32};
+    // vsetivli a0, 4, e32, m1, ta, ma; vle32.v v1, (a1), in M mode with MPRV, so the load
+    // translates as S mode does: the page at 0x400000 onto 0x3000, and 0x401000 unmapped
+    let mut mem = vec![0; 0x4000];
+    let mut put = |address: usize, words: &[u32]| {
+        for (i, w) in words.iter().enumerate() {
+            mem[address + 4 * i..address + 4 * i + 4].copy_from_slice(&w.to_le_bytes());
+        }
+    };
+    put(0, &[0xcd027557, 0x0205e087]);
+    put(0x1004, &[(0x2 << 10 | pte::V) as u32]);
+    put(0x2000, &[(0x3 << 10 | pte::V | pte::R | pte::W | pte::A | pte::D) as u32]);
+    put(0x3000, &[1, 2, 3, 4]);
+    put(0x3ff8, &[7, 8]);
+    let mut cpu = Cpu::with_memory(mem);
+    let mstatus = csr::MSTATUS_MPRV | 1 << 11 | 1 << 9;
+    for &(n, v) in [(csr::MSTATUS, mstatus), (csr::SATP, MODE_SV32 | 1)].iter() {
+        cpu.csr().write(n, v).unwrap();
+    }
+    cpu.register().write(11, 0x400000, 4).unwrap();
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert!((0..4).all(|i| cpu.vregister().get(1, i, 32) == i + 1));
+    // across the page boundary: a page fault at the third element, after the first two loaded
+    cpu.register().write(11, 0x400ff8, 4).unwrap();
+    cpu.set_pc(4);
+    cpu.step().unwrap();
+    let trap = [csr::MCAUSE, csr::MEPC, csr::MTVAL].map(|n| cpu.csr().read(n).unwrap());
+    assert!(trap == [13, 4, 0x401000] && cpu.csr().read(csr::VSTART) == Ok(2));
+    assert!(cpu.vregister().get(1, 0, 32) == 7 && cpu.vregister().get(1, 1, 32) == 8);
+}
+
+#[test]
+fn test_uncompress_fp() {
+    // c.fld fa0, 248(a1); c.flw fa5, 124(s0); c.fsd fs1, 168(a5); c.fsw fa2, 68(a3); the sp
+    // relative forms; c.jal -2048; c.jal 1234
+    let pairs = [
+        (0x3de8, 0x0f85b507),
+        (0x7c7c, 0x07c42787),
+        (0xb7c4, 0x0a97b427),
+        (0xe2f0, 0x04c6a227),
+        (0x31fe, 0x1f813187),
+        (0x7dfe, 0x0fc12d87),
+        (0xa7fe, 0x1df13427),
+        (0xe3aa, 0x0ca12227),
+        (0x3001, 0x801ff0ef),
+        (0x29c9, 0x4d2000ef),
+    ];
+    for &(c, expanded) in pairs.iter() {
+        assert!(Cpu::uncompress(c) == Ok(expanded), "{:#06x}", c);
+    }
+}
+
+/// Source registers read by `inst`, as (rs1, rs2).
+fn rs_read(inst: u32) -> (Option<usize>, Option<usize>) {
+    let (rs1, rs2) = match rv32::get_op(inst) {
+        op::JALR | op::LD | op::AIMM | op::AIMM32 => (true, false),
+        op::BRANCH | op::STORE | op::AREG | op::AREG32 => (true, true),
+        // vector strided loads and stores take the stride in rs2
+        op::LOAD_FP | op::STORE_FP => (true, vector_mem(inst) && rv32::get_bits(inst, 27, 26) == 0b10),
+        op::OP_V => match rv32::get_funct3(inst) {
+            f3v::OPIVX | f3v::OPMVX => (true, false),
+            f3v::OPCFG => (
+                rv32::get_bits(inst, 31, 30) != 0b11,
+                rv32::get_bits(inst, 31, 25) == 0b1000000,
+            ),
+            _ => (false, false),
+        },
+        op::OP_FP => (
+            matches!(rv32::get_bits(inst, 31, 27), f5fp::CVT_FROM_INT | f5fp::MV_FROM_INT),
+            false,
+        ),
+        op::CSR => match rv32::get_funct3(inst) {
+            f3c::CSRRW | f3c::CSRRS | f3c::CSRRC => (true, false),
+            // hsv, and the fences, whose funct7 is odd unlike that of the returns
+            f3c::HLSV => (true, rv32::get_bits(inst, 25, 25) == 1),
+            f3c::EXCEPT => (rv32::get_bits(inst, 25, 25) == 1, rv32::get_bits(inst, 25, 25) == 1),
+            _ => (false, false),
+        },
+        _ => (false, false),
+    };
+    (
+        Some(rv32::get_rs1(inst)).filter(|_| rs1),
+        Some(rv32::get_rs2(inst)).filter(|_| rs2),
+    )
+}
+
+/// CSR a Zicsr instruction reads; csrrw and csrrwi with rd x0 do not read it.
+fn csr_read(inst: u32) -> Option<usize> {
+    if rv32::get_op(inst) != op::CSR {
+        return None;
+    }
+    let reads = match rv32::get_funct3(inst) {
+        f3c::EXCEPT | f3c::HLSV => false,
+        f3c::CSRRW | f3c::CSRRWI => rv32::get_rd(inst) != 0,
+        _ => true,
+    };
+    Some(rv32::get_bits(inst, 31, 20) as usize).filter(|_| reads)
+}
+
+/// Whether compressed `inst` is one Zcb adds, in encodings RV32C leaves reserved.
+fn zcb(inst: u32) -> bool {
+    match (rv32::get_bits(inst, 1, 0), rv32::get_bits(inst, 15, 13)) {
+        (0, f3co_0::ZCB) => true,
+        (1, f3co_1::MISC_ALU) => rv32::get_bits(inst, 12, 10) == 0b111 && rv32::get_bits(inst, 6, 6) == 1,
+        _ => false,
+    }
+}
+
+/// Whether `inst`, compressed ones expanded, names only the x registers of RV32E.
+fn embedded(inst: u32) -> bool {
+    let (rs1, rs2) = rs_read(inst);
+    [rd_written(inst), rs1, rs2].iter().flatten().all(|&n| n < 16)
+}
+/// Whether a LOAD-FP or STORE-FP instruction is a vector one, by its width.
+fn vector_mem(inst: u32) -> bool {
+    !(1..=4).contains(&rv32::get_funct3(inst))
+}
+/// Destination register written by `inst`, if its format has one other than x0.
+fn rd_written(inst: u32) -> Option<usize> {
+    let writes = match rv32::get_op(inst) {
+        op::LUDI | op::AUIPC | op::JAL | op::JALR | op::LD | op::AIMM | op::AREG => true,
+        op::AIMM32 | op::AREG32 => true,
+        op::CSR => rv32::get_funct3(inst) != f3c::EXCEPT,
+        op::OP_FP => fp_to_int(inst),
+        // vset*, and vmv.x.s, vcpop.m and vfirst.m
+        op::OP_V => match rv32::get_funct3(inst) {
+            f3v::OPCFG => true,
+            f3v::OPMVV => rv32::get_bits(inst, 31, 26) == 0b010000,
+            _ => false,
+        },
+        _ => false,
+    };
+    Some(rv32::get_rd(inst)).filter(|&rd| writes && rd != 0)
+}
+
+/// Floating-point register written by `inst`, if any.
+fn frd_written(inst: u32) -> Option<usize> {
+    let writes = match rv32::get_op(inst) {
+        op::LOAD_FP => !vector_mem(inst),
+        op::MADD | op::MSUB | op::NMSUB | op::NMADD => true,
+        op::OP_FP => !fp_to_int(inst),
+        // vfmv.f.s
+        op::OP_V => rv32::get_funct3(inst) == f3v::OPFVV && rv32::get_bits(inst, 31, 26) == 0b010000,
+        _ => false,
+    };
+    Some(rv32::get_rd(inst)).filter(|_| writes)
+}
+
+/// Whether OP-FP instruction `inst` writes an x register: compares, fcvt to integer, fmv.x and
+/// fclass.
+fn fp_to_int(inst: u32) -> bool {
+    matches!(
+        rv32::get_bits(inst, 31, 27),
+        f5fp::CMP | f5fp::CVT_TO_INT | f5fp::MV_TO_INT_CLASS
+    )
+}
+
+#[test]
+fn test_load() {
+    // lb a0, 0x40(zero); lh a1, 0x40(zero); lhu a2, 0x40(zero); lbu a3, 0x40(zero); c.li a4, -1
+    let program = [0x04000503u32, 0x04001583, 0x04005603, 0x04004683];
+    let mut mem = Vec::new();
+    for inst in program.iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.extend_from_slice(&0x577du16.to_le_bytes());
+    mem.resize(0x40, 0);
+    mem.extend_from_slice(&0x8281u32.to_le_bytes());
+    let mut cpu = Cpu::with_memory(mem);
+    for _ in 0..program.len() {
+        cpu.step().unwrap();
+    }
+    // every width writes rd, sign extended unless unsigned
+    let x: Vec<u64> = (10..14).map(|n| cpu.register().read(n, 4).unwrap()).collect();
+    assert!(x == [0xffffff81, 0xffff8281, 0x8281, 0x81] && cpu.retired().rd == Some((13, 0x81)));
+    // c.li is an addi, not a load, which wrote nothing while lb wrote rs1
+    cpu.step().unwrap();
+    assert!(cpu.register().read(14, 4) == Ok(0xffffffff) && cpu.retired().mem.is_empty());
+}
+
+pub mod rv32 {
     pub fn get_bits(inst: u32, msb: usize, lsb: usize) -> u32 {
         (inst >> lsb) & ((1 << (msb - lsb + 1)) - 1)
     }
@@ -971,16 +3240,96 @@ mod rv32 {
     }
 
     pub fn get_imm_branch(inst: u32) -> u32 {
-        let imm = (get_bits(inst, 31, 31) << 12)
+        (get_bits(inst, 31, 31) << 12)
             + (get_bits(inst, 30, 25) << 5)
             + (get_bits(inst, 7, 7) << 11)
-            + (get_bits(inst, 11, 8) << 1);
-        return imm;
+            + (get_bits(inst, 11, 8) << 1)
     }
+    #[allow(dead_code)]
     pub fn get_imm_ld(inst: u32) -> u32 {
-        return get_bits(inst, 31, 20);
+        get_bits(inst, 31, 20)
     }
     pub fn get_imm_st(inst: u32) -> u32 {
-        return (get_bits(inst, 31, 25) << 5) + get_bits(inst, 11, 7);
+        (get_bits(inst, 31, 25) << 5) + get_bits(inst, 11, 7)
+    }
+}
+
+#[test]
+fn test_access_fault() {
+    // li t0, 0x100; csrw mtvec, t0; lui a1, 0x10; lw a0, 0(a1); sw a0, 0(a1); jr a1
+    let program = [0x10000293u32, 0x30529073, 0x000105b7, 0x0005a503, 0x00a5a023, 0x00058067];
+    let mut mem = Vec::new();
+    for inst in program.iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(0x1fe, 0);
+    // the low half of li a0, 0 at the end of memory
+    mem.extend_from_slice(&0x0513u16.to_le_bytes());
+    let mut cpu = Cpu::with_memory(mem);
+    let trap = |cpu: &mut Cpu| [csr::MCAUSE, csr::MEPC, csr::MTVAL].map(|n| cpu.csr().read(n).unwrap());
+    for _ in 0..4 {
+        cpu.step().unwrap();
+    }
+    // there is no memory at 0x10000 to load from, store to or execute
+    assert!(cpu.get_pc() == 0x100 && trap(&mut cpu) == [5, 0xc, 0x10000]);
+    assert!(cpu.retired().trap == Some(5) && cpu.retired().rd.is_none());
+    cpu.set_pc(0x10);
+    cpu.step().unwrap();
+    assert!(cpu.get_pc() == 0x100 && trap(&mut cpu) == [7, 0x10, 0x10000]);
+    cpu.set_pc(0x14);
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert!(cpu.get_pc() == 0x100 && trap(&mut cpu) == [1, 0x10000, 0x10000]);
+    cpu.set_pc(0x1fe);
+    cpu.step().unwrap();
+    assert!(cpu.get_pc() == 0x100 && trap(&mut cpu) == [1, 0x1fe, 0x1fe]);
+}
+
+#[test]
+fn test_illegal_instruction() {
+    // mret at 0x0 into U mode at 0x10: sret; csrr a0, mstatus; mret
+    let mut mem = vec![0; 0x10];
+    mem[..4].copy_from_slice(&0x30200073u32.to_le_bytes());
+    for inst in [0x10200073u32, 0x30002573, 0x30200073].iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(0x200, 0);
+    let mut cpu = Cpu::with_memory(mem);
+    cpu.csr().write(csr::MTVEC, 0x100).unwrap();
+    // none of them are allowed in U mode, so each traps to M mode with itself in mtval
+    for (pc, inst) in [(0x10, 0x10200073), (0x14, 0x30002573), (0x18, 0x30200073)].iter() {
+        cpu.csr().write(csr::MEPC, *pc).unwrap();
+        cpu.set_pc(0);
+        cpu.step().unwrap();
+        assert!(cpu.get_pc() == *pc && cpu.get_privilege() == privilege::USER);
+        cpu.step().unwrap();
+        assert!(cpu.get_pc() == 0x100 && cpu.get_privilege() == privilege::MACHINE);
+        assert!(cpu.csr().read(csr::MCAUSE) == Ok(2) && cpu.csr().read(csr::MTVAL) == Ok(*inst));
+        assert!(cpu.csr().read(csr::MEPC) == Ok(*pc) && cpu.retired().trap == Some(2));
+    }
+}
+
+#[test]
+fn test_alu_immediate() {
+    // li a0, -8; slti a1, a0, 1; sltiu a2, a0, -1; andi a3, a0, -3; slli a4, a0, 0;
+    // srai a5, a0, 2; li a7, 0x44; lw a6, -4(a7); auipc s2, 0xfffff; auipc s3, 1
+    let program = [
+        0xff800513u32, 0x00152593, 0xfff53613, 0xffd57693, 0x00051713, 0x40255793, 0x04400893, 0xffc8a803,
+        0xfffff917, 0x00001997,
+    ];
+    let mut mem = Vec::new();
+    for inst in program.iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(0x40, 0);
+    mem.extend_from_slice(&0x12345678u32.to_le_bytes());
+    let mut cpu = Cpu::with_memory(mem);
+    for _ in 0..program.len() {
+        cpu.step().unwrap();
     }
+    // immediates are sign extended and slti compares signed
+    let x: Vec<u64> = (10..17).map(|n| cpu.register().read(n, 4).unwrap()).collect();
+    assert!(x == [0xfffffff8, 1, 1, 0xfffffff8, 0xfffffff8, 0xfffffffe, 0x12345678]);
+    // auipc adds the upper immediate to its pc, wrapping
+    assert!(cpu.register().read(18, 4) == Ok(0xfffff020) && cpu.register().read(19, 4) == Ok(0x1024));
 }
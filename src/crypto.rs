@@ -0,0 +1,342 @@
+//! The scalar cryptography extensions: AES, SHA-2, SM3 and SM4 instructions, and the entropy
+//! source behind the Zkr `seed` CSR.
+use crate::{bitutils::Bits, decode::CryptoOp};
+use std::{fs::File, io::Read};
+
+/// Whether `op` only exists on RV64.
+pub fn rv64_only(op: CryptoOp) -> bool {
+    matches!(
+        op,
+        CryptoOp::Aes64Es
+            | CryptoOp::Aes64Esm
+            | CryptoOp::Aes64Ds
+            | CryptoOp::Aes64Dsm
+            | CryptoOp::Aes64Im
+            | CryptoOp::Aes64Ks1i
+            | CryptoOp::Aes64Ks2
+            | CryptoOp::Sha512Sig0
+            | CryptoOp::Sha512Sig1
+            | CryptoOp::Sha512Sum0
+            | CryptoOp::Sha512Sum1
+    )
+}
+
+/// Result of `op` on `a` and `b` on an `xlen`-bit hart; `imm` is the byte select or round
+/// number.
+pub fn exec(op: CryptoOp, a: u64, b: u64, imm: u32, xlen: u32) -> Result<u64, String> {
+    let rv32_only = matches!(
+        op,
+        CryptoOp::Aes32Esi
+            | CryptoOp::Aes32Esmi
+            | CryptoOp::Aes32Dsi
+            | CryptoOp::Aes32Dsmi
+            | CryptoOp::Sha512Sig0h
+            | CryptoOp::Sha512Sig0l
+            | CryptoOp::Sha512Sig1h
+            | CryptoOp::Sha512Sig1l
+            | CryptoOp::Sha512Sum0r
+            | CryptoOp::Sha512Sum1r
+    );
+    if (rv64_only(op) && xlen != 64) || (rv32_only && xlen != 32) {
+        return Err(format!("{:?} is not an RV{} instruction", op, xlen));
+    }
+    let low = a as u32;
+    // 32-bit results are sign extended on RV64
+    let word = |x: u32| Bits::new(x as u64, 32).extend_u64();
+    // a byte of `b` through an S-box and a linear layer, rotated back into place and added
+    let byte = |f: fn(u8) -> u32| word(a as u32 ^ f((b >> (8 * imm)) as u8).rotate_left(8 * imm));
+    // a 64-bit value split over rs1 and rs2, high half in `hi`
+    let pair = |hi: u64, lo: u64| hi << 32 | (lo & 0xffff_ffff);
+    let result = match op {
+        CryptoOp::Aes32Esi => byte(|x| SBOX[x as usize] as u32),
+        CryptoOp::Aes32Esmi => byte(|x| mix_column(SBOX[x as usize] as u32, false)),
+        CryptoOp::Aes32Dsi => byte(|x| inv_sbox(x) as u32),
+        CryptoOp::Aes32Dsmi => byte(|x| mix_column(inv_sbox(x) as u32, true)),
+        CryptoOp::Aes64Es => sub_bytes(shift_rows(a, b, false), false),
+        CryptoOp::Aes64Esm => mix_columns(sub_bytes(shift_rows(a, b, false), false), false),
+        CryptoOp::Aes64Ds => sub_bytes(shift_rows(a, b, true), true),
+        CryptoOp::Aes64Dsm => mix_columns(sub_bytes(shift_rows(a, b, true), true), true),
+        CryptoOp::Aes64Im => mix_columns(a, true),
+        CryptoOp::Aes64Ks1i => {
+            let high = (a >> 32) as u32;
+            // the last round number skips the rotation and round constant
+            let (w, rcon) = match imm {
+                0xa => (high, 0),
+                _ => (high.rotate_right(8), RCON[imm as usize]),
+            };
+            let w = sub_bytes(w as u64, false) as u32 ^ rcon;
+            (w as u64) << 32 | w as u64
+        }
+        CryptoOp::Aes64Ks2 => {
+            let w0 = (a >> 32) as u32 ^ b as u32;
+            let w1 = w0 ^ (b >> 32) as u32;
+            (w1 as u64) << 32 | w0 as u64
+        }
+        CryptoOp::Sha256Sig0 => word(low.rotate_right(7) ^ low.rotate_right(18) ^ low >> 3),
+        CryptoOp::Sha256Sig1 => word(low.rotate_right(17) ^ low.rotate_right(19) ^ low >> 10),
+        CryptoOp::Sha256Sum0 => word(low.rotate_right(2) ^ low.rotate_right(13) ^ low.rotate_right(22)),
+        CryptoOp::Sha256Sum1 => word(low.rotate_right(6) ^ low.rotate_right(11) ^ low.rotate_right(25)),
+        CryptoOp::Sha512Sig0 => sha512_sig0(a),
+        CryptoOp::Sha512Sig1 => sha512_sig1(a),
+        CryptoOp::Sha512Sum0 => sha512_sum0(a),
+        CryptoOp::Sha512Sum1 => sha512_sum1(a),
+        // rs1 holds the half computed; the rotations give the other half of the sums with the
+        // registers swapped
+        CryptoOp::Sha512Sig0l => sha512_sig0(pair(b, a)) & 0xffff_ffff,
+        CryptoOp::Sha512Sig0h => sha512_sig0(pair(a, b)) >> 32,
+        CryptoOp::Sha512Sig1l => sha512_sig1(pair(b, a)) & 0xffff_ffff,
+        CryptoOp::Sha512Sig1h => sha512_sig1(pair(a, b)) >> 32,
+        CryptoOp::Sha512Sum0r => sha512_sum0(pair(b, a)) & 0xffff_ffff,
+        CryptoOp::Sha512Sum1r => sha512_sum1(pair(b, a)) & 0xffff_ffff,
+        CryptoOp::Sm4Ed => byte(|x| {
+            let x = SM4_SBOX[x as usize] as u32;
+            x ^ x.rotate_left(2) ^ x.rotate_left(10) ^ x.rotate_left(18) ^ x.rotate_left(24)
+        }),
+        CryptoOp::Sm4Ks => byte(|x| {
+            let x = SM4_SBOX[x as usize] as u32;
+            x ^ x.rotate_left(13) ^ x.rotate_left(23)
+        }),
+        CryptoOp::Sm3P0 => word(low ^ low.rotate_left(9) ^ low.rotate_left(17)),
+        CryptoOp::Sm3P1 => word(low ^ low.rotate_left(15) ^ low.rotate_left(23)),
+    };
+    Ok(result & (u64::MAX >> (64 - xlen)))
+}
+
+fn sha512_sig0(x: u64) -> u64 {
+    x.rotate_right(1) ^ x.rotate_right(8) ^ x >> 7
+}
+fn sha512_sig1(x: u64) -> u64 {
+    x.rotate_right(19) ^ x.rotate_right(61) ^ x >> 6
+}
+fn sha512_sum0(x: u64) -> u64 {
+    x.rotate_right(28) ^ x.rotate_right(34) ^ x.rotate_right(39)
+}
+fn sha512_sum1(x: u64) -> u64 {
+    x.rotate_right(14) ^ x.rotate_right(18) ^ x.rotate_right(41)
+}
+
+const RCON: [u32; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const SM4_SBOX: [u8; 256] = [
+    0xd6, 0x90, 0xe9, 0xfe, 0xcc, 0xe1, 0x3d, 0xb7, 0x16, 0xb6, 0x14, 0xc2, 0x28, 0xfb, 0x2c, 0x05,
+    0x2b, 0x67, 0x9a, 0x76, 0x2a, 0xbe, 0x04, 0xc3, 0xaa, 0x44, 0x13, 0x26, 0x49, 0x86, 0x06, 0x99,
+    0x9c, 0x42, 0x50, 0xf4, 0x91, 0xef, 0x98, 0x7a, 0x33, 0x54, 0x0b, 0x43, 0xed, 0xcf, 0xac, 0x62,
+    0xe4, 0xb3, 0x1c, 0xa9, 0xc9, 0x08, 0xe8, 0x95, 0x80, 0xdf, 0x94, 0xfa, 0x75, 0x8f, 0x3f, 0xa6,
+    0x47, 0x07, 0xa7, 0xfc, 0xf3, 0x73, 0x17, 0xba, 0x83, 0x59, 0x3c, 0x19, 0xe6, 0x85, 0x4f, 0xa8,
+    0x68, 0x6b, 0x81, 0xb2, 0x71, 0x64, 0xda, 0x8b, 0xf8, 0xeb, 0x0f, 0x4b, 0x70, 0x56, 0x9d, 0x35,
+    0x1e, 0x24, 0x0e, 0x5e, 0x63, 0x58, 0xd1, 0xa2, 0x25, 0x22, 0x7c, 0x3b, 0x01, 0x21, 0x78, 0x87,
+    0xd4, 0x00, 0x46, 0x57, 0x9f, 0xd3, 0x27, 0x52, 0x4c, 0x36, 0x02, 0xe7, 0xa0, 0xc4, 0xc8, 0x9e,
+    0xea, 0xbf, 0x8a, 0xd2, 0x40, 0xc7, 0x38, 0xb5, 0xa3, 0xf7, 0xf2, 0xce, 0xf9, 0x61, 0x15, 0xa1,
+    0xe0, 0xae, 0x5d, 0xa4, 0x9b, 0x34, 0x1a, 0x55, 0xad, 0x93, 0x32, 0x30, 0xf5, 0x8c, 0xb1, 0xe3,
+    0x1d, 0xf6, 0xe2, 0x2e, 0x82, 0x66, 0xca, 0x60, 0xc0, 0x29, 0x23, 0xab, 0x0d, 0x53, 0x4e, 0x6f,
+    0xd5, 0xdb, 0x37, 0x45, 0xde, 0xfd, 0x8e, 0x2f, 0x03, 0xff, 0x6a, 0x72, 0x6d, 0x6c, 0x5b, 0x51,
+    0x8d, 0x1b, 0xaf, 0x92, 0xbb, 0xdd, 0xbc, 0x7f, 0x11, 0xd9, 0x5c, 0x41, 0x1f, 0x10, 0x5a, 0xd8,
+    0x0a, 0xc1, 0x31, 0x88, 0xa5, 0xcd, 0x7b, 0xbd, 0x2d, 0x74, 0xd0, 0x12, 0xb8, 0xe5, 0xb4, 0xb0,
+    0x89, 0x69, 0x97, 0x4a, 0x0c, 0x96, 0x77, 0x7e, 0x65, 0xb9, 0xf1, 0x09, 0xc5, 0x6e, 0xc6, 0x84,
+    0x18, 0xf0, 0x7d, 0xec, 0x3a, 0xdc, 0x4d, 0x20, 0x79, 0xee, 0x5f, 0x3e, 0xd7, 0xcb, 0x39, 0x48,
+];
+
+fn inv_sbox(x: u8) -> u8 {
+    SBOX.iter().position(|&s| s == x).unwrap() as u8
+}
+
+/// Product in GF(2^8) modulo the AES polynomial.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut p = 0;
+    while b != 0 {
+        if b & 1 == 1 {
+            p ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1b } else { 0 };
+        b >>= 1;
+    }
+    p
+}
+
+/// MixColumns, or InvMixColumns, of the column in the low bytes of `col`, row 0 lowest.
+fn mix_column(col: u32, inverse: bool) -> u32 {
+    let m: [u8; 4] = if inverse { [14, 11, 13, 9] } else { [2, 3, 1, 1] };
+    let b = col.to_le_bytes();
+    let out: Vec<u8> = (0..4)
+        .map(|r| (0..4).fold(0, |acc, c| acc ^ gf_mul(b[c], m[(c + 4 - r) % 4])))
+        .collect();
+    u32::from_le_bytes([out[0], out[1], out[2], out[3]])
+}
+
+fn mix_columns(x: u64, inverse: bool) -> u64 {
+    (mix_column((x >> 32) as u32, inverse) as u64) << 32 | mix_column(x as u32, inverse) as u64
+}
+
+fn sub_bytes(x: u64, inverse: bool) -> u64 {
+    let bytes = x.to_le_bytes().map(|b| if inverse { inv_sbox(b) } else { SBOX[b as usize] });
+    u64::from_le_bytes(bytes)
+}
+
+/// Columns 0 and 1 of ShiftRows, or InvShiftRows, of the state whose columns 0 and 1 are in
+/// `lo` and 2 and 3 in `hi`.
+fn shift_rows(lo: u64, hi: u64, inverse: bool) -> u64 {
+    let state = (hi as u128) << 64 | lo as u128;
+    (0..8).fold(0, |out, i| {
+        let (col, row) = (i / 4, i % 4);
+        let from = match inverse {
+            false => (col + row) % 4,
+            true => (col + 4 - row) % 4,
+        };
+        out | ((state >> (8 * (4 * from + row))) as u8 as u64) << (8 * i)
+    })
+}
+
+/// Where reads of the Zkr `seed` CSR get their entropy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Entropy {
+    /// a fixed sequence from this state, so runs can be repeated
+    Deterministic(u64),
+    /// the host's `/dev/urandom`
+    Host,
+}
+
+impl Default for Entropy {
+    fn default() -> Self {
+        Entropy::Deterministic(0)
+    }
+}
+
+impl Entropy {
+    /// Parses `host`, or a number seeding the deterministic source.
+    pub fn parse(s: &str) -> Result<Entropy, String> {
+        match s {
+            "host" => Ok(Entropy::Host),
+            _ => s
+                .parse()
+                .map(Entropy::Deterministic)
+                .map_err(|_| format!("invalid entropy source {}, expected host or a number", s)),
+        }
+    }
+    /// 16 bits of entropy.
+    pub fn draw(&mut self) -> Result<u16, String> {
+        match self {
+            // splitmix64
+            Entropy::Deterministic(state) => {
+                *state = state.wrapping_add(0x9e3779b97f4a7c15);
+                let mut z = *state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+                Ok((z ^ (z >> 31)) as u16)
+            }
+            Entropy::Host => {
+                let mut buf = [0; 2];
+                File::open("/dev/urandom")
+                    .and_then(|mut f| f.read_exact(&mut buf))
+                    .map_err(|e| format!("entropy source: {}", e))?;
+                Ok(u16::from_le_bytes(buf))
+            }
+        }
+    }
+}
+
+#[test]
+fn test_aes() {
+    // FIPS-197 appendix C.1: AES-128 of 00112233..ff under key 00010203..0f, with the RV64 round
+    // and key schedule instructions
+    let rv64 = |op, a, b, imm| exec(op, a, b, imm, 64).unwrap();
+    let mut key = [0x0706050403020100u64, 0x0f0e0d0c0b0a0908];
+    let mut keys = vec![key];
+    for round in 0..10 {
+        let t = rv64(CryptoOp::Aes64Ks1i, key[1], 0, round);
+        let k0 = rv64(CryptoOp::Aes64Ks2, t, key[0], 0);
+        key = [k0, rv64(CryptoOp::Aes64Ks2, k0, key[1], 0)];
+        keys.push(key);
+    }
+    let mut s = [0x7766554433221100 ^ keys[0][0], 0xffeeddccbbaa9988 ^ keys[0][1]];
+    for (round, k) in keys.iter().enumerate().skip(1) {
+        let op = if round == 10 { CryptoOp::Aes64Es } else { CryptoOp::Aes64Esm };
+        s = [rv64(op, s[0], s[1], 0) ^ k[0], rv64(op, s[1], s[0], 0) ^ k[1]];
+    }
+    assert!(s == [0x30047b6ad8e0c469, 0x5ac5b47080b7cdd8]);
+    // and back, with the equivalent inverse cipher's keys
+    s = [s[0] ^ keys[10][0], s[1] ^ keys[10][1]];
+    for (round, k) in keys.iter().rev().enumerate().skip(1) {
+        let op = if round == 10 { CryptoOp::Aes64Ds } else { CryptoOp::Aes64Dsm };
+        let k = match round {
+            10 => *k,
+            _ => [rv64(CryptoOp::Aes64Im, k[0], 0, 0), rv64(CryptoOp::Aes64Im, k[1], 0, 0)],
+        };
+        s = [rv64(op, s[0], s[1], 0) ^ k[0], rv64(op, s[1], s[0], 0) ^ k[1]];
+    }
+    assert!(s == [0x7766554433221100, 0xffeeddccbbaa9988]);
+    // the RV32 instructions compute a column a byte at a time; after ShiftRows, row r of column 0
+    // is row r of column r
+    let rv32 = |op, a, b, bs| exec(op, a, b, bs, 32).unwrap();
+    let state = [0x33221100u64, 0x77665544, 0xbbaa9988, 0xffeeddcc];
+    let col0 = (0..4).fold(0, |acc, bs| rv32(CryptoOp::Aes32Esmi, acc, state[bs as usize], bs));
+    assert!(col0 == rv64(CryptoOp::Aes64Esm, 0x7766554433221100, 0xffeeddccbbaa9988, 0) & 0xffff_ffff);
+    assert!(exec(CryptoOp::Aes32Esi, 0, 0, 0, 64).is_err() && exec(CryptoOp::Aes64Es, 0, 0, 0, 32).is_err());
+}
+
+#[test]
+fn test_sm4() {
+    // GB/T 32907 example 1: key and plaintext 0123456789abcdeffedcba9876543210
+    let rv32 = |op, a, b, bs| exec(op, a, b, bs, 32).unwrap();
+    let t = |op, x: u64, y: u64| (0..4).fold(x, |acc, bs| rv32(op, acc, y, bs));
+    let words = [0x01234567u64, 0x89abcdef, 0xfedcba98, 0x76543210];
+    let fk = [0xa3b1bac6u64, 0x56aa3350, 0x677d9197, 0xb27022dc];
+    let mut k: Vec<u64> = (0..4).map(|i| words[i] ^ fk[i]).collect();
+    for i in 0..32 {
+        let ck = (0..4).fold(0u64, |acc, j| acc << 8 | ((4 * i + j) * 7 % 256) as u64);
+        let next = t(CryptoOp::Sm4Ks, k[i], k[i + 1] ^ k[i + 2] ^ k[i + 3] ^ ck);
+        k.push(next);
+    }
+    let mut x = words.to_vec();
+    for i in 0..32 {
+        let next = t(CryptoOp::Sm4Ed, x[i], x[i + 1] ^ x[i + 2] ^ x[i + 3] ^ k[i + 4]);
+        x.push(next);
+    }
+    assert!(x[32..] == [0x536e4246, 0x86b3e94f, 0xd206965e, 0x681edf34]);
+    assert!(rv32(CryptoOp::Sm3P0, 1, 0, 0) == 1 | 1 << 9 | 1 << 17);
+}
+
+#[test]
+fn test_sha() {
+    let rv32 = |op, a, b| exec(op, a, b, 0, 32).unwrap();
+    let x = 0x0123456789abcdefu64;
+    let (hi, lo) = (x >> 32, x & 0xffff_ffff);
+    for (l, h, f) in [
+        (CryptoOp::Sha512Sig0l, CryptoOp::Sha512Sig0h, sha512_sig0 as fn(u64) -> u64),
+        (CryptoOp::Sha512Sig1l, CryptoOp::Sha512Sig1h, sha512_sig1),
+    ] {
+        assert!(rv32(h, hi, lo) << 32 | rv32(l, lo, hi) == f(x));
+    }
+    let sum0 = rv32(CryptoOp::Sha512Sum0r, hi, lo) << 32 | rv32(CryptoOp::Sha512Sum0r, lo, hi);
+    assert!(sum0 == sha512_sum0(x));
+    // the SHA-256 functions sign extend on RV64
+    assert!(rv32(CryptoOp::Sha256Sig0, 1, 0) == 0x02004000);
+    assert!(exec(CryptoOp::Sha256Sum0, 2, 0, 0, 64) == Ok(0xffffffff80100800));
+}
+
+#[test]
+fn test_entropy() {
+    let mut a = Entropy::parse("7").unwrap();
+    let mut b = Entropy::Deterministic(7);
+    assert!(a.draw() == b.draw() && a.draw() != Entropy::Deterministic(0).draw());
+    assert!(Entropy::parse("host") == Ok(Entropy::Host) && Entropy::Host.draw().is_ok());
+    assert!(Entropy::parse("urandom").is_err());
+}
@@ -1,16 +1,265 @@
 pub const LIMIT_CSR: usize = 4096;
+
+pub const FFLAGS: usize = 0x001;
+pub const FRM: usize = 0x002;
+pub const FCSR: usize = 0x003;
+pub const VSTART: usize = 0x008;
+pub const VXSAT: usize = 0x009;
+pub const VXRM: usize = 0x00a;
+pub const VCSR: usize = 0x00f;
+pub const SEED: usize = 0x015;
+pub const JVT: usize = 0x017;
+pub const SSTATUS: usize = 0x100;
+pub const SIE: usize = 0x104;
+pub const STVEC: usize = 0x105;
+pub const SSCRATCH: usize = 0x140;
+pub const SEPC: usize = 0x141;
+pub const SCAUSE: usize = 0x142;
+pub const STVAL: usize = 0x143;
+pub const SIP: usize = 0x144;
+pub const SATP: usize = 0x180;
+pub const VSSTATUS: usize = 0x200;
+pub const VSIE: usize = 0x204;
+pub const VSTVEC: usize = 0x205;
+pub const VSEPC: usize = 0x241;
+pub const VSCAUSE: usize = 0x242;
+pub const VSTVAL: usize = 0x243;
+pub const VSIP: usize = 0x244;
+pub const VSATP: usize = 0x280;
+pub const MSTATUS: usize = 0x300;
+pub const MISA: usize = 0x301;
+pub const MEDELEG: usize = 0x302;
+pub const MIDELEG: usize = 0x303;
+pub const MIE: usize = 0x304;
+pub const MTVEC: usize = 0x305;
+pub const MSTATUSH: usize = 0x310;
+pub const MEPC: usize = 0x341;
+pub const MCAUSE: usize = 0x342;
+pub const MTVAL: usize = 0x343;
+pub const MIP: usize = 0x344;
+pub const MTINST: usize = 0x34a;
+pub const MTVAL2: usize = 0x34b;
+pub const HSTATUS: usize = 0x600;
+pub const HEDELEG: usize = 0x602;
+pub const HIDELEG: usize = 0x603;
+pub const HIE: usize = 0x604;
+pub const HTVAL: usize = 0x643;
+pub const HIP: usize = 0x644;
+pub const HVIP: usize = 0x645;
+pub const HTINST: usize = 0x64a;
+pub const HGATP: usize = 0x680;
+pub const MSECCFG: usize = 0x747;
+pub const VL: usize = 0xc20;
+pub const VTYPE: usize = 0xc21;
+pub const VLENB: usize = 0xc22;
+
+/// mstatus.FS, the state of the floating-point unit: off, initial, clean or dirty
+pub const MSTATUS_FS: u64 = 0b11 << 13;
+/// mstatus.VS, the same for the vector unit
+pub const MSTATUS_VS: u64 = 0b11 << 9;
+/// mstatus.SD, set while some extension state is dirty
+const MSTATUS_SD: u64 = 1 << 31;
+/// mstatus.MIE and mstatus.MPIE, and the S mode ones
+pub const MSTATUS_MIE: u64 = 1 << 3;
+pub const MSTATUS_MPIE: u64 = 1 << 7;
+pub const MSTATUS_SIE: u64 = 1 << 1;
+pub const MSTATUS_SPIE: u64 = 1 << 5;
+/// mstatus.SPP and mstatus.MPP, the privilege trapped from
+pub const MSTATUS_SPP: u64 = 1 << 8;
+pub const MSTATUS_MPP: u64 = 0b11 << 11;
+/// mstatus.MPRV, loads and stores in M mode translated as in MPP
+pub const MSTATUS_MPRV: u64 = 1 << 17;
+/// mstatus.SUM and mstatus.MXR, S mode access to user pages, and reads of executable ones
+pub const MSTATUS_SUM: u64 = 1 << 18;
+pub const MSTATUS_MXR: u64 = 1 << 19;
+/// mstatus.TVM and mstatus.TSR, trapping satp and sfence.vma, and sret, in S mode
+pub const MSTATUS_TVM: u64 = 1 << 20;
+pub const MSTATUS_TSR: u64 = 1 << 22;
+/// The mstatus fields sstatus shows: SIE, SPIE, UBE, SPP, VS, FS, XS, SUM, MXR and SD.
+pub const SSTATUS_MASK: u64 = 0x800de762;
+/// mstatush.GVA and mstatush.MPV, whether mtval is a guest virtual address, and the
+/// virtualization mode trapped from
+pub const MSTATUSH_GVA: u64 = 1 << 6;
+pub const MSTATUSH_MPV: u64 = 1 << 7;
+/// hstatus.GVA, SPV and SPVP, as in mstatush for traps to HS mode, and the privilege hlv and
+/// hsv access memory as
+pub const HSTATUS_GVA: u64 = 1 << 6;
+pub const HSTATUS_SPV: u64 = 1 << 7;
+pub const HSTATUS_SPVP: u64 = 1 << 8;
+/// hstatus.HU, allowing the hypervisor loads and stores in U mode
+pub const HSTATUS_HU: u64 = 1 << 9;
+/// hstatus.VTVM, VTW and VTSR, trapping as mstatus.TVM, TW and TSR do, but in VS mode
+pub const HSTATUS_VTVM: u64 = 1 << 20;
+pub const HSTATUS_VTSR: u64 = 1 << 22;
+/// The S mode interrupts: software, timer and external.
+pub const S_INTS: u64 = 1 << 1 | 1 << 5 | 1 << 9;
+/// The VS mode interrupts, which hvip makes pending, and the guest external one.
+pub const VS_INTS: u64 = 1 << 2 | 1 << 6 | 1 << 10;
+pub const SGEI: u64 = 1 << 12;
+/// jvt.MODE, 0 for the only mode there is, jumping through a table at jvt.BASE
+pub const JVT_MODE: u64 = 0x3f;
+/// mseccfg.USEED and mseccfg.SSEED, letting U and S modes access seed
+pub const MSECCFG_USEED: u64 = 1 << 8;
+pub const MSECCFG_SSEED: u64 = 1 << 9;
+
+pub const CSR_NAMES: &[(&str, usize)] = &[
+    ("fflags", 0x001),
+    ("frm", 0x002),
+    ("fcsr", 0x003),
+    ("vstart", 0x008),
+    ("vxsat", 0x009),
+    ("vxrm", 0x00a),
+    ("vcsr", 0x00f),
+    ("seed", 0x015),
+    ("jvt", 0x017),
+    ("sstatus", 0x100),
+    ("sie", 0x104),
+    ("stvec", 0x105),
+    ("scounteren", 0x106),
+    ("sscratch", 0x140),
+    ("sepc", 0x141),
+    ("scause", 0x142),
+    ("stval", 0x143),
+    ("sip", 0x144),
+    ("satp", 0x180),
+    ("vsstatus", 0x200),
+    ("vsie", 0x204),
+    ("vstvec", 0x205),
+    ("vsscratch", 0x240),
+    ("vsepc", 0x241),
+    ("vscause", 0x242),
+    ("vstval", 0x243),
+    ("vsip", 0x244),
+    ("vsatp", 0x280),
+    ("mstatus", 0x300),
+    ("misa", 0x301),
+    ("medeleg", 0x302),
+    ("mideleg", 0x303),
+    ("mie", 0x304),
+    ("mtvec", 0x305),
+    ("mcounteren", 0x306),
+    ("mscratch", 0x340),
+    ("mepc", 0x341),
+    ("mcause", 0x342),
+    ("mtval", 0x343),
+    ("mstatush", 0x310),
+    ("mip", 0x344),
+    ("mtinst", 0x34a),
+    ("mtval2", 0x34b),
+    ("pmpcfg0", 0x3a0),
+    ("pmpaddr0", 0x3b0),
+    ("hstatus", 0x600),
+    ("hedeleg", 0x602),
+    ("hideleg", 0x603),
+    ("hie", 0x604),
+    ("hcounteren", 0x606),
+    ("hgeie", 0x607),
+    ("htval", 0x643),
+    ("hip", 0x644),
+    ("hvip", 0x645),
+    ("htinst", 0x64a),
+    ("hgatp", 0x680),
+    ("mseccfg", 0x747),
+    ("hgeip", 0xe12),
+    ("mcycle", 0xb00),
+    ("minstret", 0xb02),
+    ("cycle", 0xc00),
+    ("time", 0xc01),
+    ("instret", 0xc02),
+    ("vl", 0xc20),
+    ("vtype", 0xc21),
+    ("vlenb", 0xc22),
+    ("mvendorid", 0xf11),
+    ("marchid", 0xf12),
+    ("mimpid", 0xf13),
+    ("mhartid", 0xf14),
+];
+
+/// Parses a CSR name such as `mepc`, or a number such as `0x341`.
+pub fn parse_name(name: &str) -> Option<usize> {
+    if let Some(&(_, n)) = CSR_NAMES.iter().find(|(csr, _)| *csr == name) {
+        return Some(n);
+    }
+    let n = match name.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok()?,
+        None => name.parse().ok()?,
+    };
+    if n < LIMIT_CSR {
+        Some(n)
+    } else {
+        None
+    }
+}
+/// Name of CSR `n` for display, or its number if it has none.
+pub fn name(n: usize) -> String {
+    match CSR_NAMES.iter().find(|&&(_, c)| c == n) {
+        Some((name, _)) => name.to_string(),
+        None => format!("{:#x}", n),
+    }
+}
+
 pub struct Csr {
     register: [u64; LIMIT_CSR],
+    writes: Vec<(usize, u64)>,
 }
 impl Csr {
     pub fn new(register: [u64; LIMIT_CSR]) -> Csr {
         Csr {
             register,
+            writes: Vec::new(),
         }
     }
+    /// Returns and clears the (csr, value) writes made since the last call, oldest first.
+    pub fn take_writes(&mut self) -> Vec<(usize, u64)> {
+        std::mem::take(&mut self.writes)
+    }
+    /// Writes a CSR. fflags and frm are fields of fcsr, vxsat and vxrm of vcsr, and mstatus.SD
+    /// follows mstatus.FS and mstatus.VS. sstatus, and the interrupt enable and pending
+    /// registers of S, HS and VS modes, are views of the M mode ones, and write only what they
+    /// show; the VS interrupts pending in mip are those of hvip.
     pub fn write(&mut self, address: usize, data: u64) -> Result<(), String> {
         if address < 4096 {
-            self.register[address] = data;
+            let (fcsr, vcsr) = (self.register[FCSR], self.register[VCSR]);
+            let (mstatus, mie, mip, hvip) = (
+                self.register[MSTATUS],
+                self.register[MIE],
+                self.register[MIP],
+                self.register[HVIP],
+            );
+            let (mideleg, hideleg) = (self.register[MIDELEG], self.register[HIDELEG]);
+            // only the software interrupts are pending by a write, SSIP, and VSSIP through hvip
+            let (ssip, vssip) = (mideleg & 1 << 1, 1 << 2);
+            let (target, data) = match address {
+                SSTATUS => (MSTATUS, (mstatus & !SSTATUS_MASK) | (data & SSTATUS_MASK)),
+                SIE => (MIE, (mie & !mideleg) | (data & mideleg)),
+                SIP => (MIP, (mip & !ssip) | (data & ssip)),
+                HIE => (MIE, (mie & !(VS_INTS | SGEI)) | (data & (VS_INTS | SGEI))),
+                HIP => (HVIP, (hvip & !vssip) | (data & vssip)),
+                VSIE => (MIE, (mie & !(hideleg & VS_INTS)) | ((data << 1) & hideleg & VS_INTS)),
+                VSIP => (HVIP, (hvip & !(hideleg & vssip)) | ((data << 1) & hideleg & vssip)),
+                MIP => {
+                    self.register[HVIP] = (hvip & !VS_INTS) | (data & VS_INTS);
+                    (MIP, data & !VS_INTS)
+                }
+                HVIP => (HVIP, data & VS_INTS),
+                // the root page table of Sv32x4 is 16 KiB aligned
+                HGATP => (HGATP, data & !0b11),
+                _ => (address, data),
+            };
+            let dirty = data & MSTATUS_FS == MSTATUS_FS || data & MSTATUS_VS == MSTATUS_VS;
+            match target {
+                FFLAGS => self.register[FCSR] = (fcsr & !0x1f) | (data & 0x1f),
+                FRM => self.register[FCSR] = (fcsr & !0xe0) | ((data & 0b111) << 5),
+                FCSR => self.register[FCSR] = data & 0xff,
+                VXSAT => self.register[VCSR] = (vcsr & !1) | (data & 1),
+                VXRM => self.register[VCSR] = (vcsr & !0b110) | ((data & 0b11) << 1),
+                VCSR => self.register[VCSR] = data & 0b111,
+                MSTATUS | VSSTATUS if dirty => self.register[target] = data | MSTATUS_SD,
+                MSTATUS | VSSTATUS => self.register[target] = data & !MSTATUS_SD,
+                _ => self.register[target] = data,
+            }
+            let value = self.read(address)?;
+            self.writes.push((address, value));
             Ok(())
         } else {
             Err(String::from("referring to out-of-range csr reg"))
@@ -18,9 +267,72 @@ impl Csr {
     }
     pub fn read(&mut self, address: usize) -> Result<u64, String> {
         if address < 4096 {
-            return Ok(self.register[address]);
+            let mie = self.register[MIE];
+            let mip = self.register[MIP] | (self.register[HVIP] & VS_INTS);
+            let (mideleg, hideleg) = (self.register[MIDELEG], self.register[HIDELEG]);
+            Ok(match address {
+                FFLAGS => self.register[FCSR] & 0x1f,
+                FRM => (self.register[FCSR] >> 5) & 0b111,
+                VXSAT => self.register[VCSR] & 1,
+                VXRM => (self.register[VCSR] >> 1) & 0b11,
+                SSTATUS => self.register[MSTATUS] & SSTATUS_MASK,
+                SIE => mie & mideleg,
+                SIP => mip & mideleg,
+                HIE => mie & (VS_INTS | SGEI),
+                HIP => mip & (VS_INTS | SGEI),
+                // VS mode sees its interrupts where S mode has them
+                VSIE => (mie & hideleg & VS_INTS) >> 1,
+                VSIP => (mip & hideleg & VS_INTS) >> 1,
+                MIP => mip,
+                _ => self.register[address],
+            })
         } else {
             Err(String::from("referring to out-of-range csr reg"))
         }
     }
 }
+
+#[test]
+fn test_fcsr_fields() {
+    let mut csr = Csr::new([0; LIMIT_CSR]);
+    csr.write(FCSR, 0x1ff).unwrap();
+    assert!(csr.read(FFLAGS) == Ok(0x1f) && csr.read(FRM) == Ok(0b111));
+    csr.write(FRM, 1).unwrap();
+    csr.write(FFLAGS, 0x21).unwrap();
+    assert!(csr.read(FCSR) == Ok(0x21));
+    csr.write(MSTATUS, MSTATUS_FS).unwrap();
+    assert!(csr.read(MSTATUS) == Ok(MSTATUS_FS | 1 << 31));
+    assert!(csr.take_writes().last() == Some(&(MSTATUS, MSTATUS_FS | 1 << 31)));
+}
+
+#[test]
+fn test_vcsr_fields() {
+    let mut csr = Csr::new([0; LIMIT_CSR]);
+    csr.write(VCSR, 0xf).unwrap();
+    assert!(csr.read(VXSAT) == Ok(1) && csr.read(VXRM) == Ok(0b11));
+    csr.write(VXRM, 2).unwrap();
+    csr.write(VXSAT, 0).unwrap();
+    assert!(csr.read(VCSR) == Ok(0b100));
+    csr.write(MSTATUS, MSTATUS_VS).unwrap();
+    assert!(csr.read(MSTATUS) == Ok(MSTATUS_VS | 1 << 31));
+}
+
+#[test]
+fn test_views() {
+    let mut csr = Csr::new([0; LIMIT_CSR]);
+    csr.write(MSTATUS, MSTATUS_MPP | MSTATUS_SIE).unwrap();
+    csr.write(SSTATUS, MSTATUS_SUM).unwrap();
+    assert!(csr.read(MSTATUS) == Ok(MSTATUS_MPP | MSTATUS_SUM) && csr.read(SSTATUS) == Ok(MSTATUS_SUM));
+    // sie shows what mideleg delegates, and vsie the VS interrupts hideleg does, a bit lower
+    csr.write(MIDELEG, S_INTS | VS_INTS).unwrap();
+    csr.write(HIDELEG, 1 << 6).unwrap();
+    csr.write(SIE, 0xffff).unwrap();
+    assert!(csr.read(MIE) == Ok(S_INTS | VS_INTS) && csr.read(VSIE) == Ok(1 << 5));
+    csr.write(VSIE, 0).unwrap();
+    assert!(csr.read(MIE) == Ok(S_INTS | 1 << 2 | 1 << 10));
+    // VS interrupts pending in hvip are pending in mip
+    csr.write(HVIP, 1 << 6 | 1 << 1).unwrap();
+    assert!(csr.read(MIP) == Ok(1 << 6) && csr.read(VSIP) == Ok(1 << 5) && csr.read(HIP) == Ok(1 << 6));
+    csr.write(MIP, 1 << 7).unwrap();
+    assert!(csr.read(HVIP) == Ok(0) && csr.take_writes().last() == Some(&(MIP, 1 << 7)));
+}
@@ -0,0 +1,188 @@
+use crate::{
+    cpu::{rv32, Cpu, State, R2R},
+    csr::Csr,
+    register::Register,
+};
+
+/// Major opcodes (inst[6:0]) the ISA reserves for custom extensions.
+pub mod space {
+    pub const CUSTOM0: u32 = 0b0001011;
+    pub const CUSTOM1: u32 = 0b0101011;
+    pub const CUSTOM2: u32 = 0b1011011;
+    pub const CUSTOM3: u32 = 0b1111011;
+    pub const ALL: [u32; 4] = [CUSTOM0, CUSTOM1, CUSTOM2, CUSTOM3];
+}
+
+/// The architectural state a custom instruction may use. Memory is reached through `load` and
+/// `store`, which translate and check addresses like built-in instructions, and are reported to
+/// traces, commit logs and plugins like theirs.
+pub struct Hart<'a> {
+    /// pc of the instruction
+    pub pc: u64,
+    /// register width in bytes
+    pub xlen: u8,
+    /// set to continue somewhere other than the next instruction
+    pub next_pc: Option<u64>,
+    cpu: &'a mut Cpu,
+}
+
+impl<'a> Hart<'a> {
+    pub(crate) fn new(pc: u64, xlen: u8, cpu: &'a mut Cpu) -> Self {
+        Hart {
+            pc,
+            xlen,
+            next_pc: None,
+            cpu,
+        }
+    }
+    pub fn register(&mut self) -> &mut Register {
+        self.cpu.register()
+    }
+    pub fn csr(&mut self) -> &mut Csr {
+        self.cpu.csr()
+    }
+    /// Loads `n` bytes, at most 8, from virtual address `addr`. A page or access fault is raised
+    /// as for a load instruction; return the error to take it.
+    pub fn load(&mut self, addr: u64, n: u64) -> Result<u64, String> {
+        check_width(n)?;
+        self.cpu.read_memory(addr, n)
+    }
+    /// Stores the low `n` bytes of `data`, at most 8, to virtual address `addr`, faulting as a
+    /// store instruction would.
+    pub fn store(&mut self, addr: u64, n: u64, data: u64) -> Result<(), String> {
+        check_width(n)?;
+        self.cpu.write_memory(addr, n, data)
+    }
+}
+
+fn check_width(n: u64) -> Result<(), String> {
+    match n {
+        1..=8 => Ok(()),
+        _ => Err(format!("no {} byte memory access", n)),
+    }
+}
+
+/// Decodes and executes 32-bit instructions registered with `Cpu::add_custom`.
+pub trait Custom {
+    /// Executes `inst`, which matched the pattern this was registered with. An error stops the
+    /// cpu, as for an unknown instruction, except a fault raised by `Hart::load` or `Hart::store`,
+    /// which traps.
+    fn exec(&mut self, inst: u32, hart: &mut Hart) -> Result<(), String>;
+}
+
+/// Any register-to-register instruction is a custom instruction in R-type format, with funct7
+/// as the immediate of its `State`.
+impl<T: R2R> Custom for T {
+    fn exec(&mut self, inst: u32, hart: &mut Hart) -> Result<(), String> {
+        let state = State::new(
+            rv32::get_rd(inst),
+            rv32::get_rs1(inst),
+            rv32::get_rs2(inst),
+            rv32::get_bits(inst, 31, 25) as u64,
+        );
+        let register = hart.register();
+        *register = self.exec_register(state, register)?;
+        Ok(())
+    }
+}
+
+/// Instructions matching `pattern` under `mask` and how to execute them.
+pub(crate) struct Entry {
+    pub pattern: u32,
+    pub mask: u32,
+    pub custom: Box<dyn Custom>,
+}
+
+impl Entry {
+    pub fn new(pattern: u32, mask: u32, custom: Box<dyn Custom>) -> Result<Self, String> {
+        if pattern & !mask != 0 {
+            return Err(format!("pattern {:#x} has bits outside mask {:#x}", pattern, mask));
+        }
+        if mask & 0b11 != 0b11 || pattern & 0b11 != 0b11 {
+            return Err(format!(
+                "pattern {:#x} must match a 32-bit encoding, with inst[1:0] = 0b11",
+                pattern
+            ));
+        }
+        Ok(Entry {
+            pattern,
+            mask,
+            custom,
+        })
+    }
+    pub fn matches(&self, inst: u32) -> bool {
+        inst & self.mask == self.pattern
+    }
+}
+
+#[cfg(test)]
+struct LoadAddCsr;
+
+/// `rd = mem32[rs1] + mscratch`, then jumps over the next instruction.
+#[cfg(test)]
+impl Custom for LoadAddCsr {
+    fn exec(&mut self, inst: u32, hart: &mut Hart) -> Result<(), String> {
+        let xlen = hart.xlen;
+        let addr = hart.register().read(rv32::get_rs1(inst), xlen)?;
+        let value = hart.load(addr, 4)? + hart.csr().read(0x340)?;
+        hart.register().write(rv32::get_rd(inst), value, xlen)?;
+        hart.next_pc = Some(hart.pc + 8);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_custom() {
+    use crate::{
+        cpu::{Cpu, RegisterInst},
+        mmu::{Access, MemAccess},
+    };
+    let mut mem = vec![0; 0x40];
+    // mac a0, a1, a2 (custom-0); lwadd a0, (a1) (custom-1, funct3 1); custom-1 with funct3 2
+    for (i, inst) in [0x00c5850bu32, 0x0005952b, 0, 0x0005a52b].iter().enumerate() {
+        mem[4 * i..4 * i + 4].copy_from_slice(&inst.to_le_bytes());
+    }
+    mem[0x20..0x24].copy_from_slice(&100u32.to_le_bytes());
+    let mut cpu = Cpu::with_memory(mem);
+    let mac = RegisterInst {
+        exec: |_sel, state, reg| {
+            let mut r = *reg;
+            let product = reg.read(state.read_rs1(), 4)? * reg.read(state.read_rs2(), 4)?;
+            r.write(state.read_rd(), reg.read(state.read_rd(), 4)? + product, 4)?;
+            Ok(r)
+        },
+    };
+    cpu.add_custom_space(0, Box::new(mac)).unwrap();
+    cpu.add_custom(0x102b, 0x707f, Box::new(LoadAddCsr)).unwrap();
+    assert!(cpu.add_custom(0x102b, 0x7000, Box::new(LoadAddCsr)).is_err());
+    assert!(cpu.add_custom_space(4, Box::new(LoadAddCsr)).is_err());
+    for (n, v) in [(10, 1), (11, 6), (12, 7)].iter() {
+        cpu.register().write(*n, *v, 4).unwrap();
+    }
+    cpu.csr().write(0x340, 5).unwrap();
+
+    cpu.step().unwrap();
+    assert!(cpu.retired().rd == Some((10, 43)) && cpu.get_pc() == 4);
+    cpu.register().write(11, 0x20, 4).unwrap();
+    cpu.step().unwrap();
+    let r = cpu.retired();
+    assert!(r.rd == Some((10, 105)) && r.next_pc == 0xc);
+    assert!(
+        r.mem
+            == vec![MemAccess {
+                addr: 0x20,
+                len: 4,
+                access: Access::Read,
+                data: 100,
+            }]
+    );
+    // a load outside memory traps with an access fault
+    cpu.register().write(11, 0x1000, 4).unwrap();
+    cpu.set_pc(4);
+    cpu.step().unwrap();
+    let trap: Vec<u64> = [0x342, 0x341, 0x343].iter().map(|&n| cpu.csr().read(n).unwrap()).collect();
+    assert!(trap == [5, 4, 0x1000] && cpu.get_pc() == 0);
+    // only funct3 1 of custom-1 was registered
+    cpu.set_pc(0xc);
+    assert!(cpu.step() == Err(String::from("No instruction")));
+}
@@ -0,0 +1,500 @@
+use crate::{
+    cpu::{op, parse_inst_length, rv32, Cpu},
+    csr, disasm,
+    elf::Elf,
+    register,
+};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+step|s [n]              execute n instructions
+next|n                  step over calls
+continue|c              run until a breakpoint or error
+break|b <loc> [if <cond>]  set a breakpoint at an address or symbol
+delete|d <n>            delete breakpoint n
+info breakpoints|b      list breakpoints
+info registers|r        show integer registers
+print|p <expr>          show a value: reg, pc, csr:<name>, *<addr>, symbol or number
+set <lval> = <expr>     modify a register, pc, csr:<name> or *<addr> (32-bit word)
+x <addr> [n]            dump n 32-bit words of memory
+bt                      backtrace from the shadow stack
+disas|l [n]             disassemble n instructions around pc
+quit|q                  leave the debugger
+conditions compare two exprs with == != < <= > >=, e.g. `b loop if a0 == 3`";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operand {
+    Reg(usize),
+    Pc,
+    Csr(usize),
+    Mem(u64),
+    Const(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+struct Condition {
+    lhs: Operand,
+    cmp: Cmp,
+    rhs: Operand,
+    text: String,
+}
+
+struct Breakpoint {
+    addr: u64,
+    cond: Option<Condition>,
+}
+
+/// Line-oriented REPL driving a `Cpu` one instruction at a time.
+pub struct Debugger {
+    cpu: Cpu,
+    elf: Option<Elf>,
+    breakpoints: Vec<Breakpoint>,
+    stopped: bool,
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu, elf: Option<Elf>) -> Self {
+        Self {
+            cpu,
+            elf,
+            breakpoints: Vec::new(),
+            stopped: false,
+        }
+    }
+
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, out: &mut W) -> io::Result<()> {
+        write!(out, "(rsdb) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            match self.command(line.trim()) {
+                Ok(Some(text)) => writeln!(out, "{}", text)?,
+                Ok(None) => return Ok(()),
+                Err(e) => writeln!(out, "error: {}", e)?,
+            }
+            write!(out, "(rsdb) ")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Runs one command line and returns its output, or `None` when asked to quit.
+    fn command(&mut self, line: &str) -> Result<Option<String>, String> {
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(c) => c,
+            None => return Ok(Some(String::new())),
+        };
+        let args: Vec<&str> = words.collect();
+        let text = match cmd {
+            "help" | "h" => String::from(HELP),
+            "quit" | "q" => return Ok(None),
+            "step" | "s" | "si" => {
+                let n = match args.first() {
+                    Some(n) => parse_number(n).ok_or("invalid count")?,
+                    None => 1,
+                };
+                for _ in 0..n {
+                    if let Some(stop) = self.step()? {
+                        return Ok(Some(stop));
+                    }
+                }
+                self.where_am_i()
+            }
+            "next" | "n" => self.next()?,
+            "continue" | "c" => self.cont()?,
+            "break" | "b" => {
+                let loc = args.first().ok_or("break needs a location")?;
+                let addr = self.location(loc)?;
+                let cond = match args.get(1) {
+                    Some(&"if") => Some(self.condition(&args[2..])?),
+                    Some(_) => return Err(String::from("expected `if <cond>`")),
+                    None => None,
+                };
+                self.breakpoints.push(Breakpoint { addr, cond });
+                format!("breakpoint {} at {}", self.breakpoints.len() - 1, self.describe(addr))
+            }
+            "delete" | "d" => {
+                let n = args.first().and_then(|n| parse_number(n)).ok_or("delete needs a breakpoint number")? as usize;
+                if n >= self.breakpoints.len() {
+                    return Err(format!("no breakpoint {}", n));
+                }
+                self.breakpoints.remove(n);
+                format!("deleted breakpoint {}", n)
+            }
+            "info" => match args.first() {
+                Some(&"breakpoints") | Some(&"b") => self.list_breakpoints(),
+                Some(&"registers") | Some(&"r") => self.registers()?,
+                _ => return Err(String::from("info breakpoints|registers")),
+            },
+            "print" | "p" => {
+                let operand = self.operand(args.first().ok_or("print needs an expression")?)?;
+                let v = self.value(operand)?;
+                format!("{:#x} ({})", v, v as i64)
+            }
+            "set" => {
+                if args.len() != 3 || args[1] != "=" {
+                    return Err(String::from("set <lval> = <expr>"));
+                }
+                let lhs = self.operand(args[0])?;
+                let v = self.value(self.operand(args[2])?)?;
+                self.assign(lhs, v)?;
+                format!("{} = {:#x}", args[0], v)
+            }
+            "x" => {
+                let addr = self.value(self.operand(args.first().ok_or("x needs an address")?)?)?;
+                let n = match args.get(1) {
+                    Some(n) => parse_number(n).ok_or("invalid count")?,
+                    None => 4,
+                };
+                self.dump(addr, n)?
+            }
+            "bt" | "backtrace" => self.backtrace(),
+            "disas" | "l" => {
+                let n = match args.first() {
+                    Some(n) => parse_number(n).ok_or("invalid count")?,
+                    None => 10,
+                };
+                self.disassemble_around(n)
+            }
+            _ => return Err(format!("unknown command `{}`, try `help`", cmd)),
+        };
+        Ok(Some(text))
+    }
+
+    /// Steps one instruction; returns the stop report when the program faults.
+    fn step(&mut self) -> Result<Option<String>, String> {
+        if self.stopped {
+            return Err(String::from("the program has stopped"));
+        }
+        if self.cpu.step().is_err() {
+            self.stopped = true;
+            let report = self.cpu.report(self.elf.as_ref()).expect("step failed without a report");
+            return Ok(Some(report.to_text()));
+        }
+        Ok(None)
+    }
+
+    fn hit_breakpoint(&mut self) -> Result<Option<usize>, String> {
+        let pc = self.cpu.get_pc();
+        for i in 0..self.breakpoints.len() {
+            if self.breakpoints[i].addr != pc {
+                continue;
+            }
+            let hit = match &self.breakpoints[i].cond {
+                Some(c) => {
+                    let (lhs, cmp, rhs) = (c.lhs, c.cmp, c.rhs);
+                    let (l, r) = (self.value(lhs)?, self.value(rhs)?);
+                    match cmp {
+                        Cmp::Eq => l == r,
+                        Cmp::Ne => l != r,
+                        Cmp::Lt => l < r,
+                        Cmp::Le => l <= r,
+                        Cmp::Gt => l > r,
+                        Cmp::Ge => l >= r,
+                    }
+                }
+                None => true,
+            };
+            if hit {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Steps until `done` holds or a breakpoint is reached. The current instruction always runs.
+    fn run_until<F: Fn(&Cpu) -> bool>(&mut self, done: F) -> Result<String, String> {
+        if let Some(stop) = self.step()? {
+            return Ok(stop);
+        }
+        loop {
+            if done(&self.cpu) {
+                return Ok(self.where_am_i());
+            }
+            if let Some(i) = self.hit_breakpoint()? {
+                return Ok(format!("breakpoint {}, {}", i, self.where_am_i()));
+            }
+            if let Some(stop) = self.step()? {
+                return Ok(stop);
+            }
+        }
+    }
+
+    fn cont(&mut self) -> Result<String, String> {
+        self.run_until(|_| false)
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let pc = self.cpu.get_pc();
+        let raw = self.fetch(pc)?;
+        let len = parse_inst_length(raw);
+        let inst = match len {
+            2 => Cpu::uncompress(raw as u32).unwrap_or(0),
+            _ => raw as u32,
+        };
+        let is_call = (rv32::get_op(inst) == op::JAL || rv32::get_op(inst) == op::JALR)
+            && rv32::get_rd(inst) == 1;
+        if !is_call {
+            return self.run_until(|_| true);
+        }
+        let depth = self.cpu.sstack().frames().len();
+        let ret = pc + len;
+        self.run_until(move |cpu| cpu.get_pc() == ret && cpu.sstack().frames().len() <= depth)
+    }
+
+    fn fetch(&mut self, addr: u64) -> Result<u64, String> {
+        if !self.cpu.mmu().contains(addr, 2) {
+            return Err(format!("cannot access memory at {:#x}", addr));
+        }
+        let n = if self.cpu.mmu().contains(addr, 4) { 4 } else { 2 };
+        Ok(self.cpu.mmu().peek(addr, n))
+    }
+
+    fn describe(&self, addr: u64) -> String {
+        let mut s = format!("{:#x}", addr);
+        if let Some(elf) = &self.elf {
+            if let Some((sym, off)) = elf.symbolize(addr) {
+                s += &format!(" <{}+{:#x}>", sym.name, off);
+            }
+            if let Some((file, line)) = elf.line(addr) {
+                s += &format!(" at {}:{}", file, line);
+            }
+        }
+        s
+    }
+
+    /// Disassembles `raw` at `pc` with symbols, as Zcmp or Zcmt where the cpu has them.
+    fn disassemble(&self, raw: u64, pc: u64) -> (String, u64) {
+        match self.cpu.is_zcm(raw) {
+            true => (disasm::disassemble_zcm(raw as u16), 2),
+            false => disasm::disassemble_sym(raw, pc, self.elf.as_ref()),
+        }
+    }
+
+    fn where_am_i(&mut self) -> String {
+        let pc = self.cpu.get_pc();
+        let inst = match self.fetch(pc) {
+            Ok(raw) => self.disassemble(raw, pc).0,
+            Err(e) => e,
+        };
+        format!("{}: {}", self.describe(pc), inst)
+    }
+
+    fn location(&self, loc: &str) -> Result<u64, String> {
+        let loc = loc.trim_start_matches('*');
+        if let Some(addr) = parse_number(loc) {
+            return Ok(addr);
+        }
+        self.elf
+            .as_ref()
+            .and_then(|elf| elf.symbols.iter().find(|s| s.name == loc))
+            .map(|s| s.addr)
+            .ok_or_else(|| format!("no symbol `{}`", loc))
+    }
+
+    fn operand(&self, s: &str) -> Result<Operand, String> {
+        if s == "pc" {
+            return Ok(Operand::Pc);
+        }
+        if let Some(n) = register::parse_name(s) {
+            return Ok(Operand::Reg(n));
+        }
+        if let Some(name) = s.strip_prefix("csr:") {
+            return csr::parse_name(name)
+                .map(Operand::Csr)
+                .ok_or_else(|| format!("unknown csr `{}`", name));
+        }
+        if let Some(addr) = s.strip_prefix('*') {
+            return Ok(Operand::Mem(self.location(addr)?));
+        }
+        Ok(Operand::Const(self.location(s)?))
+    }
+
+    fn condition(&self, words: &[&str]) -> Result<Condition, String> {
+        if words.len() != 3 {
+            return Err(String::from("conditions look like `<expr> <op> <expr>`"));
+        }
+        let cmp = match words[1] {
+            "==" => Cmp::Eq,
+            "!=" => Cmp::Ne,
+            "<" => Cmp::Lt,
+            "<=" => Cmp::Le,
+            ">" => Cmp::Gt,
+            ">=" => Cmp::Ge,
+            o => return Err(format!("unknown comparison `{}`", o)),
+        };
+        Ok(Condition {
+            lhs: self.operand(words[0])?,
+            cmp,
+            rhs: self.operand(words[2])?,
+            text: words.join(" "),
+        })
+    }
+
+    fn value(&mut self, operand: Operand) -> Result<u64, String> {
+        let len = self.cpu.get_xlen_bytes();
+        match operand {
+            Operand::Reg(n) => self.cpu.register().read(n, len),
+            Operand::Pc => Ok(self.cpu.get_pc()),
+            Operand::Csr(n) => self.cpu.csr().read(n),
+            Operand::Mem(addr) => {
+                if !self.cpu.mmu().contains(addr, 4) {
+                    return Err(format!("cannot access memory at {:#x}", addr));
+                }
+                Ok(self.cpu.mmu().peek(addr, 4))
+            }
+            Operand::Const(v) => Ok(v),
+        }
+    }
+
+    fn assign(&mut self, operand: Operand, v: u64) -> Result<(), String> {
+        let len = self.cpu.get_xlen_bytes();
+        match operand {
+            Operand::Reg(n) => self.cpu.register().write(n, v, len),
+            Operand::Pc => {
+                self.cpu.set_pc(v);
+                Ok(())
+            }
+            Operand::Csr(n) => self.cpu.csr().write(n, v),
+            Operand::Mem(addr) => {
+                if !self.cpu.mmu().contains(addr, 4) {
+                    return Err(format!("cannot access memory at {:#x}", addr));
+                }
+                self.cpu.mmu().poke(addr, &(v as u32).to_le_bytes());
+                Ok(())
+            }
+            Operand::Const(_) => Err(String::from("cannot assign to a constant")),
+        }
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return String::from("no breakpoints");
+        }
+        let mut lines = Vec::new();
+        for (i, b) in self.breakpoints.iter().enumerate() {
+            let mut line = format!("{}: {}", i, self.describe(b.addr));
+            if let Some(c) = &b.cond {
+                line += &format!(" if {}", c.text);
+            }
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    fn registers(&mut self) -> Result<String, String> {
+        let len = self.cpu.get_xlen_bytes();
+        let mut lines = vec![format!("pc   {:#010x}", self.cpu.get_pc())];
+        let count = self.cpu.register().count();
+        for (i, name) in register::ABI_NAMES.iter().enumerate().take(count) {
+            let v = self.cpu.register().read(i, len)?;
+            lines.push(format!("{:<4} {:#010x}  x{}", name, v, i));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn dump(&mut self, addr: u64, n: u64) -> Result<String, String> {
+        let mut lines = Vec::new();
+        for i in 0..n {
+            let a = i
+                .checked_mul(4)
+                .and_then(|offset| addr.checked_add(offset))
+                .ok_or_else(|| format!("cannot access memory past {:#x}", addr))?;
+            let v = self.value(Operand::Mem(a))?;
+            lines.push(format!("{:#010x}: {:#010x}", a, v));
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn backtrace(&self) -> String {
+        let mut lines = vec![format!("#0  {}", self.describe(self.cpu.get_pc()))];
+        for (i, &ra) in self.cpu.sstack().frames().iter().rev().enumerate() {
+            lines.push(format!("#{:<2} {}", i + 1, self.describe(ra)));
+        }
+        lines.join("\n")
+    }
+
+    fn disassemble_around(&mut self, n: u64) -> String {
+        let pc = self.cpu.get_pc();
+        let before = n / 2;
+        // decode forward from a point before pc, from the enclosing symbol when it is close
+        let mut start = pc.saturating_sub(4 * before);
+        if let Some((sym, _)) = self.elf.as_ref().and_then(|e| e.symbolize(pc)) {
+            start = start.max(sym.addr);
+        }
+        let mut lines = Vec::new();
+        let mut addr = start;
+        let mut after = 0;
+        while after < n - before {
+            let raw = match self.fetch(addr) {
+                Ok(raw) => raw,
+                Err(_) => break,
+            };
+            let (text, len) = self.disassemble(raw, addr);
+            let marker = if addr == pc { "=>" } else { "  " };
+            lines.push(format!("{} {:#010x}: {}", marker, addr, text));
+            if addr >= pc {
+                after += 1;
+            }
+            addr += len;
+        }
+        let skip = lines.iter().position(|l| l.starts_with("=>")).unwrap_or(0).saturating_sub(before as usize);
+        lines[skip..].join("\n")
+    }
+}
+
+#[cfg(test)]
+fn test_debugger() -> Debugger {
+    // call f; loop: j loop; f: addi ra, ra, 4; ret
+    let mut mem = Vec::new();
+    for inst in [0x008000efu32, 0x0000006f, 0x00408093, 0x00008067].iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(64, 0);
+    let cpu = Cpu::with_memory(mem);
+    Debugger::new(cpu, None)
+}
+
+#[test]
+fn test_breakpoints_and_inspection() {
+    let mut dbg = test_debugger();
+    dbg.command("b 0x8 if ra == 0x10").unwrap();
+    dbg.command("b 0xc if ra >= 8").unwrap();
+    let out = dbg.command("c").unwrap().unwrap();
+    assert!(out.starts_with("breakpoint 1, 0xc"));
+    assert!(dbg.command("p ra").unwrap().unwrap().starts_with("0x8"));
+    assert!(dbg.command("bt").unwrap().unwrap().contains("#1  0x4"));
+    dbg.command("set ra = 4").unwrap();
+    assert!(dbg.command("p x1").unwrap().unwrap().starts_with("0x4"));
+    assert!(dbg.command("x 0 1").unwrap().unwrap() == "0x00000000: 0x008000ef");
+    assert!(dbg.command("x 0xffffffffffffffff").is_err() && dbg.command("x 0x3c 2").is_err());
+    assert!(dbg.command("disas 2").unwrap().unwrap().contains("=> 0x0000000c: ret"));
+    dbg.command("s").unwrap();
+    assert!(dbg.cpu.get_pc() == 4);
+    assert!(dbg.command("q").unwrap().is_none());
+}
+
+#[test]
+fn test_next_and_violation() {
+    let mut dbg = test_debugger();
+    let out = dbg.command("n").unwrap().unwrap();
+    assert!(out.contains("shadow stack violation"));
+    assert!(dbg.command("s").is_err());
+}
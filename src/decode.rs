@@ -0,0 +1,1366 @@
+use crate::{
+    cpu::{exception, f3b, f3c, f3i, f3l, f3r, f3s, f5fp, op, rv32},
+    float::{Format, F16, F32, F64},
+    mmu::PAGE_SHIFT,
+};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsrOp {
+    Rw,
+    Rs,
+    Rc,
+    Rwi,
+    Rsi,
+    Rci,
+}
+
+/// Fused multiply-add, `rs1 * rs2 + rs3` with the product or the addend negated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FmaOp {
+    Madd,
+    /// subtracts the addend
+    Msub,
+    /// negates the product
+    Nmsub,
+    /// negates both
+    Nmadd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FpOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Sqrt,
+    Sgnj,
+    Sgnjn,
+    Sgnjx,
+    Min,
+    Max,
+    /// fcvt from another format to this one
+    Cvt { from: Format },
+    Eq,
+    Lt,
+    Le,
+    /// fcvt.w and fcvt.wu, into an x register
+    ToInt { signed: bool },
+    /// fcvt from w and wu, out of an x register
+    FromInt { signed: bool },
+    /// fmv.x, the raw bits into an x register
+    MoveToInt,
+    Class,
+    /// fmv from x
+    MoveFromInt,
+}
+
+/// Bit-manipulation operations of Zba, Zbb, Zbc and Zbs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BitOp {
+    Andn,
+    Orn,
+    Xnor,
+    Min,
+    Minu,
+    Max,
+    Maxu,
+    Rol,
+    Ror,
+    Clz,
+    Ctz,
+    Cpop,
+    SextB,
+    SextH,
+    ZextH,
+    OrcB,
+    Rev8,
+    Clmul,
+    Clmulh,
+    Clmulr,
+    /// sh1add to sh3add, and add.uw as the `w` form of a shift by 0
+    Shadd(u32),
+    /// slli.uw, only a `w` form
+    SlliUw,
+    Bclr,
+    Bext,
+    Binv,
+    Bset,
+    /// pack and packw, the low halves of rs1 and rs2; zext.h is pack with x0 on RV32
+    Pack,
+    Packh,
+    Brev8,
+    /// RV32 only
+    Zip,
+    Unzip,
+    Xperm4,
+    Xperm8,
+}
+
+/// Scalar cryptography operations of Zkn and Zks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CryptoOp {
+    Aes32Esi,
+    Aes32Esmi,
+    Aes32Dsi,
+    Aes32Dsmi,
+    Aes64Es,
+    Aes64Esm,
+    Aes64Ds,
+    Aes64Dsm,
+    Aes64Im,
+    Aes64Ks1i,
+    Aes64Ks2,
+    Sha256Sig0,
+    Sha256Sig1,
+    Sha256Sum0,
+    Sha256Sum1,
+    /// the RV32 halves of the SHA-512 functions, on a value split over rs1 and rs2
+    Sha512Sig0h,
+    Sha512Sig0l,
+    Sha512Sig1h,
+    Sha512Sig1l,
+    Sha512Sum0r,
+    Sha512Sum1r,
+    Sha512Sig0,
+    Sha512Sig1,
+    Sha512Sum0,
+    Sha512Sum1,
+    Sm4Ed,
+    Sm4Ks,
+    Sm3P0,
+    Sm3P1,
+}
+
+/// The operand of a vector instruction besides vs2: vs1, an x or f register, or the
+/// immediate, sign extended unless the operation takes it unsigned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VSrc {
+    V(usize),
+    X(usize),
+    F(usize),
+    Imm(u64),
+}
+
+/// How a vector load or store addresses its elements.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VMode {
+    Unit,
+    /// by the byte stride in rs2
+    Strided,
+    /// by the byte offsets in vs2, in element order if `ordered`
+    Indexed { ordered: bool },
+    /// vlm.v and vsm.v, the bytes of a mask
+    Mask,
+    /// vl<nf>re<eew>.v and vs<nf>r.v, whole registers regardless of vtype and vl
+    Whole,
+    /// unit stride, shortening vl instead of trapping past the first element
+    FaultFirst,
+}
+
+/// Kinds of number vector conversions go between.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VNum {
+    Unsigned,
+    Signed,
+    Float,
+}
+
+/// Vector operations. Unless noted they are element-wise on SEW-bit elements of vs2 and the
+/// other operand; `W` names take vs2 at twice SEW, and `w` prefixes give twice SEW results.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VOp {
+    Add,
+    Sub,
+    /// the operand minus vs2
+    Rsub,
+    Minu,
+    Min,
+    Maxu,
+    Max,
+    And,
+    Or,
+    Xor,
+    /// vs2 elements picked by the operand, or by 16-bit vs1 elements
+    Rgather,
+    Rgatherei16,
+    Slideup,
+    Slidedown,
+    /// with the carry or borrow in v0
+    Adc,
+    Sbc,
+    /// the carry or borrow out as a mask, with the one in v0 if masked
+    Madc,
+    Msbc,
+    /// vmerge, or vmv.v when unmasked
+    Merge,
+    /// compares into a mask: vs2 against the operand
+    Mseq,
+    Msne,
+    Msltu,
+    Mslt,
+    Msleu,
+    Msle,
+    Msgtu,
+    Msgt,
+    Saddu,
+    Sadd,
+    Ssubu,
+    Ssub,
+    Sll,
+    /// fractional multiply, rounded by vxrm and saturated
+    Smul,
+    /// vmv<nr>r.v, copying whole registers
+    MvWhole(u32),
+    Srl,
+    Sra,
+    /// shifts rounding by vxrm
+    Ssrl,
+    Ssra,
+    /// narrowing shifts of vs2 at twice SEW, and the saturating ones
+    Nsrl,
+    Nsra,
+    Nclipu,
+    Nclip,
+    /// reductions into element 0 of vd from element 0 of vs1 and the active vs2 elements
+    Redsum,
+    Redand,
+    Redor,
+    Redxor,
+    Redminu,
+    Redmin,
+    Redmaxu,
+    Redmax,
+    Wredsumu,
+    Wredsum,
+    /// averaging adds and subtracts, rounded by vxrm
+    Aaddu,
+    Aadd,
+    Asubu,
+    Asub,
+    Slide1up,
+    Slide1down,
+    /// vmv.x.s, element 0 to rd
+    MvXS,
+    /// active set bits of the vs2 mask to rd, and the index of the first or -1
+    Cpop,
+    First,
+    /// vmv.s.x, rs1 to element 0
+    MvSX,
+    /// vzext and vsext from SEW divided by `factor`
+    Ext { factor: u32, signed: bool },
+    /// set before, only or including the first set bit of the vs2 mask
+    Msbf,
+    Msof,
+    Msif,
+    /// each element the count of set vs2 mask bits before it
+    Iota,
+    /// each element its index
+    Id,
+    /// the vs2 elements selected by the vs1 mask, packed
+    Compress,
+    /// mask logical operations on vs2 and vs1
+    Mandn,
+    Mand,
+    Mor,
+    Mxor,
+    Morn,
+    Mnand,
+    Mnor,
+    Mxnor,
+    Divu,
+    Div,
+    Remu,
+    Rem,
+    Mulhu,
+    Mul,
+    /// signed vs2 times the unsigned operand
+    Mulhsu,
+    Mulh,
+    /// vd times the operand plus vs2, or minus from vs2
+    Madd,
+    Nmsub,
+    /// the operand times vs2 added to vd, or subtracted from it
+    Macc,
+    Nmsac,
+    Waddu,
+    Wadd,
+    Wsubu,
+    Wsub,
+    WadduW,
+    WaddW,
+    WsubuW,
+    WsubW,
+    Wmulu,
+    /// signed vs2 times the unsigned operand
+    Wmulsu,
+    Wmul,
+    Wmaccu,
+    Wmacc,
+    /// the unsigned operand times signed vs2
+    Wmaccus,
+    /// the signed operand times unsigned vs2
+    Wmaccsu,
+    Fadd,
+    Fsub,
+    Frsub,
+    Fmin,
+    Fmax,
+    /// the unordered sum may add in any order; this one is ordered as well
+    Fredusum,
+    Fredosum,
+    Fredmin,
+    Fredmax,
+    Fsgnj,
+    Fsgnjn,
+    Fsgnjx,
+    Fslide1up,
+    Fslide1down,
+    /// vfmv.f.s and vfmv.s.f
+    FmvFS,
+    FmvSF,
+    /// vfcvt, vfwcvt or vfncvt as `width` is SEW, twice or half it, truncating if `rtz`
+    Fcvt { from: VNum, to: VNum, width: i32, rtz: bool },
+    /// vfncvt.rod.f.f.w, rounding to odd
+    FncvtRod,
+    Fsqrt,
+    Frsqrt7,
+    Frec7,
+    Fclass,
+    /// vfmerge, or vfmv.v.f when unmasked
+    Fmerge,
+    Mfeq,
+    Mfle,
+    Mflt,
+    Mfne,
+    Mfgt,
+    Mfge,
+    Fdiv,
+    /// the operand divided by vs2
+    Frdiv,
+    Fmul,
+    /// the operand times vd, plus or minus vs2
+    Fmadd,
+    Fnmadd,
+    Fmsub,
+    Fnmsub,
+    /// the operand times vs2, plus or minus vd
+    Fmacc,
+    Fnmacc,
+    Fmsac,
+    Fnmsac,
+    Fwadd,
+    Fwsub,
+    FwaddW,
+    FwsubW,
+    Fwmul,
+    Fwmacc,
+    Fwnmacc,
+    Fwmsac,
+    Fwnmsac,
+    Fwredusum,
+    Fwredosum,
+}
+
+/// The Zcmp stack instructions: cm.push, and cm.pop, also returning to ra, with a0 zeroed first
+/// for popretz.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PushPop {
+    Push,
+    Pop,
+    Popret,
+    Popretz,
+}
+
+/// ra, s0 and s1, and s2 to s11, in the order Zcmp register lists take them.
+pub const ZCMP_REGS: [usize; 13] = [1, 8, 9, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27];
+
+/// A decoded instruction. Register numbers and immediates are extracted once, in the form the
+/// executing code uses them, so execution does not look at the encoding again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    Lui { rd: usize, imm: u64 },
+    Auipc { rd: usize, imm: u64 },
+    Jal { rd: usize, offset: u64 },
+    Jalr { rd: usize, rs1: usize, imm: u64 },
+    Branch { cond: Cond, rs1: usize, rs2: usize, offset: u64 },
+    Lb { rd: usize, rs1: usize, offset: u32 },
+    Lh { rd: usize, rs1: usize, offset: u32 },
+    Lw { rd: usize, rs1: usize, offset: u32 },
+    Lbu { rd: usize, rs1: usize, offset: u32 },
+    Lhu { rd: usize, rs1: usize, offset: u32 },
+    Sb { rs1: usize, rs2: usize, offset: u64 },
+    Sh { rs1: usize, rs2: usize, offset: u64 },
+    Sw { rs1: usize, rs2: usize, offset: u64 },
+    Addi { rd: usize, rs1: usize, imm: u64 },
+    Slti { rd: usize, rs1: usize, imm: u64 },
+    Sltiu { rd: usize, rs1: usize, imm: u64 },
+    Xori { rd: usize, rs1: usize, imm: u32 },
+    Ori { rd: usize, rs1: usize, imm: u32 },
+    Andi { rd: usize, rs1: usize, imm: u32 },
+    Slli { rd: usize, rs1: usize, shamt: u32 },
+    Srli { rd: usize, rs1: usize, shamt: u32 },
+    Srai { rd: usize, rs1: usize, shamt: u32 },
+    /// add, or sub when bit 5 of funct7 is set
+    AddSub { rd: usize, rs1: usize, rs2: usize, funct7: u64 },
+    Ecall,
+    Sret,
+    Mret,
+    /// sfence.vma; there is no TLB to flush, so it only checks it may be executed
+    SfenceVma { rs1: usize, rs2: usize },
+    /// hfence.vvma, or hfence.gvma if `gvma`
+    Hfence { gvma: bool, rs1: usize, rs2: usize },
+    /// hlv, loading `width` bytes as VS or VU mode would, and hlvx if `exec`, needing execute
+    /// rather than read permission
+    Hlv { rd: usize, rs1: usize, width: u64, signed: bool, exec: bool },
+    /// hsv, storing `width` bytes as VS or VU mode would
+    Hsv { rs1: usize, rs2: usize, width: u64 },
+    /// `rs1` is the immediate of the immediate forms
+    Csr { op: CsrOp, rd: usize, rs1: usize, csr: usize },
+    Fence,
+    FenceI,
+    /// flw and fld
+    Fload { fmt: Format, rd: usize, rs1: usize, offset: u64 },
+    /// fsw and fsd
+    Fstore { fmt: Format, rs1: usize, rs2: usize, offset: u64 },
+    /// `rm` is the rounding mode field, 7 to use frm
+    Fma {
+        op: FmaOp,
+        fmt: Format,
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+        rs3: usize,
+        rm: u32,
+    },
+    /// an OP-FP instruction on values of `fmt`; `rm` is the rounding mode field, 7 to use frm
+    Fp {
+        op: FpOp,
+        fmt: Format,
+        rd: usize,
+        rs1: usize,
+        rs2: usize,
+        rm: u32,
+    },
+    /// a Zba/Zbb/Zbc/Zbs instruction on rs1 and rs2. `word` is the RV64 form on the low 32 bits,
+    /// from OP-32 or the `.uw` ones, zero extending rs1
+    Bitmanip { op: BitOp, rd: usize, rs1: usize, rs2: usize, word: bool },
+    /// the immediate and unary forms; `imm` is the shift amount field, 0 for unary ones
+    BitmanipImm { op: BitOp, rd: usize, rs1: usize, imm: u32, word: bool },
+    /// a Zkn/Zks instruction; `imm` is the byte select of the RV32 AES and SM4 ones, or the
+    /// round number of aes64ks1i
+    Crypto { op: CryptoOp, rd: usize, rs1: usize, rs2: usize, imm: u32 },
+    /// vsetvli and vsetivli with the vtype immediate, or vsetvl with vtype in rs2; `avl` is
+    /// rs1 or the vsetivli immediate
+    Vset { rd: usize, avl: VSrc, vtype: VSrc },
+    /// a vector load of `nf` fields per segment; `eew` is the element width of the encoding,
+    /// that of the indices for indexed loads. `rs2` is the stride or index register
+    Vload {
+        mode: VMode,
+        eew: u32,
+        nf: u32,
+        vd: usize,
+        rs1: usize,
+        rs2: usize,
+        vm: bool,
+    },
+    Vstore {
+        mode: VMode,
+        eew: u32,
+        nf: u32,
+        vs3: usize,
+        rs1: usize,
+        rs2: usize,
+        vm: bool,
+    },
+    /// an OP-V arithmetic instruction, masked by v0 unless `vm`
+    Vector { op: VOp, vd: usize, vs2: usize, src: VSrc, vm: bool },
+    /// Zcmp cm.push and the cm.pop family, on the first `regs` of `ZCMP_REGS`, moving sp by
+    /// `stack_adj`
+    PushPop { op: PushPop, regs: usize, stack_adj: u64 },
+    /// Zcmp cm.mvsa01, copying a0 and a1 into `r1s` and `r2s`, or cm.mva01s the other way if
+    /// `to_a`
+    Mv01s { r1s: usize, r2s: usize, to_a: bool },
+    /// Zcmt cm.jt through entry `index` of the jump table at jvt, cm.jalt linking ra from 32
+    TableJump { index: u64 },
+    /// registered with `Cpu::add_custom`, executed by the `index`th registration
+    Custom { index: usize, inst: u32 },
+}
+
+impl Instruction {
+    /// Decodes a 16-bit Zcmp or Zcmt instruction. They all take the encodings of c.fsdsp, and
+    /// have no 32-bit equivalent to expand to.
+    pub fn decode_zcm(inst: u16) -> Result<Instruction, String> {
+        let inst = inst as u32;
+        // s0 and s1, then s2 to s7
+        let sreg = |n: u32| if n < 2 { 8 + n as usize } else { 16 + n as usize };
+        match rv32::get_bits(inst, 12, 10) {
+            0b000 => Ok(Instruction::TableJump {
+                index: rv32::get_bits(inst, 9, 2) as u64,
+            }),
+            0b011 => {
+                let (r1s, r2s) = (sreg(rv32::get_bits(inst, 9, 7)), sreg(rv32::get_bits(inst, 4, 2)));
+                match rv32::get_bits(inst, 6, 5) {
+                    0b01 | 0b11 if r1s != r2s => Ok(Instruction::Mv01s {
+                        r1s,
+                        r2s,
+                        to_a: rv32::get_bits(inst, 6, 5) == 0b11,
+                    }),
+                    _ => Err(String::from("No such Zcmp move")),
+                }
+            }
+            _ => {
+                let op = match rv32::get_bits(inst, 12, 8) {
+                    0b11000 => PushPop::Push,
+                    0b11010 => PushPop::Pop,
+                    0b11100 => PushPop::Popretz,
+                    0b11110 => PushPop::Popret,
+                    _ => return Err(String::from("No such Zcmp or Zcmt inst")),
+                };
+                // ra alone up to ra and s0 to s11, s10 never without s11
+                let (regs, base) = match rv32::get_bits(inst, 7, 4) {
+                    rlist @ 4..=7 => (rlist - 3, 16),
+                    rlist @ 8..=11 => (rlist - 3, 32),
+                    rlist @ 12..=14 => (rlist - 3, 48),
+                    15 => (13, 64),
+                    _ => return Err(String::from("Reserved Zcmp register list")),
+                };
+                Ok(Instruction::PushPop {
+                    op,
+                    regs: regs as usize,
+                    stack_adj: (base + 16 * rv32::get_bits(inst, 3, 2)) as u64,
+                })
+            }
+        }
+    }
+    /// Decodes a 32-bit RV32I/Zicsr/F/D/Zfh/Zb*/Zk*/V instruction.
+    pub fn decode(inst: u32) -> Result<Instruction, String> {
+        let rd = rv32::get_rd(inst);
+        let rs1 = rv32::get_rs1(inst);
+        let rs2 = rv32::get_rs2(inst);
+        if let Some(i) = decode_bitmanip(inst).or_else(|| decode_crypto(inst)) {
+            return Ok(i);
+        }
+        let i = match rv32::get_op(inst) {
+            op::LUDI => Instruction::Lui {
+                rd,
+                imm: (rv32::get_bits_extended(inst, 31, 12) << 12) as u64,
+            },
+            op::AUIPC => Instruction::Auipc {
+                rd,
+                imm: (rv32::get_bits_extended(inst, 31, 12) << 12) as u64,
+            },
+            op::JAL => Instruction::Jal {
+                rd,
+                offset: rv32::get_imm_jal(inst) as u64,
+            },
+            op::JALR => Instruction::Jalr {
+                rd,
+                rs1,
+                imm: rv32::get_bits_extended(inst, 31, 20) as u64,
+            },
+            op::BRANCH => Instruction::Branch {
+                cond: match rv32::get_funct3(inst) {
+                    f3b::BEQ => Cond::Eq,
+                    f3b::BNE => Cond::Ne,
+                    f3b::BLT => Cond::Lt,
+                    f3b::BGE => Cond::Ge,
+                    f3b::BLTU => Cond::Ltu,
+                    f3b::BGEU => Cond::Geu,
+                    _ => return Err(String::from("No inst on branch")),
+                },
+                rs1,
+                rs2,
+                offset: rv32::sign_extend(rv32::get_imm_branch(inst), 12) as u64,
+            },
+            op::LD => {
+                let offset = rv32::get_bits_extended(inst, 31, 20);
+                match rv32::get_funct3(inst) {
+                    f3l::LB => Instruction::Lb { rd, rs1, offset },
+                    f3l::LH => Instruction::Lh { rd, rs1, offset },
+                    f3l::LW => Instruction::Lw { rd, rs1, offset },
+                    f3l::LBU => Instruction::Lbu { rd, rs1, offset },
+                    f3l::LHU => Instruction::Lhu { rd, rs1, offset },
+                    _ => return Err(String::from("No inst on load")),
+                }
+            }
+            op::STORE => {
+                let offset = rv32::sign_extend(rv32::get_imm_st(inst), 11) as u64;
+                match rv32::get_funct3(inst) {
+                    f3s::SB => Instruction::Sb { rs1, rs2, offset },
+                    f3s::SH => Instruction::Sh { rs1, rs2, offset },
+                    f3s::SW => Instruction::Sw { rs1, rs2, offset },
+                    _ => return Err(String::from("No inst on store")),
+                }
+            }
+            op::AIMM => {
+                // sign extended, sltiu included; the shift amount keeps bit 5, which RV32 reserves
+                let imm = rv32::get_bits_extended(inst, 31, 20);
+                let shamt = rv32::get_bits(inst, 25, 20);
+                match (rv32::get_funct3(inst), rv32::get_bits(inst, 31, 26)) {
+                    (f3i::ADDI, _) => Instruction::Addi { rd, rs1, imm: imm as u64 },
+                    (f3i::SLTI, _) => Instruction::Slti { rd, rs1, imm: imm as u64 },
+                    (f3i::SLTIU, _) => Instruction::Sltiu { rd, rs1, imm: imm as u64 },
+                    (f3i::XORI, _) => Instruction::Xori { rd, rs1, imm },
+                    (f3i::ORI, _) => Instruction::Ori { rd, rs1, imm },
+                    (f3i::ANDI, _) => Instruction::Andi { rd, rs1, imm },
+                    (f3i::SLLI, 0) => Instruction::Slli { rd, rs1, shamt },
+                    (f3i::SRLI_SRAI, 0) => Instruction::Srli { rd, rs1, shamt },
+                    (f3i::SRLI_SRAI, 0b010000) => Instruction::Srai { rd, rs1, shamt },
+                    _ => return Err(String::from("No inst on arithmatic immediate")),
+                }
+            }
+            op::AREG => match rv32::get_funct3(inst) {
+                f3r::ADD_SUB => Instruction::AddSub {
+                    rd,
+                    rs1,
+                    rs2,
+                    funct7: rv32::get_bits(inst, 31, 25) as u64,
+                },
+                _ => return Err(String::from("No inst on arithmatic register")),
+            },
+            op::CSR => {
+                let csr = rv32::get_bits(inst, 31, 20) as usize;
+                let op = match rv32::get_funct3(inst) {
+                    f3c::EXCEPT => {
+                        return match (rv32::get_bits(inst, 31, 20), rv32::get_bits(inst, 31, 25)) {
+                            (exception::ECALL, _) => Ok(Instruction::Ecall),
+                            (exception::SRET, _) => Ok(Instruction::Sret),
+                            (exception::MRET, _) => Ok(Instruction::Mret),
+                            (_, exception::SFENCE_VMA) => Ok(Instruction::SfenceVma { rs1, rs2 }),
+                            (_, exception::HFENCE_VVMA) => Ok(Instruction::Hfence { gvma: false, rs1, rs2 }),
+                            (_, exception::HFENCE_GVMA) => Ok(Instruction::Hfence { gvma: true, rs1, rs2 }),
+                            _ => Err(String::from("No inst on CSR EXCEPTION")),
+                        }
+                    }
+                    f3c::HLSV => return decode_hlsv(inst),
+                    f3c::CSRRW => CsrOp::Rw,
+                    f3c::CSRRS => CsrOp::Rs,
+                    f3c::CSRRC => CsrOp::Rc,
+                    f3c::CSRRWI => CsrOp::Rwi,
+                    f3c::CSRRSI => CsrOp::Rsi,
+                    f3c::CSRRCI => CsrOp::Rci,
+                    _ => return Err(String::from("No inst on CSR")),
+                };
+                Instruction::Csr { op, rd, rs1, csr }
+            }
+            op::FENCE => match rv32::get_funct3(inst) {
+                0b001 => Instruction::FenceI,
+                _ => Instruction::Fence,
+            },
+            op::LOAD_FP | op::STORE_FP | op::OP_V if is_vector(inst) => return decode_vector(inst),
+            op::LOAD_FP => Instruction::Fload {
+                fmt: fp_width(rv32::get_funct3(inst))?,
+                rd,
+                rs1,
+                offset: rv32::get_bits_extended(inst, 31, 20) as u64,
+            },
+            op::STORE_FP => Instruction::Fstore {
+                fmt: fp_width(rv32::get_funct3(inst))?,
+                rs1,
+                rs2,
+                offset: rv32::sign_extend(rv32::get_imm_st(inst), 11) as u64,
+            },
+            op::MADD | op::MSUB | op::NMSUB | op::NMADD => Instruction::Fma {
+                op: match rv32::get_op(inst) {
+                    op::MADD => FmaOp::Madd,
+                    op::MSUB => FmaOp::Msub,
+                    op::NMSUB => FmaOp::Nmsub,
+                    _ => FmaOp::Nmadd,
+                },
+                fmt: fp_format(rv32::get_bits(inst, 26, 25))?,
+                rd,
+                rs1,
+                rs2,
+                rs3: rv32::get_bits(inst, 31, 27) as usize,
+                rm: rv32::get_funct3(inst),
+            },
+            op::OP_FP => {
+                let fmt = fp_format(rv32::get_bits(inst, 26, 25))?;
+                let rm = rv32::get_funct3(inst);
+                let op = match (rv32::get_bits(inst, 31, 27), rm, rs2) {
+                    (f5fp::ADD, ..) => FpOp::Add,
+                    (f5fp::SUB, ..) => FpOp::Sub,
+                    (f5fp::MUL, ..) => FpOp::Mul,
+                    (f5fp::DIV, ..) => FpOp::Div,
+                    (f5fp::SQRT, _, 0) => FpOp::Sqrt,
+                    (f5fp::SGNJ, 0, _) => FpOp::Sgnj,
+                    (f5fp::SGNJ, 1, _) => FpOp::Sgnjn,
+                    (f5fp::SGNJ, 2, _) => FpOp::Sgnjx,
+                    (f5fp::MIN_MAX, 0, _) => FpOp::Min,
+                    (f5fp::MIN_MAX, 1, _) => FpOp::Max,
+                    (f5fp::CVT_FMT, _, from) if fp_format(from as u32)? != fmt => FpOp::Cvt {
+                        from: fp_format(from as u32)?,
+                    },
+                    (f5fp::CMP, 2, _) => FpOp::Eq,
+                    (f5fp::CMP, 1, _) => FpOp::Lt,
+                    (f5fp::CMP, 0, _) => FpOp::Le,
+                    (f5fp::CVT_TO_INT, _, 0) => FpOp::ToInt { signed: true },
+                    (f5fp::CVT_TO_INT, _, 1) => FpOp::ToInt { signed: false },
+                    (f5fp::CVT_FROM_INT, _, 0) => FpOp::FromInt { signed: true },
+                    (f5fp::CVT_FROM_INT, _, 1) => FpOp::FromInt { signed: false },
+                    // fmv.x.d and fmv.d.x are RV64 only
+                    (f5fp::MV_TO_INT_CLASS, 0, 0) if fmt != F64 => FpOp::MoveToInt,
+                    (f5fp::MV_TO_INT_CLASS, 1, 0) => FpOp::Class,
+                    (f5fp::MV_FROM_INT, 0, 0) if fmt != F64 => FpOp::MoveFromInt,
+                    _ => return Err(String::from("No inst on OP-FP")),
+                };
+                Instruction::Fp {
+                    op,
+                    fmt,
+                    rd,
+                    rs1,
+                    rs2,
+                    rm,
+                }
+            }
+            _ => return Err(String::from("No instruction")),
+        };
+        Ok(i)
+    }
+}
+
+/// Decodes the Zba/Zbb/Zbc/Zbs instructions, which share OP and OP-IMM with the base ones.
+fn decode_bitmanip(inst: u32) -> Option<Instruction> {
+    let (rd, rs1, rs2) = (rv32::get_rd(inst), rv32::get_rs1(inst), rv32::get_rs2(inst));
+    let funct3 = rv32::get_funct3(inst);
+    let funct7 = rv32::get_bits(inst, 31, 25);
+    let imm = rv32::get_bits(inst, 25, 20);
+    let reg = |op, word| Some(Instruction::Bitmanip { op, rd, rs1, rs2, word });
+    let immediate = |op, imm, word| Some(Instruction::BitmanipImm { op, rd, rs1, imm, word });
+    let word = matches!(rv32::get_op(inst), op::AIMM32 | op::AREG32);
+    match rv32::get_op(inst) {
+        op::AREG | op::AREG32 => match (funct7, funct3) {
+            (0b0100000, 0b111) if !word => reg(BitOp::Andn, false),
+            (0b0100000, 0b110) if !word => reg(BitOp::Orn, false),
+            (0b0100000, 0b100) if !word => reg(BitOp::Xnor, false),
+            (0b0000101, 0b100) if !word => reg(BitOp::Min, false),
+            (0b0000101, 0b101) if !word => reg(BitOp::Minu, false),
+            (0b0000101, 0b110) if !word => reg(BitOp::Max, false),
+            (0b0000101, 0b111) if !word => reg(BitOp::Maxu, false),
+            (0b0000101, 0b001) if !word => reg(BitOp::Clmul, false),
+            (0b0000101, 0b011) if !word => reg(BitOp::Clmulh, false),
+            (0b0000101, 0b010) if !word => reg(BitOp::Clmulr, false),
+            (0b0110000, 0b001) => reg(BitOp::Rol, word),
+            (0b0110000, 0b101) => reg(BitOp::Ror, word),
+            (0b0010000, 0b010) => reg(BitOp::Shadd(1), word),
+            (0b0010000, 0b100) => reg(BitOp::Shadd(2), word),
+            (0b0010000, 0b110) => reg(BitOp::Shadd(3), word),
+            (0b0000100, 0b000) if word => reg(BitOp::Shadd(0), true),
+            (0b0000100, 0b100) if rs2 == 0 => immediate(BitOp::ZextH, 0, word),
+            (0b0000100, 0b100) => reg(BitOp::Pack, word),
+            (0b0000100, 0b111) if !word => reg(BitOp::Packh, false),
+            (0b0010100, 0b100) if !word => reg(BitOp::Xperm8, false),
+            (0b0010100, 0b010) if !word => reg(BitOp::Xperm4, false),
+            (0b0100100, 0b001) if !word => reg(BitOp::Bclr, false),
+            (0b0100100, 0b101) if !word => reg(BitOp::Bext, false),
+            (0b0110100, 0b001) if !word => reg(BitOp::Binv, false),
+            (0b0010100, 0b001) if !word => reg(BitOp::Bset, false),
+            _ => None,
+        },
+        op::AIMM | op::AIMM32 => match (rv32::get_bits(inst, 31, 20), funct3) {
+            (0x600, 0b001) => immediate(BitOp::Clz, 0, word),
+            (0x601, 0b001) => immediate(BitOp::Ctz, 0, word),
+            (0x602, 0b001) => immediate(BitOp::Cpop, 0, word),
+            (0x604, 0b001) if !word => immediate(BitOp::SextB, 0, false),
+            (0x605, 0b001) if !word => immediate(BitOp::SextH, 0, false),
+            (0x287, 0b101) if !word => immediate(BitOp::OrcB, 0, false),
+            (0x687, 0b101) if !word => immediate(BitOp::Brev8, 0, false),
+            (0x08f, 0b001) if !word => immediate(BitOp::Zip, 0, false),
+            (0x08f, 0b101) if !word => immediate(BitOp::Unzip, 0, false),
+            // rev8 encodes XLEN - 8 as its shift amount
+            (0x698, 0b101) | (0x6b8, 0b101) if !word => immediate(BitOp::Rev8, imm, false),
+            (_, 0b001) if word && funct7 >> 1 == 0b000010 => immediate(BitOp::SlliUw, imm, true),
+            (_, 0b101) if funct7 >> 1 == 0b011000 && !(word && imm >= 32) => immediate(BitOp::Ror, imm, word),
+            (_, 0b001) if !word && funct7 >> 1 == 0b010010 => immediate(BitOp::Bclr, imm, false),
+            (_, 0b101) if !word && funct7 >> 1 == 0b010010 => immediate(BitOp::Bext, imm, false),
+            (_, 0b001) if !word && funct7 >> 1 == 0b011010 => immediate(BitOp::Binv, imm, false),
+            (_, 0b001) if !word && funct7 >> 1 == 0b001010 => immediate(BitOp::Bset, imm, false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Decodes the Zkn/Zks instructions, also in OP and OP-IMM.
+fn decode_crypto(inst: u32) -> Option<Instruction> {
+    let (rd, rs1, rs2) = (rv32::get_rd(inst), rv32::get_rs1(inst), rv32::get_rs2(inst));
+    let crypto = |op, imm| Some(Instruction::Crypto { op, rd, rs1, rs2, imm });
+    let bs = rv32::get_bits(inst, 31, 30);
+    match (rv32::get_op(inst), rv32::get_funct3(inst)) {
+        (op::AREG, 0b000) => match (rv32::get_bits(inst, 29, 25), rv32::get_bits(inst, 31, 25)) {
+            (0b10001, _) => crypto(CryptoOp::Aes32Esi, bs),
+            (0b10011, _) => crypto(CryptoOp::Aes32Esmi, bs),
+            (0b10101, _) => crypto(CryptoOp::Aes32Dsi, bs),
+            (0b10111, _) => crypto(CryptoOp::Aes32Dsmi, bs),
+            (0b11000, _) => crypto(CryptoOp::Sm4Ed, bs),
+            (0b11010, _) => crypto(CryptoOp::Sm4Ks, bs),
+            (_, 0b0101000) => crypto(CryptoOp::Sha512Sum0r, 0),
+            (_, 0b0101001) => crypto(CryptoOp::Sha512Sum1r, 0),
+            (_, 0b0101010) => crypto(CryptoOp::Sha512Sig0l, 0),
+            (_, 0b0101011) => crypto(CryptoOp::Sha512Sig1l, 0),
+            (_, 0b0101110) => crypto(CryptoOp::Sha512Sig0h, 0),
+            (_, 0b0101111) => crypto(CryptoOp::Sha512Sig1h, 0),
+            (_, 0b0011001) => crypto(CryptoOp::Aes64Es, 0),
+            (_, 0b0011011) => crypto(CryptoOp::Aes64Esm, 0),
+            (_, 0b0011101) => crypto(CryptoOp::Aes64Ds, 0),
+            (_, 0b0011111) => crypto(CryptoOp::Aes64Dsm, 0),
+            (_, 0b0111111) => crypto(CryptoOp::Aes64Ks2, 0),
+            _ => None,
+        },
+        (op::AIMM, 0b001) => match rv32::get_bits(inst, 31, 20) {
+            0x100 => crypto(CryptoOp::Sha256Sum0, 0),
+            0x101 => crypto(CryptoOp::Sha256Sum1, 0),
+            0x102 => crypto(CryptoOp::Sha256Sig0, 0),
+            0x103 => crypto(CryptoOp::Sha256Sig1, 0),
+            0x104 => crypto(CryptoOp::Sha512Sum0, 0),
+            0x105 => crypto(CryptoOp::Sha512Sum1, 0),
+            0x106 => crypto(CryptoOp::Sha512Sig0, 0),
+            0x107 => crypto(CryptoOp::Sha512Sig1, 0),
+            0x108 => crypto(CryptoOp::Sm3P0, 0),
+            0x109 => crypto(CryptoOp::Sm3P1, 0),
+            0x300 => crypto(CryptoOp::Aes64Im, 0),
+            // round numbers above 0xa are reserved
+            imm if imm >> 4 == 0x31 && imm & 0xf <= 0xa => crypto(CryptoOp::Aes64Ks1i, imm & 0xf),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Decodes the hypervisor loads and stores under SYSTEM: funct7 0110 then the width, b, h or
+/// w, and whether it is a store. The rs2 field of loads selects signed, unsigned or hlvx.
+fn decode_hlsv(inst: u32) -> Result<Instruction, String> {
+    let funct7 = rv32::get_bits(inst, 31, 25);
+    let (rd, rs1, rs2) = (rv32::get_rd(inst), rv32::get_rs1(inst), rv32::get_rs2(inst));
+    // hlv.d and hsv.d are RV64 only
+    if funct7 >> 3 != 0b0110 || (funct7 >> 1) & 0b11 == 0b11 {
+        return Err(String::from("No inst on hypervisor load and store"));
+    }
+    let width = 1 << ((funct7 >> 1) & 0b11);
+    if funct7 & 1 == 1 {
+        return match rd {
+            0 => Ok(Instruction::Hsv { rs1, rs2, width }),
+            _ => Err(String::from("No inst on hypervisor load and store")),
+        };
+    }
+    let (signed, exec) = match (rs2, width) {
+        (0, _) => (true, false),
+        // hlv.wu is RV64 only, and there is no hlvx.bu
+        (1, 1) | (1, 2) => (false, false),
+        (3, 2) | (3, 4) => (false, true),
+        _ => return Err(String::from("No inst on hypervisor load and store")),
+    };
+    Ok(Instruction::Hlv { rd, rs1, width, signed, exec })
+}
+
+/// Whether `inst` is in the vector encoding space: OP-V, or LOAD-FP and STORE-FP with a
+/// vector element width.
+fn is_vector(inst: u32) -> bool {
+    match rv32::get_op(inst) {
+        op::OP_V => true,
+        _ => matches!(rv32::get_funct3(inst), 0b000 | 0b101 | 0b110 | 0b111),
+    }
+}
+
+/// Decodes a vector configuration, load, store or arithmetic instruction.
+fn decode_vector(inst: u32) -> Result<Instruction, String> {
+    let (rd, rs1, rs2) = (rv32::get_rd(inst), rv32::get_rs1(inst), rv32::get_rs2(inst));
+    let vm = rv32::get_bits(inst, 25, 25) == 1;
+    let funct3 = rv32::get_funct3(inst);
+    if rv32::get_op(inst) != op::OP_V {
+        let store = rv32::get_op(inst) == op::STORE_FP;
+        let eew = match funct3 {
+            0b000 => 8,
+            0b101 => 16,
+            0b110 => 32,
+            _ => 64,
+        };
+        let nf = rv32::get_bits(inst, 31, 29) + 1;
+        if rv32::get_bits(inst, 28, 28) == 1 {
+            return Err(String::from("Reserved vector memory element width"));
+        }
+        let mode = match (rv32::get_bits(inst, 27, 26), rs2) {
+            (0b00, 0b00000) => VMode::Unit,
+            (0b00, 0b01000) if vm && nf.is_power_of_two() => VMode::Whole,
+            (0b00, 0b01011) if vm && nf == 1 && eew == 8 => VMode::Mask,
+            (0b00, 0b10000) if !store => VMode::FaultFirst,
+            (0b00, _) => return Err(String::from("No such unit-stride vector load or store")),
+            (0b01, _) => VMode::Indexed { ordered: false },
+            (0b10, _) => VMode::Strided,
+            _ => VMode::Indexed { ordered: true },
+        };
+        return Ok(match store {
+            false => Instruction::Vload { mode, eew, nf, vd: rd, rs1, rs2, vm },
+            true => Instruction::Vstore { mode, eew, nf, vs3: rd, rs1, rs2, vm },
+        });
+    }
+    if funct3 == 0b111 {
+        let (avl, vtype) = match (rv32::get_bits(inst, 31, 31), rv32::get_bits(inst, 31, 25)) {
+            (0, _) => (VSrc::X(rs1), VSrc::Imm(rv32::get_bits(inst, 30, 20) as u64)),
+            (_, 0b1000000) => (VSrc::X(rs1), VSrc::X(rs2)),
+            _ if rv32::get_bits(inst, 31, 30) == 0b11 => {
+                (VSrc::Imm(rs1 as u64), VSrc::Imm(rv32::get_bits(inst, 29, 20) as u64))
+            }
+            _ => return Err(String::from("No such vector configuration instruction")),
+        };
+        return Ok(Instruction::Vset { rd, avl, vtype });
+    }
+    let op = vector_op(rv32::get_bits(inst, 31, 26), funct3, rs1, rs2, vm)
+        .ok_or_else(|| String::from("No inst on OP-V"))?;
+    let unsigned = matches!(
+        op,
+        VOp::Sll
+            | VOp::Srl
+            | VOp::Sra
+            | VOp::Ssrl
+            | VOp::Ssra
+            | VOp::Nsrl
+            | VOp::Nsra
+            | VOp::Nclipu
+            | VOp::Nclip
+            | VOp::Slideup
+            | VOp::Slidedown
+            | VOp::Rgather
+    );
+    // the unary ones take vs1 as part of the opcode
+    let unary = matches!(
+        op,
+        VOp::MvXS
+            | VOp::FmvFS
+            | VOp::Cpop
+            | VOp::First
+            | VOp::Ext { .. }
+            | VOp::Msbf
+            | VOp::Msof
+            | VOp::Msif
+            | VOp::Iota
+            | VOp::Id
+            | VOp::Fsqrt
+            | VOp::Frsqrt7
+            | VOp::Frec7
+            | VOp::Fclass
+            | VOp::Fcvt { .. }
+            | VOp::FncvtRod
+    );
+    let src = match funct3 {
+        _ if unary => VSrc::Imm(0),
+        0b000..=0b010 => VSrc::V(rs1),
+        0b011 if unsigned => VSrc::Imm(rs1 as u64),
+        0b011 => VSrc::Imm(rv32::sign_extend(rs1 as u32, 4) as i32 as i64 as u64),
+        0b101 => VSrc::F(rs1),
+        _ => VSrc::X(rs1),
+    };
+    Ok(Instruction::Vector { op, vd: rd, vs2: rs2, src, vm })
+}
+
+/// Operation of an OP-V arithmetic instruction by its funct6 and funct3. Unary operations are
+/// selected by the vs1 or vs2 field instead of reading the register.
+fn vector_op(funct6: u32, funct3: u32, vs1: usize, vs2: usize, vm: bool) -> Option<VOp> {
+    // OPIVV, OPIVX and OPIVI share a table, as do OPMVV and OPMVX, and OPFVV and OPFVF
+    let (v, x, i) = (matches!(funct3, 0b000..=0b010), matches!(funct3, 0b100..=0b110), funct3 == 0b011);
+    let op = match (funct3, funct6) {
+        (0b000 | 0b011 | 0b100, _) => match funct6 {
+            0b000000 => VOp::Add,
+            0b000010 if !i => VOp::Sub,
+            0b000011 if !v => VOp::Rsub,
+            0b000100 if !i => VOp::Minu,
+            0b000101 if !i => VOp::Min,
+            0b000110 if !i => VOp::Maxu,
+            0b000111 if !i => VOp::Max,
+            0b001001 => VOp::And,
+            0b001010 => VOp::Or,
+            0b001011 => VOp::Xor,
+            0b001100 => VOp::Rgather,
+            0b001110 if v => VOp::Rgatherei16,
+            0b001110 => VOp::Slideup,
+            0b001111 if !v => VOp::Slidedown,
+            0b010000 if !vm => VOp::Adc,
+            0b010001 => VOp::Madc,
+            0b010010 if !vm && !i => VOp::Sbc,
+            0b010011 if !i => VOp::Msbc,
+            // vmv.v has vs2 0
+            0b010111 if !vm || vs2 == 0 => VOp::Merge,
+            0b011000 => VOp::Mseq,
+            0b011001 => VOp::Msne,
+            0b011010 if !i => VOp::Msltu,
+            0b011011 if !i => VOp::Mslt,
+            0b011100 => VOp::Msleu,
+            0b011101 => VOp::Msle,
+            0b011110 if !v => VOp::Msgtu,
+            0b011111 if !v => VOp::Msgt,
+            0b100000 => VOp::Saddu,
+            0b100001 => VOp::Sadd,
+            0b100010 if !i => VOp::Ssubu,
+            0b100011 if !i => VOp::Ssub,
+            0b100101 => VOp::Sll,
+            0b100111 if !i => VOp::Smul,
+            // the immediate is the register count minus one
+            0b100111 if vm && matches!(vs1, 0 | 1 | 3 | 7) => VOp::MvWhole(vs1 as u32 + 1),
+            0b101000 => VOp::Srl,
+            0b101001 => VOp::Sra,
+            0b101010 => VOp::Ssrl,
+            0b101011 => VOp::Ssra,
+            0b101100 => VOp::Nsrl,
+            0b101101 => VOp::Nsra,
+            0b101110 => VOp::Nclipu,
+            0b101111 => VOp::Nclip,
+            0b110000 if v => VOp::Wredsumu,
+            0b110001 if v => VOp::Wredsum,
+            _ => return None,
+        },
+        (0b010 | 0b110, _) => match funct6 {
+            0b000000 if v => VOp::Redsum,
+            0b000001 if v => VOp::Redand,
+            0b000010 if v => VOp::Redor,
+            0b000011 if v => VOp::Redxor,
+            0b000100 if v => VOp::Redminu,
+            0b000101 if v => VOp::Redmin,
+            0b000110 if v => VOp::Redmaxu,
+            0b000111 if v => VOp::Redmax,
+            0b001000 => VOp::Aaddu,
+            0b001001 => VOp::Aadd,
+            0b001010 => VOp::Asubu,
+            0b001011 => VOp::Asub,
+            0b001110 if x => VOp::Slide1up,
+            0b001111 if x => VOp::Slide1down,
+            0b010000 if v && vm && vs1 == 0 => VOp::MvXS,
+            0b010000 if v && vs1 == 0b10000 => VOp::Cpop,
+            0b010000 if v && vs1 == 0b10001 => VOp::First,
+            0b010000 if x && vm && vs2 == 0 => VOp::MvSX,
+            0b010010 if v && (2..8).contains(&vs1) => VOp::Ext {
+                factor: 1 << (4 - vs1 / 2),
+                signed: vs1 % 2 == 1,
+            },
+            0b010100 if v && vs1 == 0b00001 => VOp::Msbf,
+            0b010100 if v && vs1 == 0b00010 => VOp::Msof,
+            0b010100 if v && vs1 == 0b00011 => VOp::Msif,
+            0b010100 if v && vs1 == 0b10000 => VOp::Iota,
+            0b010100 if v && vs1 == 0b10001 && vs2 == 0 => VOp::Id,
+            0b010111 if v && vm => VOp::Compress,
+            0b011000 if v && vm => VOp::Mandn,
+            0b011001 if v && vm => VOp::Mand,
+            0b011010 if v && vm => VOp::Mor,
+            0b011011 if v && vm => VOp::Mxor,
+            0b011100 if v && vm => VOp::Morn,
+            0b011101 if v && vm => VOp::Mnand,
+            0b011110 if v && vm => VOp::Mnor,
+            0b011111 if v && vm => VOp::Mxnor,
+            0b100000 => VOp::Divu,
+            0b100001 => VOp::Div,
+            0b100010 => VOp::Remu,
+            0b100011 => VOp::Rem,
+            0b100100 => VOp::Mulhu,
+            0b100101 => VOp::Mul,
+            0b100110 => VOp::Mulhsu,
+            0b100111 => VOp::Mulh,
+            0b101001 => VOp::Madd,
+            0b101011 => VOp::Nmsub,
+            0b101101 => VOp::Macc,
+            0b101111 => VOp::Nmsac,
+            0b110000 => VOp::Waddu,
+            0b110001 => VOp::Wadd,
+            0b110010 => VOp::Wsubu,
+            0b110011 => VOp::Wsub,
+            0b110100 => VOp::WadduW,
+            0b110101 => VOp::WaddW,
+            0b110110 => VOp::WsubuW,
+            0b110111 => VOp::WsubW,
+            0b111000 => VOp::Wmulu,
+            0b111010 => VOp::Wmulsu,
+            0b111011 => VOp::Wmul,
+            0b111100 => VOp::Wmaccu,
+            0b111101 => VOp::Wmacc,
+            0b111110 if x => VOp::Wmaccus,
+            0b111111 => VOp::Wmaccsu,
+            _ => return None,
+        },
+        _ => match funct6 {
+            0b000000 => VOp::Fadd,
+            0b000001 if v => VOp::Fredusum,
+            0b000010 => VOp::Fsub,
+            0b000011 if v => VOp::Fredosum,
+            0b000100 => VOp::Fmin,
+            0b000101 if v => VOp::Fredmin,
+            0b000110 => VOp::Fmax,
+            0b000111 if v => VOp::Fredmax,
+            0b001000 => VOp::Fsgnj,
+            0b001001 => VOp::Fsgnjn,
+            0b001010 => VOp::Fsgnjx,
+            0b001110 if !v => VOp::Fslide1up,
+            0b001111 if !v => VOp::Fslide1down,
+            0b010000 if v && vm && vs1 == 0 => VOp::FmvFS,
+            0b010000 if !v && vm && vs2 == 0 => VOp::FmvSF,
+            0b010010 if v => fcvt(vs1)?,
+            0b010011 if v && vs1 == 0b00000 => VOp::Fsqrt,
+            0b010011 if v && vs1 == 0b00100 => VOp::Frsqrt7,
+            0b010011 if v && vs1 == 0b00101 => VOp::Frec7,
+            0b010011 if v && vs1 == 0b10000 => VOp::Fclass,
+            0b010111 if !v && (!vm || vs2 == 0) => VOp::Fmerge,
+            0b011000 => VOp::Mfeq,
+            0b011001 => VOp::Mfle,
+            0b011011 => VOp::Mflt,
+            0b011100 => VOp::Mfne,
+            0b011101 if !v => VOp::Mfgt,
+            0b011111 if !v => VOp::Mfge,
+            0b100000 => VOp::Fdiv,
+            0b100001 if !v => VOp::Frdiv,
+            0b100100 => VOp::Fmul,
+            0b100111 if !v => VOp::Frsub,
+            0b101000 => VOp::Fmadd,
+            0b101001 => VOp::Fnmadd,
+            0b101010 => VOp::Fmsub,
+            0b101011 => VOp::Fnmsub,
+            0b101100 => VOp::Fmacc,
+            0b101101 => VOp::Fnmacc,
+            0b101110 => VOp::Fmsac,
+            0b101111 => VOp::Fnmsac,
+            0b110000 => VOp::Fwadd,
+            0b110001 if v => VOp::Fwredusum,
+            0b110010 => VOp::Fwsub,
+            0b110011 if v => VOp::Fwredosum,
+            0b110100 => VOp::FwaddW,
+            0b110110 => VOp::FwsubW,
+            0b111000 => VOp::Fwmul,
+            0b111100 => VOp::Fwmacc,
+            0b111101 => VOp::Fwnmacc,
+            0b111110 => VOp::Fwmsac,
+            0b111111 => VOp::Fwnmsac,
+            _ => return None,
+        },
+    };
+    Some(op)
+}
+
+/// The VFUNARY0 conversion selected by the vs1 field: the kind in the low three bits, and
+/// whether it widens or narrows above them.
+fn fcvt(vs1: usize) -> Option<VOp> {
+    let width = match vs1 >> 3 {
+        0b00 => 0,
+        0b01 => 1,
+        0b10 => -1,
+        _ => return None,
+    };
+    let (from, to, rtz) = match vs1 & 0b111 {
+        0b000 => (VNum::Float, VNum::Unsigned, false),
+        0b001 => (VNum::Float, VNum::Signed, false),
+        0b010 => (VNum::Unsigned, VNum::Float, false),
+        0b011 => (VNum::Signed, VNum::Float, false),
+        0b100 if width != 0 => (VNum::Float, VNum::Float, false),
+        0b101 if width == -1 => return Some(VOp::FncvtRod),
+        0b110 => (VNum::Float, VNum::Unsigned, true),
+        0b111 => (VNum::Float, VNum::Signed, true),
+        _ => return None,
+    };
+    Some(VOp::Fcvt { from, to, width, rtz })
+}
+
+/// Format of the `fmt` field of floating-point instructions.
+fn fp_format(fmt: u32) -> Result<Format, String> {
+    match fmt {
+        0b00 => Ok(F32),
+        0b01 => Ok(F64),
+        0b10 => Ok(F16),
+        _ => Err(String::from("No such floating-point format")),
+    }
+}
+
+/// Format of a floating-point load or store, by its funct3.
+fn fp_width(funct3: u32) -> Result<Format, String> {
+    match funct3 {
+        0b001 => Ok(F16),
+        0b010 => Ok(F32),
+        0b011 => Ok(F64),
+        _ => Err(String::from("No such floating-point load or store")),
+    }
+}
+
+/// An instruction as fetched and decoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decoded {
+    /// instruction bits as fetched, 16 bits wide if compressed
+    pub raw: u64,
+    pub len: u64,
+    /// the 32-bit instruction executed, the expansion if compressed, 0 for Zcmp and Zcmt
+    /// instructions which have none
+    pub expanded: u32,
+    pub inst: Instruction,
+}
+
+const SLOTS: usize = 1 << (PAGE_SHIFT - 1);
+
+/// Decoded instructions by address, kept a page of 2-byte aligned slots at a time so code
+/// written to can be dropped by page.
+#[derive(Default)]
+pub struct DecodeCache {
+    pages: HashMap<u64, Box<[Option<Decoded>]>>,
+}
+
+impl DecodeCache {
+    pub fn get(&self, pc: u64) -> Option<Decoded> {
+        let page = self.pages.get(&(pc >> PAGE_SHIFT))?;
+        page[slot(pc)?]
+    }
+    pub fn insert(&mut self, pc: u64, decoded: Decoded) {
+        if let Some(slot) = slot(pc) {
+            self.pages
+                .entry(pc >> PAGE_SHIFT)
+                .or_insert_with(|| vec![None; SLOTS].into_boxed_slice())[slot] = Some(decoded);
+        }
+    }
+    /// Drops the instructions that may overlap `page`, including one straddling into it from
+    /// the page before.
+    pub fn invalidate(&mut self, page: u64) {
+        self.pages.remove(&page);
+        self.pages.remove(&page.wrapping_sub(1));
+    }
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+}
+
+/// Slot of a 2-byte aligned `pc` in its page.
+fn slot(pc: u64) -> Option<usize> {
+    match pc & 1 {
+        0 => Some(((pc & ((1 << PAGE_SHIFT) - 1)) >> 1) as usize),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_decode() {
+    assert!(Instruction::decode(0x00500513) == Ok(Instruction::Addi { rd: 10, rs1: 0, imm: 5 }));
+    assert!(Instruction::decode(0xfff00513) == Ok(Instruction::Addi { rd: 10, rs1: 0, imm: 0xffffffff }));
+    assert!(
+        Instruction::decode(0xfe0518e3)
+            == Ok(Instruction::Branch {
+                cond: Cond::Ne,
+                rs1: 10,
+                rs2: 0,
+                offset: 0xfffffff0,
+            })
+    );
+    assert!(Instruction::decode(0x00a02823) == Ok(Instruction::Sw { rs1: 0, rs2: 10, offset: 16 }));
+    assert!(Instruction::decode(0x4020d093) == Ok(Instruction::Srai { rd: 1, rs1: 1, shamt: 2 }));
+    assert!(
+        Instruction::decode(0x34051073)
+            == Ok(Instruction::Csr {
+                op: CsrOp::Rw,
+                rd: 0,
+                rs1: 10,
+                csr: 0x340,
+            })
+    );
+    assert!(Instruction::decode(0x30200073) == Ok(Instruction::Mret));
+    // sret; sfence.vma a0, a1; hfence.gvma; hlv.hu a0, (a1); hlvx.wu a0, (a1); hsv.w a0, (a1)
+    assert!(Instruction::decode(0x10200073) == Ok(Instruction::Sret));
+    assert!(Instruction::decode(0x12b50073) == Ok(Instruction::SfenceVma { rs1: 10, rs2: 11 }));
+    assert!(Instruction::decode(0x62000073) == Ok(Instruction::Hfence { gvma: true, rs1: 0, rs2: 0 }));
+    let hlv = |width, signed, exec| Ok(Instruction::Hlv { rd: 10, rs1: 11, width, signed, exec });
+    assert!(Instruction::decode(0x6415c573) == hlv(2, false, false));
+    assert!(Instruction::decode(0x6835c573) == hlv(4, false, true));
+    assert!(Instruction::decode(0x6aa5c073) == Ok(Instruction::Hsv { rs1: 11, rs2: 10, width: 4 }));
+    // hlv.wu is RV64 only
+    assert!(Instruction::decode(0x6815c573).is_err());
+    assert!(Instruction::decode(0x0000100f) == Ok(Instruction::FenceI));
+    assert!(Instruction::decode(0x00001033) == Err(String::from("No inst on arithmatic register")));
+    // fld fa0, 8(a1); fmadd.d fa0, fa1, fa2, fa3; fcvt.s.d fa0, fa1, rtz; feq.s a0, fa1, fa2
+    assert!(Instruction::decode(0x0085b507) == Ok(Instruction::Fload { fmt: F64, rd: 10, rs1: 11, offset: 8 }));
+    assert!(
+        Instruction::decode(0x6ac5f543)
+            == Ok(Instruction::Fma {
+                op: FmaOp::Madd,
+                fmt: F64,
+                rd: 10,
+                rs1: 11,
+                rs2: 12,
+                rs3: 13,
+                rm: 7,
+            })
+    );
+    assert!(
+        Instruction::decode(0x40159553)
+            == Ok(Instruction::Fp {
+                op: FpOp::Cvt { from: F64 },
+                fmt: F32,
+                rd: 10,
+                rs1: 11,
+                rs2: 1,
+                rm: 1,
+            })
+    );
+    assert!(matches!(Instruction::decode(0xa0c5a553), Ok(Instruction::Fp { op: FpOp::Eq, rd: 10, .. })));
+    // flh fa0, 2(a1); fcvt.h.s fa0, fa1
+    assert!(Instruction::decode(0x00259507) == Ok(Instruction::Fload { fmt: F16, rd: 10, rs1: 11, offset: 2 }));
+    assert!(matches!(
+        Instruction::decode(0x4405f553),
+        Ok(Instruction::Fp { op: FpOp::Cvt { from: F32 }, fmt: F16, .. })
+    ));
+    // sh2add a0, a1, a2; bclri a0, a1, 3; rev8 a0, a1 on RV32 and RV64; clzw a0, a1
+    let bit = |op, word| Instruction::Bitmanip { op, rd: 10, rs1: 11, rs2: 12, word };
+    let imm = |op, imm, word| Instruction::BitmanipImm { op, rd: 10, rs1: 11, imm, word };
+    assert!(Instruction::decode(0x20c5c533) == Ok(bit(BitOp::Shadd(2), false)));
+    assert!(Instruction::decode(0x48359513) == Ok(imm(BitOp::Bclr, 3, false)));
+    assert!(Instruction::decode(0x6985d513) == Ok(imm(BitOp::Rev8, 24, false)));
+    assert!(Instruction::decode(0x6b85d513) == Ok(imm(BitOp::Rev8, 56, false)));
+    assert!(Instruction::decode(0x6005951b) == Ok(imm(BitOp::Clz, 0, true)));
+    // still slli, srli and srai around them
+    assert!(Instruction::decode(0x00359513) == Ok(Instruction::Slli { rd: 10, rs1: 11, shamt: 3 }));
+    assert!(Instruction::decode(0x4035d513) == Ok(Instruction::Srai { rd: 10, rs1: 11, shamt: 3 }));
+    // aes32esmi a0, a1, a2, 3; sha256sig0 a0, a1; pack a0, a1, a2
+    let crypto = |op, imm| Instruction::Crypto { op, rd: 10, rs1: 11, rs2: 12, imm };
+    assert!(Instruction::decode(0xe6c58533) == Ok(crypto(CryptoOp::Aes32Esmi, 3)));
+    assert!(matches!(Instruction::decode(0x10259513), Ok(Instruction::Crypto { op: CryptoOp::Sha256Sig0, .. })));
+    assert!(Instruction::decode(0x08c5c533) == Ok(bit(BitOp::Pack, false)));
+    // fmv.x.d is RV64 only
+    assert!(Instruction::decode(0xe2058553) == Err(String::from("No inst on OP-FP")));
+    // vsetvli a0, a0, e32, m1, ta, ma; vle32.v v2, (a1); flw still scalar
+    let vtype = VSrc::Imm(0xd0);
+    assert!(Instruction::decode(0x0d057557) == Ok(Instruction::Vset { rd: 10, avl: VSrc::X(10), vtype }));
+    let vle = Instruction::decode(0x0205e107);
+    assert!(vle == Ok(Instruction::Vload { mode: VMode::Unit, eew: 32, nf: 1, vd: 2, rs1: 11, rs2: 0, vm: true }));
+    assert!(matches!(Instruction::decode(0x0085a507), Ok(Instruction::Fload { .. })));
+    // vluxei16.v v1, (a1), v3; vslideup.vi v1, v2, 3; vzext.vf2 v1, v2, v0.t
+    let index = VMode::Indexed { ordered: false };
+    assert!(matches!(Instruction::decode(0x0635d087), Ok(Instruction::Vload { mode, eew: 16, rs2: 3, .. }) if mode == index));
+    let slide = Instruction::Vector { op: VOp::Slideup, vd: 1, vs2: 2, src: VSrc::Imm(3), vm: true };
+    assert!(Instruction::decode(0x3a21b0d7) == Ok(slide));
+    let ext = VOp::Ext { factor: 2, signed: false };
+    assert!(Instruction::decode(0x482320d7) == Ok(Instruction::Vector { op: ext, vd: 1, vs2: 2, src: VSrc::Imm(0), vm: false }));
+    // cm.push {ra, s0-s2}, -32; cm.popret {ra}, 32; cm.mva01s s1, s2; cm.jalt 33
+    let push = Instruction::PushPop { op: PushPop::Push, regs: 4, stack_adj: 32 };
+    assert!(Instruction::decode_zcm(0xb876) == Ok(push));
+    assert!(Instruction::decode_zcm(0xbe46) == Ok(Instruction::PushPop { op: PushPop::Popret, regs: 1, stack_adj: 32 }));
+    assert!(Instruction::decode_zcm(0xacea) == Ok(Instruction::Mv01s { r1s: 9, r2s: 18, to_a: true }));
+    assert!(Instruction::decode_zcm(0xa086) == Ok(Instruction::TableJump { index: 33 }));
+    // no rlist below 4, nor the same register twice
+    assert!(Instruction::decode_zcm(0xb836).is_err() && Instruction::decode_zcm(0xace6).is_err());
+}
+
+#[test]
+fn test_decode_cache() {
+    let mut cache = DecodeCache::default();
+    let d = Decoded {
+        raw: 0x00500513,
+        len: 4,
+        expanded: 0x00500513,
+        inst: Instruction::Ecall,
+    };
+    cache.insert(0x80000ffe, d);
+    cache.insert(0x80001002, d);
+    assert!(cache.get(0x80000ffe) == Some(d) && cache.get(0x80001000).is_none());
+    assert!(cache.get(0x80000fff).is_none());
+    // a write to the next page may change the upper half of the straddling instruction
+    cache.invalidate(0x80001);
+    assert!(cache.get(0x80000ffe).is_none() && cache.get(0x80001002).is_none());
+}
@@ -0,0 +1,811 @@
+use crate::{
+    cpu::{
+        exception, f3b, f3c, f3i, f3l, f3r, f3s, f5fp, op, parse_inst_length,
+        rv32::{self, get_bits, get_bits_extended},
+        Cpu,
+    },
+    csr,
+    decode::{BitOp, CryptoOp, Instruction, PushPop, VMode, VNum, VOp, VSrc},
+    elf::{self, Elf},
+    register::{ABI_NAMES, FP_ABI_NAMES},
+};
+use byteorder::{ByteOrder, LittleEndian};
+use std::fmt::Write;
+
+fn reg(n: usize) -> &'static str {
+    ABI_NAMES[n]
+}
+
+fn freg(n: usize) -> &'static str {
+    FP_ABI_NAMES[n]
+}
+
+/// Suffix of the `fmt` field of floating-point instructions.
+fn fp_suffix(inst: u32) -> &'static str {
+    ["s", "d", "h", "q"][get_bits(inst, 26, 25) as usize]
+}
+
+/// The rounding mode operand, left out when it is frm as usual.
+fn rounding(inst: u32) -> &'static str {
+    match rv32::get_funct3(inst) {
+        0 => ", rne",
+        1 => ", rtz",
+        2 => ", rdn",
+        3 => ", rup",
+        4 => ", rmm",
+        7 => "",
+        _ => ", invalid",
+    }
+}
+
+/// Renders a floating-point computational instruction, or `None` if it is not one.
+fn disassemble_fp(inst: u32) -> Option<String> {
+    let (rd_n, rs1_n, rs2_n) = (rv32::get_rd(inst), rv32::get_rs1(inst), rv32::get_rs2(inst));
+    let (rd, rs1, rs2) = (freg(rd_n), freg(rs1_n), freg(rs2_n));
+    let f = fp_suffix(inst);
+    let rm = rounding(inst);
+    let text = match rv32::get_op(inst) {
+        op::MADD | op::MSUB | op::NMSUB | op::NMADD => {
+            let name = match rv32::get_op(inst) {
+                op::MADD => "fmadd",
+                op::MSUB => "fmsub",
+                op::NMSUB => "fnmsub",
+                _ => "fnmadd",
+            };
+            let rs3 = freg(get_bits(inst, 31, 27) as usize);
+            format!("{}.{} {}, {}, {}, {}{}", name, f, rd, rs1, rs2, rs3, rm)
+        }
+        op::OP_FP => match (get_bits(inst, 31, 27), rv32::get_funct3(inst), rs2_n) {
+            (f5fp::ADD, ..) => format!("fadd.{} {}, {}, {}{}", f, rd, rs1, rs2, rm),
+            (f5fp::SUB, ..) => format!("fsub.{} {}, {}, {}{}", f, rd, rs1, rs2, rm),
+            (f5fp::MUL, ..) => format!("fmul.{} {}, {}, {}{}", f, rd, rs1, rs2, rm),
+            (f5fp::DIV, ..) => format!("fdiv.{} {}, {}, {}{}", f, rd, rs1, rs2, rm),
+            (f5fp::SQRT, _, 0) => format!("fsqrt.{} {}, {}{}", f, rd, rs1, rm),
+            (f5fp::SGNJ, 0, _) if rs1_n == rs2_n => format!("fmv.{} {}, {}", f, rd, rs1),
+            (f5fp::SGNJ, 1, _) if rs1_n == rs2_n => format!("fneg.{} {}, {}", f, rd, rs1),
+            (f5fp::SGNJ, 2, _) if rs1_n == rs2_n => format!("fabs.{} {}, {}", f, rd, rs1),
+            (f5fp::SGNJ, 0, _) => format!("fsgnj.{} {}, {}, {}", f, rd, rs1, rs2),
+            (f5fp::SGNJ, 1, _) => format!("fsgnjn.{} {}, {}, {}", f, rd, rs1, rs2),
+            (f5fp::SGNJ, 2, _) => format!("fsgnjx.{} {}, {}, {}", f, rd, rs1, rs2),
+            (f5fp::MIN_MAX, 0, _) => format!("fmin.{} {}, {}, {}", f, rd, rs1, rs2),
+            (f5fp::MIN_MAX, 1, _) => format!("fmax.{} {}, {}, {}", f, rd, rs1, rs2),
+            (f5fp::CVT_FMT, _, from) if from < 4 => {
+                format!("fcvt.{}.{} {}, {}{}", f, ["s", "d", "h", "q"][from], rd, rs1, rm)
+            }
+            (f5fp::CMP, 2, _) => format!("feq.{} {}, {}, {}", f, reg(rd_n), rs1, rs2),
+            (f5fp::CMP, 1, _) => format!("flt.{} {}, {}, {}", f, reg(rd_n), rs1, rs2),
+            (f5fp::CMP, 0, _) => format!("fle.{} {}, {}, {}", f, reg(rd_n), rs1, rs2),
+            (f5fp::CVT_TO_INT, _, 0) => format!("fcvt.w.{} {}, {}{}", f, reg(rd_n), rs1, rm),
+            (f5fp::CVT_TO_INT, _, 1) => format!("fcvt.wu.{} {}, {}{}", f, reg(rd_n), rs1, rm),
+            (f5fp::CVT_FROM_INT, _, 0) => format!("fcvt.{}.w {}, {}{}", f, rd, reg(rs1_n), rm),
+            (f5fp::CVT_FROM_INT, _, 1) => format!("fcvt.{}.wu {}, {}{}", f, rd, reg(rs1_n), rm),
+            (f5fp::MV_TO_INT_CLASS, 0, 0) => {
+                format!("fmv.x.{} {}, {}", f.replace('s', "w"), reg(rd_n), rs1)
+            }
+            (f5fp::MV_TO_INT_CLASS, 1, 0) => format!("fclass.{} {}, {}", f, reg(rd_n), rs1),
+            (f5fp::MV_FROM_INT, 0, 0) => {
+                format!("fmv.{}.x {}, {}", f.replace('s', "w"), rd, reg(rs1_n))
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Target of a direct jump or branch at `pc`, for callers that annotate it with a symbol.
+pub fn branch_target(inst: u32, pc: u64) -> Option<u64> {
+    match rv32::get_op(inst) {
+        op::JAL => Some(pc.wrapping_add(rv32::get_imm_jal(inst) as i32 as i64 as u64)),
+        op::BRANCH => Some(
+            pc.wrapping_add(rv32::sign_extend(rv32::get_imm_branch(inst), 12) as i32 as i64 as u64),
+        ),
+        _ => None,
+    }
+}
+
+/// Renders a Zba/Zbb/Zbc/Zbs instruction.
+fn disassemble_bitmanip(inst: u32) -> Option<String> {
+    let (op, rd, rs1, second, word, imm) = match Instruction::decode(inst).ok()? {
+        Instruction::Bitmanip { op, rd, rs1, rs2, word } => {
+            (op, rd, rs1, Some(reg(rs2).to_string()), word, false)
+        }
+        Instruction::BitmanipImm { op, rd, rs1, imm, word } => {
+            let shifts = matches!(
+                op,
+                BitOp::Ror | BitOp::SlliUw | BitOp::Bclr | BitOp::Bext | BitOp::Binv | BitOp::Bset
+            );
+            (op, rd, rs1, Some(imm.to_string()).filter(|_| shifts), word, true)
+        }
+        _ => return None,
+    };
+    let name = match op {
+        BitOp::Andn => String::from("andn"),
+        BitOp::Orn => String::from("orn"),
+        BitOp::Xnor => String::from("xnor"),
+        BitOp::Min => String::from("min"),
+        BitOp::Minu => String::from("minu"),
+        BitOp::Max => String::from("max"),
+        BitOp::Maxu => String::from("maxu"),
+        BitOp::Rol => String::from("rol"),
+        BitOp::Ror if imm => String::from("rori"),
+        BitOp::Ror => String::from("ror"),
+        BitOp::Clz => String::from("clz"),
+        BitOp::Ctz => String::from("ctz"),
+        BitOp::Cpop => String::from("cpop"),
+        BitOp::SextB => String::from("sext.b"),
+        BitOp::SextH => String::from("sext.h"),
+        BitOp::ZextH => String::from("zext.h"),
+        BitOp::OrcB => String::from("orc.b"),
+        BitOp::Rev8 => String::from("rev8"),
+        BitOp::Clmul => String::from("clmul"),
+        BitOp::Clmulh => String::from("clmulh"),
+        BitOp::Clmulr => String::from("clmulr"),
+        BitOp::Shadd(0) => String::from("add.uw"),
+        BitOp::Shadd(n) if word => format!("sh{}add.uw", n),
+        BitOp::Shadd(n) => format!("sh{}add", n),
+        BitOp::SlliUw => String::from("slli.uw"),
+        BitOp::Bclr => String::from("bclr"),
+        BitOp::Bext => String::from("bext"),
+        BitOp::Binv => String::from("binv"),
+        BitOp::Bset => String::from("bset"),
+        BitOp::Pack => String::from("pack"),
+        BitOp::Packh => String::from("packh"),
+        BitOp::Brev8 => String::from("brev8"),
+        BitOp::Zip => String::from("zip"),
+        BitOp::Unzip => String::from("unzip"),
+        BitOp::Xperm4 => String::from("xperm4"),
+        BitOp::Xperm8 => String::from("xperm8"),
+    };
+    // the `w` suffix for OP-32 forms, the `i` one for immediates of single-bit instructions
+    let suffix = match op {
+        BitOp::Rol | BitOp::Ror | BitOp::Clz | BitOp::Ctz | BitOp::Cpop | BitOp::Pack if word => "w",
+        BitOp::Bclr | BitOp::Bext | BitOp::Binv | BitOp::Bset if imm => "i",
+        _ => "",
+    };
+    Some(match second {
+        Some(second) => format!("{}{} {}, {}, {}", name, suffix, reg(rd), reg(rs1), second),
+        None => format!("{}{} {}, {}", name, suffix, reg(rd), reg(rs1)),
+    })
+}
+
+/// Renders a Zkn/Zks instruction.
+fn disassemble_crypto(inst: u32) -> Option<String> {
+    let (op, rd, rs1, rs2, imm) = match Instruction::decode(inst).ok()? {
+        Instruction::Crypto { op, rd, rs1, rs2, imm } => (op, rd, rs1, rs2, imm),
+        _ => return None,
+    };
+    // the variant names are the mnemonics
+    let name = format!("{:?}", op).to_lowercase();
+    let unary = rv32::get_op(inst) == op::AIMM && op != CryptoOp::Aes64Ks1i;
+    Some(match op {
+        CryptoOp::Aes32Esi
+        | CryptoOp::Aes32Esmi
+        | CryptoOp::Aes32Dsi
+        | CryptoOp::Aes32Dsmi
+        | CryptoOp::Sm4Ed
+        | CryptoOp::Sm4Ks => format!("{} {}, {}, {}, {}", name, reg(rd), reg(rs1), reg(rs2), imm),
+        CryptoOp::Aes64Ks1i => format!("{} {}, {}, {}", name, reg(rd), reg(rs1), imm),
+        _ if unary => format!("{} {}, {}", name, reg(rd), reg(rs1)),
+        _ => format!("{} {}, {}, {}", name, reg(rd), reg(rs1), reg(rs2)),
+    })
+}
+
+/// The vtype operand of vsetvli and vsetivli, such as `e32, m2, ta, mu`.
+fn vtype(bits: u32) -> String {
+    let lmul = match bits & 0b111 {
+        l @ 0..=3 => format!("m{}", 1 << l),
+        4 => String::from("reserved"),
+        l => format!("mf{}", 1 << (8 - l)),
+    };
+    let ta = if bits >> 6 & 1 == 1 { "ta" } else { "tu" };
+    let ma = if bits >> 7 & 1 == 1 { "ma" } else { "mu" };
+    format!("e{}, {}, {}, {}", 8 << (bits >> 3 & 0b111), lmul, ta, ma)
+}
+
+/// Renders a V instruction.
+fn disassemble_vector(inst: u32) -> Option<String> {
+    let mask = |vm: bool| if vm { "" } else { ", v0.t" };
+    let text = match Instruction::decode(inst).ok()? {
+        Instruction::Vset { rd, vtype: VSrc::X(rs2), .. } => {
+            format!("vsetvl {}, {}, {}", reg(rd), reg(rv32::get_rs1(inst)), reg(rs2))
+        }
+        Instruction::Vset { rd, avl: VSrc::Imm(avl), vtype: VSrc::Imm(bits) } => {
+            format!("vsetivli {}, {}, {}", reg(rd), avl, self::vtype(bits as u32))
+        }
+        Instruction::Vset { rd, vtype: VSrc::Imm(bits), .. } => {
+            format!("vsetvli {}, {}, {}", reg(rd), reg(rv32::get_rs1(inst)), self::vtype(bits as u32))
+        }
+        Instruction::Vload {
+            mode,
+            eew,
+            nf,
+            vd,
+            rs1,
+            rs2,
+            vm,
+        } => disassemble_vmem("vl", mode, eew, nf, vd, rs1, rs2) + mask(vm),
+        Instruction::Vstore {
+            mode,
+            eew,
+            nf,
+            vs3,
+            rs1,
+            rs2,
+            vm,
+        } => disassemble_vmem("vs", mode, eew, nf, vs3, rs1, rs2) + mask(vm),
+        Instruction::Vector { op, vd, vs2, src, vm } => disassemble_vop(op, vd, vs2, src, vm),
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Renders a vector load or store, `prefix` being `vl` or `vs`.
+fn disassemble_vmem(prefix: &str, mode: VMode, eew: u32, nf: u32, vd: usize, rs1: usize, rs2: usize) -> String {
+    let seg = match nf {
+        1 => String::new(),
+        _ => format!("seg{}", nf),
+    };
+    let (name, extra) = match mode {
+        VMode::Unit => (format!("{}{}e{}.v", prefix, seg, eew), String::new()),
+        VMode::FaultFirst => (format!("{}{}e{}ff.v", prefix, seg, eew), String::new()),
+        VMode::Strided => (format!("{}s{}e{}.v", prefix, seg, eew), format!(", {}", reg(rs2))),
+        VMode::Indexed { ordered } => {
+            let order = if ordered { "o" } else { "u" };
+            (format!("{}{}x{}ei{}.v", prefix, order, seg, eew), format!(", v{}", rs2))
+        }
+        VMode::Mask => (format!("{}m.v", prefix), String::new()),
+        VMode::Whole if prefix == "vs" => (format!("vs{}r.v", nf), String::new()),
+        VMode::Whole => (format!("vl{}re{}.v", nf, eew), String::new()),
+    };
+    format!("{} v{}, ({}){}", name, vd, reg(rs1), extra)
+}
+
+/// Renders a vector arithmetic instruction.
+fn disassemble_vop(op: VOp, vd: usize, vs2: usize, src: VSrc, vm: bool) -> String {
+    let (kind, operand) = match src {
+        VSrc::V(n) => ("v", format!("v{}", n)),
+        VSrc::X(n) => ("x", reg(n).to_string()),
+        VSrc::F(n) => ("f", freg(n).to_string()),
+        VSrc::Imm(x) => ("i", (x as i64).to_string()),
+    };
+    let num = |n: VNum| match n {
+        VNum::Unsigned => "xu",
+        VNum::Signed => "x",
+        VNum::Float => "f",
+    };
+    let mask = if vm { "" } else { ", v0.t" };
+    let text = match op {
+        // unmasked merges are moves
+        VOp::Merge if vm => format!("vmv.v.{} v{}, {}", kind, vd, operand),
+        VOp::Fmerge if vm => format!("vfmv.v.f v{}, {}", vd, operand),
+        // with the carry or the merge selector in v0 instead of a mask
+        VOp::Adc | VOp::Sbc | VOp::Merge | VOp::Fmerge => {
+            let name = format!("{:?}", op).to_lowercase();
+            return format!("v{}.v{}m v{}, v{}, {}, v0", name, kind, vd, vs2, operand);
+        }
+        VOp::Madc | VOp::Msbc if !vm => {
+            let name = format!("{:?}", op).to_lowercase();
+            return format!("v{}.v{}m v{}, v{}, {}, v0", name, kind, vd, vs2, operand);
+        }
+        VOp::MvWhole(n) => format!("vmv{}r.v v{}, v{}", n, vd, vs2),
+        VOp::MvXS => format!("vmv.x.s {}, v{}", reg(vd), vs2),
+        VOp::FmvFS => format!("vfmv.f.s {}, v{}", freg(vd), vs2),
+        VOp::MvSX => format!("vmv.s.x v{}, {}", vd, operand),
+        VOp::FmvSF => format!("vfmv.s.f v{}, {}", vd, operand),
+        VOp::Cpop => format!("vcpop.m {}, v{}", reg(vd), vs2),
+        VOp::First => format!("vfirst.m {}, v{}", reg(vd), vs2),
+        VOp::Msbf | VOp::Msof | VOp::Msif | VOp::Iota => {
+            format!("v{}.m v{}, v{}", format!("{:?}", op).to_lowercase(), vd, vs2)
+        }
+        VOp::Id => format!("vid.v v{}", vd),
+        VOp::Ext { factor, signed } => {
+            let s = if signed { "s" } else { "z" };
+            format!("v{}ext.vf{} v{}, v{}", s, factor, vd, vs2)
+        }
+        VOp::Fsqrt | VOp::Frsqrt7 | VOp::Frec7 | VOp::Fclass => {
+            format!("v{}.v v{}, v{}", format!("{:?}", op).to_lowercase(), vd, vs2)
+        }
+        VOp::Fcvt { from, to, width, rtz } => {
+            let (w, suffix) = match width {
+                1 => ("w", "v"),
+                -1 => ("n", "w"),
+                _ => ("", "v"),
+            };
+            let rtz = if rtz { "rtz." } else { "" };
+            format!("vf{}cvt.{}{}.{}.{} v{}, v{}", w, rtz, num(to), num(from), suffix, vd, vs2)
+        }
+        VOp::FncvtRod => format!("vfncvt.rod.f.f.w v{}, v{}", vd, vs2),
+        VOp::Compress => format!("vcompress.vm v{}, v{}, {}", vd, vs2, operand),
+        VOp::Mandn | VOp::Mand | VOp::Mor | VOp::Mxor | VOp::Morn | VOp::Mnand | VOp::Mnor | VOp::Mxnor => {
+            format!("v{}.mm v{}, v{}, {}", format!("{:?}", op).to_lowercase(), vd, vs2, operand)
+        }
+        _ => {
+            let debug = format!("{:?}", op);
+            // the variant names are the mnemonics, `W` marking a wide vs2
+            let name = debug.trim_end_matches('W').to_lowercase();
+            let wide = debug.ends_with('W') || matches!(op, VOp::Nsrl | VOp::Nsra | VOp::Nclipu | VOp::Nclip);
+            let reduction = name.contains("red");
+            let first = if wide { "w" } else { "v" };
+            let second = if reduction { "s" } else { kind };
+            // the multiply-adds name the addend first
+            let fused = matches!(
+                op,
+                VOp::Madd
+                    | VOp::Nmsub
+                    | VOp::Macc
+                    | VOp::Nmsac
+                    | VOp::Wmaccu
+                    | VOp::Wmacc
+                    | VOp::Wmaccus
+                    | VOp::Wmaccsu
+                    | VOp::Fmadd
+                    | VOp::Fnmadd
+                    | VOp::Fmsub
+                    | VOp::Fnmsub
+                    | VOp::Fmacc
+                    | VOp::Fnmacc
+                    | VOp::Fmsac
+                    | VOp::Fnmsac
+                    | VOp::Fwmacc
+                    | VOp::Fwnmacc
+                    | VOp::Fwmsac
+                    | VOp::Fwnmsac
+            );
+            match fused {
+                true => format!("v{}.{}{} v{}, {}, v{}", name, first, second, vd, operand, vs2),
+                false => format!("v{}.{}{} v{}, v{}, {}", name, first, second, vd, vs2, operand),
+            }
+        }
+    };
+    text + mask
+}
+
+/// Renders one 32-bit instruction at `pc`, preferring the standard pseudo-instruction aliases.
+pub fn disassemble(inst: u32, pc: u64) -> String {
+    let rd_n = rv32::get_rd(inst);
+    let rs1_n = rv32::get_rs1(inst);
+    let rs2_n = rv32::get_rs2(inst);
+    let (rd, rs1, rs2) = (reg(rd_n), reg(rs1_n), reg(rs2_n));
+    let imm_i = get_bits_extended(inst, 31, 20) as i32;
+    let f3 = rv32::get_funct3(inst);
+    let unknown = || format!(".word {:#010x}", inst);
+    let target = branch_target(inst, pc).unwrap_or(0);
+    let special = disassemble_bitmanip(inst)
+        .or_else(|| disassemble_crypto(inst))
+        .or_else(|| disassemble_vector(inst));
+    if let Some(text) = special {
+        return text;
+    }
+    match rv32::get_op(inst) {
+        op::LUDI => format!("lui {}, {:#x}", rd, get_bits(inst, 31, 12)),
+        op::AUIPC => format!("auipc {}, {:#x}", rd, get_bits(inst, 31, 12)),
+        op::JAL => match rd_n {
+            0 => format!("j {:#x}", target),
+            1 => format!("jal {:#x}", target),
+            _ => format!("jal {}, {:#x}", rd, target),
+        },
+        op::JALR => match (rd_n, rs1_n, imm_i) {
+            (0, 1, 0) => String::from("ret"),
+            (0, _, 0) => format!("jr {}", rs1),
+            (1, _, 0) => format!("jalr {}", rs1),
+            _ => format!("jalr {}, {}({})", rd, imm_i, rs1),
+        },
+        op::BRANCH => {
+            let name = match f3 {
+                f3b::BEQ => "beq",
+                f3b::BNE => "bne",
+                f3b::BLT => "blt",
+                f3b::BGE => "bge",
+                f3b::BLTU => "bltu",
+                f3b::BGEU => "bgeu",
+                _ => return unknown(),
+            };
+            match (f3, rs1_n, rs2_n) {
+                (f3b::BEQ, _, 0) => format!("beqz {}, {:#x}", rs1, target),
+                (f3b::BNE, _, 0) => format!("bnez {}, {:#x}", rs1, target),
+                (f3b::BGE, 0, _) => format!("blez {}, {:#x}", rs2, target),
+                (f3b::BGE, _, 0) => format!("bgez {}, {:#x}", rs1, target),
+                (f3b::BLT, _, 0) => format!("bltz {}, {:#x}", rs1, target),
+                (f3b::BLT, 0, _) => format!("bgtz {}, {:#x}", rs2, target),
+                _ => format!("{} {}, {}, {:#x}", name, rs1, rs2, target),
+            }
+        }
+        op::LD => {
+            let name = match f3 {
+                f3l::LB => "lb",
+                f3l::LH => "lh",
+                f3l::LW => "lw",
+                f3l::LBU => "lbu",
+                f3l::LHU => "lhu",
+                _ => return unknown(),
+            };
+            format!("{} {}, {}({})", name, rd, imm_i, rs1)
+        }
+        op::STORE => {
+            let name = match f3 {
+                f3s::SB => "sb",
+                f3s::SH => "sh",
+                f3s::SW => "sw",
+                _ => return unknown(),
+            };
+            let offset = rv32::sign_extend(rv32::get_imm_st(inst), 11) as i32;
+            format!("{} {}, {}({})", name, rs2, offset, rs1)
+        }
+        op::AIMM => match (f3, rd_n, rs1_n, imm_i) {
+            (f3i::ADDI, 0, 0, 0) => String::from("nop"),
+            (f3i::ADDI, _, 0, _) => format!("li {}, {}", rd, imm_i),
+            (f3i::ADDI, _, _, 0) => format!("mv {}, {}", rd, rs1),
+            (f3i::SLTIU, _, _, 1) => format!("seqz {}, {}", rd, rs1),
+            (f3i::XORI, _, _, -1) => format!("not {}, {}", rd, rs1),
+            (f3i::SLLI, ..) => format!("slli {}, {}, {}", rd, rs1, get_bits(inst, 24, 20)),
+            (f3i::SRLI_SRAI, ..) => {
+                let name = if get_bits(inst, 30, 30) == 1 { "srai" } else { "srli" };
+                format!("{} {}, {}, {}", name, rd, rs1, get_bits(inst, 24, 20))
+            }
+            _ => {
+                let name = match f3 {
+                    f3i::ADDI => "addi",
+                    f3i::SLTI => "slti",
+                    f3i::SLTIU => "sltiu",
+                    f3i::XORI => "xori",
+                    f3i::ORI => "ori",
+                    _ => "andi",
+                };
+                format!("{} {}, {}, {}", name, rd, rs1, imm_i)
+            }
+        },
+        op::AREG => {
+            let alt = get_bits(inst, 31, 25) == 0b0100000;
+            let name = match (f3, alt) {
+                (f3r::ADD_SUB, false) => "add",
+                (f3r::ADD_SUB, true) => "sub",
+                (f3r::SLL, false) => "sll",
+                (f3r::SLT, false) => "slt",
+                (f3r::SLTU, false) => "sltu",
+                (f3r::XOR, false) => "xor",
+                (f3r::SRL_SRA, false) => "srl",
+                (f3r::SRL_SRA, true) => "sra",
+                (f3r::OR, false) => "or",
+                (f3r::AND, false) => "and",
+                _ => return unknown(),
+            };
+            match (name, rs1_n, rs2_n) {
+                ("sub", 0, _) => format!("neg {}, {}", rd, rs2),
+                ("sltu", 0, _) => format!("snez {}, {}", rd, rs2),
+                ("slt", _, 0) => format!("sltz {}, {}", rd, rs1),
+                ("slt", 0, _) => format!("sgtz {}, {}", rd, rs2),
+                _ => format!("{} {}, {}, {}", name, rd, rs1, rs2),
+            }
+        }
+        op::CSR => {
+            let csr = csr::name(get_bits(inst, 31, 20) as usize);
+            let uimm = get_bits(inst, 19, 15);
+            match f3 {
+                f3c::EXCEPT => match (get_bits(inst, 31, 20), get_bits(inst, 31, 25)) {
+                    (exception::ECALL, _) => String::from("ecall"),
+                    (1, _) => String::from("ebreak"),
+                    (exception::SRET, _) => String::from("sret"),
+                    (exception::MRET, _) => String::from("mret"),
+                    (_, exception::SFENCE_VMA) => format!("sfence.vma {}, {}", rs1, rs2),
+                    (_, exception::HFENCE_VVMA) => format!("hfence.vvma {}, {}", rs1, rs2),
+                    (_, exception::HFENCE_GVMA) => format!("hfence.gvma {}, {}", rs1, rs2),
+                    _ => unknown(),
+                },
+                f3c::HLSV => {
+                    let suffix = |width| match width {
+                        1 => "b",
+                        2 => "h",
+                        _ => "w",
+                    };
+                    match Instruction::decode(inst) {
+                        Ok(Instruction::Hlv { width, signed, exec, .. }) => {
+                            let (x, u) = (if exec { "x" } else { "" }, if signed { "" } else { "u" });
+                            format!("hlv{}.{}{} {}, ({})", x, suffix(width), u, rd, rs1)
+                        }
+                        Ok(Instruction::Hsv { width, .. }) => format!("hsv.{} {}, ({})", suffix(width), rs2, rs1),
+                        _ => unknown(),
+                    }
+                }
+                f3c::CSRRS if rs1_n == 0 => format!("csrr {}, {}", rd, csr),
+                f3c::CSRRW if rd_n == 0 => format!("csrw {}, {}", csr, rs1),
+                f3c::CSRRS if rd_n == 0 => format!("csrs {}, {}", csr, rs1),
+                f3c::CSRRC if rd_n == 0 => format!("csrc {}, {}", csr, rs1),
+                f3c::CSRRWI if rd_n == 0 => format!("csrwi {}, {}", csr, uimm),
+                f3c::CSRRSI if rd_n == 0 => format!("csrsi {}, {}", csr, uimm),
+                f3c::CSRRCI if rd_n == 0 => format!("csrci {}, {}", csr, uimm),
+                f3c::CSRRW => format!("csrrw {}, {}, {}", rd, csr, rs1),
+                f3c::CSRRS => format!("csrrs {}, {}, {}", rd, csr, rs1),
+                f3c::CSRRC => format!("csrrc {}, {}, {}", rd, csr, rs1),
+                f3c::CSRRWI => format!("csrrwi {}, {}, {}", rd, csr, uimm),
+                f3c::CSRRSI => format!("csrrsi {}, {}, {}", rd, csr, uimm),
+                f3c::CSRRCI => format!("csrrci {}, {}, {}", rd, csr, uimm),
+                _ => unknown(),
+            }
+        }
+        op::FENCE => match f3 {
+            1 => String::from("fence.i"),
+            _ => String::from("fence"),
+        },
+        op::LOAD_FP => match f3 {
+            0b001 => format!("flh {}, {}({})", freg(rd_n), imm_i, rs1),
+            0b010 => format!("flw {}, {}({})", freg(rd_n), imm_i, rs1),
+            0b011 => format!("fld {}, {}({})", freg(rd_n), imm_i, rs1),
+            _ => unknown(),
+        },
+        op::STORE_FP => {
+            let offset = rv32::sign_extend(rv32::get_imm_st(inst), 11) as i32;
+            match f3 {
+                0b001 => format!("fsh {}, {}({})", freg(rs2_n), offset, rs1),
+                0b010 => format!("fsw {}, {}({})", freg(rs2_n), offset, rs1),
+                0b011 => format!("fsd {}, {}({})", freg(rs2_n), offset, rs1),
+                _ => unknown(),
+            }
+        }
+        _ => disassemble_fp(inst).unwrap_or_else(unknown),
+    }
+}
+
+/// Mnemonic of a 16-bit compressed instruction, following the RVC opcode map for RV32.
+pub fn compressed_name(inst: u16) -> &'static str {
+    let inst = inst as u32;
+    let f3 = get_bits(inst, 15, 13);
+    let rd = get_bits(inst, 11, 7);
+    let rs2 = get_bits(inst, 6, 2);
+    match (get_bits(inst, 1, 0), f3) {
+        (0, 0b000) if inst == 0 => "c.unimp",
+        (0, 0b000) => "c.addi4spn",
+        (0, 0b001) => "c.fld",
+        (0, 0b010) => "c.lw",
+        (0, 0b011) => "c.flw",
+        (0, 0b100) => match (get_bits(inst, 12, 10), get_bits(inst, 6, 6)) {
+            (0b000, _) => "c.lbu",
+            (0b001, 0) => "c.lhu",
+            (0b001, _) => "c.lh",
+            (0b010, _) => "c.sb",
+            (0b011, 0) => "c.sh",
+            _ => "c.reserved",
+        },
+        (0, 0b101) => "c.fsd",
+        (0, 0b110) => "c.sw",
+        (0, 0b111) => "c.fsw",
+        (1, 0b000) if rd == 0 => "c.nop",
+        (1, 0b000) => "c.addi",
+        (1, 0b001) => "c.jal",
+        (1, 0b010) => "c.li",
+        (1, 0b011) if rd == 2 => "c.addi16sp",
+        (1, 0b011) => "c.lui",
+        (1, 0b100) => match (get_bits(inst, 11, 10), get_bits(inst, 12, 12), get_bits(inst, 6, 5)) {
+            (0b00, ..) => "c.srli",
+            (0b01, ..) => "c.srai",
+            (0b10, ..) => "c.andi",
+            (_, 0, 0b00) => "c.sub",
+            (_, 0, 0b01) => "c.xor",
+            (_, 0, 0b10) => "c.or",
+            (_, 0, _) => "c.and",
+            (0b11, 1, 0b10) => "c.mul",
+            (0b11, 1, 0b11) => match get_bits(inst, 4, 2) {
+                0b000 => "c.zext.b",
+                0b001 => "c.sext.b",
+                0b010 => "c.zext.h",
+                0b011 => "c.sext.h",
+                0b101 => "c.not",
+                _ => "c.reserved",
+            },
+            _ => "c.reserved",
+        },
+        (1, 0b101) => "c.j",
+        (1, 0b110) => "c.beqz",
+        (1, 0b111) => "c.bnez",
+        (2, 0b000) => "c.slli",
+        (2, 0b001) => "c.fldsp",
+        (2, 0b010) => "c.lwsp",
+        (2, 0b011) => "c.flwsp",
+        (2, 0b100) => match (get_bits(inst, 12, 12), rd, rs2) {
+            (0, _, 0) => "c.jr",
+            (0, ..) => "c.mv",
+            (_, 0, 0) => "c.ebreak",
+            (_, _, 0) => "c.jalr",
+            _ => "c.add",
+        },
+        (2, 0b101) => "c.fsdsp",
+        (2, 0b110) => "c.swsp",
+        (2, 0b111) => "c.fswsp",
+        _ => "c.reserved",
+    }
+}
+
+/// Renders the instruction at the start of `raw` (as fetched, possibly compressed) located at
+/// `pc`, and returns its length in bytes. Compressed instructions are followed by their expansion.
+pub fn disassemble_raw(raw: u64, pc: u64) -> (String, u64) {
+    let len = parse_inst_length(raw);
+    let text = match len {
+        2 => match Cpu::uncompress(raw as u16 as u32) {
+            Ok(inst) => format!("{} ({})", compressed_name(raw as u16), disassemble(inst, pc)),
+            // not executed by this core yet, so there is no expansion to show
+            Err(_) => match compressed_name(raw as u16) {
+                "c.reserved" => format!(".half {:#06x}", raw as u16),
+                name => String::from(name),
+            },
+        },
+        4 => disassemble(raw as u32, pc),
+        _ => format!(".dword {:#018x}", raw),
+    };
+    (text, len)
+}
+
+/// Renders a Zcmp or Zcmt instruction, which take the encodings of c.fsdsp.
+pub fn disassemble_zcm(inst: u16) -> String {
+    match Instruction::decode_zcm(inst) {
+        Ok(Instruction::PushPop { op, regs, stack_adj }) => {
+            let name = match op {
+                PushPop::Push => "cm.push",
+                PushPop::Pop => "cm.pop",
+                PushPop::Popret => "cm.popret",
+                PushPop::Popretz => "cm.popretz",
+            };
+            let list = match regs {
+                1 => String::from("ra"),
+                2 => String::from("ra, s0"),
+                _ => format!("ra, s0-s{}", regs - 2),
+            };
+            let sign = if op == PushPop::Push { "-" } else { "" };
+            format!("{} {{{}}}, {}{}", name, list, sign, stack_adj)
+        }
+        Ok(Instruction::Mv01s { r1s, r2s, to_a }) => {
+            let name = if to_a { "cm.mva01s" } else { "cm.mvsa01" };
+            format!("{} {}, {}", name, reg(r1s), reg(r2s))
+        }
+        Ok(Instruction::TableJump { index }) if index < 32 => format!("cm.jt {}", index),
+        Ok(Instruction::TableJump { index }) => format!("cm.jalt {}", index),
+        _ => format!(".half {:#06x}", inst),
+    }
+}
+
+/// Like `disassemble_raw`, with direct jump and branch targets annotated as `<symbol+offset>`.
+pub fn disassemble_sym(raw: u64, pc: u64, elf: Option<&Elf>) -> (String, u64) {
+    let (mut text, len) = disassemble_raw(raw, pc);
+    let sym = expand(raw)
+        .and_then(|inst| branch_target(inst, pc))
+        .and_then(|target| elf?.symbolize(target));
+    match sym {
+        Some((sym, 0)) => text += &format!(" <{}>", sym.name),
+        Some((sym, off)) => text += &format!(" <{}+{:#x}>", sym.name, off),
+        None => {}
+    }
+    (text, len)
+}
+
+/// The 32-bit instruction that is executed for `raw`, decompressed if needed.
+pub fn expand(raw: u64) -> Option<u32> {
+    match parse_inst_length(raw) {
+        2 => Cpu::uncompress(raw as u16 as u32).ok(),
+        4 => Some(raw as u32),
+        _ => None,
+    }
+}
+
+/// Disassembles a whole file in the style of `objdump -d`: the executable sections of an ELF, or
+/// only `section` if given. Anything else is treated as a raw binary starting at address 0.
+pub fn dump(buf: &[u8], section: Option<&str>) -> Result<String, String> {
+    let mut out = String::new();
+    if !elf::is_elf(buf) {
+        dump_bytes(&mut out, buf, 0, None);
+        return Ok(out);
+    }
+    let elf = Elf::parse(buf)?;
+    let sections = match section {
+        Some(name) => vec![elf
+            .section(name)
+            .ok_or_else(|| format!("no section named {}", name))?],
+        None => elf.sections.iter().filter(|s| s.executable).collect(),
+    };
+    for s in sections {
+        writeln!(out, "\nDisassembly of section {}:", s.name).unwrap();
+        dump_bytes(&mut out, &s.data, s.addr, Some(&elf));
+    }
+    Ok(out)
+}
+
+fn dump_bytes(out: &mut String, data: &[u8], base: u64, elf: Option<&Elf>) {
+    let mut off = 0;
+    while off < data.len() {
+        let addr = base + off as u64;
+        if let Some((sym, 0)) = elf.and_then(|e| e.symbolize(addr)) {
+            writeln!(out, "\n{:08x} <{}>:", addr, sym.name).unwrap();
+        }
+        let mut bytes = [0; 8];
+        let n = (data.len() - off).min(8);
+        bytes[..n].copy_from_slice(&data[off..off + n]);
+        let raw = LittleEndian::read_u64(&bytes);
+        let (text, len) = disassemble_sym(raw, addr, elf);
+        let len = len as usize;
+        if off + len > data.len() {
+            writeln!(out, "{:8x}:\t{:02x?}\t(truncated)", addr, &data[off..]).unwrap();
+            break;
+        }
+        let word = if len >= 8 { raw } else { raw & ((1 << (8 * len)) - 1) };
+        writeln!(out, "{:8x}:\t{:0width$x}\t{}", addr, word, text, width = 2 * len).unwrap();
+        off += len;
+    }
+}
+
+#[test]
+fn test_disassemble() {
+    assert!(disassemble(0x00408093, 0) == "addi ra, ra, 4");
+    assert!(disassemble(0x00008067, 0) == "ret");
+    assert!(disassemble(0x000300e7, 0) == "jalr t1");
+    assert!(disassemble(0x008000ef, 0x100) == "jal 0x108");
+    assert!(disassemble(0x0000006f, 0x100) == "j 0x100");
+    assert!(disassemble(0xfe209ee3, 0x100) == "bne ra, sp, 0xfc");
+    assert!(disassemble(0xfe009ee3, 0x100) == "bnez ra, 0xfc");
+    assert!(disassemble(0x40208033, 0) == "sub zero, ra, sp");
+    assert!(disassemble(0x40200033, 0) == "neg zero, sp");
+    assert!(disassemble(0x00000013, 0) == "nop");
+    assert!(disassemble(0x00500513, 0) == "li a0, 5");
+    assert!(disassemble(0x00050593, 0) == "mv a1, a0");
+    assert!(disassemble(0x30200073, 0) == "mret");
+    assert!(disassemble(0x10200073, 0) == "sret");
+    assert!(disassemble(0x12b50073, 0) == "sfence.vma a0, a1");
+    assert!(disassemble(0x6415c573, 0) == "hlv.hu a0, (a1)");
+    assert!(disassemble(0x6835c573, 0) == "hlvx.wu a0, (a1)");
+    assert!(disassemble(0x6aa5c073, 0) == "hsv.w a0, (a1)");
+    assert!(disassemble(0x341022f3, 0) == "csrr t0, mepc");
+    assert!(disassemble(0x30529073, 0) == "csrw mtvec, t0");
+    assert!(disassemble(0x0085b507, 0) == "fld fa0, 8(a1)");
+    assert!(disassemble(0x6ac5f543, 0) == "fmadd.d fa0, fa1, fa2, fa3");
+    assert!(disassemble(0x40159553, 0) == "fcvt.s.d fa0, fa1, rtz");
+    assert!(disassemble(0xa0c5a553, 0) == "feq.s a0, fa1, fa2");
+    assert!(disassemble(0xe0058553, 0) == "fmv.x.w a0, fa1");
+    assert!(disassemble(0x22b59553, 0) == "fneg.d fa0, fa1");
+    assert!(disassemble(0xfea11f27, 0) == "fsh fa0, -2(sp)");
+    assert!(disassemble(0x40c5f533, 0) == "andn a0, a1, a2");
+    assert!(disassemble(0x6985d513, 0) == "rev8 a0, a1");
+    assert!(disassemble(0x20c5e53b, 0) == "sh3add.uw a0, a1, a2");
+    assert!(disassemble(0x48359513, 0) == "bclri a0, a1, 3");
+    assert!(disassemble(0x6035d51b, 0) == "roriw a0, a1, 3");
+    assert!(disassemble(0x0a85951b, 0) == "slli.uw a0, a1, 40");
+    assert!(disassemble(0x0805c533, 0) == "zext.h a0, a1");
+    assert!(disassemble(0x08c5c533, 0) == "pack a0, a1, a2");
+    assert!(disassemble(0xe6c58533, 0) == "aes32esmi a0, a1, a2, 3");
+    assert!(disassemble(0x10259513, 0) == "sha256sig0 a0, a1");
+    assert!(disassemble(0x4405f553, 0) == "fcvt.h.s fa0, fa1");
+    assert!(disassemble(0xe4058553, 0) == "fmv.x.h a0, fa1");
+    assert!(disassemble(0x0d057557, 0) == "vsetvli a0, a0, e32, m1, ta, ma");
+    assert!(disassemble(0xc1027557, 0) == "vsetivli a0, 4, e32, m1, tu, mu");
+    assert!(disassemble(0x0205e107, 0) == "vle32.v v2, (a1)");
+    assert!(disassemble(0x0ab5e107, 0) == "vlse32.v v2, (a1), a1");
+    assert!(disassemble(0x2205f1a7, 0) == "vsseg2e64.v v3, (a1)");
+    assert!(disassemble(0x02208057, 0) == "vadd.vv v0, v2, v1");
+    assert!(disassemble(0x0021b057, 0) == "vadd.vi v0, v2, 3, v0.t");
+    assert!(disassemble(0x5e05c157, 0) == "vmv.v.x v2, a1");
+    assert!(disassemble(0x4a2510d7, 0) == "vfwcvt.f.xu.v v1, v2");
+    assert!(disassemble(0xb220d0d7, 0) == "vfmacc.vf v1, ft1, v2");
+    assert!(disassemble(0x40218057, 0) == "vadc.vvm v0, v2, v3, v0");
+    assert!(disassemble(0xba2180d7, 0) == "vnclipu.wv v1, v2, v3");
+}
+
+#[test]
+fn test_compressed() {
+    assert!(compressed_name(0x4515) == "c.li");
+    assert!(compressed_name(0x8082) == "c.jr");
+    assert!(compressed_name(0x9002) == "c.ebreak");
+    assert!(disassemble_raw(0x050a, 0) == (String::from("c.slli (slli a0, a0, 2)"), 2));
+    assert!(disassemble_raw(0x0001, 0).0.starts_with("c.nop"));
+    assert!(disassemble_raw(0x8164, 0) == (String::from("c.lbu (lbu s1, 3(a0))"), 2));
+    assert!(disassemble_raw(0x9c75, 0).0 == "c.not (not s0, s0)");
+    assert!(compressed_name(0x9c4d) == "c.mul" && disassemble_raw(0x9c4d, 0).0 == "c.mul");
+    assert!(disassemble_zcm(0xb876) == "cm.push {ra, s0-s2}, -32");
+    assert!(disassemble_zcm(0xbe46) == "cm.popret {ra}, 32");
+    assert!(disassemble_zcm(0xacea) == "cm.mva01s s1, s2" && disassemble_zcm(0xa086) == "cm.jalt 33");
+}
+
+#[test]
+fn test_dump() {
+    let out = dump(include_bytes!("../riscv-tests/rv32ui-p-add"), None).unwrap();
+    assert!(out.contains("Disassembly of section .text.init:"));
+    assert!(out.contains("80000000 <_start>:"));
+    assert!(out.contains("80000000:\t0480006f\tj 0x80000048 <reset_vector>"));
+    let raw = dump(&[0x93, 0x80, 0x40, 0x00, 0x67, 0x80, 0x00, 0x00], None).unwrap();
+    assert!(raw == "       0:\t00408093\taddi ra, ra, 4\n       4:\t00008067\tret\n");
+}
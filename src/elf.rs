@@ -0,0 +1,688 @@
+use crate::mmu::Mmu;
+use byteorder::{ByteOrder, LittleEndian};
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHF_EXECINSTR: u64 = 0x4;
+const STT_NOTYPE: u8 = 0;
+const STT_FUNC: u8 = 2;
+
+pub struct Segment {
+    pub addr: u64,
+    pub data: Vec<u8>,
+    pub mem_size: u64,
+}
+
+pub struct Section {
+    pub name: String,
+    pub addr: u64,
+    pub executable: bool,
+    pub data: Vec<u8>,
+}
+
+pub struct Symbol {
+    pub name: String,
+    pub addr: u64,
+    pub size: u64,
+    pub is_func: bool,
+}
+
+pub struct LineRow {
+    pub addr: u64,
+    pub file: String,
+    pub line: u64,
+    pub end_sequence: bool,
+}
+
+/// The parts of a little-endian RISC-V ELF image the emulator needs: loadable segments,
+/// symbols for symbolization and, when present, the DWARF line table.
+pub struct Elf {
+    pub entry: u64,
+    pub segments: Vec<Segment>,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    pub lines: Vec<LineRow>,
+    /// Problems that did not stop the image from loading, such as an unreadable line table.
+    pub warnings: Vec<String>,
+}
+
+pub fn is_elf(buf: &[u8]) -> bool {
+    buf.len() >= 4 && buf[0..4] == [0x7f, b'E', b'L', b'F']
+}
+
+fn slice(buf: &[u8], off: u64, len: u64) -> Result<&[u8], String> {
+    let start = off as usize;
+    let end = start
+        .checked_add(len as usize)
+        .ok_or_else(|| String::from("ELF offset overflow"))?;
+    buf.get(start..end)
+        .ok_or_else(|| String::from("ELF is truncated"))
+}
+
+fn cstr(buf: &[u8], off: usize) -> String {
+    let tail = buf.get(off..).unwrap_or(&[]);
+    let end = tail.iter().position(|&b| b == 0).unwrap_or(tail.len());
+    String::from_utf8_lossy(&tail[..end]).into_owned()
+}
+
+/// File offset of entry `i` of a header table at `off` with entries of `size` bytes.
+fn table_entry(off: u64, i: u64, size: u16) -> Result<u64, String> {
+    i.checked_mul(size as u64)
+        .and_then(|o| o.checked_add(off))
+        .ok_or_else(|| String::from("ELF offset overflow"))
+}
+
+impl Elf {
+    pub fn parse(buf: &[u8]) -> Result<Elf, String> {
+        if !is_elf(buf) || buf.len() < 52 {
+            return Err(String::from("Not an ELF file"));
+        }
+        if buf[5] != 1 {
+            return Err(String::from("Only little-endian ELF is supported"));
+        }
+        let is_64 = match buf[4] {
+            1 => false,
+            2 => true,
+            _ => return Err(String::from("Invalid ELF class")),
+        };
+        // (entry, phoff, shoff, phentsize, phnum, shentsize, shnum, shstrndx)
+        let h = if is_64 {
+            slice(buf, 0, 64)?;
+            (
+                LittleEndian::read_u64(&buf[24..]),
+                LittleEndian::read_u64(&buf[32..]),
+                LittleEndian::read_u64(&buf[40..]),
+                LittleEndian::read_u16(&buf[54..]),
+                LittleEndian::read_u16(&buf[56..]),
+                LittleEndian::read_u16(&buf[58..]),
+                LittleEndian::read_u16(&buf[60..]),
+                LittleEndian::read_u16(&buf[62..]),
+            )
+        } else {
+            (
+                LittleEndian::read_u32(&buf[24..]) as u64,
+                LittleEndian::read_u32(&buf[28..]) as u64,
+                LittleEndian::read_u32(&buf[32..]) as u64,
+                LittleEndian::read_u16(&buf[42..]),
+                LittleEndian::read_u16(&buf[44..]),
+                LittleEndian::read_u16(&buf[46..]),
+                LittleEndian::read_u16(&buf[48..]),
+                LittleEndian::read_u16(&buf[50..]),
+            )
+        };
+        let (entry, phoff, shoff, phentsize, phnum, shentsize, shnum, shstrndx) = h;
+        // the headers are read up to their last field used below
+        let (ph_min, sh_min) = if is_64 { (56, 64) } else { (32, 40) };
+        if phnum > 0 && phentsize < ph_min {
+            return Err(format!("ELF program header size {} is too small", phentsize));
+        }
+        if shnum > 0 && shentsize < sh_min {
+            return Err(format!("ELF section header size {} is too small", shentsize));
+        }
+
+        let mut segments = Vec::new();
+        for i in 0..phnum as u64 {
+            let ph = slice(buf, table_entry(phoff, i, phentsize)?, phentsize as u64)?;
+            // (type, offset, paddr, filesz, memsz)
+            let (p_type, offset, paddr, filesz, memsz) = if is_64 {
+                (
+                    LittleEndian::read_u32(&ph[0..]),
+                    LittleEndian::read_u64(&ph[8..]),
+                    LittleEndian::read_u64(&ph[24..]),
+                    LittleEndian::read_u64(&ph[32..]),
+                    LittleEndian::read_u64(&ph[40..]),
+                )
+            } else {
+                (
+                    LittleEndian::read_u32(&ph[0..]),
+                    LittleEndian::read_u32(&ph[4..]) as u64,
+                    LittleEndian::read_u32(&ph[12..]) as u64,
+                    LittleEndian::read_u32(&ph[16..]) as u64,
+                    LittleEndian::read_u32(&ph[20..]) as u64,
+                )
+            };
+            if p_type == PT_LOAD && memsz > 0 {
+                if paddr.checked_add(memsz).is_none() {
+                    return Err(format!("segment at {:#x} of {:#x} bytes wraps around", paddr, memsz));
+                }
+                if filesz > memsz {
+                    return Err(format!(
+                        "segment at {:#x} has {:#x} bytes in the file but {:#x} in memory",
+                        paddr, filesz, memsz
+                    ));
+                }
+                segments.push(Segment {
+                    addr: paddr,
+                    data: slice(buf, offset, filesz)?.to_vec(),
+                    mem_size: memsz,
+                });
+            }
+        }
+
+        // (name, type, addr, offset, size, link)
+        let mut headers = Vec::new();
+        for i in 0..shnum as u64 {
+            let sh = slice(buf, table_entry(shoff, i, shentsize)?, shentsize as u64)?;
+            headers.push(if is_64 {
+                (
+                    LittleEndian::read_u32(&sh[0..]),
+                    LittleEndian::read_u32(&sh[4..]),
+                    LittleEndian::read_u64(&sh[8..]),
+                    LittleEndian::read_u64(&sh[16..]),
+                    LittleEndian::read_u64(&sh[24..]),
+                    LittleEndian::read_u64(&sh[32..]),
+                    LittleEndian::read_u32(&sh[40..]),
+                )
+            } else {
+                (
+                    LittleEndian::read_u32(&sh[0..]),
+                    LittleEndian::read_u32(&sh[4..]),
+                    LittleEndian::read_u32(&sh[8..]) as u64,
+                    LittleEndian::read_u32(&sh[12..]) as u64,
+                    LittleEndian::read_u32(&sh[16..]) as u64,
+                    LittleEndian::read_u32(&sh[20..]) as u64,
+                    LittleEndian::read_u32(&sh[24..]),
+                )
+            });
+        }
+        let shstrtab = match headers.get(shstrndx as usize) {
+            Some(&(_, _, _, _, off, size, _)) => slice(buf, off, size)?,
+            None => &[],
+        };
+        let mut sections = Vec::new();
+        let mut symbols = Vec::new();
+        for &(name, sh_type, flags, addr, off, size, link) in headers.iter() {
+            // SHT_NOBITS has no file contents
+            let data = if sh_type == 8 {
+                Vec::new()
+            } else {
+                slice(buf, off, size)?.to_vec()
+            };
+            if sh_type == SHT_SYMTAB {
+                let strtab = match headers.get(link as usize) {
+                    Some(&(_, _, _, _, off, size, _)) => slice(buf, off, size)?,
+                    None => &[],
+                };
+                let entsize = if is_64 { 24 } else { 16 };
+                for sym in data.chunks_exact(entsize) {
+                    let (st_name, value, size, info, shndx) = if is_64 {
+                        (
+                            LittleEndian::read_u32(&sym[0..]),
+                            LittleEndian::read_u64(&sym[8..]),
+                            LittleEndian::read_u64(&sym[16..]),
+                            sym[4],
+                            LittleEndian::read_u16(&sym[6..]),
+                        )
+                    } else {
+                        (
+                            LittleEndian::read_u32(&sym[0..]),
+                            LittleEndian::read_u32(&sym[4..]) as u64,
+                            LittleEndian::read_u32(&sym[8..]) as u64,
+                            sym[12],
+                            LittleEndian::read_u16(&sym[14..]),
+                        )
+                    };
+                    let st_type = info & 0xf;
+                    let name = cstr(strtab, st_name as usize);
+                    if shndx == 0 || name.is_empty() || (st_type != STT_FUNC && st_type != STT_NOTYPE) {
+                        continue;
+                    }
+                    symbols.push(Symbol {
+                        name,
+                        addr: value,
+                        size,
+                        is_func: st_type == STT_FUNC,
+                    });
+                }
+            }
+            sections.push(Section {
+                name: cstr(shstrtab, name as usize),
+                addr,
+                executable: flags & SHF_EXECINSTR != 0,
+                data,
+            });
+        }
+        symbols.sort_by_key(|s| (s.addr, !s.is_func));
+
+        let mut elf = Elf {
+            entry,
+            segments,
+            sections,
+            symbols,
+            lines: Vec::new(),
+            warnings: Vec::new(),
+        };
+        // line info is optional, so an image whose line table cannot be read still runs
+        if let Some(debug_line) = elf.section(".debug_line") {
+            let line_str = elf.section(".debug_line_str").map(|s| &s.data[..]).unwrap_or(&[]);
+            let debug_str = elf.section(".debug_str").map(|s| &s.data[..]).unwrap_or(&[]);
+            match dwarf::parse_debug_line(&debug_line.data, line_str, debug_str) {
+                Ok(mut lines) => {
+                    lines.sort_by_key(|r| (r.addr, !r.end_sequence));
+                    elf.lines = lines;
+                }
+                Err(e) => elf.warnings.push(format!("ignoring .debug_line: {}", e)),
+            }
+        }
+        Ok(elf)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// Lowest and highest (exclusive) address covered by the loadable segments.
+    pub fn span(&self) -> Option<(u64, u64)> {
+        let low = self.segments.iter().map(|s| s.addr).min()?;
+        let high = self.segments.iter().map(|s| s.addr + s.mem_size).max()?;
+        Some((low, high))
+    }
+
+    /// Copies the loadable segments into `mmu`, zero-filling each up to its memory size.
+    pub fn load(&self, mmu: &mut Mmu) -> Result<(), String> {
+        for seg in self.segments.iter() {
+            if !mmu.contains(seg.addr, seg.mem_size) {
+                return Err(format!(
+                    "segment at {:#x} of {:#x} bytes is outside memory",
+                    seg.addr, seg.mem_size
+                ));
+            }
+            let mut data = seg.data.clone();
+            data.resize(seg.mem_size as usize, 0);
+            mmu.poke(seg.addr, &data);
+        }
+        Ok(())
+    }
+
+    /// Returns the closest symbol at or before `addr` and the offset into it.
+    pub fn symbolize(&self, addr: u64) -> Option<(&Symbol, u64)> {
+        let idx = self.symbols.partition_point(|s| s.addr <= addr);
+        let sym = self.symbols[..idx].iter().rev().find(|s| s.is_func || s.size == 0 || addr < s.addr.saturating_add(s.size))?;
+        Some((sym, addr - sym.addr))
+    }
+
+    /// Source location of `addr` from the DWARF line table.
+    pub fn line(&self, addr: u64) -> Option<(&str, u64)> {
+        let idx = self.lines.partition_point(|r| r.addr <= addr);
+        let row = self.lines[..idx].last()?;
+        if row.end_sequence {
+            return None;
+        }
+        Some((&row.file, row.line))
+    }
+}
+
+mod dwarf {
+    use super::{cstr, LineRow};
+    use byteorder::{ByteOrder, LittleEndian};
+
+    struct Reader<'a> {
+        buf: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+            let end = self.pos.saturating_add(n);
+            let s = self
+                .buf
+                .get(self.pos..end)
+                .ok_or_else(|| String::from(".debug_line is truncated"))?;
+            self.pos = end;
+            Ok(s)
+        }
+        fn u8(&mut self) -> Result<u8, String> {
+            Ok(self.take(1)?[0])
+        }
+        fn u16(&mut self) -> Result<u16, String> {
+            Ok(LittleEndian::read_u16(self.take(2)?))
+        }
+        fn u32(&mut self) -> Result<u32, String> {
+            Ok(LittleEndian::read_u32(self.take(4)?))
+        }
+        fn u64(&mut self) -> Result<u64, String> {
+            Ok(LittleEndian::read_u64(self.take(8)?))
+        }
+        fn uint(&mut self, size: usize) -> Result<u64, String> {
+            Ok(match size {
+                1 => self.u8()? as u64,
+                2 => self.u16()? as u64,
+                4 => self.u32()? as u64,
+                8 => self.u64()?,
+                _ => return Err(format!("Unsupported DWARF integer size {}", size)),
+            })
+        }
+        fn uleb(&mut self) -> Result<u64, String> {
+            let mut result = 0u64;
+            let mut shift = 0;
+            loop {
+                let b = self.u8()?;
+                if shift < 64 {
+                    result |= ((b & 0x7f) as u64) << shift;
+                }
+                shift += 7;
+                if b & 0x80 == 0 {
+                    return Ok(result);
+                }
+            }
+        }
+        fn sleb(&mut self) -> Result<i64, String> {
+            let mut result = 0i64;
+            let mut shift = 0;
+            loop {
+                let b = self.u8()?;
+                if shift < 64 {
+                    result |= ((b & 0x7f) as i64) << shift;
+                }
+                shift += 7;
+                if b & 0x80 == 0 {
+                    if shift < 64 && b & 0x40 != 0 {
+                        result |= -1 << shift;
+                    }
+                    return Ok(result);
+                }
+            }
+        }
+        fn string(&mut self) -> Result<String, String> {
+            let s = cstr(self.buf, self.pos);
+            self.take(s.len() + 1)?;
+            Ok(s)
+        }
+    }
+
+    const DW_LNCT_PATH: u64 = 1;
+    const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+    /// Reads one DWARF 5 directory/file entry attribute, returning it as a string or number.
+    fn read_form(
+        r: &mut Reader,
+        form: u64,
+        offset_size: usize,
+        line_str: &[u8],
+        debug_str: &[u8],
+    ) -> Result<(Option<String>, u64), String> {
+        Ok(match form {
+            0x08 => (Some(r.string()?), 0),
+            0x1f => (Some(cstr(line_str, r.uint(offset_size)? as usize)), 0),
+            0x0e => (Some(cstr(debug_str, r.uint(offset_size)? as usize)), 0),
+            0x0b => (None, r.u8()? as u64),
+            0x05 => (None, r.u16()? as u64),
+            0x06 => (None, r.u32()? as u64),
+            0x07 => (None, r.u64()?),
+            0x0f => (None, r.uleb()?),
+            0x1e => {
+                r.take(16)?;
+                (None, 0)
+            }
+            0x09 => {
+                let len = r.uleb()? as usize;
+                r.take(len)?;
+                (None, 0)
+            }
+            _ => return Err(format!("Unsupported DWARF form {:#x} in line table", form)),
+        })
+    }
+
+    fn read_entries(
+        r: &mut Reader,
+        offset_size: usize,
+        line_str: &[u8],
+        debug_str: &[u8],
+    ) -> Result<Vec<(String, u64)>, String> {
+        let format_count = r.u8()?;
+        let mut format = Vec::new();
+        for _ in 0..format_count {
+            format.push((r.uleb()?, r.uleb()?));
+        }
+        let count = r.uleb()?;
+        // every entry takes at least a byte
+        if count > 0 && (format.is_empty() || count > (r.buf.len() - r.pos) as u64) {
+            return Err(String::from(".debug_line is truncated"));
+        }
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let mut path = String::new();
+            let mut dir = 0;
+            for &(content, form) in format.iter() {
+                let (s, n) = read_form(r, form, offset_size, line_str, debug_str)?;
+                match content {
+                    DW_LNCT_PATH => path = s.unwrap_or_default(),
+                    DW_LNCT_DIRECTORY_INDEX => dir = n,
+                    _ => {}
+                }
+            }
+            entries.push((path, dir));
+        }
+        Ok(entries)
+    }
+
+    fn join(dirs: &[String], name: &str, dir: u64) -> String {
+        if name.starts_with('/') {
+            return name.to_string();
+        }
+        match dirs.get(dir as usize) {
+            Some(d) if !d.is_empty() => format!("{}/{}", d, name),
+            _ => name.to_string(),
+        }
+    }
+
+    /// Runs every line number program in `.debug_line` (DWARF 2 to 5) and collects its rows.
+    pub fn parse_debug_line(
+        section: &[u8],
+        line_str: &[u8],
+        debug_str: &[u8],
+    ) -> Result<Vec<LineRow>, String> {
+        let mut rows = Vec::new();
+        let mut r = Reader { buf: section, pos: 0 };
+        while r.pos < section.len() {
+            let mut unit_length = r.u32()? as u64;
+            let mut offset_size = 4;
+            if unit_length == 0xffff_ffff {
+                unit_length = r.u64()?;
+                offset_size = 8;
+            }
+            let unit_end = r.pos.saturating_add(unit_length as usize);
+            let version = r.u16()?;
+            let mut address_size = 4;
+            if version >= 5 {
+                address_size = r.u8()? as usize;
+                r.u8()?;
+            }
+            let header_length = r.uint(offset_size)? as usize;
+            let program_start = r.pos.saturating_add(header_length);
+            let min_inst_len = r.u8()? as u64;
+            if version >= 4 {
+                r.u8()?;
+            }
+            // default_is_stmt
+            r.u8()?;
+            let line_base = r.u8()? as i8 as i64;
+            let line_range = r.u8()? as u64;
+            if line_range == 0 {
+                return Err(String::from("line_range of 0 in .debug_line"));
+            }
+            let opcode_base = r.u8()?;
+            let mut std_lengths = Vec::new();
+            for _ in 1..opcode_base {
+                std_lengths.push(r.u8()?);
+            }
+            let mut dirs = Vec::new();
+            let mut files = Vec::new();
+            if version >= 5 {
+                dirs = read_entries(&mut r, offset_size, line_str, debug_str)?
+                    .into_iter()
+                    .map(|(p, _)| p)
+                    .collect();
+                for (name, dir) in read_entries(&mut r, offset_size, line_str, debug_str)? {
+                    files.push(join(&dirs, &name, dir));
+                }
+            } else {
+                // index 0 is the compilation directory, which DWARF 4 leaves implicit
+                dirs.push(String::new());
+                loop {
+                    let d = r.string()?;
+                    if d.is_empty() {
+                        break;
+                    }
+                    dirs.push(d);
+                }
+                // file indices are 1-based before DWARF 5
+                files.push(String::new());
+                loop {
+                    let name = r.string()?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    let dir = r.uleb()?;
+                    r.uleb()?;
+                    r.uleb()?;
+                    files.push(join(&dirs, &name, dir));
+                }
+            }
+            r.pos = program_start;
+
+            let file_name = |files: &Vec<String>, f: u64| {
+                files.get(f as usize).cloned().unwrap_or_else(|| String::from("??"))
+            };
+            let mut addr = 0u64;
+            let mut file = 1u64;
+            let mut line = 1i64;
+            while r.pos < unit_end {
+                let opcode = r.u8()?;
+                if opcode >= opcode_base {
+                    let adjusted = (opcode - opcode_base) as u64;
+                    addr = addr.wrapping_add((adjusted / line_range) * min_inst_len);
+                    line = line.wrapping_add(line_base + (adjusted % line_range) as i64);
+                    rows.push(LineRow {
+                        addr,
+                        file: file_name(&files, file),
+                        line: line as u64,
+                        end_sequence: false,
+                    });
+                    continue;
+                }
+                match opcode {
+                    0 => {
+                        let len = r.uleb()? as usize;
+                        let next = r.pos.saturating_add(len);
+                        match r.u8()? {
+                            1 => {
+                                rows.push(LineRow {
+                                    addr,
+                                    file: file_name(&files, file),
+                                    line: line as u64,
+                                    end_sequence: true,
+                                });
+                                addr = 0;
+                                file = 1;
+                                line = 1;
+                            }
+                            2 => addr = r.uint(address_size.min(len.saturating_sub(1)))?,
+                            3 => {
+                                let name = r.string()?;
+                                let dir = r.uleb()?;
+                                files.push(join(&dirs, &name, dir));
+                            }
+                            _ => {}
+                        }
+                        r.pos = next;
+                    }
+                    1 => rows.push(LineRow {
+                        addr,
+                        file: file_name(&files, file),
+                        line: line as u64,
+                        end_sequence: false,
+                    }),
+                    2 => addr = addr.wrapping_add(r.uleb()?.wrapping_mul(min_inst_len)),
+                    3 => line = line.wrapping_add(r.sleb()?),
+                    4 => file = r.uleb()?,
+                    8 => addr = addr.wrapping_add(((255 - opcode_base) as u64 / line_range) * min_inst_len),
+                    9 => addr = addr.wrapping_add(r.u16()? as u64),
+                    _ => {
+                        for _ in 0..std_lengths[opcode as usize - 1] {
+                            r.uleb()?;
+                        }
+                    }
+                }
+            }
+            r.pos = unit_end;
+        }
+        Ok(rows)
+    }
+}
+
+#[test]
+fn test_parse_riscv_test() {
+    let elf = Elf::parse(include_bytes!("../riscv-tests/rv32ui-p-add")).unwrap();
+    assert!(elf.entry == 0x80000000);
+    assert!(elf.span().unwrap().0 == 0x80000000);
+    let (sym, off) = elf.symbolize(0x8000004c).unwrap();
+    assert!(sym.name == "reset_vector");
+    assert!(off == 4);
+    let text = elf.section(".text.init").unwrap();
+    assert!(text.addr == 0x80000000 && text.executable);
+}
+
+#[test]
+fn test_debug_line_v4() {
+    #[rustfmt::skip]
+    let program: Vec<u8> = vec![
+        // set_address 0x1000
+        0, 5, 2, 0x00, 0x10, 0, 0,
+        // copy
+        1,
+        // special opcode (1 - line_base) + 4 * line_range + opcode_base: addr += 4, line += 1
+        75,
+        // advance_pc 8, end_sequence
+        2, 8, 0, 1, 1,
+    ];
+    let mut header: Vec<u8> = vec![4, 0, 0, 0, 0, 0];
+    // min_inst_len, max_ops, default_is_stmt, line_base, line_range, opcode_base
+    header.extend_from_slice(&[1, 1, 1, (-5i8) as u8, 14, 13]);
+    header.extend_from_slice(&[0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+    header.extend_from_slice(b"src\0\0main.c\0\x01\0\0\0");
+    let header_length = (header.len() - 6) as u32;
+    header[2..6].copy_from_slice(&header_length.to_le_bytes());
+    let mut section = ((header.len() + program.len()) as u32).to_le_bytes().to_vec();
+    section.extend_from_slice(&header);
+    section.extend_from_slice(&program);
+    let rows = dwarf::parse_debug_line(&section, &[], &[]).unwrap();
+    assert!(rows.len() == 3);
+    assert!(rows[0].addr == 0x1000 && rows[0].line == 1 && rows[0].file == "src/main.c");
+    assert!(rows[1].addr == 0x1004 && rows[1].line == 2);
+    assert!(rows[2].addr == 0x100c && rows[2].end_sequence);
+}
+
+#[test]
+fn test_malformed() {
+    // program headers too small to hold their fields, or past the end of the address space
+    let mut buf = include_bytes!("../riscv-tests/rv32ui-p-add").to_vec();
+    buf[42..44].copy_from_slice(&8u16.to_le_bytes());
+    assert!(Elf::parse(&buf).is_err());
+    let mut buf = include_bytes!("../riscv-tests/rv32ui-p-add").to_vec();
+    buf[28..32].copy_from_slice(&0xffffffffu32.to_le_bytes());
+    assert!(Elf::parse(&buf).is_err());
+    // a segment with more bytes in the file than in memory
+    let mut buf = include_bytes!("../riscv-tests/rv32ui-p-add").to_vec();
+    buf[72..76].copy_from_slice(&1000u32.to_le_bytes());
+    assert!(matches!(Elf::parse(&buf), Err(e) if e.contains("in memory")));
+    // a symbol running past the end of the address space
+    let mut elf = Elf::parse(include_bytes!("../riscv-tests/rv32ui-p-add")).unwrap();
+    elf.symbols = vec![Symbol {
+        name: String::from("last"),
+        addr: u64::MAX - 0xf,
+        size: 0x100,
+        is_func: false,
+    }];
+    assert!(elf.symbolize(u64::MAX - 1).map(|(s, off)| (s.name.as_str(), off)) == Some(("last", 0xe)));
+    // a line table with a line_range of 0, and a set_address with no operand
+    let mut header: Vec<u8> = vec![4, 0, 8, 0, 0, 0];
+    header.extend_from_slice(&[1, 1, 1, (-5i8) as u8, 0, 1, 0, 0]);
+    let mut section = (header.len() as u32).to_le_bytes().to_vec();
+    section.extend_from_slice(&header);
+    assert!(matches!(dwarf::parse_debug_line(&section, &[], &[]), Err(e) if e.contains("line_range")));
+    section[14] = 14;
+    section[0] += 3;
+    section.extend_from_slice(&[0, 0, 2]);
+    assert!(matches!(dwarf::parse_debug_line(&section, &[], &[]), Err(e) if e.contains("size 0")));
+}
@@ -0,0 +1,739 @@
+//! IEEE 754 binary floating point in software, for the F, D and Zfh extensions. Results are rounded
+//! in any RISC-V rounding mode and raise the exception flags accrued in fflags. A NaN result is
+//! always the canonical NaN, as RISC-V does not propagate NaN payloads.
+
+/// Exception flags, as laid out in fflags.
+pub mod flag {
+    /// inexact
+    pub const NX: u32 = 1;
+    /// underflow
+    pub const UF: u32 = 2;
+    /// overflow
+    pub const OF: u32 = 4;
+    /// divide by zero
+    pub const DZ: u32 = 8;
+    /// invalid operation
+    pub const NV: u32 = 16;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rounding {
+    /// to nearest, ties to even
+    Rne,
+    /// towards zero
+    Rtz,
+    /// down, towards -inf
+    Rdn,
+    /// up, towards +inf
+    Rup,
+    /// to nearest, ties away from zero
+    Rmm,
+}
+
+impl Rounding {
+    /// Rounding mode encoded as `rm` in an instruction or frm, or `None` for the reserved
+    /// encodings and 7, which in an instruction selects frm.
+    pub fn from_bits(rm: u32) -> Option<Rounding> {
+        match rm {
+            0 => Some(Rounding::Rne),
+            1 => Some(Rounding::Rtz),
+            2 => Some(Rounding::Rdn),
+            3 => Some(Rounding::Rup),
+            4 => Some(Rounding::Rmm),
+            _ => None,
+        }
+    }
+}
+
+/// Rounding mode an operation uses and the exception flags it raised.
+pub struct Env {
+    pub rm: Rounding,
+    pub flags: u32,
+}
+
+impl Env {
+    pub fn new(rm: Rounding) -> Env {
+        Env { rm, flags: 0 }
+    }
+}
+
+/// A binary interchange format. Values are passed around as their encoding in the low bits of
+/// a u64.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Format {
+    exp: u32,
+    frac: u32,
+}
+
+pub const F16: Format = Format { exp: 5, frac: 10 };
+pub const F32: Format = Format { exp: 8, frac: 23 };
+pub const F64: Format = Format { exp: 11, frac: 52 };
+
+/// What an encoding stands for. Finite values are `sig * 2^exp`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Class {
+    Zero,
+    Finite { exp: i32, sig: u128 },
+    Inf,
+    Nan { quiet: bool },
+}
+
+impl Format {
+    pub fn width(self) -> u32 {
+        1 + self.exp + self.frac
+    }
+    fn mask(self) -> u64 {
+        u64::MAX >> (64 - self.width())
+    }
+    fn sign_bit(self) -> u64 {
+        1 << (self.width() - 1)
+    }
+    fn exp_max(self) -> u64 {
+        (1 << self.exp) - 1
+    }
+    fn bias(self) -> i32 {
+        (1 << (self.exp - 1)) - 1
+    }
+    /// exponent of the smallest normal numbers
+    fn emin(self) -> i32 {
+        1 - self.bias()
+    }
+    pub fn canonical_nan(self) -> u64 {
+        (self.exp_max() << self.frac) | (1 << (self.frac - 1))
+    }
+    fn signed(self, sign: bool, x: u64) -> u64 {
+        match sign {
+            true => x | self.sign_bit(),
+            false => x,
+        }
+    }
+    /// `x` with its sign flipped.
+    pub fn negate(self, x: u64) -> u64 {
+        x ^ self.sign_bit()
+    }
+    fn inf(self, sign: bool) -> u64 {
+        self.signed(sign, self.exp_max() << self.frac)
+    }
+    fn max_finite(self, sign: bool) -> u64 {
+        self.signed(sign, ((self.exp_max() - 1) << self.frac) | ((1 << self.frac) - 1))
+    }
+
+    /// `x` as held in a wider register: NaN-boxed, with all the bits above it set.
+    pub fn nan_box(self, x: u64) -> u64 {
+        (x & self.mask()) | !self.mask()
+    }
+    /// Value of this format held in register value `r`, or the canonical NaN if `r` is not a
+    /// properly NaN-boxed one.
+    pub fn unbox(self, r: u64) -> u64 {
+        match r | self.mask() {
+            u64::MAX => r & self.mask(),
+            _ => self.canonical_nan(),
+        }
+    }
+
+    fn unpack(self, x: u64) -> (bool, Class) {
+        let e = (x >> self.frac) & self.exp_max();
+        let f = x & ((1 << self.frac) - 1);
+        let class = match (e, f) {
+            (0, 0) => Class::Zero,
+            (0, f) => Class::Finite {
+                exp: self.emin() - self.frac as i32,
+                sig: f as u128,
+            },
+            (e, 0) if e == self.exp_max() => Class::Inf,
+            (e, f) if e == self.exp_max() => Class::Nan {
+                quiet: f >> (self.frac - 1) == 1,
+            },
+            (e, f) => Class::Finite {
+                exp: e as i32 - self.bias() - self.frac as i32,
+                sig: (f | 1 << self.frac) as u128,
+            },
+        };
+        (x & self.sign_bit() != 0, class)
+    }
+    pub fn is_nan(self, x: u64) -> bool {
+        matches!(self.unpack(x).1, Class::Nan { .. })
+    }
+    fn is_snan(self, x: u64) -> bool {
+        self.unpack(x).1 == Class::Nan { quiet: false }
+    }
+    /// The canonical NaN, raising invalid if one of `inputs` is a signaling NaN.
+    fn nan(self, inputs: &[u64], env: &mut Env) -> u64 {
+        if inputs.iter().any(|&x| self.is_snan(x)) {
+            env.flags |= flag::NV;
+        }
+        self.canonical_nan()
+    }
+    fn invalid(self, env: &mut Env) -> u64 {
+        env.flags |= flag::NV;
+        self.canonical_nan()
+    }
+
+    /// Rounds `sign * sig * 2^exp` to this format. A `sig` that is not exact must carry at
+    /// least two bits below the rounding point, the lowest of them sticky.
+    fn round(self, sign: bool, exp: i32, sig: u128, env: &mut Env) -> u64 {
+        if sig == 0 {
+            return self.signed(sign, 0);
+        }
+        let frac = self.frac as i32;
+        // exponent of the leading bit
+        let top = exp + 127 - sig.leading_zeros() as i32;
+        let mut lsb = top.max(self.emin()) - frac;
+        let (mut q, inexact) = round_at(sig, lsb - exp, sign, env.rm);
+        if inexact {
+            env.flags |= flag::NX;
+            // tininess is detected after rounding, as if the exponent range were unbounded
+            let (unbounded, _) = round_at(sig, top - frac - exp, sign, env.rm);
+            let tiny = top < self.emin() && !(top + 1 == self.emin() && unbounded >> (frac + 1) == 1);
+            if tiny {
+                env.flags |= flag::UF;
+            }
+        }
+        if q >> (frac + 1) == 1 {
+            // rounded up to the next power of two
+            q >>= 1;
+            lsb += 1;
+        }
+        let e = match q >> frac {
+            0 => 0,
+            _ => (lsb + frac + self.bias()) as u64,
+        };
+        if e >= self.exp_max() {
+            env.flags |= flag::OF | flag::NX;
+            return match (env.rm, sign) {
+                (Rounding::Rne, _) | (Rounding::Rmm, _) | (Rounding::Rdn, true) | (Rounding::Rup, false) => {
+                    self.inf(sign)
+                }
+                _ => self.max_finite(sign),
+            };
+        }
+        self.signed(sign, (e << self.frac) | (q as u64 & ((1 << self.frac) - 1)))
+    }
+
+    /// Exact sum of two finite values, rounded.
+    fn sum(self, a: (bool, i32, u128), b: (bool, i32, u128), env: &mut Env) -> u64 {
+        let (sa, ea, ma) = a;
+        let (sb, eb, mb) = b;
+        match (ma, mb) {
+            (0, 0) => {
+                let sign = if sa == sb { sa } else { env.rm == Rounding::Rdn };
+                return self.signed(sign, 0);
+            }
+            (0, _) => return self.round(sb, eb, mb, env),
+            (_, 0) => return self.round(sa, ea, ma, env),
+            _ => {}
+        }
+        // both with their leading bit at bit 125: the larger loses nothing and the smaller only
+        // what lies far below the rounding point
+        let normalize = |e: i32, m: u128| {
+            let s = m.leading_zeros() as i32 - 2;
+            (e - s, m << s)
+        };
+        let (ea, ma) = normalize(ea, ma);
+        let (eb, mb) = normalize(eb, mb);
+        let ((sa, ea, ma), (sb, eb, mb)) = match ea >= eb {
+            true => ((sa, ea, ma), (sb, eb, mb)),
+            false => ((sb, eb, mb), (sa, ea, ma)),
+        };
+        let mb = shift_right_jam(mb, (ea - eb) as u32);
+        let (sign, m) = match (sa == sb, ma >= mb) {
+            (true, _) => (sa, ma + mb),
+            (false, true) => (sa, ma - mb),
+            (false, false) => (sb, mb - ma),
+        };
+        match m {
+            0 => self.signed(env.rm == Rounding::Rdn, 0),
+            _ => self.round(sign, ea, m, env),
+        }
+    }
+
+    pub fn add(self, a: u64, b: u64, env: &mut Env) -> u64 {
+        if self.is_nan(a) || self.is_nan(b) {
+            return self.nan(&[a, b], env);
+        }
+        let (sa, ca) = self.unpack(a);
+        let (sb, cb) = self.unpack(b);
+        match (ca, cb) {
+            (Class::Inf, Class::Inf) if sa != sb => self.invalid(env),
+            (Class::Inf, _) => self.inf(sa),
+            (_, Class::Inf) => self.inf(sb),
+            _ => self.sum(finite(sa, ca), finite(sb, cb), env),
+        }
+    }
+    pub fn sub(self, a: u64, b: u64, env: &mut Env) -> u64 {
+        match self.is_nan(b) {
+            true => self.add(a, b, env),
+            false => self.add(a, b ^ self.sign_bit(), env),
+        }
+    }
+    pub fn mul(self, a: u64, b: u64, env: &mut Env) -> u64 {
+        if self.is_nan(a) || self.is_nan(b) {
+            return self.nan(&[a, b], env);
+        }
+        let (sa, ca) = self.unpack(a);
+        let (sb, cb) = self.unpack(b);
+        let sign = sa != sb;
+        match (ca, cb) {
+            (Class::Inf, Class::Zero) | (Class::Zero, Class::Inf) => self.invalid(env),
+            (Class::Inf, _) | (_, Class::Inf) => self.inf(sign),
+            (Class::Finite { exp: ea, sig: ma }, Class::Finite { exp: eb, sig: mb }) => {
+                self.round(sign, ea + eb, ma * mb, env)
+            }
+            _ => self.signed(sign, 0),
+        }
+    }
+    /// `a * b + c` with a single rounding. Invalid is raised for infinity times zero even when
+    /// `c` is a quiet NaN.
+    pub fn fma(self, a: u64, b: u64, c: u64, env: &mut Env) -> u64 {
+        let (sa, ca) = self.unpack(a);
+        let (sb, cb) = self.unpack(b);
+        let (sc, cc) = self.unpack(c);
+        let sign = sa != sb;
+        match (ca, cb) {
+            (Class::Inf, Class::Zero) | (Class::Zero, Class::Inf) => return self.invalid(env),
+            _ if self.is_nan(a) || self.is_nan(b) || self.is_nan(c) => return self.nan(&[a, b, c], env),
+            _ => {}
+        }
+        match (ca, cb, cc) {
+            (Class::Inf, ..) | (_, Class::Inf, _) => match cc {
+                Class::Inf if sc != sign => self.invalid(env),
+                _ => self.inf(sign),
+            },
+            (.., Class::Inf) => self.inf(sc),
+            (Class::Finite { exp: ea, sig: ma }, Class::Finite { exp: eb, sig: mb }, _) => {
+                self.sum((sign, ea + eb, ma * mb), finite(sc, cc), env)
+            }
+            _ => self.sum((sign, 0, 0), finite(sc, cc), env),
+        }
+    }
+    pub fn div(self, a: u64, b: u64, env: &mut Env) -> u64 {
+        if self.is_nan(a) || self.is_nan(b) {
+            return self.nan(&[a, b], env);
+        }
+        let (sa, ca) = self.unpack(a);
+        let (sb, cb) = self.unpack(b);
+        let sign = sa != sb;
+        match (ca, cb) {
+            (Class::Inf, Class::Inf) | (Class::Zero, Class::Zero) => self.invalid(env),
+            (Class::Inf, _) => self.inf(sign),
+            (_, Class::Zero) => {
+                env.flags |= flag::DZ;
+                self.inf(sign)
+            }
+            (Class::Finite { exp: ea, sig: ma }, Class::Finite { exp: eb, sig: mb }) => {
+                // a dividend as wide as fits leaves well over the bits needed in the quotient
+                let k = ma.leading_zeros() as i32 - 1;
+                let n = ma << k;
+                let sig = (n / mb) | !n.is_multiple_of(mb) as u128;
+                self.round(sign, ea - k - eb, sig, env)
+            }
+            _ => self.signed(sign, 0),
+        }
+    }
+    pub fn sqrt(self, a: u64, env: &mut Env) -> u64 {
+        match self.unpack(a) {
+            (_, Class::Nan { .. }) => self.nan(&[a], env),
+            (_, Class::Zero) | (false, Class::Inf) => a,
+            (true, _) => self.invalid(env),
+            (false, Class::Finite { exp, sig }) => {
+                // widen the significand, keeping the exponent even
+                let mut s = sig.leading_zeros() as i32 - 2;
+                if (exp - s) & 1 != 0 {
+                    s -= 1;
+                }
+                let n = sig << s;
+                let root = isqrt(n);
+                self.round(false, (exp - s) / 2, root | (root * root != n) as u128, env)
+            }
+        }
+    }
+
+    /// Smaller of `a` and `b`, with -0 below +0. A single NaN operand is ignored.
+    pub fn min(self, a: u64, b: u64, env: &mut Env) -> u64 {
+        self.min_max(a, b, true, env)
+    }
+    pub fn max(self, a: u64, b: u64, env: &mut Env) -> u64 {
+        self.min_max(a, b, false, env)
+    }
+    fn min_max(self, a: u64, b: u64, min: bool, env: &mut Env) -> u64 {
+        // as `key`, with -0 below +0
+        let order = |x: u64| 2 * self.key(x) - (x & self.sign_bit() != 0) as i128;
+        if self.is_snan(a) || self.is_snan(b) {
+            env.flags |= flag::NV;
+        }
+        match (self.is_nan(a), self.is_nan(b)) {
+            (true, true) => self.canonical_nan(),
+            (true, false) => b,
+            (false, true) => a,
+            _ if (order(a) <= order(b)) == min => a,
+            _ => b,
+        }
+    }
+    /// Orders non-NaN values by their encodings, both zeros equal.
+    fn key(self, x: u64) -> i128 {
+        let magnitude = (x & !self.sign_bit()) as i128;
+        match x & self.sign_bit() {
+            0 => magnitude,
+            _ => -magnitude,
+        }
+    }
+    /// Quiet comparison: only a signaling NaN raises invalid.
+    pub fn eq(self, a: u64, b: u64, env: &mut Env) -> bool {
+        if self.is_nan(a) || self.is_nan(b) {
+            self.nan(&[a, b], env);
+            return false;
+        }
+        self.key(a) == self.key(b)
+    }
+    /// Signaling comparison: any NaN raises invalid.
+    pub fn lt(self, a: u64, b: u64, env: &mut Env) -> bool {
+        if self.is_nan(a) || self.is_nan(b) {
+            self.invalid(env);
+            return false;
+        }
+        self.key(a) < self.key(b)
+    }
+    pub fn le(self, a: u64, b: u64, env: &mut Env) -> bool {
+        if self.is_nan(a) || self.is_nan(b) {
+            self.invalid(env);
+            return false;
+        }
+        self.key(a) <= self.key(b)
+    }
+
+    /// The fclass mask of `x`: one bit of -inf, -normal, -subnormal, -0, +0, +subnormal,
+    /// +normal, +inf, signaling and quiet NaN, from bit 0 up.
+    pub fn classify(self, x: u64) -> u64 {
+        let (sign, class) = self.unpack(x);
+        let subnormal = (x >> self.frac) & self.exp_max() == 0;
+        let bit = match (class, sign) {
+            (Class::Nan { quiet }, _) => 8 + quiet as u32,
+            (Class::Inf, true) => 0,
+            (Class::Finite { .. }, true) if !subnormal => 1,
+            (Class::Finite { .. }, true) => 2,
+            (Class::Zero, true) => 3,
+            (Class::Zero, false) => 4,
+            (Class::Finite { .. }, false) if subnormal => 5,
+            (Class::Finite { .. }, false) => 6,
+            (Class::Inf, false) => 7,
+        };
+        1 << bit
+    }
+
+    /// Sign injection: `a` with the sign of `b`, its inverse, or their exclusive or, for
+    /// `funct3` 0, 1 and 2.
+    pub fn sign_inject(self, a: u64, b: u64, funct3: u32) -> u64 {
+        let s = self.sign_bit();
+        match funct3 {
+            0 => (a & !s) | (b & s),
+            1 => (a & !s) | (!b & s),
+            _ => a ^ (b & s),
+        }
+    }
+
+    /// `x` rounded to a `bits` wide integer, signed or not, sign extended to 64 bits. Out of
+    /// range values and NaNs saturate and raise invalid.
+    pub fn to_int(self, x: u64, signed: bool, bits: u32, env: &mut Env) -> u64 {
+        let (min, max): (i128, i128) = match signed {
+            true => (-(1 << (bits - 1)), (1 << (bits - 1)) - 1),
+            false => (0, (1 << bits) - 1),
+        };
+        let (sign, class) = self.unpack(x);
+        let saturated = |env: &mut Env, negative: bool| {
+            env.flags |= flag::NV;
+            if negative {
+                min
+            } else {
+                max
+            }
+        };
+        let value = match class {
+            Class::Nan { .. } => saturated(env, false),
+            Class::Inf => saturated(env, sign),
+            Class::Zero => 0,
+            Class::Finite { exp, .. } if exp > 64 => saturated(env, sign),
+            Class::Finite { exp, sig } => {
+                let (q, inexact) = match exp >= 0 {
+                    true => (sig << exp, false),
+                    false => round_at(sig, -exp, sign, env.rm),
+                };
+                let v = if sign { -(q as i128) } else { q as i128 };
+                if v < min || v > max {
+                    saturated(env, sign)
+                } else {
+                    if inexact {
+                        env.flags |= flag::NX;
+                    }
+                    v
+                }
+            }
+        };
+        (((value as u64) << (64 - bits)) as i64 >> (64 - bits)) as u64
+    }
+    /// The low `bits` of `x` as a signed or unsigned integer, rounded to this format.
+    pub fn from_int(self, x: u64, signed: bool, bits: u32, env: &mut Env) -> u64 {
+        let x = x << (64 - bits);
+        let (sign, magnitude) = match signed && (x as i64) < 0 {
+            true => (true, (x as i64).unsigned_abs() >> (64 - bits)),
+            false => (false, x >> (64 - bits)),
+        };
+        self.round(sign, 0, magnitude as u128, env)
+    }
+    /// `x` of format `from`, rounded to this format.
+    pub fn convert(self, from: Format, x: u64, env: &mut Env) -> u64 {
+        match from.unpack(x) {
+            (_, Class::Nan { .. }) => {
+                from.nan(&[x], env);
+                self.canonical_nan()
+            }
+            (sign, Class::Inf) => self.inf(sign),
+            (sign, Class::Zero) => self.signed(sign, 0),
+            (sign, Class::Finite { exp, sig }) => self.round(sign, exp, sig, env),
+        }
+    }
+
+    /// Estimate of `1 / x` to 7 bits, as vfrec7. Subnormals too small to have a finite
+    /// reciprocal overflow as the rounding mode directs.
+    pub fn rec7(self, x: u64, env: &mut Env) -> u64 {
+        let (sign, class) = self.unpack(x);
+        match class {
+            Class::Nan { .. } => return self.nan(&[x], env),
+            Class::Inf => return self.signed(sign, 0),
+            Class::Zero => {
+                env.flags |= flag::DZ;
+                return self.inf(sign);
+            }
+            Class::Finite { .. } => {}
+        }
+        let (exp, sig) = self.normalize_frac(x);
+        if exp < -1 {
+            env.flags |= flag::OF | flag::NX;
+            return match (env.rm, sign) {
+                (Rounding::Rtz, _) | (Rounding::Rdn, false) | (Rounding::Rup, true) => self.max_finite(sign),
+                _ => self.inf(sign),
+            };
+        }
+        let mut out_sig = (REC7[(sig >> (self.frac - 7)) as usize] as u64) << (self.frac - 7);
+        let mut out_exp = 2 * self.bias() as i64 - 1 - exp;
+        // the result is subnormal
+        if out_exp <= 0 {
+            out_sig = (out_sig >> 1) | 1 << (self.frac - 1);
+            if out_exp < 0 {
+                out_sig >>= 1;
+                out_exp = 0;
+            }
+        }
+        self.signed(sign, (out_exp as u64) << self.frac | out_sig)
+    }
+    /// Estimate of `1 / sqrt(x)` to 7 bits, as vfrsqrt7.
+    pub fn rsqrt7(self, x: u64, env: &mut Env) -> u64 {
+        let (sign, class) = self.unpack(x);
+        match (sign, class) {
+            (_, Class::Nan { .. }) => return self.nan(&[x], env),
+            (_, Class::Zero) => {
+                env.flags |= flag::DZ;
+                return self.inf(sign);
+            }
+            (true, _) => return self.invalid(env),
+            (false, Class::Inf) => return 0,
+            (false, Class::Finite { .. }) => {}
+        }
+        let (exp, sig) = self.normalize_frac(x);
+        let index = ((exp & 1) as u64) << 6 | sig >> (self.frac - 6);
+        let out_exp = (3 * self.bias() as i64 - 1 - exp) / 2;
+        (out_exp as u64) << self.frac | (RSQRT7[index as usize] as u64) << (self.frac - 7)
+    }
+    /// Biased exponent and fraction of finite nonzero `x`, a subnormal normalized with an
+    /// exponent of 0 or below.
+    fn normalize_frac(self, x: u64) -> (i64, u64) {
+        let mut exp = ((x >> self.frac) & self.exp_max()) as i64;
+        let mut sig = x & ((1 << self.frac) - 1);
+        if exp == 0 {
+            while sig >> (self.frac - 1) & 1 == 0 {
+                exp -= 1;
+                sig <<= 1;
+            }
+            sig = (sig << 1) & ((1 << self.frac) - 1);
+        }
+        (exp, sig)
+    }
+}
+
+/// Significands of the vfrec7 estimates, by the top 7 bits of the input's.
+const REC7: [u8; 128] = [
+    127, 125, 123, 121, 119, 117, 116, 114, 112, 110, 109, 107, 105, 104, 102, 100, 99, 97, 96, 94,
+    93, 91, 90, 88, 87, 85, 84, 83, 81, 80, 79, 77, 76, 75, 74, 72, 71, 70, 69, 68, 66, 65, 64, 63,
+    62, 61, 60, 59, 58, 57, 56, 55, 54, 53, 52, 51, 50, 49, 48, 47, 46, 45, 44, 43, 42, 41, 40, 40,
+    39, 38, 37, 36, 35, 35, 34, 33, 32, 31, 31, 30, 29, 28, 28, 27, 26, 25, 25, 24, 23, 23, 22, 21,
+    21, 20, 19, 19, 18, 17, 17, 16, 15, 15, 14, 14, 13, 12, 12, 11, 11, 10, 9, 9, 8, 8, 7, 7, 6, 5,
+    5, 4, 4, 3, 3, 2, 2, 1, 1, 0,
+];
+
+/// Significands of the vfrsqrt7 estimates, by the exponent's low bit and the top 6 bits of the
+/// input's significand.
+const RSQRT7: [u8; 128] = [
+    52, 51, 50, 48, 47, 46, 44, 43, 42, 41, 40, 39, 38, 36, 35, 34, 33, 32, 31, 30, 30, 29, 28, 27,
+    26, 25, 24, 23, 23, 22, 21, 20, 19, 19, 18, 17, 16, 16, 15, 14, 14, 13, 12, 12, 11, 10, 10, 9,
+    9, 8, 7, 7, 6, 6, 5, 4, 4, 3, 3, 2, 2, 1, 1, 0, 127, 125, 123, 121, 119, 118, 116, 114, 113,
+    111, 109, 108, 106, 105, 103, 102, 100, 99, 97, 96, 95, 93, 92, 91, 90, 88, 87, 86, 85, 84, 83,
+    82, 80, 79, 78, 77, 76, 75, 74, 73, 72, 71, 70, 70, 69, 68, 67, 66, 65, 64, 63, 63, 62, 61, 60,
+    59, 59, 58, 57, 56, 56, 55, 54, 53,
+];
+
+/// A finite or zero value as (sign, exp, sig).
+fn finite(sign: bool, class: Class) -> (bool, i32, u128) {
+    match class {
+        Class::Finite { exp, sig } => (sign, exp, sig),
+        _ => (sign, 0, 0),
+    }
+}
+
+/// `sig` shifted right by `n`, with the bits shifted out or-ed into the lowest bit.
+fn shift_right_jam(sig: u128, n: u32) -> u128 {
+    match n {
+        0 => sig,
+        1..=127 => (sig >> n) | (sig & ((1 << n) - 1) != 0) as u128,
+        _ => (sig != 0) as u128,
+    }
+}
+
+/// `sig` shifted right by `n`, rounded in mode `rm`, and whether that was inexact.
+fn round_at(sig: u128, n: i32, sign: bool, rm: Rounding) -> (u128, bool) {
+    if n <= 0 {
+        return (sig << -n, false);
+    }
+    let (q, half, sticky) = match n {
+        1..=127 => (
+            sig >> n,
+            (sig >> (n - 1)) & 1 == 1,
+            sig & ((1 << (n - 1)) - 1) != 0,
+        ),
+        128 => (0, sig >> 127 == 1, sig & (u128::MAX >> 1) != 0),
+        _ => (0, false, sig != 0),
+    };
+    let inexact = half || sticky;
+    let up = match rm {
+        Rounding::Rne => half && (sticky || q & 1 == 1),
+        Rounding::Rtz => false,
+        Rounding::Rdn => sign && inexact,
+        Rounding::Rup => !sign && inexact,
+        Rounding::Rmm => half,
+    };
+    (q + up as u128, inexact)
+}
+
+fn isqrt(n: u128) -> u128 {
+    let mut rest = n;
+    let mut root = 0;
+    let mut bit = 1 << 126;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if rest >= root + bit {
+            rest -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
+}
+
+#[test]
+fn test_arithmetic() {
+    let mut env = Env::new(Rounding::Rne);
+    // 1 + 2^-24 ties to even, 1 + 3 * 2^-24 rounds up
+    assert!(F32.add(0x3f800000, 0x33800000, &mut env) == 0x3f800000 && env.flags == flag::NX);
+    assert!(F32.add(0x3f800000, 0x34400000, &mut env) == 0x3f800002);
+    env = Env::new(Rounding::Rmm);
+    assert!(F32.add(0x3f800000, 0x33800000, &mut env) == 0x3f800001);
+    env = Env::new(Rounding::Rdn);
+    assert!(F64.sub(0x3ff0000000000000, 0x3ff0000000000000, &mut env) == 0x8000000000000000);
+    assert!(env.flags == 0);
+    env = Env::new(Rounding::Rtz);
+    // overflow saturates to the largest finite number towards zero
+    assert!(F32.mul(0x7f000000, 0x40000000, &mut env) == 0x7f7fffff);
+    assert!(env.flags == flag::OF | flag::NX);
+    env = Env::new(Rounding::Rne);
+    assert!(F32.div(0x3f800000, 0x00000000, &mut env) == 0x7f800000 && env.flags == flag::DZ);
+    env = Env::new(Rounding::Rne);
+    assert!(F64.sqrt(0x4000000000000000, &mut env) == 0x3ff6a09e667f3bcd && env.flags == flag::NX);
+    env = Env::new(Rounding::Rne);
+    assert!(F32.sqrt(0xbf800000, &mut env) == 0x7fc00000 && env.flags == flag::NV);
+    // smallest normal halved is exact, so tiny but no underflow; a third of it is inexact
+    env = Env::new(Rounding::Rne);
+    assert!(F32.mul(0x00800000, 0x3f000000, &mut env) == 0x00400000 && env.flags == 0);
+    assert!(F32.div(0x00800000, 0x40400000, &mut env) == 0x002aaaab);
+    assert!(env.flags == flag::UF | flag::NX);
+    // infinity times zero is invalid even with a quiet NaN addend
+    env = Env::new(Rounding::Rne);
+    assert!(F32.fma(0x7f800000, 0, 0x7fc00000, &mut env) == 0x7fc00000 && env.flags == flag::NV);
+    env = Env::new(Rounding::Rne);
+    assert!(F32.fma(0x3f800000, 0x3f800000, 0x7fc00001, &mut env) == 0x7fc00000 && env.flags == 0);
+}
+
+#[test]
+fn test_compare_convert() {
+    let mut env = Env::new(Rounding::Rne);
+    let (qnan, snan) = (0x7fc00000, 0x7f800001);
+    assert!(F32.min(0x80000000, 0, &mut env) == 0x80000000 && F32.max(0x80000000, 0, &mut env) == 0);
+    assert!(F32.min(qnan, 0x3f800000, &mut env) == 0x3f800000 && env.flags == 0);
+    assert!(F32.max(snan, qnan, &mut env) == qnan && env.flags == flag::NV);
+    env = Env::new(Rounding::Rne);
+    assert!(F32.eq(0x80000000, 0, &mut env) && !F32.eq(qnan, qnan, &mut env) && env.flags == 0);
+    assert!(!F32.le(qnan, 0, &mut env) && env.flags == flag::NV);
+    assert!(F32.classify(0xff800000) == 1 && F32.classify(0x80000001) == 1 << 2);
+    assert!(F32.classify(snan) == 1 << 8 && F64.classify(0x7ff8000000000000) == 1 << 9);
+    assert!(F32.sign_inject(0x3f800000, 0x80000000, 1) == 0x3f800000);
+    assert!(F32.sign_inject(0xbf800000, 0x80000000, 2) == 0x3f800000);
+    // -1.5 to the nearest even integer, and unsigned
+    env = Env::new(Rounding::Rne);
+    assert!(F32.to_int(0xbfc00000, true, 32, &mut env) == (-2i64) as u64 && env.flags == flag::NX);
+    env = Env::new(Rounding::Rtz);
+    assert!(F32.to_int(0xbf000000, false, 32, &mut env) == 0 && env.flags == flag::NX);
+    env = Env::new(Rounding::Rne);
+    assert!(F32.to_int(0xbfc00000, false, 32, &mut env) == 0 && env.flags == flag::NV);
+    env = Env::new(Rounding::Rne);
+    assert!(F64.to_int(0x41f0000000000000, false, 32, &mut env) == u64::MAX && env.flags == flag::NV);
+    env = Env::new(Rounding::Rne);
+    assert!(F32.from_int(0xffffffff, false, 32, &mut env) == 0x4f800000 && env.flags == flag::NX);
+    assert!(F64.from_int(0x80000000, true, 32, &mut env) == 0xc1e0000000000000);
+    env = Env::new(Rounding::Rne);
+    assert!(F64.convert(F32, snan, &mut env) == 0x7ff8000000000000 && env.flags == flag::NV);
+    assert!(F32.unbox(0xffffffff3f800000) == 0x3f800000 && F32.unbox(0x3f800000) == qnan);
+    assert!(F32.nan_box(0x3f800000) == 0xffffffff3f800000);
+}
+
+
+#[test]
+fn test_half() {
+    let mut env = Env::new(Rounding::Rne);
+    assert!(F16.canonical_nan() == 0x7e00 && F16.nan_box(0x3c00) == 0xffffffffffff3c00);
+    // 65504 is the largest half; 65520 is the first value rounding to infinity
+    assert!(F16.convert(F32, 0x477fe000, &mut env) == 0x7bff && env.flags == 0);
+    assert!(F16.convert(F32, 0x477ff000, &mut env) == 0x7c00 && env.flags == flag::OF | flag::NX);
+    env = Env::new(Rounding::Rne);
+    assert!(F32.convert(F16, 0x0001, &mut env) == 0x33800000 && env.flags == 0);
+    assert!(F16.add(0x3c00, 0x3c00, &mut env) == 0x4000);
+    assert!(F16.from_int(70000, true, 32, &mut env) == 0x7c00);
+    env = Env::new(Rounding::Rtz);
+    assert!(F16.to_int(0xfbff, true, 32, &mut env) == (-65504i64) as u64 && env.flags == 0);
+}
+
+#[test]
+fn test_estimates() {
+    let mut env = Env::new(Rounding::Rne);
+    // 1/1 and 1/sqrt(1) just below 1, 1/sqrt(4) just below 1/2
+    assert!(F32.rec7(0x3f800000, &mut env) == 0x3f7f0000 && env.flags == 0);
+    assert!(F32.rsqrt7(0x3f800000, &mut env) == 0x3f7f0000);
+    assert!(F32.rsqrt7(0x40800000, &mut env) == 0x3eff0000);
+    // the reciprocal of the largest finite number is subnormal
+    assert!(F32.rec7(0x7f7fffff, &mut env) == 0x00200000 && env.flags == 0);
+    assert!(F64.rec7(0x8000000000000000, &mut env) == 0xfff0000000000000 && env.flags == flag::DZ);
+    env = Env::new(Rounding::Rtz);
+    assert!(F32.rec7(0x00000001, &mut env) == 0x7f7fffff && env.flags == flag::OF | flag::NX);
+    env = Env::new(Rounding::Rne);
+    assert!(F32.rsqrt7(0xbf800000, &mut env) == 0x7fc00000 && env.flags == flag::NV);
+}
@@ -0,0 +1,527 @@
+use crate::{conn::Conn, cpu::Cpu, csr::CSR_NAMES, mmu::Access, register::ABI_NAMES};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+
+/// GDB numbers RISC-V CSRs from 65 (after x0-x31, pc and f0-f31) and the privilege level after them.
+const CSR_REGNUM: usize = 65;
+const PRIV_REGNUM: usize = CSR_REGNUM + 4096;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+enum Action {
+    Reply(String),
+    ReplyAndClose(String),
+    Close,
+}
+
+/// Serves the GDB remote serial protocol for one hart.
+pub struct GdbStub {
+    cpu: Cpu,
+    sw_breaks: Vec<u64>,
+    hw_breaks: Vec<u64>,
+    access_watches: Vec<u64>,
+    catch_traps: bool,
+    last_stop: String,
+    halted: bool,
+    console: Vec<String>,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |a, &b| a.wrapping_add(b))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut s = String::new();
+    for b in bytes {
+        write!(s, "{:02x}", b).unwrap();
+    }
+    s
+}
+
+fn unhex(s: &[u8]) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    s.chunks(2)
+        .map(|c| u8::from_str_radix(std::str::from_utf8(c).ok()?, 16).ok())
+        .collect()
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s, 16).ok()
+}
+
+impl GdbStub {
+    pub fn new(cpu: Cpu) -> Self {
+        Self {
+            cpu,
+            sw_breaks: Vec::new(),
+            hw_breaks: Vec::new(),
+            access_watches: Vec::new(),
+            catch_traps: false,
+            last_stop: format!("S{:02x}", SIGTRAP),
+            halted: false,
+            console: Vec::new(),
+        }
+    }
+
+    pub fn serve(&mut self, conn: &mut Conn) -> io::Result<()> {
+        let mut pending: Vec<u8> = Vec::new();
+        loop {
+            let packet = match read_packet(conn, &mut pending)? {
+                Some(p) => p,
+                None => return Ok(()),
+            };
+            let mut interrupted = || poll_interrupt(conn, &mut pending);
+            let action = self.handle(&packet, &mut interrupted);
+            for line in self.console.drain(..) {
+                send_packet(conn, &format!("O{}", hex(line.as_bytes())))?;
+            }
+            match action {
+                Action::Reply(r) => send_packet(conn, &r)?,
+                Action::ReplyAndClose(r) => {
+                    send_packet(conn, &r)?;
+                    return Ok(());
+                }
+                Action::Close => return Ok(()),
+            }
+        }
+    }
+
+    fn xlen(&self) -> usize {
+        self.cpu.get_xlen_bytes() as usize
+    }
+
+    fn reg_hex(&self, v: u64) -> String {
+        hex(&v.to_le_bytes()[..self.xlen()])
+    }
+
+    fn read_reg(&mut self, n: usize) -> Option<u64> {
+        let len = self.cpu.get_xlen_bytes();
+        match n {
+            0..=31 => self.cpu.register().read(n, len).ok(),
+            32 => Some(self.cpu.get_pc()),
+            PRIV_REGNUM => Some(self.cpu.get_privilege() as u64),
+            n if (CSR_REGNUM..PRIV_REGNUM).contains(&n) => self.cpu.csr().read(n - CSR_REGNUM).ok(),
+            _ => None,
+        }
+    }
+
+    fn write_reg(&mut self, n: usize, v: u64) -> Result<(), String> {
+        let len = self.cpu.get_xlen_bytes();
+        match n {
+            0..=31 => self.cpu.register().write(n, v, len),
+            32 => {
+                self.cpu.set_pc(v);
+                Ok(())
+            }
+            PRIV_REGNUM => self.cpu.set_privilege(v as u8 & 0b11),
+            n if (CSR_REGNUM..PRIV_REGNUM).contains(&n) => self.cpu.csr().write(n - CSR_REGNUM, v),
+            _ => Err(format!("no register {}", n)),
+        }
+    }
+
+    fn target_xml(&self) -> String {
+        let bits = self.xlen() * 8;
+        let mut xml = format!(
+            "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\"><target version=\"1.0\"><architecture>riscv:rv{}</architecture><feature name=\"org.gnu.gdb.riscv.cpu\">",
+            bits
+        );
+        for (i, name) in ABI_NAMES.iter().enumerate() {
+            let ty = match i {
+                1 => "code_ptr",
+                2 | 8 => "data_ptr",
+                _ => "int",
+            };
+            write!(xml, "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" regnum=\"{}\"/>", name, bits, ty, i).unwrap();
+        }
+        write!(xml, "<reg name=\"pc\" bitsize=\"{}\" type=\"code_ptr\" regnum=\"32\"/></feature><feature name=\"org.gnu.gdb.riscv.csr\">", bits).unwrap();
+        for (name, n) in CSR_NAMES.iter() {
+            write!(xml, "<reg name=\"{}\" bitsize=\"{}\" regnum=\"{}\" group=\"system\"/>", name, bits, CSR_REGNUM + n).unwrap();
+        }
+        write!(xml, "</feature><feature name=\"org.gnu.gdb.riscv.virtual\"><reg name=\"priv\" bitsize=\"{}\" regnum=\"{}\"/></feature></target>", bits, PRIV_REGNUM).unwrap();
+        xml
+    }
+
+    fn handle(&mut self, packet: &[u8], interrupted: &mut dyn FnMut() -> bool) -> Action {
+        let text = String::from_utf8_lossy(packet).into_owned();
+        let reply = match packet.first() {
+            Some(b'?') => self.last_stop.clone(),
+            Some(b'g') => {
+                let mut s = String::new();
+                for n in 0..33 {
+                    let v = self.read_reg(n).unwrap_or(0);
+                    s += &self.reg_hex(v);
+                }
+                s
+            }
+            Some(b'G') => {
+                let bytes = match unhex(&packet[1..]) {
+                    Some(b) => b,
+                    None => return Action::Reply(String::from("E01")),
+                };
+                for (n, chunk) in bytes.chunks(self.xlen()).enumerate().take(33) {
+                    let mut v = [0u8; 8];
+                    v[..chunk.len()].copy_from_slice(chunk);
+                    if self.write_reg(n, u64::from_le_bytes(v)).is_err() {
+                        return Action::Reply(String::from("E01"));
+                    }
+                }
+                String::from("OK")
+            }
+            Some(b'p') => match parse_hex(&text[1..]).and_then(|n| self.read_reg(n as usize)) {
+                Some(v) => self.reg_hex(v),
+                None => "xx".repeat(self.xlen()),
+            },
+            Some(b'P') => {
+                let mut it = text[1..].splitn(2, '=');
+                let n = it.next().and_then(parse_hex);
+                let v = it.next().and_then(|v| unhex(v.as_bytes()));
+                match (n, v) {
+                    (Some(n), Some(bytes)) => {
+                        let mut v = [0u8; 8];
+                        let len = bytes.len().min(8);
+                        v[..len].copy_from_slice(&bytes[..len]);
+                        match self.write_reg(n as usize, u64::from_le_bytes(v)) {
+                            Ok(()) => String::from("OK"),
+                            Err(_) => String::from("E01"),
+                        }
+                    }
+                    _ => String::from("E01"),
+                }
+            }
+            Some(b'm') => {
+                let mut it = text[1..].split(',');
+                match (it.next().and_then(parse_hex), it.next().and_then(parse_hex)) {
+                    (Some(addr), Some(len)) if self.cpu.mmu().contains(addr, len) => {
+                        let bytes: Vec<u8> = (0..len).map(|i| self.cpu.mmu().peek(addr + i, 1) as u8).collect();
+                        hex(&bytes)
+                    }
+                    _ => String::from("E01"),
+                }
+            }
+            Some(b'M') | Some(b'X') => {
+                let colon = match packet.iter().position(|&b| b == b':') {
+                    Some(c) => c,
+                    None => return Action::Reply(String::from("E01")),
+                };
+                let head = String::from_utf8_lossy(&packet[1..colon]).into_owned();
+                let mut it = head.split(',');
+                let addr = it.next().and_then(parse_hex);
+                let len = it.next().and_then(parse_hex);
+                let data = if packet[0] == b'M' {
+                    unhex(&packet[colon + 1..])
+                } else {
+                    Some(unescape(&packet[colon + 1..]))
+                };
+                match (addr, len, data) {
+                    (Some(addr), Some(len), Some(data))
+                        if data.len() as u64 == len && self.cpu.mmu().contains(addr, len) =>
+                    {
+                        self.cpu.mmu().poke(addr, &data);
+                        String::from("OK")
+                    }
+                    _ => String::from("E01"),
+                }
+            }
+            Some(b'c') => self.resume(false, interrupted),
+            Some(b's') => self.resume(true, interrupted),
+            Some(b'Z') | Some(b'z') => self.breakpoint(packet[0] == b'Z', &text[1..]),
+            Some(b'H') | Some(b'T') => String::from("OK"),
+            Some(b'k') => return Action::Close,
+            Some(b'D') => return Action::ReplyAndClose(String::from("OK")),
+            _ => {
+                if text.starts_with("qSupported") {
+                    String::from("PacketSize=4000;qXfer:features:read+;swbreak+;hwbreak+;vContSupported+")
+                } else if let Some(args) = text.strip_prefix("qXfer:features:read:target.xml:") {
+                    let mut it = args.split(',');
+                    let off = it.next().and_then(parse_hex).unwrap_or(0) as usize;
+                    let len = it.next().and_then(parse_hex).unwrap_or(0) as usize;
+                    let xml = self.target_xml();
+                    let end = off.saturating_add(len).min(xml.len());
+                    let chunk = xml.get(off.min(end)..end).unwrap_or("");
+                    format!("{}{}", if end >= xml.len() { 'l' } else { 'm' }, chunk)
+                } else if text == "vCont?" {
+                    String::from("vCont;c;C;s;S")
+                } else if let Some(actions) = text.strip_prefix("vCont;") {
+                    let step = actions.starts_with('s') || actions.starts_with('S');
+                    self.resume(step, interrupted)
+                } else if let Some(cmd) = text.strip_prefix("qRcmd,") {
+                    let cmd = unhex(cmd.as_bytes()).unwrap_or_default();
+                    hex(self.monitor(&String::from_utf8_lossy(&cmd)).as_bytes())
+                } else if text == "qAttached" {
+                    String::from("1")
+                } else if text == "qC" {
+                    String::from("QC1")
+                } else if text == "qfThreadInfo" {
+                    String::from("m1")
+                } else if text == "qsThreadInfo" {
+                    String::from("l")
+                } else {
+                    String::new()
+                }
+            }
+        };
+        Action::Reply(reply)
+    }
+
+    fn monitor(&mut self, cmd: &str) -> String {
+        match cmd.trim() {
+            "catch traps" | "catch traps on" => {
+                self.catch_traps = true;
+                String::from("stopping on trap entry\n")
+            }
+            "catch traps off" => {
+                self.catch_traps = false;
+                String::from("not stopping on trap entry\n")
+            }
+            "sstack" | "bt" => {
+                let mut s = String::new();
+                for (i, ra) in self.cpu.sstack().frames().iter().rev().enumerate() {
+                    writeln!(s, "#{:<2} {:#x}", i + 1, ra).unwrap();
+                }
+                s
+            }
+            _ => String::from("commands: catch traps [on|off], sstack\n"),
+        }
+    }
+
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut it = args.split(',');
+        let kind = it.next();
+        let addr = match it.next().and_then(parse_hex) {
+            Some(a) => a,
+            None => return String::from("E01"),
+        };
+        let len = it.next().and_then(parse_hex).unwrap_or(4);
+        if addr.checked_add(len).is_none() {
+            return String::from("E01");
+        }
+        let set = |list: &mut Vec<u64>| {
+            if insert {
+                list.push(addr);
+            } else if let Some(i) = list.iter().position(|&a| a == addr) {
+                list.remove(i);
+            }
+        };
+        match kind {
+            Some("0") => set(&mut self.sw_breaks),
+            Some("1") => set(&mut self.hw_breaks),
+            Some(k @ "2") | Some(k @ "3") | Some(k @ "4") => {
+                let (read, write) = match k {
+                    "2" => (false, true),
+                    "3" => (true, false),
+                    _ => (true, true),
+                };
+                if k == "4" {
+                    set(&mut self.access_watches);
+                }
+                if insert {
+                    self.cpu.mmu().add_watchpoint(addr, len, read, write);
+                } else {
+                    self.cpu.mmu().remove_watchpoint(addr, len, read, write);
+                }
+            }
+            _ => return String::new(),
+        }
+        String::from("OK")
+    }
+
+    fn resume(&mut self, step: bool, interrupted: &mut dyn FnMut() -> bool) -> String {
+        if self.halted {
+            return self.last_stop.clone();
+        }
+        let mut count: u64 = 0;
+        let stop = loop {
+            if count > 0 {
+                let pc = self.cpu.get_pc();
+                if self.sw_breaks.contains(&pc) {
+                    break format!("T{:02x}swbreak:;", SIGTRAP);
+                }
+                if self.hw_breaks.contains(&pc) {
+                    break format!("T{:02x}hwbreak:;", SIGTRAP);
+                }
+                if count.is_multiple_of(1024) && interrupted() {
+                    break format!("T{:02x}", SIGINT);
+                }
+            }
+            count += 1;
+            if self.cpu.step().is_err() {
+                self.halted = true;
+                let report = self.cpu.report(None).expect("step failed without a report");
+                let signal = if report.violation.is_some() { SIGSEGV } else { SIGILL };
+                self.console.push(report.to_text());
+                break format!("T{:02x}", signal);
+            }
+            if let Some((addr, access)) = self.cpu.mmu().take_watch_hit() {
+                let kind = if self.access_watches.contains(&addr) {
+                    "awatch"
+                } else if access == Access::Read {
+                    "rwatch"
+                } else {
+                    "watch"
+                };
+                break format!("T{:02x}{}:{:x};", SIGTRAP, kind, addr);
+            }
+            if let Some(cause) = self.cpu.take_trap() {
+                if self.catch_traps {
+                    self.console.push(format!("trap: cause {}\n", cause));
+                    break format!("T{:02x}trap:{:x};", SIGTRAP, cause);
+                }
+            }
+            if step {
+                break format!("T{:02x}", SIGTRAP);
+            }
+        };
+        self.last_stop = stop.clone();
+        stop
+    }
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut it = data.iter();
+    while let Some(&b) = it.next() {
+        if b == b'}' {
+            if let Some(&n) = it.next() {
+                out.push(n ^ 0x20);
+            }
+        } else {
+            out.push(b);
+        }
+    }
+    out
+}
+
+fn send_packet<W: Write>(out: &mut W, data: &str) -> io::Result<()> {
+    write!(out, "${}#{:02x}", data, checksum(data.as_bytes()))?;
+    out.flush()
+}
+
+fn next_byte<R: Read>(conn: &mut R, pending: &mut Vec<u8>) -> io::Result<Option<u8>> {
+    if !pending.is_empty() {
+        return Ok(Some(pending.remove(0)));
+    }
+    let mut b = [0u8; 1];
+    match conn.read(&mut b)? {
+        0 => Ok(None),
+        _ => Ok(Some(b[0])),
+    }
+}
+
+/// Reads the next packet body, acknowledging it. Returns `None` when the debugger hangs up.
+fn read_packet<S: Read + Write>(conn: &mut S, pending: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+    loop {
+        match next_byte(conn, pending)? {
+            None => return Ok(None),
+            Some(b'$') => {}
+            // acks, and interrupts that arrive while already stopped
+            Some(_) => continue,
+        }
+        let mut body = Vec::new();
+        loop {
+            match next_byte(conn, pending)? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(b) => body.push(b),
+            }
+        }
+        let mut cs = [0u8; 2];
+        for c in cs.iter_mut() {
+            *c = match next_byte(conn, pending)? {
+                Some(b) => b,
+                None => return Ok(None),
+            };
+        }
+        let expected = u8::from_str_radix(&String::from_utf8_lossy(&cs), 16).ok();
+        if expected == Some(checksum(&body)) {
+            conn.write_all(b"+")?;
+            return Ok(Some(body));
+        }
+        conn.write_all(b"-")?;
+    }
+}
+
+/// Checks, without blocking, whether the debugger sent a ^C. Other bytes are kept for later.
+fn poll_interrupt(conn: &mut Conn, pending: &mut Vec<u8>) -> bool {
+    if conn.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut buf = [0u8; 64];
+    let mut hit = false;
+    while let Ok(n) = conn.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        for &b in buf[..n].iter() {
+            if b == 0x03 {
+                hit = true;
+            } else {
+                pending.push(b);
+            }
+        }
+    }
+    let _ = conn.set_nonblocking(false);
+    hit
+}
+
+#[cfg(test)]
+fn test_stub() -> GdbStub {
+    // call f; loop: j loop; f: sw ra, 32(x0); ret
+    let mut mem = Vec::new();
+    for inst in [0x008000efu32, 0x0000006f, 0x02102023, 0x00008067].iter() {
+        mem.extend_from_slice(&inst.to_le_bytes());
+    }
+    mem.resize(64, 0);
+    let cpu = Cpu::with_memory(mem);
+    GdbStub::new(cpu)
+}
+
+#[cfg(test)]
+fn reply(stub: &mut GdbStub, packet: &str) -> String {
+    match stub.handle(packet.as_bytes(), &mut || false) {
+        Action::Reply(r) | Action::ReplyAndClose(r) => r,
+        Action::Close => String::from("<close>"),
+    }
+}
+
+#[test]
+fn test_packets() {
+    let mut stub = test_stub();
+    assert!(reply(&mut stub, "qSupported:multiprocess+").contains("qXfer:features:read+"));
+    assert!(reply(&mut stub, "qXfer:features:read:target.xml:0,20").starts_with("m<?xml"));
+    assert!(reply(&mut stub, "m0,4") == "ef008000");
+    assert!(reply(&mut stub, "p20") == "00000000");
+    assert!(reply(&mut stub, "P1=78563412") == "OK");
+    assert!(reply(&mut stub, "p1") == "78563412");
+    assert!(reply(&mut stub, "g").len() == 33 * 8);
+    assert!(reply(&mut stub, &format!("p{:x}", CSR_REGNUM + 0xf14)) == "00000000");
+    assert!(reply(&mut stub, "X30,2:}]}\x03") == "OK");
+    assert!(reply(&mut stub, "m30,2") == "7d23");
+    assert!(reply(&mut stub, "mffffffffffffffff,2") == "E01");
+    assert!(reply(&mut stub, "Z2,ffffffffffffffff,4") == "E01");
+    assert!(reply(&mut stub, "k") == "<close>");
+}
+
+#[test]
+fn test_resume() {
+    let mut stub = test_stub();
+    assert!(reply(&mut stub, "s") == "T05");
+    assert!(reply(&mut stub, "p20") == "08000000");
+    assert!(reply(&mut stub, "Z2,20,4") == "OK");
+    assert!(reply(&mut stub, "c") == "T05watch:20;");
+    assert!(reply(&mut stub, "z2,20,4") == "OK");
+    assert!(reply(&mut stub, "Z0,4,4") == "OK");
+    assert!(reply(&mut stub, "vCont;c") == "T05swbreak:;");
+    assert!(reply(&mut stub, "p20") == "04000000");
+}
+
+#[test]
+fn test_checksum() {
+    let mut out = Vec::new();
+    send_packet(&mut out, "OK").unwrap();
+    assert!(out == b"$OK#9a");
+}
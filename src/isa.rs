@@ -0,0 +1,538 @@
+use crate::{
+    crypto,
+    decode::{BitOp, CryptoOp, FpOp, Instruction},
+    float::{Format, F16, F32, F64},
+    vector::VectorConfig,
+};
+use std::{collections::BTreeSet, iter::Peekable, str::Chars};
+
+/// Extensions that stand for a group of others in ISA strings, or have them as subsets.
+const GROUPS: &[(&str, &[&str])] = &[
+    ("b", &["zba", "zbb", "zbs"]),
+    ("zk", &["zkn", "zkr", "zkt"]),
+    ("zkn", &["zbkb", "zbkc", "zbkx", "zkne", "zknd", "zknh"]),
+    ("zks", &["zbkb", "zbkc", "zbkx", "zksed", "zksh"]),
+    ("zfh", &["zfhmin"]),
+    ("zhinx", &["zhinxmin"]),
+    ("v", &["zve64d"]),
+    ("zve64d", &["zve64f"]),
+    ("zve64f", &["zve64x", "zve32f"]),
+    ("zve64x", &["zve32x"]),
+    ("zve32f", &["zve32x"]),
+];
+
+/// Extensions, and one each needs as well.
+const DEPENDENCIES: &[(&str, &str)] = &[
+    ("f", "zicsr"),
+    ("d", "f"),
+    ("zfhmin", "f"),
+    ("zfinx", "zicsr"),
+    ("zdinx", "zfinx"),
+    ("zhinxmin", "zfinx"),
+    ("zcf", "f"),
+    ("zcd", "d"),
+    ("zcb", "zca"),
+    ("zcmp", "zca"),
+    ("zcmt", "zca"),
+    ("zcmt", "zicsr"),
+    ("zkr", "zicsr"),
+    ("h", "zicsr"),
+    ("v", "d"),
+    ("zve32x", "zicsr"),
+    ("zve32f", "f"),
+    ("zve64d", "d"),
+];
+
+/// Extensions that take the same encodings or registers, and cannot be there together.
+const CONFLICTS: &[(&str, &str)] = &[("f", "zfinx"), ("zcd", "zcmp"), ("zcd", "zcmt")];
+
+/// The multi-letter extensions this hart can have, besides the vector ones and zvl*b.
+const EXTENSIONS: &[&str] = &[
+    "zicsr", "zifencei", "zca", "zcf", "zcd", "zcb", "zcmp", "zcmt", "zfh", "zfhmin", "zfinx", "zdinx",
+    "zhinx", "zhinxmin", "zba", "zbb", "zbc", "zbs", "zbkb", "zbkc", "zbkx", "zk", "zkn", "zks", "zkne",
+    "zknd", "zknh", "zksed", "zksh", "zkr", "zkt",
+];
+
+/// The vector extensions, a later one chosen over an earlier one.
+const VECTORS: &[&str] = &["zve32x", "zve32f", "zve64x", "zve64f", "zve64d", "v"];
+
+/// `name` without a version suffix such as `2p0`.
+fn unversioned(name: &str) -> &str {
+    let name = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    match name.strip_suffix('p') {
+        Some(major) if major.ends_with(|c: char| c.is_ascii_digit()) => {
+            major.trim_end_matches(|c: char| c.is_ascii_digit())
+        }
+        _ => name,
+    }
+}
+
+/// Skips the version such as `2p1` after a single-letter extension, its minor only after a major.
+fn skip_version(letters: &mut Peekable<Chars>) {
+    let mut major = false;
+    while letters.next_if(char::is_ascii_digit).is_some() {
+        major = true;
+    }
+    if major && letters.next_if_eq(&'p').is_some() {
+        while letters.next_if(char::is_ascii_digit).is_some() {}
+    }
+}
+
+/// Whether `inst` is an RV64 encoding, which the RV32 hart reserves.
+fn rv64_only(inst: &Instruction) -> bool {
+    match *inst {
+        Instruction::Slli { shamt, .. } | Instruction::Srli { shamt, .. } | Instruction::Srai { shamt, .. } => {
+            shamt >= 32
+        }
+        // the .w and .uw forms, shift amounts from 32, and rev8 for XLEN 64
+        Instruction::Bitmanip { word, .. } => word,
+        Instruction::BitmanipImm { word, imm, .. } => word || imm >= 32,
+        Instruction::Crypto { op, .. } => crypto::rv64_only(op),
+        _ => false,
+    }
+}
+
+/// Optional extensions a hart implements. Everything else the decoder knows is always there.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Isa {
+    /// RV32E, with only x0 to x15
+    pub e: bool,
+    pub f: bool,
+    pub d: bool,
+    /// all half-precision instructions
+    pub zfh: bool,
+    /// only half-precision loads, stores, moves and conversions to and from F and D
+    pub zfhmin: bool,
+    /// floating-point instructions use the x registers, as Zfinx, Zdinx and Zhinx: no f
+    /// registers, FP loads, stores or moves, and a double takes an even-odd pair on RV32
+    pub zfinx: bool,
+    /// address generation: shift and add, and the RV64 `.uw` forms
+    pub zba: bool,
+    /// basic bit manipulation
+    pub zbb: bool,
+    /// carry-less multiplication
+    pub zbc: bool,
+    /// single-bit instructions
+    pub zbs: bool,
+    /// the bit manipulation for cryptography: rotates, packs, brev8, zip and unzip
+    pub zbkb: bool,
+    /// clmul and clmulh
+    pub zbkc: bool,
+    /// crossbar permutations
+    pub zbkx: bool,
+    /// AES decryption and encryption
+    pub zknd: bool,
+    pub zkne: bool,
+    /// SHA-256 and SHA-512
+    pub zknh: bool,
+    /// SM4
+    pub zksed: bool,
+    /// SM3
+    pub zksh: bool,
+    /// the seed CSR
+    pub zkr: bool,
+    /// the CSR instructions
+    pub zicsr: bool,
+    pub zifencei: bool,
+    /// V, or a Zve* subset, and its VLEN
+    pub vector: Option<VectorConfig>,
+    /// the C instructions other than floating-point loads and stores, Zca
+    pub c: bool,
+    /// the compressed single- and double-precision loads and stores
+    pub zcf: bool,
+    pub zcd: bool,
+    /// the simple compressed instructions: byte and half loads and stores, extensions, c.not
+    /// and c.mul
+    pub zcb: bool,
+    /// cm.push, cm.pop and the s register moves, in the encodings of c.fsdsp
+    pub zcmp: bool,
+    /// table jumps through jvt, in the encodings of c.fsdsp as well
+    pub zcmt: bool,
+    /// the hypervisor extension: VS and VU modes, two-stage translation, and the hypervisor
+    /// loads, stores and fences
+    pub h: bool,
+}
+
+impl Default for Isa {
+    fn default() -> Self {
+        Isa {
+            e: false,
+            f: true,
+            d: true,
+            zfh: true,
+            zfhmin: true,
+            zfinx: false,
+            zba: true,
+            zbb: true,
+            zbc: true,
+            zbs: true,
+            zbkb: true,
+            zbkc: true,
+            zbkx: true,
+            zknd: true,
+            zkne: true,
+            zknh: true,
+            zksed: true,
+            zksh: true,
+            zkr: true,
+            zicsr: true,
+            zifencei: true,
+            vector: Some(VectorConfig::default()),
+            c: true,
+            zcf: true,
+            zcd: true,
+            zcb: true,
+            zcmp: false,
+            zcmt: false,
+            h: false,
+        }
+    }
+}
+
+impl Isa {
+    /// Parses an ISA string such as `rv32imac_zicsr_zba`: the base, single-letter extensions
+    /// in canonical order, then multi-letter ones after underscores, any of them with a version
+    /// such as `2p1`. Only the extensions named, or in a group named like `b`, are there, and
+    /// those they depend on must be named as well. VLEN is 128 bits unless a `zvl*b` says so.
+    pub fn parse(s: &str) -> Result<Isa, String> {
+        let lower = s.to_ascii_lowercase();
+        let rest = lower
+            .strip_prefix("rv32")
+            .ok_or_else(|| format!("{} is not an RV32 ISA string", s))?;
+        let mut parts = rest.split('_');
+        let mut letters = parts.next().unwrap_or("").chars().peekable();
+        let mut names = BTreeSet::new();
+        let e = match letters.next() {
+            Some('i') => false,
+            Some('e') => true,
+            Some('g') => return Err(String::from("M not implemented, so no G")),
+            _ => return Err(format!("{} has no base, i or e", s)),
+        };
+        skip_version(&mut letters);
+        let mut last = 0;
+        while let Some(c) = letters.next() {
+            skip_version(&mut letters);
+            let order = "mafdqlcbkjtpvh".find(c).ok_or_else(|| format!("unknown extension {}", c))?;
+            if order < last {
+                return Err(format!("{} out of canonical order in {}", c, s));
+            }
+            last = order;
+            match c {
+                'f' | 'd' | 'c' | 'b' | 'v' | 'h' => names.insert(c.to_string()),
+                _ => return Err(format!("{} not implemented", c.to_ascii_uppercase())),
+            };
+        }
+        let mut vlen = None;
+        for name in parts.filter(|n| !n.is_empty()).map(unversioned) {
+            let zvl = name.strip_prefix("zvl").and_then(|n| n.strip_suffix('b'));
+            if let Some(bits) = zvl {
+                let bits: u32 = bits.parse().map_err(|_| format!("bad extension {}", name))?;
+                vlen = vlen.max(Some(bits));
+            } else if EXTENSIONS.contains(&name) || VECTORS.contains(&name) {
+                names.insert(name.to_string());
+            } else {
+                return Err(format!("{} not implemented", name));
+            }
+        }
+        // C is Zca, with Zcf and Zcd as F and D are there
+        if names.contains("c") {
+            names.insert(String::from("zca"));
+            for (fp, zc) in [("f", "zcf"), ("d", "zcd")] {
+                if names.contains(fp) {
+                    names.insert(String::from(zc));
+                }
+            }
+        }
+        // in order, so zk brings in zkn, and v zve64d, before it is expanded
+        for &(group, members) in GROUPS.iter() {
+            if names.contains(group) {
+                names.extend(members.iter().map(|m| m.to_string()));
+            }
+        }
+        for &(ext, needs) in DEPENDENCIES.iter() {
+            if names.contains(ext) && !names.contains(needs) {
+                return Err(format!("{} needs {}", ext, needs));
+            }
+        }
+        for &(a, b) in CONFLICTS.iter() {
+            if names.contains(a) && names.contains(b) {
+                return Err(format!("{} and {} cannot be there together", a, b));
+            }
+        }
+        let has = |name: &str| names.contains(name);
+        let vector = match VECTORS.iter().rev().find(|v| has(v)) {
+            Some(name) => Some(VectorConfig::named(name, vlen.unwrap_or(128))?),
+            None if vlen.is_some() => return Err(String::from("zvl*b without a vector extension")),
+            None => None,
+        };
+        Ok(Isa {
+            e,
+            f: has("f") || has("zfinx"),
+            d: has("d") || has("zdinx"),
+            zfh: has("zfh") || has("zhinx"),
+            zfhmin: has("zfhmin") || has("zhinxmin"),
+            zfinx: has("zfinx"),
+            zba: has("zba"),
+            zbb: has("zbb"),
+            zbc: has("zbc"),
+            zbs: has("zbs"),
+            zbkb: has("zbkb"),
+            zbkc: has("zbkc"),
+            zbkx: has("zbkx"),
+            zknd: has("zknd"),
+            zkne: has("zkne"),
+            zknh: has("zknh"),
+            zksed: has("zksed"),
+            zksh: has("zksh"),
+            zkr: has("zkr"),
+            zicsr: has("zicsr"),
+            zifencei: has("zifencei"),
+            vector,
+            c: has("zca"),
+            zcf: has("zcf"),
+            zcd: has("zcd"),
+            zcb: has("zcb"),
+            zcmp: has("zcmp"),
+            zcmt: has("zcmt"),
+            h: has("h"),
+        })
+    }
+    /// The extension bits of misa, one per letter from bit 0 for A.
+    pub fn misa(&self) -> u64 {
+        let letter = |c: char, on: bool| (on as u64) << (c as u8 - b'A');
+        // E or I
+        letter('E', self.e)
+            | letter('I', !self.e)
+            | letter('C', self.c)
+            | letter('F', self.f && !self.zfinx)
+            | letter('D', self.d && !self.zfinx)
+            | letter('V', self.vector.is_some_and(|v| v.full))
+            // H needs S mode, which is otherwise left out like U
+            | letter('H', self.h)
+            | letter('S', self.h)
+    }
+    /// Checks that compressed `inst` is implemented, as far as `check` leaves it to C and its
+    /// floating-point loads and stores.
+    pub fn check_compressed(&self, inst: &Instruction) -> Result<(), String> {
+        let fmt = match *inst {
+            Instruction::Fload { fmt, .. } | Instruction::Fstore { fmt, .. } => Some(fmt),
+            _ => None,
+        };
+        let (name, on) = match fmt {
+            Some(F32) => ("Zcf", self.zcf),
+            Some(_) => ("Zcd", self.zcd),
+            None => ("C", self.c),
+        };
+        match on && self.c {
+            true => Ok(()),
+            false => Err(format!("{} not implemented", name)),
+        }
+    }
+    /// Whether instructions on `fmt` are implemented, only the Zfhmin ones if `min`.
+    fn has_format(&self, fmt: Format, min: bool) -> bool {
+        match fmt {
+            F16 => self.zfh || (min && self.zfhmin),
+            F64 => self.d,
+            F32 => self.f,
+            _ => false,
+        }
+    }
+    /// Checks that `inst` is implemented by this configuration.
+    pub fn check(&self, inst: &Instruction) -> Result<(), String> {
+        if rv64_only(inst) {
+            return Err(String::from("RV64 only instruction"));
+        }
+        // the extensions with the instruction; it is there if any of them is
+        let extensions: &[(&str, bool)] = match *inst {
+            Instruction::Bitmanip { op, .. } | Instruction::BitmanipImm { op, .. } => match op {
+                BitOp::Shadd(_) | BitOp::SlliUw => &[("Zba", self.zba)],
+                BitOp::Clmul | BitOp::Clmulh => &[("Zbc", self.zbc), ("Zbkc", self.zbkc)],
+                BitOp::Clmulr => &[("Zbc", self.zbc)],
+                BitOp::Bclr | BitOp::Bext | BitOp::Binv | BitOp::Bset => &[("Zbs", self.zbs)],
+                BitOp::Andn | BitOp::Orn | BitOp::Xnor | BitOp::Rol | BitOp::Ror | BitOp::Rev8 => {
+                    &[("Zbb", self.zbb), ("Zbkb", self.zbkb)]
+                }
+                // zext.h is pack with x0
+                BitOp::ZextH => &[("Zbb", self.zbb), ("Zbkb", self.zbkb)],
+                BitOp::Pack | BitOp::Packh | BitOp::Brev8 | BitOp::Zip | BitOp::Unzip => &[("Zbkb", self.zbkb)],
+                BitOp::Xperm4 | BitOp::Xperm8 => &[("Zbkx", self.zbkx)],
+                _ => &[("Zbb", self.zbb)],
+            },
+            Instruction::Crypto { op, .. } => match op {
+                CryptoOp::Aes32Esi | CryptoOp::Aes32Esmi | CryptoOp::Aes64Es | CryptoOp::Aes64Esm => {
+                    &[("Zkne", self.zkne)]
+                }
+                CryptoOp::Aes32Dsi
+                | CryptoOp::Aes32Dsmi
+                | CryptoOp::Aes64Ds
+                | CryptoOp::Aes64Dsm
+                | CryptoOp::Aes64Im => &[("Zknd", self.zknd)],
+                CryptoOp::Aes64Ks1i | CryptoOp::Aes64Ks2 => &[("Zkne", self.zkne), ("Zknd", self.zknd)],
+                CryptoOp::Sm4Ed | CryptoOp::Sm4Ks => &[("Zksed", self.zksed)],
+                CryptoOp::Sm3P0 | CryptoOp::Sm3P1 => &[("Zksh", self.zksh)],
+                _ => &[("Zknh", self.zknh)],
+            },
+            Instruction::Vset { .. }
+            | Instruction::Vload { .. }
+            | Instruction::Vstore { .. }
+            | Instruction::Vector { .. } => &[("V", self.vector.is_some())],
+            Instruction::Csr { .. } => &[("Zicsr", self.zicsr)],
+            Instruction::FenceI => &[("Zifencei", self.zifencei)],
+            Instruction::PushPop { .. } | Instruction::Mv01s { .. } => &[("Zcmp", self.zcmp)],
+            Instruction::TableJump { .. } => &[("Zcmt", self.zcmt)],
+            Instruction::Hlv { .. } | Instruction::Hsv { .. } | Instruction::Hfence { .. } => &[("H", self.h)],
+            _ => &[],
+        };
+        if let Some(&(name, _)) = extensions.first().filter(|_| extensions.iter().all(|e| !e.1)) {
+            return Err(format!("{} not implemented", name));
+        }
+        // format, format converted from, whether Zfhmin has it, whether it moves raw f register bits
+        let (fmt, from, min, transfer) = match *inst {
+            Instruction::Fload { fmt, .. } | Instruction::Fstore { fmt, .. } => (fmt, fmt, true, true),
+            Instruction::Fma { fmt, .. } => (fmt, fmt, false, false),
+            Instruction::Fp { op, fmt, .. } => match op {
+                FpOp::Cvt { from } => (fmt, from, fmt == F16 || from == F16, false),
+                FpOp::MoveToInt | FpOp::MoveFromInt => (fmt, fmt, true, true),
+                _ => (fmt, fmt, false, false),
+            },
+            _ => return Ok(()),
+        };
+        if !self.has_format(fmt, min) || !self.has_format(from, min) {
+            return Err(String::from("Floating-point format not implemented"));
+        }
+        if self.zfinx && transfer {
+            return Err(String::from("No floating-point loads, stores or moves with Zfinx"));
+        }
+        // on RV32 a Zdinx double is a register pair, named by its even register
+        let odd = |n: usize| n % 2 == 1;
+        let pairs = match *inst {
+            Instruction::Fma { rd, rs1, rs2, rs3, .. } => [rd, rs1, rs2, rs3].iter().any(|&n| odd(n)),
+            Instruction::Fp { op, fmt, rd, rs1, rs2, .. } => match op {
+                FpOp::Cvt { .. } => (fmt == F64 && odd(rd)) || (from == F64 && odd(rs1)),
+                FpOp::ToInt { .. } | FpOp::Class => odd(rs1),
+                FpOp::Eq | FpOp::Lt | FpOp::Le => odd(rs1) || odd(rs2),
+                FpOp::FromInt { .. } => odd(rd),
+                FpOp::Sqrt => odd(rd) || odd(rs1),
+                _ => odd(rd) || odd(rs1) || odd(rs2),
+            },
+            _ => false,
+        };
+        let double = matches!(*inst, Instruction::Fp { op: FpOp::Cvt { .. }, .. }) || fmt == F64;
+        if self.zfinx && double && pairs {
+            return Err(String::from("Odd register for a Zdinx register pair"));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_check() {
+    let fp = |op, fmt, rd| Instruction::Fp {
+        op,
+        fmt,
+        rd,
+        rs1: 2,
+        rs2: 4,
+        rm: 7,
+    };
+    let min = Isa {
+        zfh: false,
+        ..Isa::default()
+    };
+    assert!(min.check(&fp(FpOp::Cvt { from: F32 }, F16, 1)).is_ok());
+    assert!(min.check(&fp(FpOp::Add, F16, 1)).is_err() && Isa::default().check(&fp(FpOp::Add, F16, 1)).is_ok());
+    let no_d = Isa {
+        d: false,
+        ..Isa::default()
+    };
+    assert!(no_d.check(&fp(FpOp::Cvt { from: F64 }, F16, 1)).is_err());
+    let zfinx = Isa {
+        zfinx: true,
+        ..Isa::default()
+    };
+    assert!(zfinx.check(&fp(FpOp::Add, F32, 1)).is_ok() && zfinx.check(&fp(FpOp::MoveToInt, F32, 1)).is_err());
+    // doubles are in pairs, but fcvt.s.d may write any register
+    assert!(zfinx.check(&fp(FpOp::Add, F64, 1)).is_err() && zfinx.check(&fp(FpOp::Add, F64, 6)).is_ok());
+    assert!(zfinx.check(&fp(FpOp::Cvt { from: F64 }, F32, 1)).is_ok());
+    assert!(zfinx.check(&fp(FpOp::Cvt { from: F32 }, F64, 1)).is_err());
+    assert!(zfinx.check(&Instruction::Fload { fmt: F32, rd: 1, rs1: 2, offset: 0 }).is_err());
+    let zbb = Isa {
+        zbb: false,
+        ..Isa::default()
+    };
+    let rev8 = Instruction::BitmanipImm {
+        op: BitOp::Rev8,
+        rd: 1,
+        rs1: 2,
+        imm: 24,
+        word: false,
+    };
+    assert!(zbb.check(&rev8).is_ok() && Isa::default().check(&rev8).is_ok());
+    let rev8_rv64 = Instruction::BitmanipImm {
+        op: BitOp::Rev8,
+        rd: 1,
+        rs1: 2,
+        imm: 56,
+        word: false,
+    };
+    assert!(Isa::default().check(&rev8_rv64) == Err(String::from("RV64 only instruction")));
+    let aes64es = Instruction::Crypto {
+        op: CryptoOp::Aes64Es,
+        rd: 1,
+        rs1: 2,
+        rs2: 3,
+        imm: 0,
+    };
+    assert!(Isa::default().check(&aes64es) == Err(String::from("RV64 only instruction")));
+    let e = Isa {
+        e: true,
+        vector: None,
+        ..Isa::default()
+    };
+    // C, D, E and F, and no I
+    assert!(e.misa() == 0x3c && Isa::default().misa() & (1 << 8 | 1 << 21) == 1 << 8 | 1 << 21);
+    let zbb = Isa { zbkb: false, ..zbb };
+    assert!(zbb.check(&rev8) == Err(String::from("Zbb not implemented")));
+    let pop = Instruction::PushPop {
+        op: crate::decode::PushPop::Pop,
+        regs: 1,
+        stack_adj: 16,
+    };
+    let zcmp = Isa {
+        zcmp: true,
+        ..Isa::default()
+    };
+    assert!(Isa::default().check(&pop).is_err() && zcmp.check(&pop).is_ok());
+    assert!(zcmp.check(&Instruction::TableJump { index: 0 }) == Err(String::from("Zcmt not implemented")));
+}
+
+#[test]
+fn test_parse() {
+    let isa = Isa::parse("rv32ic_zicsr_zba").unwrap();
+    assert!(isa.c && isa.zba && !isa.zbb && !isa.f && isa.vector.is_none() && !isa.zcf);
+    assert!(isa.misa() == 0x104);
+    let isa = Isa::parse("RV32I2p1FDCV_Zicsr_Zifencei_Zvl256b_zkn").unwrap();
+    assert!(isa.zcd && isa.zknh && isa.zbkx && !isa.zksh && isa.vector.map(|v| v.vlen) == Some(256));
+    assert!(Isa::parse("rv32ic_zcb").unwrap().zcb && Isa::parse("rv32ic_zbx").is_err());
+    let isa = Isa::parse("rv32e_zicsr_zfinx_zdinx_zve32x").unwrap();
+    assert!(isa.e && isa.zfinx && isa.d && isa.vector.map(|v| v.elen) == Some(32));
+    // not implemented, out of order, unmet dependencies and conflicts
+    assert!(Isa::parse("rv32imac") == Err(String::from("M not implemented")));
+    assert!(Isa::parse("rv32icf_zicsr").is_err() && Isa::parse("rv64i").is_err());
+    assert!(Isa::parse("rv32id_zicsr") == Err(String::from("d needs f")));
+    assert!(Isa::parse("rv32ifdc_zicsr_zcmp") == Err(String::from("zcd and zcmp cannot be there together")));
+    assert!(Isa::parse("rv32if_zicsr_zfinx").is_err() && Isa::parse("rv32iv_zicsr").is_err());
+    assert!(Isa::parse("rv32i_zve64x") == Err(String::from("zve32x needs zicsr")));
+    assert!(Isa::parse("rv32i_zicsr_zve64f") == Err(String::from("zve32f needs f")));
+    assert!(Isa::parse("rv32i_zicsr_zve64x").unwrap().vector.map(|v| v.elen) == Some(64));
+    // p is only the minor version after a major one
+    assert!(Isa::parse("rv32ip") == Err(String::from("P not implemented")));
+    assert!(Isa::parse("rv32i2p1c2").unwrap().c && Isa::parse("rv32i2pc").unwrap().c);
+    let isa = Isa::parse("rv32ic").unwrap();
+    let lw = Instruction::Fload { fmt: F32, rd: 8, rs1: 2, offset: 0 };
+    assert!(isa.check(&Instruction::FenceI).is_err() && isa.check_compressed(&lw).is_err());
+    let isa = Isa::parse("rv32ich_zicsr").unwrap();
+    let hfence = Instruction::Hfence { gvma: true, rs1: 0, rs2: 0 };
+    assert!(isa.h && isa.misa() == 0x40184 && isa.check(&hfence).is_ok());
+    assert!(Isa::default().check(&hfence).is_err() && Isa::parse("rv32ih") == Err(String::from("h needs zicsr")));
+}
@@ -0,0 +1,444 @@
+#[cfg(not(all(target_arch = "x86_64", unix)))]
+compile_error!("the jit feature needs an x86-64 unix host");
+
+use crate::{
+    cpu::rv32,
+    decode::{Cond, Decoded, Instruction},
+    mmu::Mmu,
+};
+use std::{marker::PhantomData, mem::offset_of, ptr};
+
+/// Runs of a block before it is compiled.
+pub const HOT: u32 = 16;
+
+// helper results
+const CONTINUE: u64 = 0;
+/// the access is not to RAM; the interpreter executes the instruction instead
+const FALLBACK: u64 = 1;
+/// the store wrote translated code, so the block must not go on
+const LEAVE: u64 = 2;
+
+/// What compiled code runs on: the registers, mirroring `Register`, the pc it stopped at, and
+/// the memory loads and stores go to.
+#[repr(C)]
+pub struct Context<'a> {
+    pub regs: [u64; 32],
+    pub pc: u64,
+    /// data of the last load
+    value: u64,
+    mmu: *mut Mmu,
+    _mmu: PhantomData<&'a mut Mmu>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(regs: [u64; 32], pc: u64, mmu: &'a mut Mmu) -> Self {
+        Context {
+            regs,
+            pc,
+            value: 0,
+            mmu,
+            _mmu: PhantomData,
+        }
+    }
+}
+
+extern "sysv64" fn load(ctx: &mut Context, addr: u64, width: u64, signed: u64) -> u64 {
+    let mmu = unsafe { &mut *ctx.mmu };
+    if !mmu.contains(addr, width) {
+        return FALLBACK;
+    }
+    let data = mmu.read_nbytes(addr, width);
+    // lw sign extends to the 32 bits there are
+    ctx.value = match (signed, width) {
+        (0, _) | (_, 4) => data,
+        _ => rv32::sign_extend(data as u32, 8 * width as u32 - 1) as u64,
+    };
+    CONTINUE
+}
+
+extern "sysv64" fn store(ctx: &mut Context, addr: u64, value: u64, width: u64) -> u64 {
+    let mmu = unsafe { &mut *ctx.mmu };
+    if !mmu.contains(addr, width) {
+        return FALLBACK;
+    }
+    match width {
+        1 => mmu.write_byte(addr, value as u8),
+        2 => mmu.write_2byte(addr, value as u16),
+        _ => mmu.write_4byte(addr, value as u32),
+    }
+    match mmu.code_written() {
+        true => LEAVE,
+        false => CONTINUE,
+    }
+}
+
+/// Native code for a block, in its own read-only executable mapping.
+pub struct Code {
+    ptr: *mut u8,
+    size: usize,
+    /// instructions compiled, from the start of the block
+    pub insts: usize,
+}
+
+impl Code {
+    fn new(bytes: &[u8], insts: usize) -> Option<Self> {
+        let size = (bytes.len() + 4095) & !4095;
+        unsafe {
+            let ptr = libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if ptr == libc::MAP_FAILED {
+                return None;
+            }
+            let code = Code {
+                ptr: ptr as *mut u8,
+                size,
+                insts,
+            };
+            ptr::copy_nonoverlapping(bytes.as_ptr(), code.ptr, bytes.len());
+            match libc::mprotect(ptr, size, libc::PROT_READ | libc::PROT_EXEC) {
+                0 => Some(code),
+                _ => None,
+            }
+        }
+    }
+    /// Runs the block from its start, and returns how many of its instructions executed.
+    /// `ctx.pc` is then where the interpreter goes on.
+    pub fn run(&self, ctx: &mut Context) -> usize {
+        let f: extern "sysv64" fn(*mut Context) -> u64 = unsafe { std::mem::transmute(self.ptr) };
+        f(ctx) as usize
+    }
+}
+
+impl Drop for Code {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.size);
+        }
+    }
+}
+
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+
+/// Just the x86-64 encodings compiled blocks use. `rbx` holds the context throughout.
+#[derive(Default)]
+struct Asm {
+    buf: Vec<u8>,
+}
+
+impl Asm {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+    fn disp(&mut self, offset: usize) {
+        self.emit(&(offset as u32).to_le_bytes());
+    }
+    /// `dst` = x`n` as read with xlen 4, zero extended
+    fn read_reg(&mut self, dst: u8, n: usize) {
+        match n {
+            // xor dst, dst
+            0 => self.emit(&[0x31, 0xc0 | dst << 3 | dst]),
+            // mov dst, [rbx + regs[n]]
+            _ => {
+                self.emit(&[0x8b, 0x83 | dst << 3]);
+                self.disp(offset_of!(Context, regs) + 8 * n);
+            }
+        }
+    }
+    /// x`n` = rax, as written with xlen 4
+    fn write_reg(&mut self, n: usize) {
+        self.emit(&[0x48, 0x89, 0x83]);
+        self.disp(offset_of!(Context, regs) + 8 * n);
+    }
+    /// mov dst, imm64
+    fn mov_imm(&mut self, dst: u8, imm: u64) {
+        self.emit(&[0x48, 0xb8 | dst]);
+        self.emit(&imm.to_le_bytes());
+    }
+    /// rax = helper(ctx, rsi, rdx, rcx); the arguments after ctx are already in place
+    fn call(&mut self, helper: u64) {
+        // mov rdi, rbx
+        self.emit(&[0x48, 0x89, 0xdf]);
+        self.mov_imm(RAX, helper);
+        // call rax
+        self.emit(&[0xff, 0xd0]);
+    }
+    /// Returns `insts` executed, with the pc at `pc`.
+    fn exit(&mut self, pc: u64, insts: usize) {
+        self.mov_imm(RAX, pc);
+        self.emit(&[0x48, 0x89, 0x83]);
+        self.disp(offset_of!(Context, pc));
+        // mov eax, insts; pop rbx; ret
+        self.emit(&[0xb8]);
+        self.emit(&(insts as u32).to_le_bytes());
+        self.emit(&[0x5b, 0xc3]);
+    }
+    /// Jump on condition code `cc` to where `bind` is called with what this returns.
+    fn jump(&mut self, cc: u8) -> usize {
+        self.emit(&[0x0f, 0x80 | cc, 0, 0, 0, 0]);
+        self.buf.len()
+    }
+    fn bind(&mut self, jump: usize) {
+        let rel = (self.buf.len() - jump) as u32;
+        self.buf[jump - 4..jump].copy_from_slice(&rel.to_le_bytes());
+    }
+    /// Leaves after a helper: where the access fell back to the interpreter before the
+    /// instruction at `pc`, `n` instructions in, or after it if it wrote code.
+    fn check(&mut self, pc: u64, next: u64, n: usize) {
+        // test eax, eax; je
+        self.emit(&[0x85, 0xc0]);
+        let ok = self.jump(CC_E);
+        // cmp eax, FALLBACK; jne
+        self.emit(&[0x83, 0xf8, FALLBACK as u8]);
+        let leave = self.jump(CC_NE);
+        self.exit(pc, n);
+        self.bind(leave);
+        self.exit(next, n + 1);
+        self.bind(ok);
+    }
+}
+
+const CC_B: u8 = 0x2;
+const CC_AE: u8 = 0x3;
+const CC_E: u8 = 0x4;
+const CC_NE: u8 = 0x5;
+const CC_L: u8 = 0xc;
+const CC_GE: u8 = 0xd;
+
+/// Compiles the block of `insts` at `pc` up to its first instruction that must be
+/// interpreted, or returns `None` if that is the first.
+pub fn compile(pc: u64, insts: &[Decoded]) -> Option<Code> {
+    let mut a = Asm::default();
+    // push rbx; mov rbx, rdi
+    a.emit(&[0x53, 0x48, 0x89, 0xfb]);
+    let mut pc = pc;
+    for (n, d) in insts.iter().enumerate() {
+        let next = pc + d.len;
+        // as the interpreter does, an instruction that leaves the pc alone goes on to the next
+        let target = |t: u64| if t == pc { next } else { t };
+        match d.inst {
+            Instruction::Lui { rd, imm } => {
+                a.mov_imm(RAX, imm);
+                a.write_reg(rd);
+            }
+            Instruction::Auipc { rd, imm } => {
+                a.mov_imm(RAX, pc.wrapping_add(imm) as u32 as u64);
+                a.write_reg(rd);
+            }
+            Instruction::Addi { rd, rs1, imm } => {
+                a.read_reg(RAX, rs1);
+                a.mov_imm(RCX, imm);
+                // add rax, rcx
+                a.emit(&[0x48, 0x01, 0xc8]);
+                a.write_reg(rd);
+            }
+            Instruction::Slti { rd, rs1, imm } | Instruction::Sltiu { rd, rs1, imm } => {
+                a.read_reg(RAX, rs1);
+                a.mov_imm(RCX, imm);
+                // setl for slti, setb for sltiu
+                let set = match d.inst {
+                    Instruction::Slti { .. } => 0x9c,
+                    _ => 0x92,
+                };
+                // cmp eax, ecx; set al; movzx eax, al
+                a.emit(&[0x39, 0xc8, 0x0f, set, 0xc0, 0x0f, 0xb6, 0xc0]);
+                a.write_reg(rd);
+            }
+            Instruction::Xori { rd, rs1, imm }
+            | Instruction::Ori { rd, rs1, imm }
+            | Instruction::Andi { rd, rs1, imm } => {
+                a.read_reg(RAX, rs1);
+                // mov ecx, imm
+                a.emit(&[0xb9]);
+                a.emit(&imm.to_le_bytes());
+                let op = match d.inst {
+                    Instruction::Xori { .. } => 0x31,
+                    Instruction::Ori { .. } => 0x09,
+                    _ => 0x21,
+                };
+                a.emit(&[op, 0xc8]);
+                a.write_reg(rd);
+            }
+            // larger shift amounts are RV64 ones, which are illegal and never translated
+            Instruction::Slli { rd, rs1, shamt } if shamt < 32 => {
+                a.read_reg(RAX, rs1);
+                a.emit(&[0xc1, 0xe0, shamt as u8]);
+                a.write_reg(rd);
+            }
+            Instruction::Srli { rd, rs1, shamt } if shamt < 32 => {
+                a.read_reg(RAX, rs1);
+                a.emit(&[0xc1, 0xe8, shamt as u8]);
+                a.write_reg(rd);
+            }
+            Instruction::Srai { rd, rs1, shamt } if shamt < 32 => {
+                a.read_reg(RAX, rs1);
+                a.emit(&[0xc1, 0xf8, shamt as u8]);
+                a.write_reg(rd);
+            }
+            Instruction::AddSub {
+                rd,
+                rs1,
+                rs2,
+                funct7,
+            } => {
+                a.read_reg(RAX, rs1);
+                a.read_reg(RCX, rs2);
+                // the same test as the interpreter's
+                let op = match rv32::get_bits(funct7 as u32, 10, 10) {
+                    1 => 0x29,
+                    _ => 0x01,
+                };
+                a.emit(&[op, 0xc8]);
+                a.write_reg(rd);
+            }
+            Instruction::Lb { rd, rs1, offset }
+            | Instruction::Lh { rd, rs1, offset }
+            | Instruction::Lw { rd, rs1, offset }
+            | Instruction::Lbu { rd, rs1, offset }
+            | Instruction::Lhu { rd, rs1, offset } => {
+                let (width, signed) = match d.inst {
+                    Instruction::Lb { .. } => (1, 1),
+                    Instruction::Lh { .. } => (2, 1),
+                    Instruction::Lw { .. } => (4, 1),
+                    Instruction::Lbu { .. } => (1, 0),
+                    _ => (2, 0),
+                };
+                a.read_reg(RAX, rs1);
+                // add eax, offset; mov rsi, rax; mov edx, width; mov ecx, signed
+                a.emit(&[0x05]);
+                a.emit(&offset.to_le_bytes());
+                a.emit(&[0x48, 0x89, 0xc6, 0xba]);
+                a.emit(&(width as u32).to_le_bytes());
+                a.emit(&[0xb9]);
+                a.emit(&(signed as u32).to_le_bytes());
+                a.call(load as *const () as u64);
+                a.check(pc, next, n);
+                // mov rax, [rbx + value]
+                a.emit(&[0x48, 0x8b, 0x83]);
+                a.disp(offset_of!(Context, value));
+                a.write_reg(rd);
+            }
+            Instruction::Sb { rs1, rs2, offset }
+            | Instruction::Sh { rs1, rs2, offset }
+            | Instruction::Sw { rs1, rs2, offset } => {
+                let width: u32 = match d.inst {
+                    Instruction::Sb { .. } => 1,
+                    Instruction::Sh { .. } => 2,
+                    _ => 4,
+                };
+                a.read_reg(RAX, rs1);
+                a.emit(&[0x05]);
+                a.emit(&(offset as u32).to_le_bytes());
+                a.emit(&[0x48, 0x89, 0xc6]);
+                a.read_reg(RDX, rs2);
+                a.emit(&[0xb9]);
+                a.emit(&width.to_le_bytes());
+                a.call(store as *const () as u64);
+                a.check(pc, next, n);
+            }
+            Instruction::Branch {
+                cond,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                a.read_reg(RAX, rs1);
+                a.read_reg(RCX, rs2);
+                // cmp eax, ecx
+                a.emit(&[0x39, 0xc8]);
+                let cc = match cond {
+                    Cond::Eq => CC_E,
+                    Cond::Ne => CC_NE,
+                    Cond::Lt => CC_L,
+                    Cond::Ge => CC_GE,
+                    Cond::Ltu => CC_B,
+                    Cond::Geu => CC_AE,
+                };
+                let taken = a.jump(cc);
+                a.exit(next, n + 1);
+                a.bind(taken);
+                let t = match cond {
+                    Cond::Ne => (pc + offset) & 0xffffffff,
+                    _ => pc + offset,
+                };
+                a.exit(target(t), n + 1);
+                return Code::new(&a.buf, n + 1);
+            }
+            // calls push the shadow stack, so are interpreted
+            Instruction::Jal { rd, offset } if rd != 1 => {
+                a.mov_imm(RAX, pc + 4);
+                a.write_reg(rd);
+                a.exit(target(pc + offset), n + 1);
+                return Code::new(&a.buf, n + 1);
+            }
+            _ => {
+                if n == 0 {
+                    return None;
+                }
+                a.exit(pc, n);
+                return Code::new(&a.buf, n);
+            }
+        }
+        pc = next;
+    }
+    a.exit(pc, insts.len());
+    Code::new(&a.buf, insts.len())
+}
+
+#[test]
+fn test_jit() {
+    use crate::cpu::Cpu;
+    let program: [u32; 21] = [
+        0x06400513, // li a0, 100
+        0x123455b7, // lui a1, 0x12345
+        0x00001e37, // lui t3, 0x1
+        0x7ff58593, // loop: addi a1, a1, 2047
+        0xfff5c613, // not a2, a1
+        0x00359693, // slli a3, a1, 3
+        0x40565713, // srai a4, a2, 5
+        0x00765793, // srli a5, a2, 7
+        0x00d80833, // add a6, a6, a3
+        0x10fe2023, // sw a5, 256(t3)
+        0x100e2883, // lw a7, 256(t3)
+        0x101e4283, // lbu t0, 257(t3)
+        0x101e0903, // lb s2, 257(t3)
+        0x100e1983, // lh s3, 256(t3)
+        0x100e5a03, // lhu s4, 256(t3)
+        0x00162e93, // slti t4, a2, 1
+        0x00059f13, // slli t5, a1, 0
+        0x8008b313, // sltiu t1, a7, -2048
+        0x340293f3, // csrrw t2, mscratch, t0; interpreted
+        0xfff50513, // addi a0, a0, -1
+        0xfa051ee3, // bnez a0, loop
+    ];
+    let run = |jit: bool| {
+        let mut mem = vec![0; 0x1200];
+        for (i, inst) in program.iter().enumerate() {
+            mem[4 * i..4 * i + 4].copy_from_slice(&inst.to_le_bytes());
+        }
+        let mut cpu = Cpu::with_memory(mem);
+        cpu.set_jit(jit);
+        cpu.execute().unwrap();
+        assert!(cpu.report(None).unwrap().pc == 0x54);
+        let regs: Vec<u64> = (0..32).map(|n| cpu.register().read(n, 4).unwrap()).collect();
+        let csr = cpu.csr().read(0x340).unwrap();
+        (regs, cpu.mmu().peek(0x1100, 4), csr, cpu.block_stats().clone())
+    };
+    let (regs, mem, csr, stats) = run(true);
+    let (iregs, imem, icsr, istats) = run(false);
+    assert!(regs == iregs && mem == imem && csr == icsr);
+    assert!(regs[10] == 0 && regs[17] == mem && mem != 0);
+    // slti compares signed, and a2 is negative
+    assert!(regs[29] == 1 && regs[30] == regs[11]);
+    // every load width writes rd
+    assert!(regs[20] == mem & 0xffff && regs[19] as u16 == regs[20] as u16 && regs[18] as u8 == regs[5] as u8);
+    // the loop body up to csrrw, and the loop end
+    assert!(stats.compiled == 2 && stats.native > 1000 && istats.native == 0);
+}
@@ -0,0 +1,31 @@
+pub mod backtrace;
+pub mod bitmanip;
+pub mod bitutils;
+pub mod block;
+pub mod capi;
+pub mod commitlog;
+pub mod compare;
+pub mod conn;
+pub mod cpu;
+pub mod crypto;
+pub mod csr;
+pub mod custom;
+pub mod debugger;
+pub mod decode;
+pub mod disasm;
+pub mod elf;
+pub mod float;
+pub mod gdbstub;
+pub mod isa;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod mmu;
+pub mod paging;
+pub mod plugin;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod register;
+pub mod rvfi;
+pub mod shadowstack;
+pub mod trace;
+pub mod vector;
@@ -1,40 +1,210 @@
-use clap::{App, Arg};
-use std::fs::File;
-use std::io::Read;
-use std::{env, io};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 
-use crate::cpu::Cpu;
-use crate::csr::Csr;
-use crate::mmu::Mmu;
-use crate::register::Register;
-use crate::shadowstack::ShadowStack;
-
-mod cpu;
-mod csr;
-mod mmu;
-mod register;
-mod shadowstack;
-mod bitutils;
+use rs_riscv_sc::commitlog::CommitLog;
+use rs_riscv_sc::compare::Comparator;
+use rs_riscv_sc::cpu::Cpu;
+use rs_riscv_sc::crypto::Entropy;
+use rs_riscv_sc::csr::Csr;
+use rs_riscv_sc::debugger::Debugger;
+use rs_riscv_sc::elf::Elf;
+use rs_riscv_sc::gdbstub::GdbStub;
+use rs_riscv_sc::isa::Isa;
+use rs_riscv_sc::mmu::Mmu;
+use rs_riscv_sc::register::Register;
+use rs_riscv_sc::shadowstack::ShadowStacks;
+use rs_riscv_sc::trace::Tracer;
+use rs_riscv_sc::vector::VectorConfig;
+use rs_riscv_sc::{conn, disasm, elf, rvfi, trace};
 
 fn main() -> io::Result<()> {
     let matches = App::new("rs-riscv-sc, a risc-v emulator written in rust.")
         .version("0.0")
-        .arg(Arg::with_name("INPUT_FILE").help("Path to raw riscv binary starting 0, not elf."))
+        .arg(Arg::with_name("INPUT_FILE").help("Path to riscv ELF, or raw riscv binary starting 0."))
         .arg(Arg::with_name("test-mode").short("t").long("test-mode").help("Run riscv-tests"))
+        .arg(Arg::with_name("sstack-resync").long("sstack-resync").help("Unwind the shadow stack on returns to deeper frames (longjmp, exception unwinding) instead of stopping"))
+        .arg(Arg::with_name("report-json").long("report-json").takes_value(true).value_name("FILE").help("Write the stop report and symbolized shadow stack as JSON"))
+        .arg(Arg::with_name("isa").long("isa").takes_value(true).value_name("ISA").conflicts_with_all(&["zfinx", "rv32e", "vector", "vlen"]).help("Implement only the extensions of an ISA string such as rv32ic_zicsr_zba, VLEN given by zvl*b (default: everything)"))
+        .arg(Arg::with_name("zfinx").long("zfinx").help("Run F, D and half-precision instructions on the x registers (Zfinx, Zdinx, Zhinx)"))
+        .arg(Arg::with_name("rv32e").long("rv32e").help("Implement RV32E, with only registers x0 to x15"))
+        .arg(Arg::with_name("vector").long("vector").takes_value(true).value_name("v|zve32x|zve32f|zve64x|zve64f|zve64d|none").help("Vector extension implemented (default v)"))
+        .arg(Arg::with_name("vlen").long("vlen").takes_value(true).value_name("BITS").help("Bits per vector register (default 128)"))
+        .arg(Arg::with_name("entropy").long("entropy").takes_value(true).value_name("host|SEED").help("Entropy behind the Zkr seed CSR: the host's, or a fixed sequence from SEED (default 0)"))
+        .arg(Arg::with_name("debug").short("d").long("debug").help("Start the interactive debugger"))
+        .arg(Arg::with_name("gdb").long("gdb").takes_value(true).value_name("PORT|SOCKET").help("Serve the GDB remote protocol on a TCP port or Unix socket"))
+        .arg(Arg::with_name("trace").long("trace").takes_value(true).value_name("CATEGORIES").help("Trace execution to stderr: comma separated fetch,decode,regs,mem,csr,sstack,trap or all"))
+        .arg(Arg::with_name("trace-level").long("trace-level").takes_value(true).value_name("N").help("Trace verbosity: 1 (default) reports events, 2 adds operand values and register dumps"))
+        .arg(Arg::with_name("trace-pc").long("trace-pc").takes_value(true).value_name("START:END").help("Only trace instructions whose pc is in [START, END)"))
+        .arg(Arg::with_name("trace-skip").long("trace-skip").takes_value(true).value_name("N").help("Start tracing after N instructions"))
+        .arg(Arg::with_name("trace-count").long("trace-count").takes_value(true).value_name("N").help("Trace at most N instructions"))
+        .arg(Arg::with_name("trace-file").long("trace-file").takes_value(true).value_name("FILE").help("Write the trace to FILE instead of stderr"))
+        .arg(Arg::with_name("log-commits").long("log-commits").help("Log every retired instruction's register and memory writes in Spike's format, to stderr"))
+        .arg(Arg::with_name("log").long("log").takes_value(true).value_name("FILE").requires("log-commits").help("Write the commit log to FILE instead of stderr"))
+        .arg(Arg::with_name("compare").long("compare").takes_value(true).value_name("TRACE").help("Check every retired instruction against a reference --log-commits trace and stop at the first divergence"))
+        .arg(Arg::with_name("compare-context").long("compare-context").takes_value(true).value_name("N").requires("compare").help("Instructions shown before a divergence (default 8)"))
+        .arg(Arg::with_name("block-stats").long("block-stats").help("Print basic block cache statistics to stderr when the program stops"))
+        .arg(Arg::with_name("rvfi").long("rvfi").takes_value(true).value_name("PORT|SOCKET").help("Send an RVFI-DII execution packet for every retired instruction to a TCP port or Unix socket"))
+        .arg(Arg::with_name("rvfi-dii").long("rvfi-dii").takes_value(true).value_name("PORT|SOCKET").help("Act as an RVFI-DII golden model, executing instructions injected over a TCP port or Unix socket"))
+        .subcommand(
+            SubCommand::with_name("disasm")
+                .about("Disassemble an ELF or raw riscv binary")
+                .arg(Arg::with_name("FILE").required(true).help("Path to riscv ELF, or raw riscv binary starting 0."))
+                .arg(Arg::with_name("section").short("j").long("section").takes_value(true).value_name("NAME").help("Only disassemble this section (default: all executable sections)")),
+        )
         .get_matches();
+    if let Some(m) = matches.subcommand_matches("disasm") {
+        let buf = fs::read(m.value_of("FILE").unwrap())?;
+        let out = disasm::dump(&buf, m.value_of("section"))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        print!("{}", out);
+        return Ok(());
+    }
     let f = matches.value_of("INPUT_FILE").unwrap();
     let test_mode = matches.is_present("test-mode");
+    let sstack_resync = matches.is_present("sstack-resync");
+    let vlen = match matches.value_of("vlen") {
+        Some(n) => n
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "--vlen expects a number"))?,
+        None => 128,
+    };
+    let vector = match matches.value_of("vector").unwrap_or("v") {
+        "none" => None,
+        name => Some(VectorConfig::named(name, vlen).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?),
+    };
+    let isa = match matches.value_of("isa") {
+        Some(s) => Isa::parse(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        None => Isa {
+            e: matches.is_present("rv32e"),
+            zfinx: matches.is_present("zfinx"),
+            vector,
+            ..Isa::default()
+        },
+    };
+    let entropy = match matches.value_of("entropy") {
+        Some(s) => Entropy::parse(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        None => Entropy::default(),
+    };
     let mut f = File::open(f)?;
     let mut buf = Vec::new();
     f.read_to_end(&mut buf)?;
-    let mut memory: Vec<u8> = vec![0; 8192];
-    buf.append(&mut memory);
-    let mut mmu = Mmu::new(buf, test_mode);
-    let mut csr = Csr::new([0; 4096]);
-    let mut reg = Register::new([0; 32]);
-    let mut sstack = ShadowStack::new(0,[0;255]);
+    let elf = if elf::is_elf(&buf) {
+        Some(Elf::parse(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+    } else {
+        None
+    };
+    for warning in elf.iter().flat_map(|e| e.warnings.iter()) {
+        eprintln!("warning: {}", warning);
+    }
+    let new_cpu = || {
+        let (mmu, pc) = load(&buf, elf.as_ref(), test_mode)?;
+        let csr = Csr::new([0; 4096]);
+        let reg = Register::new([0; 32]);
+        let sstack = ShadowStacks::new(0, 0b11, sstack_resync);
+        let mut cpu = Cpu::new(pc, csr, reg, 0b11, mmu, sstack);
+        cpu.set_isa(isa);
+        cpu.set_entropy(entropy);
+        Ok(cpu)
+    };
+    if let Some(addr) = matches.value_of("rvfi-dii") {
+        let mut conn = conn::listen(addr, "RVFI-DII")?;
+        return rvfi::serve_dii(&mut conn, new_cpu);
+    }
     //test
-    let mut cpu = Cpu::new(0, csr, reg, 0b11, mmu,sstack);
-    cpu.execute()?;
+    let mut cpu = new_cpu()?;
+    if matches.is_present("log-commits") {
+        let out: Box<dyn Write> = match matches.value_of("log") {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stderr()),
+        };
+        cpu.set_commit_log(CommitLog::new(out, 0));
+    }
+    if let Some(path) = matches.value_of("compare") {
+        let context = match matches.value_of("compare-context") {
+            Some(n) => n.parse().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "--compare-context expects a number")
+            })?,
+            None => 8,
+        };
+        let reference = io::BufReader::new(File::open(path)?);
+        cpu.set_compare(Comparator::new(Box::new(reference), context));
+    }
+    if let Some(categories) = matches.value_of("trace") {
+        cpu.set_tracer(tracer(&matches, categories)?);
+    }
+    if matches.is_present("debug") {
+        let stdin = io::stdin();
+        return Debugger::new(cpu, elf).run(stdin.lock(), &mut io::stdout());
+    }
+    if let Some(addr) = matches.value_of("gdb") {
+        let mut conn = conn::listen(addr, "gdb")?;
+        return GdbStub::new(cpu).serve(&mut conn);
+    }
+    if let Some(addr) = matches.value_of("rvfi") {
+        let mut conn = conn::listen(addr, "RVFI")?;
+        rvfi::stream(&mut cpu, &mut conn)?;
+    } else {
+        cpu.execute()?;
+    }
+    if matches.is_present("block-stats") {
+        eprint!("{}", cpu.block_stats().to_text());
+    }
+    if let Some(report) = cpu.report(elf.as_ref()) {
+        print!("{}", report.to_text());
+        if let Some(path) = matches.value_of("report-json") {
+            fs::write(path, report.to_json())?;
+        }
+    }
     Ok(())
 }
+
+/// The most memory given to a program, from its lowest segment to past its highest.
+const MEMORY_LIMIT: u64 = 1 << 30;
+
+/// Memory holding the program, and the pc to start at.
+fn load(buf: &[u8], elf: Option<&Elf>, test_mode: bool) -> io::Result<(Mmu, u64)> {
+    match elf {
+        Some(elf) => {
+            let (base, end) = elf.span().unwrap_or((0, 0));
+            let size = end - base + 8192;
+            if size > MEMORY_LIMIT {
+                let e = format!("segments span {:#x} bytes, more than {:#x}", end - base, MEMORY_LIMIT);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            let mut mmu = Mmu::with_base(vec![0; size as usize], base, test_mode);
+            elf.load(&mut mmu).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok((mmu, elf.entry))
+        }
+        None => {
+            let mut mem = buf.to_vec();
+            mem.resize(buf.len() + 8192, 0);
+            Ok((Mmu::new(mem, test_mode), 0))
+        }
+    }
+}
+
+fn tracer(matches: &ArgMatches, categories: &str) -> io::Result<Tracer> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidInput, e);
+    let number = |name: &str| -> io::Result<Option<u64>> {
+        match matches.value_of(name) {
+            Some(v) => v
+                .parse()
+                .map(Some)
+                .map_err(|_| invalid(format!("--{} expects a number", name))),
+            None => Ok(None),
+        }
+    };
+    let out: Box<dyn Write> = match matches.value_of("trace-file") {
+        // unbuffered, since test mode ends the process with exit()
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stderr()),
+    };
+    let level = number("trace-level")?.unwrap_or(1) as u8;
+    let mut tracer = Tracer::new(trace::parse_categories(categories).map_err(invalid)?, level, out)
+        .window(number("trace-skip")?.unwrap_or(0), number("trace-count")?);
+    if let Some(range) = matches.value_of("trace-pc") {
+        tracer = tracer.pc_range(trace::parse_range(range).map_err(invalid)?);
+    }
+    Ok(tracer)
+}
@@ -1,46 +1,240 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
 use std::process::exit;
 
+/// Pages are 4 KiB, as far as decoded code is concerned.
+pub const PAGE_SHIFT: u64 = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A data access performed by a load or store, as reported to traces.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemAccess {
+    pub addr: u64,
+    pub len: u64,
+    pub access: Access,
+    pub data: u64,
+}
+
+struct Watchpoint {
+    addr: u64,
+    len: u64,
+    read: bool,
+    write: bool,
+}
+
+/// Where riscv-tests report their result in test mode: tohost, at the start of the page after
+/// the code.
+const TOHOST: u64 = 0x1000;
+
 pub struct Mmu {
     mem: Vec<u8>,
+    base: u64,
     test_mode: bool,
+    watchpoints: Vec<Watchpoint>,
+    watch_hit: Cell<Option<(u64, Access)>>,
+    accesses: RefCell<Vec<MemAccess>>,
+    /// pages holding decoded instructions, see `watch_code`
+    code_pages: HashSet<u64>,
+    code_writes: Vec<u64>,
 }
 
 impl Mmu {
     pub fn new(mem: Vec<u8>, test_mode: bool) -> Mmu {
-        Mmu { mem, test_mode }
+        Mmu::with_base(mem, 0, test_mode)
+    }
+    /// Memory whose first byte is at physical address `base`.
+    pub fn with_base(mem: Vec<u8>, base: u64, test_mode: bool) -> Mmu {
+        Mmu {
+            mem,
+            base,
+            test_mode,
+            watchpoints: Vec::new(),
+            watch_hit: Cell::new(None),
+            accesses: RefCell::new(Vec::new()),
+            code_pages: HashSet::new(),
+            code_writes: Vec::new(),
+        }
+    }
+    pub fn add_watchpoint(&mut self, addr: u64, len: u64, read: bool, write: bool) {
+        self.watchpoints.push(Watchpoint {
+            addr,
+            len,
+            read,
+            write,
+        });
+    }
+    pub fn remove_watchpoint(&mut self, addr: u64, len: u64, read: bool, write: bool) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints
+            .retain(|w| !(w.addr == addr && w.len == len && w.read == read && w.write == write));
+        self.watchpoints.len() != before
+    }
+    /// Returns and clears the first data access that hit a watchpoint since the last call.
+    pub fn take_watch_hit(&mut self) -> Option<(u64, Access)> {
+        self.watch_hit.take()
+    }
+    /// Returns and clears the data accesses made since the last call, oldest first.
+    pub fn take_accesses(&mut self) -> Vec<MemAccess> {
+        self.accesses.take()
+    }
+    /// Reports writes to `page`, by stores or `poke`, through `take_code_writes`.
+    pub fn watch_code(&mut self, page: u64) {
+        self.code_pages.insert(page);
+    }
+    /// Returns the watched pages written since the last call, which are no longer watched.
+    pub fn take_code_writes(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.code_writes)
+    }
+    /// Whether a watched page was written since the last `take_code_writes`.
+    pub fn code_written(&self) -> bool {
+        !self.code_writes.is_empty()
+    }
+    fn note_write(&mut self, p: u64, n: u64) {
+        if self.code_pages.is_empty() {
+            return;
+        }
+        for page in [p >> PAGE_SHIFT, (p + n - 1) >> PAGE_SHIFT].iter() {
+            if self.code_pages.remove(page) {
+                self.code_writes.push(*page);
+            }
+        }
+    }
+    fn record(&self, addr: u64, len: u64, access: Access, data: u64) {
+        self.accesses.borrow_mut().push(MemAccess {
+            addr,
+            len,
+            access,
+            data,
+        });
+    }
+    fn check_watch(&self, p: u64, n: u64, access: Access) {
+        if self.watch_hit.get().is_some() {
+            return;
+        }
+        for w in self.watchpoints.iter() {
+            let wanted = match access {
+                Access::Read => w.read,
+                Access::Write => w.write,
+            };
+            if wanted && p < w.addr + w.len && w.addr < p + n {
+                self.watch_hit.set(Some((w.addr, access)));
+                return;
+            }
+        }
     }
+    /// Whether `n` bytes starting at `p` are backed by memory.
+    pub fn contains(&self, p: u64, n: u64) -> bool {
+        match p.checked_sub(self.base).and_then(|offset| offset.checked_add(n)) {
+            Some(end) => end <= self.mem.len() as u64,
+            None => false,
+        }
+    }
+    /// Data read as performed by loads; may hit a watchpoint.
     pub fn read_nbytes(&self, p: u64, n: u64) -> u64 {
-        let p: usize = p as usize;
-        let n: usize = n as usize;
-        let mut result = 0_u64;
-        for i in 0..n {
-            result += (self.mem[i + p] as u64) << (8 * i);
+        self.check_watch(p, n, Access::Read);
+        let data = self.peek(p, n);
+        self.record(p, n, Access::Read, data);
+        data
+    }
+    /// Reads without side effects, for instruction fetch and debuggers.
+    pub fn peek(&self, p: u64, n: u64) -> u64 {
+        let p: usize = p.wrapping_sub(self.base) as usize;
+        let mut bytes = [0; 8];
+        bytes[..n as usize].copy_from_slice(&self.mem[p..p + n as usize]);
+        u64::from_le_bytes(bytes)
+    }
+    /// Writes without side effects, for debuggers.
+    pub fn poke(&mut self, p: u64, data: &[u8]) {
+        if !data.is_empty() {
+            self.note_write(p, data.len() as u64);
         }
-        return result
+        let p = p.wrapping_sub(self.base) as usize;
+        self.mem[p..p + data.len()].copy_from_slice(data);
     }
     pub fn write_byte(&mut self, p: u64, data: u8) {
-        let p: usize = p as usize;
+        self.check_watch(p, 1, Access::Write);
+        self.record(p, 1, Access::Write, data as u64);
+        self.note_write(p, 1);
+        let p: usize = p.wrapping_sub(self.base) as usize;
         self.mem[p] = data;
     }
     pub fn write_2byte(&mut self, p: u64, data: u16) {
+        self.check_watch(p, 2, Access::Write);
+        self.record(p, 2, Access::Write, data as u64);
+        self.note_write(p, 2);
+        let p = p.wrapping_sub(self.base);
         for i in 0..2 {
             self.mem[i + p as usize] = (data >> (i * 8)) as u8;
         }
     }
+    pub fn write_8byte(&mut self, p: u64, data: u64) {
+        self.check_watch(p, 8, Access::Write);
+        self.record(p, 8, Access::Write, data);
+        self.note_write(p, 8);
+        let p = p.wrapping_sub(self.base) as usize;
+        self.mem[p..p + 8].copy_from_slice(&data.to_le_bytes());
+    }
     pub fn write_4byte(&mut self, p: u64, data: u32) {
-        for i in 0..4 as usize {
-            if self.test_mode {
-                if i + p as usize == 1 {
-                    if (data >> (i * 8)) as u8 == 1 {
-                        println!("success");
-                        exit(0);
-                    } else{
-                        println!("failed");
-                        exit(1);
-                    }
-                }
+        self.check_watch(p, 4, Access::Write);
+        self.record(p, 4, Access::Write, data as u64);
+        self.note_write(p, 4);
+        let p = p.wrapping_sub(self.base);
+        if self.test_mode && p == TOHOST {
+            if data == 1 {
+                println!("success");
+                exit(0);
+            } else {
+                println!("failed");
+                exit(1);
             }
+        }
+        for i in 0..4_usize {
             self.mem[i + p as usize] = (data >> (i * 8)) as u8;
         }
     }
 }
+
+#[test]
+fn test_watchpoints() {
+    let mut mmu = Mmu::new(vec![0; 16], false);
+    mmu.add_watchpoint(4, 4, false, true);
+    assert!(!mmu.contains(u64::MAX, 2) && !mmu.contains(4, u64::MAX) && mmu.contains(12, 4));
+    mmu.read_nbytes(4, 4);
+    assert!(mmu.take_watch_hit().is_none());
+    mmu.write_byte(6, 1);
+    assert!(mmu.take_watch_hit() == Some((4, Access::Write)));
+    assert!(mmu.take_watch_hit().is_none());
+    assert!(mmu.remove_watchpoint(4, 4, false, true));
+    mmu.write_byte(6, 1);
+    assert!(mmu.take_watch_hit().is_none());
+}
+
+#[test]
+fn test_accesses() {
+    let mut mmu = Mmu::with_base(vec![0; 16], 0x100, false);
+    mmu.write_4byte(0x104, 0xdeadbeef);
+    assert!(mmu.read_nbytes(0x106, 2) == 0xdead);
+    mmu.peek(0x104, 4);
+    let accesses = mmu.take_accesses();
+    assert!(accesses.len() == 2);
+    assert!(accesses[0] == MemAccess { addr: 0x104, len: 4, access: Access::Write, data: 0xdeadbeef });
+    assert!(accesses[1].access == Access::Read && accesses[1].data == 0xdead);
+    assert!(mmu.take_accesses().is_empty());
+}
+
+#[test]
+fn test_code_writes() {
+    let mut mmu = Mmu::with_base(vec![0; 0x3000], 0x1000, false);
+    mmu.watch_code(1);
+    mmu.watch_code(2);
+    mmu.write_byte(0x1ffe, 1);
+    mmu.write_4byte(0x2ffe, 1);
+    mmu.poke(0x3000, &[1]);
+    assert!(mmu.take_code_writes() == vec![1, 2]);
+    assert!(mmu.take_code_writes().is_empty());
+}
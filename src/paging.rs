@@ -0,0 +1,221 @@
+//! Sv32 address translation, and the Sv32x4 G-stage the hypervisor extension adds beneath it
+//! for virtual machines. Page table entries must already have A set, and D for stores; a
+//! walk that would update them faults instead, as Svade has it. There is no TLB, so the
+//! fences have nothing to flush.
+use crate::mmu::Mmu;
+
+/// Page table entry bits.
+pub mod pte {
+    pub const V: u64 = 1 << 0;
+    pub const R: u64 = 1 << 1;
+    pub const W: u64 = 1 << 2;
+    pub const X: u64 = 1 << 3;
+    pub const U: u64 = 1 << 4;
+    pub const A: u64 = 1 << 6;
+    pub const D: u64 = 1 << 7;
+}
+
+pub const PAGE_SIZE: u64 = 1 << 12;
+/// satp.MODE, hgatp.MODE and vsatp.MODE for Sv32 and Sv32x4.
+pub const MODE_SV32: u64 = 1 << 31;
+/// The PPN field of satp, vsatp and hgatp.
+pub const PPN: u64 = (1 << 22) - 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Fetch,
+    Load,
+    Store,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// no memory at the physical address, of the access or a page table entry
+    Access,
+    /// the VS-stage, or single-stage, walk failed
+    Page,
+    /// the G-stage walk of guest physical address `gpa` failed
+    GuestPage { gpa: u64 },
+}
+
+impl Fault {
+    /// The exception cause of the fault on a `kind` access.
+    pub fn cause(self, kind: Kind) -> u64 {
+        let causes = match self {
+            Fault::Access => [1, 5, 7],
+            Fault::Page => [12, 13, 15],
+            Fault::GuestPage { .. } => [20, 21, 23],
+        };
+        causes[kind as usize]
+    }
+}
+
+/// What a translation is checked against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Walk {
+    /// satp, or vsatp in a virtual machine
+    pub satp: u64,
+    /// hgatp in a virtual machine
+    pub hgatp: Option<u64>,
+    /// a U-mode or VU-mode access
+    pub user: bool,
+    /// sstatus.SUM, or vsstatus.SUM: S-mode may read and write user pages
+    pub sum: bool,
+    /// mstatus.MXR, readable executable pages in both stages
+    pub mxr: bool,
+    /// vsstatus.MXR, the same for the VS-stage alone
+    pub vmxr: bool,
+    /// a hlvx load, needing execute permission instead of read
+    pub hlvx: bool,
+}
+
+/// Reads the page table entry at `address`.
+fn entry(mmu: &Mmu, address: u64) -> Result<u64, Fault> {
+    match mmu.contains(address, 4) {
+        true => Ok(mmu.peek(address, 4)),
+        false => Err(Fault::Access),
+    }
+}
+
+/// Walks the two levels of an Sv32 table rooted at `root` for `va`, of 32 bits, or 34 for the
+/// x4 table of the G-stage, whose entries are fetched through `fetch`. Returns the physical
+/// address and the leaf entry.
+fn walk(
+    root: u64,
+    va: u64,
+    fetch: &mut dyn FnMut(u64) -> Result<u64, Fault>,
+) -> Result<(u64, u64), Fault> {
+    let vpn = [(va >> 12) & 0x3ff, va >> 22];
+    let mut table = root;
+    for level in (0..2).rev() {
+        let pte = fetch(table + 4 * vpn[level])?;
+        if pte & pte::V == 0 || (pte & pte::R == 0 && pte & pte::W != 0) {
+            return Err(Fault::Page);
+        }
+        let ppn = pte >> 10;
+        if pte & (pte::R | pte::X) == 0 {
+            table = ppn << 12;
+            continue;
+        }
+        // a misaligned megapage
+        if level == 1 && ppn & 0x3ff != 0 {
+            return Err(Fault::Page);
+        }
+        let offset = va & ((1 << (12 + 10 * level)) - 1);
+        return Ok(((ppn << 12) & !((1 << (12 + 10 * level)) - 1) | offset, pte));
+    }
+    Err(Fault::Page)
+}
+
+/// Checks the leaf `pte` allows a `kind` access; `user` says whether the access is a user one,
+/// `sum` whether supervisor loads and stores may touch user pages, and `mxr` whether
+/// executable pages may be read.
+fn permits(pte: u64, kind: Kind, user: bool, sum: bool, mxr: bool, hlvx: bool) -> bool {
+    let allowed = match kind {
+        Kind::Fetch => pte & pte::X != 0,
+        Kind::Load if hlvx => pte & pte::X != 0,
+        Kind::Load => pte & pte::R != 0 || (mxr && pte & pte::X != 0),
+        Kind::Store => pte & pte::W != 0,
+    };
+    let privileged = match pte & pte::U != 0 {
+        true => user || (sum && kind != Kind::Fetch),
+        false => !user,
+    };
+    let dirty = pte & pte::A != 0 && (kind != Kind::Store || pte & pte::D != 0);
+    allowed && privileged && dirty
+}
+
+/// Translates guest physical address `gpa` through the G-stage table of `hgatp`, where every
+/// access is a user one. An hlvx load needs execute permission here too.
+fn g_stage(mmu: &Mmu, gpa: u64, kind: Kind, hgatp: u64, mxr: bool, hlvx: bool) -> Result<u64, Fault> {
+    if hgatp & MODE_SV32 == 0 {
+        return Ok(gpa);
+    }
+    let fault = Fault::GuestPage { gpa };
+    // the root is four pages, selected by gpa[33:32]
+    if gpa >> 34 != 0 {
+        return Err(fault);
+    }
+    let root = (hgatp & PPN & !0b11) << 12;
+    let (pa, pte) = walk(root, gpa, &mut |address| entry(mmu, address)).map_err(|f| match f {
+        Fault::Page => fault,
+        f => f,
+    })?;
+    match permits(pte, kind, true, false, mxr, hlvx) {
+        true => Ok(pa),
+        false => Err(fault),
+    }
+}
+
+/// Translates virtual address `va` for a `kind` access as `walk` says.
+pub fn translate(mmu: &Mmu, va: u64, kind: Kind, walk_: &Walk) -> Result<u64, Fault> {
+    let w = *walk_;
+    let g = |gpa: u64, kind: Kind, hlvx: bool| match w.hgatp {
+        Some(hgatp) => g_stage(mmu, gpa, kind, hgatp, w.mxr, hlvx),
+        None => Ok(gpa),
+    };
+    if w.satp & MODE_SV32 == 0 {
+        return g(va, kind, w.hlvx);
+    }
+    let root = (w.satp & PPN) << 12;
+    // the VS-stage tables are at guest physical addresses, read as implicit loads
+    let (gpa, pte) = walk(root, va, &mut |address| entry(mmu, g(address, Kind::Load, false)?))?;
+    match permits(pte, kind, w.user, w.sum, w.mxr || w.vmxr, w.hlvx) {
+        true => g(gpa, kind, w.hlvx),
+        false => Err(Fault::Page),
+    }
+}
+
+#[test]
+fn test_translate() {
+    // a table at 0x1000 mapping 0x40000000 to 0x3000 with a table at 0x2000, and the megapage
+    // 0x80000000 onto 0x0 for supervisor reads only
+    let mut mmu = Mmu::new(vec![0; 0x4000], false);
+    let ad = pte::V | pte::A | pte::D;
+    mmu.poke(0x1000 + 4 * 0x100, &((0x2 << 10) | pte::V).to_le_bytes()[..4]);
+    mmu.poke(0x2000, &((0x3 << 10) | pte::R | pte::W | pte::U | ad).to_le_bytes()[..4]);
+    mmu.poke(0x1000 + 4 * 0x200, &(pte::R | ad).to_le_bytes()[..4]);
+    let satp = MODE_SV32 | 1;
+    let w = Walk {
+        satp,
+        hgatp: None,
+        user: true,
+        sum: false,
+        mxr: false,
+        vmxr: false,
+        hlvx: false,
+    };
+    assert!(translate(&mmu, 0x40000123, Kind::Store, &w) == Ok(0x3123));
+    assert!(translate(&mmu, 0x40000123, Kind::Fetch, &w) == Err(Fault::Page));
+    assert!(translate(&mmu, 0x80001234, Kind::Load, &w) == Err(Fault::Page));
+    let s = Walk { user: false, ..w };
+    assert!(translate(&mmu, 0x80001234, Kind::Load, &s) == Ok(0x1234));
+    // user pages only with SUM, never to execute
+    assert!(translate(&mmu, 0x40000000, Kind::Load, &s) == Err(Fault::Page));
+    assert!(translate(&mmu, 0x40000000, Kind::Load, &Walk { sum: true, ..s }) == Ok(0x3000));
+    assert!(translate(&mmu, 0x00000000, Kind::Load, &s) == Err(Fault::Page));
+    // a G-stage with the x4 root at 0x0 mapping guest megapage 0 onto physical 0, so the guest
+    // tables are where they are: the same results, and guest addresses past it fault
+    let mut root = vec![0; 0x4000];
+    root[..4].copy_from_slice(&(pte::R | pte::W | pte::X | pte::U | ad).to_le_bytes()[..4]);
+    // and guest megapage 1 onto physical 0 too, but not executable
+    root[4..8].copy_from_slice(&(pte::R | pte::U | ad).to_le_bytes()[..4]);
+    let mut gmmu = Mmu::new(root, false);
+    for address in [0x1000 + 4 * 0x100, 0x2000, 0x1000 + 4 * 0x200] {
+        gmmu.poke(address, &mmu.peek(address, 4).to_le_bytes()[..4]);
+    }
+    let v = Walk {
+        hgatp: Some(MODE_SV32),
+        ..w
+    };
+    assert!(translate(&gmmu, 0x40000123, Kind::Store, &v) == Ok(0x3123));
+    let gpa = translate(&gmmu, 0x80400000, Kind::Load, &Walk { satp: 0, ..v });
+    assert!(gpa == Err(Fault::GuestPage { gpa: 0x80400000 }));
+    // hlvx needs execute permission in the G-stage as well
+    let bare = Walk { satp: 0, ..v };
+    assert!(translate(&gmmu, 0x400010, Kind::Load, &bare) == Ok(0x10));
+    assert!(translate(&gmmu, 0x10, Kind::Load, &Walk { hlvx: true, ..bare }) == Ok(0x10));
+    let hlvx = translate(&gmmu, 0x400010, Kind::Load, &Walk { hlvx: true, ..bare });
+    assert!(hlvx == Err(Fault::GuestPage { gpa: 0x400010 }));
+    assert!(Fault::GuestPage { gpa: 0 }.cause(Kind::Store) == 23);
+}
@@ -0,0 +1,176 @@
+use crate::{cpu::Retired, shadowstack::Event};
+
+/// Observes execution from outside `Cpu::exec`; register one with `Cpu::add_plugin`.
+///
+/// Every callback does nothing by default. Except for `fetch`, they are called after the
+/// instruction executed, in the order declared here. Returning an error stops the cpu with it
+/// as the reason, once the remaining plugins have seen the event.
+pub trait Plugin {
+    /// Before the instruction at `pc` executes; an error stops the cpu before it does.
+    fn fetch(&mut self, _pc: u64, _inst: u64) -> Result<(), String> {
+        Ok(())
+    }
+    fn mem_read(&mut self, _addr: u64, _width: u64, _value: u64) -> Result<(), String> {
+        Ok(())
+    }
+    fn mem_write(&mut self, _addr: u64, _width: u64, _value: u64) -> Result<(), String> {
+        Ok(())
+    }
+    /// A Zicsr instruction read `csr`, before any write.
+    fn csr_read(&mut self, _csr: usize, _value: u64) -> Result<(), String> {
+        Ok(())
+    }
+    /// `csr` was written, by a Zicsr instruction or by trap entry and exit.
+    fn csr_write(&mut self, _csr: usize, _value: u64) -> Result<(), String> {
+        Ok(())
+    }
+    /// A call at `pc` pushed a return address or a return was checked, including a mismatch,
+    /// which stops the cpu regardless of what the plugin returns.
+    fn sstack(&mut self, _pc: u64, _event: Event) -> Result<(), String> {
+        Ok(())
+    }
+    /// The instruction at `pc`, or an interrupt taken before it, trapped with `cause` into the
+    /// handler at `handler`.
+    fn trap_enter(&mut self, _pc: u64, _cause: u64, _handler: u64) -> Result<(), String> {
+        Ok(())
+    }
+    /// The trap return at `pc` went back to `target`.
+    fn trap_exit(&mut self, _pc: u64, _target: u64) -> Result<(), String> {
+        Ok(())
+    }
+    /// Everything the instruction did. Trapped instructions and interrupts are reported too,
+    /// with `trap` set; an interrupt has `len` 0.
+    fn retire(&mut self, _retired: &Retired) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Calls `f` on every plugin, even after one fails, and returns the first error.
+pub(crate) fn notify(
+    plugins: &mut [Box<dyn Plugin>],
+    mut f: impl FnMut(&mut dyn Plugin) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut result = Ok(());
+    for p in plugins.iter_mut() {
+        let r = f(p.as_mut());
+        if result.is_ok() {
+            result = r;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+struct Recorder {
+    events: std::rc::Rc<std::cell::RefCell<Vec<String>>>,
+    stop_on_write: bool,
+}
+
+#[cfg(test)]
+impl Plugin for Recorder {
+    fn fetch(&mut self, pc: u64, _inst: u64) -> Result<(), String> {
+        self.events.borrow_mut().push(format!("fetch {:#x}", pc));
+        Ok(())
+    }
+    fn mem_write(&mut self, addr: u64, width: u64, value: u64) -> Result<(), String> {
+        self.events.borrow_mut().push(format!("write {:#x} {} {}", addr, width, value));
+        match self.stop_on_write {
+            true => Err(String::from("write seen")),
+            false => Ok(()),
+        }
+    }
+    fn csr_read(&mut self, csr: usize, value: u64) -> Result<(), String> {
+        self.events.borrow_mut().push(format!("csr read {:#x} {}", csr, value));
+        Ok(())
+    }
+    fn csr_write(&mut self, csr: usize, value: u64) -> Result<(), String> {
+        self.events.borrow_mut().push(format!("csr write {:#x} {:#x}", csr, value));
+        Ok(())
+    }
+    fn sstack(&mut self, pc: u64, event: Event) -> Result<(), String> {
+        self.events.borrow_mut().push(format!("sstack {:#x} {:?}", pc, event));
+        Ok(())
+    }
+    fn trap_enter(&mut self, pc: u64, cause: u64, handler: u64) -> Result<(), String> {
+        self.events.borrow_mut().push(format!("trap {:#x} {} {:#x}", pc, cause, handler));
+        Ok(())
+    }
+    fn trap_exit(&mut self, pc: u64, target: u64) -> Result<(), String> {
+        self.events.borrow_mut().push(format!("trap exit {:#x} {:#x}", pc, target));
+        Ok(())
+    }
+    fn retire(&mut self, retired: &Retired) -> Result<(), String> {
+        self.events.borrow_mut().push(format!("retire {:#x}", retired.pc));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_plugin() {
+    use crate::cpu::Cpu;
+    let new_cpu = || {
+        let mut mem = vec![0; 0x80];
+        let program: [(usize, u32); 6] = [
+            (0x00, 0x00c000ef), // jal ra, 0xc
+            (0x04, 0x340512f3), // csrrw t0, mscratch, a0
+            (0x08, 0x00000073), // ecall
+            (0x0c, 0x04a02023), // sw a0, 0x40(zero)
+            (0x10, 0x00008067), // ret
+            (0x20, 0x30200073), // mret
+        ];
+        for (addr, inst) in program.iter() {
+            mem[*addr..*addr + 4].copy_from_slice(&inst.to_le_bytes());
+        }
+        let mut cpu = Cpu::with_memory(mem);
+        cpu.csr().write(0x305, 0x20).unwrap();
+        cpu.register().write(10, 7, 4).unwrap();
+        cpu
+    };
+    let events = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    let mut cpu = new_cpu();
+    cpu.add_plugin(Box::new(Recorder {
+        events: events.clone(),
+        stop_on_write: false,
+    }));
+    for _ in 0..6 {
+        cpu.step().unwrap();
+    }
+    let expected = [
+        "fetch 0x0",
+        "sstack 0x0 Push(4)",
+        "retire 0x0",
+        "fetch 0xc",
+        "write 0x40 4 7",
+        "retire 0xc",
+        "fetch 0x10",
+        "sstack 0x10 Return(4, Match)",
+        "retire 0x10",
+        "fetch 0x4",
+        "csr read 0x340 0",
+        "csr write 0x340 0x7",
+        "retire 0x4",
+        "fetch 0x8",
+        "csr write 0x341 0x8",
+        "csr write 0x342 0xb",
+        "csr write 0x343 0x0",
+        "csr write 0x300 0x1800",
+        "trap 0x8 11 0x20",
+        "retire 0x8",
+        "fetch 0x20",
+        "csr write 0x300 0x80",
+        "trap exit 0x20 0x8",
+        "retire 0x20",
+    ];
+    assert!(*events.borrow() == expected);
+
+    // a plugin error stops the cpu after the instruction
+    let mut cpu = new_cpu();
+    cpu.add_plugin(Box::new(Recorder {
+        events: std::rc::Rc::new(std::cell::RefCell::new(Vec::new())),
+        stop_on_write: true,
+    }));
+    cpu.step().unwrap();
+    assert!(cpu.step() == Err(String::from("write seen")));
+    assert!(cpu.mmu().peek(0x40, 4) == 7 && cpu.get_pc() == 0x10);
+    assert!(cpu.report(None).unwrap().reason == "write seen");
+}
@@ -0,0 +1,382 @@
+//! Python extension module for scripting experiments, built with `--features python`:
+//!
+//! ```text
+//! cargo build --release --features python
+//! cp target/release/librs_riscv_sc.so rs_riscv_sc.so
+//! ```
+//!
+//! ```python
+//! from rs_riscv_sc import Machine
+//! m = Machine(0x80000000, 0x10000)
+//! m.load_elf("prog")
+//! m.on_sstack(lambda m, e: print(e))
+//! m.add_breakpoint(0x80000010, lambda m: m.write_reg("ra", 0x80000000))
+//! print(m.run(), m.stop_reason)
+//! ```
+// the code pyo3 generates for PyResult returns trips this lint
+#![allow(clippy::useless_conversion)]
+use crate::{
+    cpu::{self, Cpu},
+    csr::{self, Csr},
+    elf::Elf,
+    mmu::{Access, Mmu},
+    register::{self, Register},
+    shadowstack::{self, Return, ShadowStacks},
+};
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::collections::BTreeMap;
+use std::fs;
+
+fn value_error(e: String) -> PyErr {
+    PyValueError::new_err(e)
+}
+
+/// A shadow stack event, passed to the hooks registered with `Machine.on_sstack`.
+#[pyclass(frozen)]
+#[derive(Clone)]
+pub struct SstackEvent {
+    /// "push", "match", "resync" or "mismatch"
+    #[pyo3(get)]
+    kind: &'static str,
+    /// pc of the call or return
+    #[pyo3(get)]
+    pc: u64,
+    /// return address pushed, or the address returned to
+    #[pyo3(get)]
+    target: u64,
+    /// top of the shadow stack a mismatching return was checked against
+    #[pyo3(get)]
+    expected: Option<u64>,
+    /// frames dropped by a resync
+    #[pyo3(get)]
+    skipped: Option<usize>,
+}
+
+impl SstackEvent {
+    fn new(pc: u64, event: shadowstack::Event) -> Self {
+        let mut e = SstackEvent {
+            kind: "push",
+            pc,
+            target: 0,
+            expected: None,
+            skipped: None,
+        };
+        match event {
+            shadowstack::Event::Push(addr) => e.target = addr,
+            shadowstack::Event::Return(target, ret) => {
+                e.target = target;
+                match ret {
+                    Return::Match => e.kind = "match",
+                    Return::Resync(skipped) => {
+                        e.kind = "resync";
+                        e.skipped = Some(skipped);
+                    }
+                    Return::Mismatch(expected) => {
+                        e.kind = "mismatch";
+                        e.expected = Some(expected);
+                    }
+                }
+            }
+        }
+        e
+    }
+}
+
+#[pymethods]
+impl SstackEvent {
+    fn __repr__(&self) -> String {
+        let mut s = format!("SstackEvent({} at {:#x}, target {:#x}", self.kind, self.pc, self.target);
+        if let Some(expected) = self.expected {
+            s += &format!(", expected {:#x}", expected);
+        }
+        if let Some(skipped) = self.skipped {
+            s += &format!(", skipped {}", skipped);
+        }
+        s + ")"
+    }
+}
+
+/// What one `Machine.step` did.
+#[pyclass(frozen)]
+pub struct Retired {
+    #[pyo3(get)]
+    pc: u64,
+    #[pyo3(get)]
+    insn: u64,
+    /// 2 or 4, or 0 when an interrupt was taken instead
+    #[pyo3(get)]
+    len: u64,
+    #[pyo3(get)]
+    privilege: u8,
+    /// (register, value) written, if any
+    #[pyo3(get)]
+    rd: Option<(usize, u64)>,
+    /// (f register, value) written, if any
+    #[pyo3(get)]
+    frd: Option<(usize, u64)>,
+    /// data accesses as (addr, len, is_write, data)
+    #[pyo3(get)]
+    mem: Vec<(u64, u64, bool, u64)>,
+    /// cause of the trap taken instead of completing
+    #[pyo3(get)]
+    trap: Option<u64>,
+    #[pyo3(get)]
+    next_pc: u64,
+}
+
+impl From<&cpu::Retired> for Retired {
+    fn from(r: &cpu::Retired) -> Self {
+        Retired {
+            pc: r.pc,
+            insn: r.inst,
+            len: r.len,
+            privilege: r.privilege,
+            rd: r.rd,
+            frd: r.frd,
+            mem: r
+                .mem
+                .iter()
+                .map(|a| (a.addr, a.len, a.access == Access::Write, a.data))
+                .collect(),
+            trap: r.trap,
+            next_pc: r.next_pc,
+        }
+    }
+}
+
+#[pymethods]
+impl Retired {
+    fn __repr__(&self) -> String {
+        format!("Retired(pc {:#x}, insn {:#x}, next pc {:#x})", self.pc, self.insn, self.next_pc)
+    }
+}
+
+/// A machine-mode hart with its memory, breakpoints and hooks.
+#[pyclass(unsendable)]
+pub struct Machine {
+    cpu: Cpu,
+    /// breakpoint address and its callback, if any
+    breakpoints: BTreeMap<u64, Option<PyObject>>,
+    sstack_hooks: Vec<PyObject>,
+}
+
+/// Register number from a number or a name such as `a0` or `x10`.
+fn reg_number(reg: &Bound<'_, PyAny>) -> PyResult<usize> {
+    let n = match reg.extract::<usize>() {
+        Ok(n) if n < 32 => Some(n),
+        Ok(_) => None,
+        Err(_) => register::parse_name(reg.extract::<&str>()?),
+    };
+    n.ok_or_else(|| value_error(format!("no register {}", reg)))
+}
+
+/// CSR number from a number or a name such as `mepc`.
+fn csr_number(reg: &Bound<'_, PyAny>) -> PyResult<usize> {
+    let n = match reg.extract::<usize>() {
+        Ok(n) if n < csr::LIMIT_CSR => Some(n),
+        Ok(_) => None,
+        Err(_) => csr::parse_name(reg.extract::<&str>()?),
+    };
+    n.ok_or_else(|| value_error(format!("no CSR {}", reg)))
+}
+
+#[pymethods]
+impl Machine {
+    /// Zeroed memory of `mem_size` bytes at `mem_base`, with the pc at `mem_base`. With `resync`
+    /// a return deeper into the shadow stack unwinds to it instead of being a mismatch.
+    #[new]
+    #[pyo3(signature = (mem_base=0x80000000, mem_size=0x100000, resync=false))]
+    fn new(mem_base: u64, mem_size: u64, resync: bool) -> Self {
+        let cpu = Cpu::new(
+            mem_base,
+            Csr::new([0; 4096]),
+            Register::new([0; 32]),
+            0b11,
+            Mmu::with_base(vec![0; mem_size as usize], mem_base, false),
+            ShadowStacks::new(0, 0b11, resync),
+        );
+        Machine {
+            cpu,
+            breakpoints: BTreeMap::new(),
+            sstack_hooks: Vec::new(),
+        }
+    }
+
+    /// Copies the loadable segments of an ELF file into memory and jumps to its entry point,
+    /// which is returned.
+    fn load_elf(&mut self, path: &str) -> PyResult<u64> {
+        let buf = fs::read(path).map_err(|e| PyOSError::new_err(format!("{}: {}", path, e)))?;
+        let elf = Elf::parse(&buf).map_err(|e| value_error(format!("{}: {}", path, e)))?;
+        elf.load(self.cpu.mmu()).map_err(value_error)?;
+        self.cpu.set_pc(elf.entry);
+        Ok(elf.entry)
+    }
+
+    #[getter]
+    fn get_pc(&self) -> u64 {
+        self.cpu.get_pc()
+    }
+    #[setter]
+    fn set_pc(&mut self, pc: u64) {
+        self.cpu.set_pc(pc);
+    }
+    #[getter]
+    fn get_privilege(&self) -> u8 {
+        self.cpu.get_privilege()
+    }
+    #[setter]
+    fn set_privilege(&mut self, privilege: u8) -> PyResult<()> {
+        self.cpu.set_privilege(privilege).map_err(value_error)
+    }
+
+    fn read_reg(&mut self, reg: &Bound<'_, PyAny>) -> PyResult<u64> {
+        let n = reg_number(reg)?;
+        let len = self.cpu.get_xlen_bytes();
+        self.cpu.register().read(n, len).map_err(value_error)
+    }
+    fn write_reg(&mut self, reg: &Bound<'_, PyAny>, value: u64) -> PyResult<()> {
+        let n = reg_number(reg)?;
+        let len = self.cpu.get_xlen_bytes();
+        self.cpu.register().write(n, value, len).map_err(value_error)
+    }
+    fn read_csr(&mut self, reg: &Bound<'_, PyAny>) -> PyResult<u64> {
+        let n = csr_number(reg)?;
+        self.cpu.csr().read(n).map_err(value_error)
+    }
+    fn write_csr(&mut self, reg: &Bound<'_, PyAny>, value: u64) -> PyResult<()> {
+        let n = csr_number(reg)?;
+        self.cpu.csr().write(n, value).map_err(value_error)
+    }
+
+    fn read_mem<'py>(&mut self, py: Python<'py>, addr: u64, len: u64) -> PyResult<Bound<'py, PyBytes>> {
+        let mmu = self.cpu.mmu();
+        if !mmu.contains(addr, len) {
+            return Err(value_error(format!("{:#x} of {:#x} bytes is outside memory", addr, len)));
+        }
+        let data: Vec<u8> = (addr..addr + len).map(|a| mmu.peek(a, 1) as u8).collect();
+        Ok(PyBytes::new_bound(py, &data))
+    }
+    fn write_mem(&mut self, addr: u64, data: &[u8]) -> PyResult<()> {
+        let mmu = self.cpu.mmu();
+        if !mmu.contains(addr, data.len() as u64) {
+            return Err(value_error(format!(
+                "{:#x} of {:#x} bytes is outside memory",
+                addr,
+                data.len()
+            )));
+        }
+        mmu.poke(addr, data);
+        Ok(())
+    }
+
+    /// Return addresses on the active shadow stack, outermost first.
+    #[getter]
+    fn shadow_stack(&self) -> Vec<u64> {
+        self.cpu.sstack().frames().to_vec()
+    }
+
+    /// Why the last step failed, or None.
+    #[getter]
+    fn stop_reason(&self) -> Option<String> {
+        self.cpu.report(None).map(|r| r.reason)
+    }
+
+    /// Makes machine interrupt `cause` pending; it is taken once enabled by mie and mstatus.MIE.
+    fn raise_interrupt(&mut self, cause: u64) -> PyResult<()> {
+        self.cpu.raise_interrupt(cause).map_err(value_error)
+    }
+
+    /// Calls `callback(machine, event)` with an `SstackEvent` for every shadow stack push and
+    /// checked return, including a mismatch that stops the machine.
+    fn on_sstack(&mut self, callback: PyObject) {
+        self.sstack_hooks.push(callback);
+    }
+
+    /// Makes `run` stop before executing the instruction at `addr`. With a callback,
+    /// `callback(machine)` is called there instead and `run` only stops if it returns True.
+    #[pyo3(signature = (addr, callback=None))]
+    fn add_breakpoint(&mut self, addr: u64, callback: Option<PyObject>) {
+        self.breakpoints.insert(addr, callback);
+    }
+    fn remove_breakpoint(&mut self, addr: u64) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    /// Executes one instruction, or takes a pending interrupt. Returns what it did, or None if
+    /// the machine stopped on an error, see `stop_reason`. Breakpoints are not checked.
+    fn step(slf: &Bound<'_, Self>) -> PyResult<Option<Retired>> {
+        let py = slf.py();
+        let (retired, event, hooks) = {
+            let mut m = slf.borrow_mut();
+            let (retired, event) = match m.cpu.step() {
+                Ok(()) => {
+                    let r = m.cpu.retired();
+                    (Some(Retired::from(r)), r.sstack.map(|e| SstackEvent::new(r.pc, e)))
+                }
+                Err(_) => {
+                    let event = m.cpu.report(None).and_then(|r| {
+                        let v = r.violation?;
+                        let ret = Return::Mismatch(v.expected);
+                        Some(SstackEvent::new(r.pc, shadowstack::Event::Return(v.target, ret)))
+                    });
+                    (None, event)
+                }
+            };
+            let hooks: Vec<PyObject> = match event {
+                Some(_) => m.sstack_hooks.iter().map(|h| h.clone_ref(py)).collect(),
+                None => Vec::new(),
+            };
+            (retired, event, hooks)
+        };
+        // the machine is not borrowed while hooks run, so they can inspect and modify it
+        if let Some(event) = event {
+            for hook in hooks.iter() {
+                hook.call1(py, (slf, event.clone()))?;
+            }
+        }
+        Ok(retired)
+    }
+
+    /// Runs until the machine stops, a breakpoint is hit or `max_steps` instructions have
+    /// executed, and returns "stopped", "breakpoint" or "limit". A breakpoint on the instruction
+    /// `run` starts from is not hit, so a stopped run can be resumed.
+    #[pyo3(signature = (max_steps=None))]
+    fn run(slf: &Bound<'_, Self>, max_steps: Option<u64>) -> PyResult<&'static str> {
+        let py = slf.py();
+        let mut steps = 0;
+        loop {
+            if max_steps.is_some_and(|max| steps >= max) {
+                return Ok("limit");
+            }
+            if steps > 0 {
+                let hit = {
+                    let m = slf.borrow();
+                    let pc = m.cpu.get_pc();
+                    m.breakpoints.get(&pc).map(|cb| cb.as_ref().map(|cb| cb.clone_ref(py)))
+                };
+                let stop = match hit {
+                    Some(None) => true,
+                    Some(Some(cb)) => cb.call1(py, (slf,))?.is_truthy(py)?,
+                    None => false,
+                };
+                if stop {
+                    return Ok("breakpoint");
+                }
+            }
+            if Machine::step(slf)?.is_none() {
+                return Ok("stopped");
+            }
+            steps += 1;
+        }
+    }
+}
+
+#[pymodule]
+fn rs_riscv_sc(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Machine>()?;
+    m.add_class::<Retired>()?;
+    m.add_class::<SstackEvent>()?;
+    Ok(())
+}
@@ -1,26 +1,66 @@
+pub const ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+pub const FP_ABI_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+/// Parses `x5`, `t0` or `fp` into a register number.
+pub fn parse_name(name: &str) -> Option<usize> {
+    if name == "fp" {
+        return Some(8);
+    }
+    if let Some(n) = name.strip_prefix('x') {
+        return n.parse::<usize>().ok().filter(|&n| n < 32);
+    }
+    ABI_NAMES.iter().position(|&a| a == name)
+}
+
 #[derive(Clone, Copy)]
 pub struct Register {
     registers: [u64; 32],
+    /// registers that exist, 16 for RV32E
+    count: usize,
 }
 
 impl Register {
     pub fn new(registers: [u64; 32]) -> Register {
-        Register { registers }
+        Register { registers, count: 32 }
+    }
+    /// Leaves only x0 to x15, as RV32E, or all 32 registers.
+    pub fn set_embedded(&mut self, embedded: bool) {
+        self.count = if embedded { 16 } else { 32 };
+    }
+    /// Number of registers that exist.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+    /// All registers as stored, x0 included.
+    pub fn raw(&self) -> [u64; 32] {
+        self.registers
     }
     pub fn read(&self, n: usize, len: u8) -> Result<u64, String> {
         if n >= 32 {
             return Err(String::from("Too large register number"));
         }
+        if n >= self.count {
+            return Err(format!("No register x{} in RV32E", n));
+        }
         if n==0 {
             return Ok(0);
         }
         match len {
-            1 => return Ok(self.registers[n] as u8 as u64),
-            2 => return Ok(self.registers[n] as u16 as u64),
-            4 => return Ok(self.registers[n] as u32 as u64),
-            8 => return Ok(self.registers[n] as u64 as u64),
+            1 => Ok(self.registers[n] as u8 as u64),
+            2 => Ok(self.registers[n] as u16 as u64),
+            4 => Ok(self.registers[n] as u32 as u64),
+            8 => Ok(self.registers[n]),
             _ => {
-                return Err(String::from("No such register length"));
+                Err(String::from("No such register length"))
             }
         }
     }
@@ -28,15 +68,64 @@ impl Register {
         if n >= 32 {
             return Err(String::from("Too large register number"));
         }
+        if n >= self.count {
+            return Err(format!("No register x{} in RV32E", n));
+        }
         match len {
             1 => self.registers[n] = d as u8 as u64,
             2 => self.registers[n] = d as u16 as u64,
             3 => self.registers[n] = d as u32 as u64,
-            4 => self.registers[n] = d as u64 as u64,
+            4 => self.registers[n] = d,
             _ => {
                 return Err(String::from("No such register length"));
             }
         }
-        return Ok(());
+        Ok(())
+    }
+}
+
+/// The floating-point registers, 64 bits wide; narrower values are NaN-boxed in them.
+#[derive(Clone, Copy)]
+pub struct FRegister {
+    registers: [u64; 32],
+}
+
+impl FRegister {
+    pub fn new(registers: [u64; 32]) -> FRegister {
+        FRegister { registers }
+    }
+    pub fn raw(&self) -> [u64; 32] {
+        self.registers
+    }
+    pub fn read(&self, n: usize) -> Result<u64, String> {
+        match self.registers.get(n) {
+            Some(&r) => Ok(r),
+            None => Err(String::from("Too large register number")),
+        }
+    }
+    pub fn write(&mut self, n: usize, d: u64) -> Result<(), String> {
+        match self.registers.get_mut(n) {
+            Some(r) => *r = d,
+            None => return Err(String::from("Too large register number")),
+        }
+        Ok(())
     }
 }
+
+#[test]
+fn test_parse_name() {
+    assert!(parse_name("x0") == Some(0));
+    assert!(parse_name("x31") == Some(31));
+    assert!(parse_name("x32").is_none());
+    assert!(parse_name("a0") == Some(10));
+    assert!(parse_name("fp") == Some(8));
+    assert!(parse_name("t6") == Some(31));
+}
+
+#[test]
+fn test_embedded() {
+    let mut r = Register::new([0; 32]);
+    r.set_embedded(true);
+    assert!(r.write(15, 7, 4).is_ok() && r.read(15, 4) == Ok(7));
+    assert!(r.read(16, 4).is_err() && r.write(31, 1, 4).is_err() && r.count() == 16);
+}
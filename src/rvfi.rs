@@ -0,0 +1,256 @@
+use crate::{
+    cpu::{Cpu, Retired},
+    mmu::Access,
+};
+use byteorder::{ByteOrder, LittleEndian};
+use std::io::{self, Read, Write};
+
+/// DII command ending the current trace; the model resets and answers with a halt packet.
+const CMD_END: u8 = 0;
+/// DII command carrying an instruction to execute.
+const CMD_INSTRUCTION: u8 = 1;
+
+/// One RISC-V Formal Interface record of a retired instruction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Packet {
+    pub order: u64,
+    pub insn: u64,
+    pub trap: bool,
+    pub halt: bool,
+    pub intr: bool,
+    /// privilege level; not part of the version 1 wire format, see `to_bytes`
+    pub mode: u8,
+    pub rs1_addr: u8,
+    pub rs2_addr: u8,
+    pub rs1_rdata: u64,
+    pub rs2_rdata: u64,
+    pub rd_addr: u8,
+    pub rd_wdata: u64,
+    pub pc_rdata: u64,
+    pub pc_wdata: u64,
+    pub mem_addr: u64,
+    pub mem_rmask: u8,
+    pub mem_wmask: u8,
+    pub mem_rdata: u64,
+    pub mem_wdata: u64,
+}
+
+impl Packet {
+    /// The 88 byte RVFI-DII version 1 execution packet used by TestRIG.
+    pub fn to_bytes(&self) -> [u8; 88] {
+        let mut b = [0; 88];
+        let words = [
+            self.order,
+            self.pc_rdata,
+            self.pc_wdata,
+            self.insn,
+            self.rs1_rdata,
+            self.rs2_rdata,
+            self.rd_wdata,
+            self.mem_addr,
+            self.mem_rdata,
+            self.mem_wdata,
+        ];
+        for (i, w) in words.iter().enumerate() {
+            LittleEndian::write_u64(&mut b[i * 8..], *w);
+        }
+        b[80] = self.mem_rmask;
+        b[81] = self.mem_wmask;
+        b[82] = self.rs1_addr;
+        b[83] = self.rs2_addr;
+        b[84] = self.rd_addr;
+        b[85] = self.trap as u8;
+        b[86] = self.halt as u8;
+        b[87] = self.intr as u8;
+        b
+    }
+}
+
+/// Numbers retired instructions and marks the first instruction of each trap handler.
+#[derive(Default)]
+pub struct Rvfi {
+    order: u64,
+    in_trap: bool,
+}
+
+impl Rvfi {
+    pub fn packet(&mut self, r: &Retired) -> Packet {
+        let mut p = Packet {
+            order: self.order,
+            insn: r.inst,
+            trap: r.trap.is_some(),
+            intr: self.in_trap,
+            mode: r.privilege,
+            pc_rdata: r.pc,
+            pc_wdata: r.next_pc,
+            ..Packet::default()
+        };
+        if let Some((n, v)) = r.rs1 {
+            p.rs1_addr = n as u8;
+            p.rs1_rdata = v;
+        }
+        if let Some((n, v)) = r.rs2 {
+            p.rs2_addr = n as u8;
+            p.rs2_rdata = v;
+        }
+        if let Some((n, v)) = r.rd {
+            p.rd_addr = n as u8;
+            p.rd_wdata = v;
+        }
+        // the base ISA makes at most one data access per instruction
+        if let Some(a) = r.mem.first() {
+            let mask = ((1u16 << a.len) - 1) as u8;
+            p.mem_addr = a.addr;
+            match a.access {
+                Access::Read => {
+                    p.mem_rmask = mask;
+                    p.mem_rdata = a.data;
+                }
+                Access::Write => {
+                    p.mem_wmask = mask;
+                    p.mem_wdata = a.data;
+                }
+            }
+        }
+        self.order += 1;
+        self.in_trap = p.trap;
+        p
+    }
+    /// Packet for an instruction the model could not execute, which is reported as a trap.
+    fn failed(&mut self, pc: u64, insn: u64, mode: u8) -> Packet {
+        let p = Packet {
+            order: self.order,
+            insn,
+            trap: true,
+            intr: self.in_trap,
+            mode,
+            pc_rdata: pc,
+            pc_wdata: pc,
+            ..Packet::default()
+        };
+        self.order += 1;
+        self.in_trap = true;
+        p
+    }
+    fn halt(&self) -> Packet {
+        Packet {
+            order: self.order,
+            halt: true,
+            ..Packet::default()
+        }
+    }
+}
+
+/// Runs `cpu` from memory and sends a packet for every retired instruction, then a halt packet.
+pub fn stream(cpu: &mut Cpu, conn: &mut dyn Write) -> io::Result<()> {
+    let mut rvfi = Rvfi::default();
+    while cpu.step().is_ok() {
+        conn.write_all(&rvfi.packet(cpu.retired()).to_bytes())?;
+    }
+    conn.write_all(&rvfi.halt().to_bytes())?;
+    conn.flush()
+}
+
+/// Acts as the golden model for direct instruction injection: executes every instruction
+/// received at the current pc and answers with its packet. An end-of-trace command resets the
+/// model to a fresh cpu from `reset` and is answered with a halt packet.
+pub fn serve_dii<C: Read + Write>(
+    conn: &mut C,
+    mut reset: impl FnMut() -> io::Result<Cpu>,
+) -> io::Result<()> {
+    let mut cpu = reset()?;
+    let mut rvfi = Rvfi::default();
+    let mut buf = [0; 8];
+    loop {
+        match conn.read_exact(&mut buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        // rvfi_insn in bits 31..0, rvfi_time in 47..32, rvfi_cmd in 55..48, then padding
+        let packet = LittleEndian::read_u64(&buf);
+        let insn = packet as u32;
+        let reply = match (packet >> 48) as u8 {
+            CMD_END => {
+                cpu = reset()?;
+                rvfi = Rvfi::default();
+                rvfi.halt()
+            }
+            CMD_INSTRUCTION => {
+                let (pc, mode) = (cpu.get_pc(), cpu.get_privilege());
+                match cpu.step_injected(insn) {
+                    Ok(()) => rvfi.packet(cpu.retired()),
+                    Err(_) => rvfi.failed(pc, insn as u64, mode),
+                }
+            }
+            cmd => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown RVFI-DII command {}", cmd),
+                ))
+            }
+        };
+        conn.write_all(&reply.to_bytes())?;
+    }
+}
+
+#[cfg(test)]
+fn test_cpu() -> Cpu {
+    Cpu::with_memory(vec![0; 64])
+}
+
+#[test]
+fn test_packet_layout() {
+    let p = Packet {
+        order: 1,
+        insn: 0x00a02823,
+        rs2_addr: 10,
+        rs2_rdata: 5,
+        mem_addr: 0x10,
+        mem_wmask: 0xf,
+        mem_wdata: 5,
+        pc_rdata: 4,
+        pc_wdata: 8,
+        intr: true,
+        ..Packet::default()
+    };
+    let b = p.to_bytes();
+    assert!(LittleEndian::read_u64(&b[0..]) == 1);
+    assert!(LittleEndian::read_u64(&b[16..]) == 8);
+    assert!(LittleEndian::read_u64(&b[24..]) == 0x00a02823);
+    assert!(LittleEndian::read_u64(&b[40..]) == 5);
+    assert!(LittleEndian::read_u64(&b[56..]) == 0x10);
+    assert!(b[80..88] == [0, 0xf, 0, 10, 0, 0, 0, 1]);
+}
+
+#[test]
+fn test_dii() {
+    use std::os::unix::net::UnixStream;
+    let (mut tool, mut model) = UnixStream::pair().unwrap();
+    // with a time stamp, which the model ignores
+    let inject = |insn: u32, cmd: u64| (cmd << 48 | 7 << 32 | insn as u64).to_le_bytes();
+    // li a0, 5; sw a0, 16(zero); ecall; end; addi a0, a0, 1
+    for (insn, cmd) in [(0x00500513, 1), (0x00a02823, 1), (0x00000073, 1), (0, 0), (0x00150513, 1)].iter() {
+        tool.write_all(&inject(*insn, *cmd)).unwrap();
+    }
+    tool.shutdown(std::net::Shutdown::Write).unwrap();
+    serve_dii(&mut model, || Ok(test_cpu())).unwrap();
+    drop(model);
+    let mut out = Vec::new();
+    tool.read_to_end(&mut out).unwrap();
+    assert!(out.len() == 5 * 88);
+    let p: Vec<&[u8]> = out.chunks(88).collect();
+    // li: rd a0 = 5
+    assert!(p[0][84] == 10 && LittleEndian::read_u64(&p[0][48..]) == 5);
+    // sw: rs1 zero, rs2 a0, 4 byte write of 5 to 0x10
+    assert!(LittleEndian::read_u64(&p[1][0..]) == 1);
+    assert!(p[1][82] == 0 && p[1][83] == 10 && LittleEndian::read_u64(&p[1][40..]) == 5);
+    assert!(LittleEndian::read_u64(&p[1][56..]) == 0x10 && p[1][81] == 0xf);
+    assert!(LittleEndian::read_u64(&p[1][72..]) == 5);
+    // ecall traps
+    assert!(p[2][85] == 1 && LittleEndian::read_u64(&p[2][8..]) == 8);
+    // end of trace: halt, then a fresh model with a0 = 0
+    assert!(p[3][86] == 1);
+    assert!(LittleEndian::read_u64(&p[4][0..]) == 0 && p[4][87] == 0);
+    assert!(LittleEndian::read_u64(&p[4][32..]) == 0 && LittleEndian::read_u64(&p[4][48..]) == 1);
+}
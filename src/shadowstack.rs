@@ -1,16 +1,23 @@
+use std::collections::HashMap;
+
+pub const SSTACK_DEPTH: usize = 255;
+
 pub struct ShadowStack {
     sp: usize,
-    stack: [u64; 255],
+    stack: [u64; SSTACK_DEPTH],
 }
 
 impl ShadowStack {
-    pub fn new(sp: usize, stack: [u64; 255]) -> Self {
+    pub fn new(sp: usize, stack: [u64; SSTACK_DEPTH]) -> Self {
         Self { sp, stack }
     }
     pub fn push(&mut self, data: u64) -> Result<(),String> {
+        if self.sp >= SSTACK_DEPTH {
+            return Err(String::from("Error, shadowstack overflow"));
+        }
         self.stack[self.sp] = data;
         self.sp += 1;
-        return Ok(())
+        Ok(())
     }
     pub fn pop(&mut self) -> Result<u64, String>{
         if self.sp < 1{
@@ -18,21 +25,108 @@ impl ShadowStack {
         }
         let ret = self.stack[self.sp-1];
         self.sp -= 1;
-        return Ok(ret)
+        Ok(ret)
+    }
+    /// Searches the stack from the top for `target` and, if found, drops every frame above it
+    /// together with the matching one. Returns how many frames were skipped.
+    pub fn unwind_to(&mut self, target: u64) -> Option<usize> {
+        let depth = self.stack[..self.sp].iter().rposition(|&v| v == target)?;
+        let skipped = self.sp - depth - 1;
+        self.sp = depth;
+        Some(skipped)
+    }
+    /// Return addresses currently on the stack, outermost first.
+    pub fn frames(&self) -> &[u64] {
+        &self.stack[..self.sp]
     }
+    #[cfg(test)]
     fn get_sp(&self) -> usize{
         self.sp
     }
+    #[cfg(test)]
     fn get_stack(&self, i: usize) -> u64{
         self.stack[i]
     }
 }
+
+/// Outcome of a checked return.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Return {
+    Match,
+    /// The return address was found deeper in the stack; the frames above it were dropped.
+    Resync(usize),
+    /// The return address did not match; holds the expected address, which stays on the stack.
+    Mismatch(u64),
+}
+
+/// Shadow stack activity of one instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    /// A call pushed its return address.
+    Push(u64),
+    /// A return to the address was checked.
+    Return(u64, Return),
+}
+
+/// One shadow stack per (hart, privilege level); the active one is switched on trap entry/exit.
+pub struct ShadowStacks {
+    stacks: HashMap<(u64, u8), ShadowStack>,
+    current: (u64, u8),
+    resync: bool,
+}
+
+impl ShadowStacks {
+    pub fn new(hart: u64, privilege: u8, resync: bool) -> Self {
+        let mut stacks = HashMap::new();
+        stacks.insert((hart, privilege), ShadowStack::new(0, [0; SSTACK_DEPTH]));
+        Self {
+            stacks,
+            current: (hart, privilege),
+            resync,
+        }
+    }
+    pub fn switch(&mut self, hart: u64, privilege: u8) {
+        self.current = (hart, privilege);
+        self.stacks
+            .entry(self.current)
+            .or_insert_with(|| ShadowStack::new(0, [0; SSTACK_DEPTH]));
+    }
+    fn current_mut(&mut self) -> &mut ShadowStack {
+        self.stacks
+            .get_mut(&self.current)
+            .expect("current shadow stack is always allocated by switch")
+    }
+    pub fn push(&mut self, data: u64) -> Result<(), String> {
+        self.current_mut().push(data)
+    }
+    pub fn frames(&self) -> &[u64] {
+        self.stacks[&self.current].frames()
+    }
+    /// Checks a return to `target` against the active stack. In resync mode a return to an
+    /// address deeper in the stack (longjmp, exception unwinding) unwinds to it instead of failing.
+    pub fn ret(&mut self, target: u64) -> Result<Return, String> {
+        let resync = self.resync;
+        let stack = self.current_mut();
+        let sv = stack.pop()?;
+        if target == sv {
+            return Ok(Return::Match);
+        }
+        if resync {
+            if let Some(skipped) = stack.unwind_to(target) {
+                return Ok(Return::Resync(skipped + 1));
+            }
+        }
+        stack.push(sv)?;
+        Ok(Return::Mismatch(sv))
+    }
+}
+
 #[test]
 fn push(){
     let mut sstack = ShadowStack::new(0,[0;255]);
     assert!(sstack.get_sp()==0);
     assert!(sstack.get_stack(0)==0);
-    sstack.push(1);
+    sstack.push(1).unwrap();
     assert!(sstack.get_sp()==1);
     assert!(sstack.get_stack(0)==1);
 }
@@ -41,12 +135,47 @@ fn push(){
 #[test]
 fn pushandpop(){
     let mut sstack = ShadowStack::new(0,[0;255]);
-    sstack.push(1);
+    sstack.push(1).unwrap();
     match sstack.pop() {
         Ok(r) => {
             println!("{}",r);
             assert!(r==1);
         },
-        Err(r) => panic!(r),
+        Err(r) => panic!("{}", r),
+    }
+}
+
+#[test]
+fn overflow(){
+    let mut sstack = ShadowStack::new(0,[0;255]);
+    for i in 0..SSTACK_DEPTH {
+        sstack.push(i as u64).unwrap();
+    }
+    assert!(sstack.push(0).is_err());
+}
+
+#[test]
+fn per_privilege(){
+    let mut sstacks = ShadowStacks::new(0, 3, false);
+    sstacks.push(0x100).unwrap();
+    sstacks.switch(0, 0);
+    sstacks.push(0x200).unwrap();
+    assert!(sstacks.ret(0x200) == Ok(Return::Match));
+    sstacks.switch(0, 3);
+    assert!(sstacks.ret(0x100) == Ok(Return::Match));
+}
+
+#[test]
+fn resync(){
+    let mut sstacks = ShadowStacks::new(0, 3, true);
+    for ra in [0x10, 0x20, 0x30, 0x40].iter() {
+        sstacks.push(*ra).unwrap();
     }
+    assert!(sstacks.ret(0x20) == Ok(Return::Resync(2)));
+    assert!(sstacks.ret(0x10) == Ok(Return::Match));
+    let mut strict = ShadowStacks::new(0, 3, false);
+    strict.push(0x10).unwrap();
+    strict.push(0x20).unwrap();
+    assert!(strict.ret(0x10) == Ok(Return::Mismatch(0x20)));
+    assert!(strict.frames() == [0x10, 0x20]);
 }
@@ -0,0 +1,185 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// Trace categories, combined as a bit mask.
+pub mod category {
+    pub const FETCH: u32 = 1 << 0;
+    pub const DECODE: u32 = 1 << 1;
+    pub const REGS: u32 = 1 << 2;
+    pub const MEMORY: u32 = 1 << 3;
+    pub const CSR: u32 = 1 << 4;
+    pub const SSTACK: u32 = 1 << 5;
+    pub const TRAP: u32 = 1 << 6;
+    pub const ALL: u32 = (1 << 7) - 1;
+}
+
+pub const CATEGORY_NAMES: [(&str, u32); 7] = [
+    ("fetch", category::FETCH),
+    ("decode", category::DECODE),
+    ("regs", category::REGS),
+    ("mem", category::MEMORY),
+    ("csr", category::CSR),
+    ("sstack", category::SSTACK),
+    ("trap", category::TRAP),
+];
+
+/// Parses a comma separated list of category names such as `fetch,regs`, or `all`.
+pub fn parse_categories(list: &str) -> Result<u32, String> {
+    let mut mask = 0;
+    for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        mask |= match name {
+            "all" => category::ALL,
+            "memory" => category::MEMORY,
+            _ => CATEGORY_NAMES
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|&(_, c)| c)
+                .ok_or_else(|| format!("unknown trace category {}", name))?,
+        };
+    }
+    Ok(mask)
+}
+
+/// Parses an address range written as `start:end` (end exclusive).
+pub fn parse_range(range: &str) -> Result<Range<u64>, String> {
+    let number = |s: &str| match s.trim().strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.trim().parse().ok(),
+    };
+    let mut parts = range.splitn(2, ':');
+    match (parts.next().and_then(number), parts.next().and_then(number)) {
+        (Some(start), Some(end)) if start < end => Ok(start..end),
+        _ => Err(format!("invalid pc range {}, expected start:end", range)),
+    }
+}
+
+fn category_name(cat: u32) -> &'static str {
+    CATEGORY_NAMES
+        .iter()
+        .find(|&&(_, c)| c == cat)
+        .map_or("?", |&(n, _)| n)
+}
+
+/// Decides which execution events are reported and writes them out. The default traces nothing.
+pub struct Tracer {
+    categories: u32,
+    level: u8,
+    pc_range: Option<Range<u64>>,
+    skip: u64,
+    count: Option<u64>,
+    out: Box<dyn Write>,
+    /// number of the instruction being executed, counted from 0
+    inst: u64,
+    active: bool,
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Tracer::new(0, 1, Box::new(io::stderr()))
+    }
+}
+
+impl Tracer {
+    /// Traces `categories` at verbosity `level`: 1 reports events, 2 adds operand values and
+    /// full register dumps.
+    pub fn new(categories: u32, level: u8, out: Box<dyn Write>) -> Self {
+        Tracer {
+            categories,
+            level,
+            pc_range: None,
+            skip: 0,
+            count: None,
+            out,
+            inst: 0,
+            active: false,
+        }
+    }
+    /// Only trace instructions whose pc is in `range`.
+    pub fn pc_range(mut self, range: Range<u64>) -> Self {
+        self.pc_range = Some(range);
+        self
+    }
+    /// Only trace from the `skip`th instruction on, for at most `count` instructions.
+    pub fn window(mut self, skip: u64, count: Option<u64>) -> Self {
+        self.skip = skip;
+        self.count = count;
+        self
+    }
+    /// Called before each instruction; decides whether its events pass the filters.
+    pub fn begin(&mut self, pc: u64) {
+        let in_window = self.inst >= self.skip
+            && self.count.is_none_or(|count| self.inst - self.skip < count);
+        let in_range = self.pc_range.as_ref().is_none_or(|r| r.contains(&pc));
+        self.active = self.categories != 0 && in_window && in_range;
+    }
+    /// Called after each instruction that completed.
+    pub fn end(&mut self) {
+        self.inst += 1;
+    }
+    /// Whether no category is traced at all.
+    pub fn is_off(&self) -> bool {
+        self.categories == 0
+    }
+    pub fn enabled(&self, cat: u32, level: u8) -> bool {
+        self.active && self.categories & cat != 0 && level <= self.level
+    }
+    pub fn emit(&mut self, cat: u32, args: fmt::Arguments) {
+        // tracing must never stop the guest, so a closed sink is ignored
+        let _ = writeln!(self.out, "{:>8} {:<6} {}", self.inst, category_name(cat), args);
+    }
+}
+
+/// Emits a trace event if `$cat` is enabled at `$level`; the message is only formatted then.
+#[macro_export]
+macro_rules! trace {
+    ($tracer:expr, $cat:expr, $level:expr, $($arg:tt)*) => {
+        if $tracer.enabled($cat, $level) {
+            $tracer.emit($cat, format_args!($($arg)*));
+        }
+    };
+}
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct SharedBuf(pub std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse() {
+    assert!(parse_categories("fetch, regs") == Ok(category::FETCH | category::REGS));
+    assert!(parse_categories("all") == Ok(category::ALL));
+    assert!(parse_categories("memory,sstack") == Ok(category::MEMORY | category::SSTACK));
+    assert!(parse_categories("bogus").is_err());
+    assert!(parse_range("0x80000000:0x80000100") == Ok(0x80000000..0x80000100));
+    assert!(parse_range("0x10:0x10").is_err());
+}
+
+#[test]
+fn test_filters() {
+    let buf = SharedBuf::default();
+    let mut tracer = Tracer::new(category::FETCH, 1, Box::new(buf.clone()))
+        .pc_range(0x100..0x200)
+        .window(1, Some(2));
+    for (pc, expected) in [(0x100, false), (0x104, true), (0x80, false), (0x108, false)].iter() {
+        tracer.begin(*pc);
+        assert!(tracer.enabled(category::FETCH, 1) == *expected);
+        assert!(!tracer.enabled(category::REGS, 1));
+        assert!(!tracer.enabled(category::FETCH, 2));
+        trace!(tracer, category::FETCH, 1, "pc {:#x}", pc);
+        tracer.end();
+    }
+    assert!(String::from_utf8(buf.0.borrow().clone()).unwrap() == "       1 fetch  pc 0x104\n");
+    let mut quiet = Tracer::default();
+    quiet.begin(0);
+    assert!(!quiet.enabled(category::ALL, 1));
+}
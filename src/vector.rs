@@ -0,0 +1,1343 @@
+//! The vector extension, RVV 1.0, and its embedded Zve* subsets: the vector register file,
+//! vtype, and the semantics of vector loads, stores and arithmetic. Tail and inactive
+//! elements are always left undisturbed, which agnostic policies allow as well.
+use crate::{
+    decode::{VMode, VNum, VOp},
+    float::{flag, Env, Format, Rounding, F32, F64},
+};
+
+/// A vector unit: V, or one of the embedded Zve* subsets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VectorConfig {
+    /// bits per vector register
+    pub vlen: u32,
+    /// widest element, 32 or 64 bits
+    pub elen: u32,
+    /// single- and double-precision elements
+    pub f: bool,
+    pub d: bool,
+    /// V itself, which unlike Zve64* has vmulh and vsmul on 64-bit elements
+    pub full: bool,
+}
+
+impl Default for VectorConfig {
+    fn default() -> Self {
+        VectorConfig {
+            vlen: 128,
+            elen: 64,
+            f: true,
+            d: true,
+            full: true,
+        }
+    }
+}
+
+impl VectorConfig {
+    /// V, or a Zve* subset such as `zve32x`, by name, with `vlen` bit registers.
+    pub fn named(name: &str, vlen: u32) -> Result<VectorConfig, String> {
+        let (elen, f, d, full, min) = match name {
+            "v" => (64, true, true, true, 128),
+            "zve32x" => (32, false, false, false, 32),
+            "zve32f" => (32, true, false, false, 32),
+            "zve64x" => (64, false, false, false, 64),
+            "zve64f" => (64, true, false, false, 64),
+            "zve64d" => (64, true, true, false, 64),
+            _ => return Err(format!("no vector extension {}", name)),
+        };
+        if !vlen.is_power_of_two() || vlen < min || vlen > 65536 {
+            return Err(format!("{} needs a power of two VLEN from {} to 65536, not {}", name, min, vlen));
+        }
+        Ok(VectorConfig { vlen, elen, f, d, full })
+    }
+    /// Floating-point format of `sew` bit elements, if this unit has it.
+    fn format(&self, sew: u32) -> Result<Format, String> {
+        match sew {
+            32 if self.f => Ok(F32),
+            64 if self.d => Ok(F64),
+            _ => Err(format!("No {}-bit vector floating point", sew)),
+        }
+    }
+}
+
+/// The vtype CSR.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vtype {
+    /// log2 of LMUL, -3 to 3
+    pub lmul: i32,
+    pub sew: u32,
+    /// tail and mask agnostic
+    pub ta: bool,
+    pub ma: bool,
+    /// set by an unsupported setting, making vector instructions illegal
+    pub vill: bool,
+}
+
+impl Vtype {
+    pub const ILLEGAL: Vtype = Vtype {
+        lmul: 0,
+        sew: 8,
+        ta: false,
+        ma: false,
+        vill: true,
+    };
+    /// vtype as set by vsetvl from `bits`, illegal unless `config` supports it. vill, in the
+    /// top bit, and any other reserved bit make it illegal too.
+    pub fn parse(bits: u64, config: &VectorConfig) -> Vtype {
+        let vlmul = (bits & 0b111) as i32;
+        let vsew = (bits >> 3) & 0b111;
+        let lmul = (vlmul << 29) >> 29;
+        let sew = 8 << vsew.min(4);
+        // every LMUL must hold an ELEN element for fractional ones, SEW <= LMUL * ELEN
+        let fits = match lmul {
+            0..=3 => true,
+            _ => sew << -lmul <= config.elen,
+        };
+        if bits >> 8 != 0 || vlmul == 0b100 || vsew > 3 || sew > config.elen || !fits {
+            return Vtype::ILLEGAL;
+        }
+        Vtype {
+            lmul,
+            sew,
+            ta: bits >> 6 & 1 == 1,
+            ma: bits >> 7 & 1 == 1,
+            vill: false,
+        }
+    }
+    /// The CSR value, vill in the top bit.
+    pub fn bits(&self, xlen: u32) -> u64 {
+        match self.vill {
+            true => 1 << (xlen - 1),
+            false => {
+                (self.lmul & 0b111) as u64
+                    | ((self.sew / 8).trailing_zeros() as u64) << 3
+                    | (self.ta as u64) << 6
+                    | (self.ma as u64) << 7
+            }
+        }
+    }
+    /// Elements in a register group, VLEN * LMUL / SEW.
+    pub fn vlmax(&self, vlen: u32) -> u64 {
+        match self.lmul {
+            l if l >= 0 => ((vlen as u64) << l) / self.sew as u64,
+            l => ((vlen as u64) >> -l) / self.sew as u64,
+        }
+    }
+}
+
+/// The 32 vector registers, VLEN bits each. Elements are little-endian, so a register group
+/// is one array of elements over consecutive registers.
+#[derive(Clone)]
+pub struct VRegister {
+    vlenb: usize,
+    data: Vec<u8>,
+}
+
+impl VRegister {
+    pub fn new(vlen: u32) -> VRegister {
+        let vlenb = vlen as usize / 8;
+        VRegister {
+            vlenb,
+            data: vec![0; 32 * vlenb],
+        }
+    }
+    /// Bytes per register.
+    pub fn vlenb(&self) -> usize {
+        self.vlenb
+    }
+    /// The bytes of register `n`.
+    pub fn raw(&self, n: usize) -> &[u8] {
+        &self.data[n * self.vlenb..(n + 1) * self.vlenb]
+    }
+    /// Element `i`, `eew` bits wide, of the group starting at register `n`.
+    pub fn get(&self, n: usize, i: u64, eew: u32) -> u64 {
+        let width = eew as usize / 8;
+        let at = n * self.vlenb + i as usize * width;
+        let mut bytes = [0; 8];
+        bytes[..width].copy_from_slice(&self.data[at..at + width]);
+        u64::from_le_bytes(bytes)
+    }
+    pub fn set(&mut self, n: usize, i: u64, eew: u32, value: u64) {
+        let width = eew as usize / 8;
+        let at = n * self.vlenb + i as usize * width;
+        self.data[at..at + width].copy_from_slice(&value.to_le_bytes()[..width]);
+    }
+    /// Bit `i` of the mask in register `n`.
+    pub fn mask(&self, n: usize, i: u64) -> bool {
+        self.data[n * self.vlenb + i as usize / 8] >> (i % 8) & 1 == 1
+    }
+    pub fn set_mask(&mut self, n: usize, i: u64, bit: bool) {
+        let byte = &mut self.data[n * self.vlenb + i as usize / 8];
+        *byte = (*byte & !(1 << (i % 8))) | (bit as u8) << (i % 8);
+    }
+}
+
+/// What a vector instruction reads besides registers and memory.
+pub struct Context {
+    pub config: VectorConfig,
+    pub vtype: Vtype,
+    pub vl: u64,
+    pub vstart: u64,
+    /// fixed-point rounding mode
+    pub vxrm: u32,
+    /// floating-point rounding mode, `None` if frm holds a reserved one
+    pub frm: Option<Rounding>,
+    pub xlen: u32,
+}
+
+/// What a vector instruction did besides writing vector registers and memory.
+#[derive(Debug, Default, PartialEq)]
+pub struct Effects {
+    /// value for the x or f destination of vmv.x.s, vcpop, vfirst and vfmv.f.s
+    pub scalar: Option<u64>,
+    pub fflags: u32,
+    /// a fixed-point result saturated
+    pub vxsat: bool,
+    /// vl, shortened by a fault-only-first load
+    pub vl: Option<u64>,
+}
+
+/// The operand besides vs2: vs1, or a scalar sign extended to 64 bits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    V(usize),
+    Scalar(u64),
+}
+
+/// Whether `op` is a floating-point one, illegal while mstatus.FS is off.
+pub fn is_float(op: VOp) -> bool {
+    match op {
+        VOp::Fcvt { from, to, .. } => from == VNum::Float || to == VNum::Float,
+        _ => matches!(
+            op,
+            VOp::Fadd
+                | VOp::Fsub
+                | VOp::Frsub
+                | VOp::Fmin
+                | VOp::Fmax
+                | VOp::Fsgnj
+                | VOp::Fsgnjn
+                | VOp::Fsgnjx
+                | VOp::Fdiv
+                | VOp::Frdiv
+                | VOp::Fmul
+                | VOp::Fmadd
+                | VOp::Fnmadd
+                | VOp::Fmsub
+                | VOp::Fnmsub
+                | VOp::Fmacc
+                | VOp::Fnmacc
+                | VOp::Fmsac
+                | VOp::Fnmsac
+                | VOp::Fwadd
+                | VOp::Fwsub
+                | VOp::FwaddW
+                | VOp::FwsubW
+                | VOp::Fwmul
+                | VOp::Fwmacc
+                | VOp::Fwnmacc
+                | VOp::Fwmsac
+                | VOp::Fwnmsac
+                | VOp::Fsqrt
+                | VOp::Frsqrt7
+                | VOp::Frec7
+                | VOp::Fclass
+                | VOp::FncvtRod
+                | VOp::Fredusum
+                | VOp::Fredosum
+                | VOp::Fredmin
+                | VOp::Fredmax
+                | VOp::Fwredusum
+                | VOp::Fwredosum
+                | VOp::Fmerge
+                | VOp::Fslide1up
+                | VOp::Fslide1down
+                | VOp::FmvFS
+                | VOp::FmvSF
+                | VOp::Mfeq
+                | VOp::Mfle
+                | VOp::Mflt
+                | VOp::Mfne
+                | VOp::Mfgt
+                | VOp::Mfge
+        ),
+    }
+}
+
+fn ones(bits: u32) -> u64 {
+    u64::MAX >> (64 - bits)
+}
+
+fn sext(x: u64, bits: u32) -> i64 {
+    ((x << (64 - bits)) as i64) >> (64 - bits)
+}
+
+/// Registers in a group of log2 size `emul`; fractional groups take one.
+fn regs(emul: i32) -> usize {
+    1 << emul.max(0)
+}
+
+/// Checks that the group of log2 size `emul` from register `n` exists and is aligned.
+fn check_group(n: usize, emul: i32) -> Result<(), String> {
+    if !(-3..=3).contains(&emul) {
+        return Err(String::from("Vector register group size out of range"));
+    }
+    match n.is_multiple_of(regs(emul)) && n + regs(emul) <= 32 {
+        true => Ok(()),
+        false => Err(format!("Vector register v{} misaligned for its group", n)),
+    }
+}
+
+/// A register group by first register, log2 of its size and element width; masks are 1 bit.
+type Group = (usize, i32, u32);
+
+/// Checks that a destination group overlaps a source group only where the spec allows: with
+/// the same element width, in the lowest part of a wider source, or the highest part of a
+/// wider destination.
+fn check_overlap(d: Group, s: Group) -> Result<(), String> {
+    let (dn, sn) = (regs(d.1), regs(s.1));
+    if d.0 >= s.0 + sn || s.0 >= d.0 + dn || d.2 == s.2 {
+        return Ok(());
+    }
+    match (d.2 < s.2 && d.0 == s.0) || (d.2 > s.2 && s.1 >= 0 && s.0 + sn == d.0 + dn) {
+        true => Ok(()),
+        false => Err(String::from("Vector destination overlaps a source of another width")),
+    }
+}
+
+/// Checks that a destination group does not overlap a source group at all.
+fn check_disjoint(d: Group, s: Group) -> Result<(), String> {
+    match d.0 >= s.0 + regs(s.1) || s.0 >= d.0 + regs(d.1) {
+        true => Ok(()),
+        false => Err(String::from("Vector destination overlaps its source")),
+    }
+}
+
+/// `v` shifted right by `d` bits and rounded as fixed-point rounding mode `vxrm` directs.
+fn roundoff(v: i128, d: u32, vxrm: u32) -> i128 {
+    if d == 0 {
+        return v;
+    }
+    let bit = |n: u32| (v >> n) & 1 == 1;
+    let below = v & ((1 << (d - 1)) - 1) != 0;
+    let up = match vxrm {
+        // to nearest up, to nearest even, down, to odd
+        0 => bit(d - 1),
+        1 => bit(d - 1) && (below || bit(d)),
+        2 => false,
+        _ => !bit(d) && (bit(d - 1) || below),
+    };
+    (v >> d) + up as i128
+}
+
+/// `x` clamped to `[lo, hi]`, noting in `sat` if it was out of range.
+fn saturate(x: i128, lo: i128, hi: i128, sat: &mut bool) -> i128 {
+    if x < lo || x > hi {
+        *sat = true;
+    }
+    x.clamp(lo, hi)
+}
+
+/// Runs vector arithmetic instruction `op`.
+pub fn exec(
+    v: &mut VRegister,
+    op: VOp,
+    vd: usize,
+    vs2: usize,
+    src: Operand,
+    vm: bool,
+    ctx: &Context,
+) -> Result<Effects, String> {
+    let mut fx = Effects::default();
+    let (sew, lmul) = (ctx.vtype.sew, ctx.vtype.lmul);
+    let vlmax = ctx.vtype.vlmax(ctx.config.vlen);
+    let active: Vec<u64> = (ctx.vstart..ctx.vl).filter(|&i| vm || v.mask(0, i)).collect();
+    // the operand at element `i`, `eew` bits wide
+    let operand = |v: &VRegister, i: u64, eew: u32| match src {
+        Operand::V(n) => v.get(n, i, eew),
+        Operand::Scalar(x) => x & ones(eew),
+    };
+    let vs1 = match src {
+        Operand::V(n) => Some(n),
+        Operand::Scalar(_) => None,
+    };
+    // checks the sources of a `dw`-bit destination group of log2 size `demul`, vs2 at `sw`
+    // bits and vs1 at SEW
+    let check = |d: Group, sw: u32| -> Result<(), String> {
+        let s2 = (vs2, lmul + (sw / sew).trailing_zeros() as i32, sw);
+        check_group(d.0, d.1)?;
+        check_group(vs2, s2.1)?;
+        check_overlap(d, s2)?;
+        if let Some(n) = vs1 {
+            check_group(n, lmul)?;
+            check_overlap(d, (n, lmul, sew))?;
+        }
+        // only mask destinations may be v0 when masked
+        match !vm && d.0 == 0 && d.2 != 1 {
+            true => Err(String::from("Masked vector instruction overwrites v0")),
+            false => Ok(()),
+        }
+    };
+    let needs_vstart_0 = || match ctx.vstart {
+        0 => Ok(()),
+        _ => Err(String::from("Vector instruction needs vstart 0")),
+    };
+    let wide = 2 * sew;
+    let widens = || match wide <= ctx.config.elen && lmul < 3 {
+        true => Ok(()),
+        false => Err(String::from("No vector elements twice SEW")),
+    };
+    match op {
+        VOp::Add
+        | VOp::Sub
+        | VOp::Rsub
+        | VOp::Minu
+        | VOp::Min
+        | VOp::Maxu
+        | VOp::Max
+        | VOp::And
+        | VOp::Or
+        | VOp::Xor
+        | VOp::Sll
+        | VOp::Srl
+        | VOp::Sra
+        | VOp::Ssrl
+        | VOp::Ssra
+        | VOp::Saddu
+        | VOp::Sadd
+        | VOp::Ssubu
+        | VOp::Ssub
+        | VOp::Aaddu
+        | VOp::Aadd
+        | VOp::Asubu
+        | VOp::Asub
+        | VOp::Smul
+        | VOp::Divu
+        | VOp::Div
+        | VOp::Remu
+        | VOp::Rem
+        | VOp::Mulhu
+        | VOp::Mul
+        | VOp::Mulhsu
+        | VOp::Mulh => {
+            check((vd, lmul, sew), sew)?;
+            let high = matches!(op, VOp::Mulhu | VOp::Mulhsu | VOp::Mulh | VOp::Smul);
+            if high && sew == 64 && !ctx.config.full {
+                return Err(String::from("No 64-bit vmulh or vsmul in Zve64"));
+            }
+            for &i in active.iter() {
+                let (a, b) = (v.get(vs2, i, sew), operand(v, i, sew));
+                let r = int_binary(op, a, b, sew, ctx.vxrm, &mut fx.vxsat);
+                v.set(vd, i, sew, r);
+            }
+        }
+        VOp::Madd | VOp::Nmsub | VOp::Macc | VOp::Nmsac => {
+            check((vd, lmul, sew), sew)?;
+            for &i in active.iter() {
+                let (a, b, d) = (v.get(vs2, i, sew), operand(v, i, sew), v.get(vd, i, sew));
+                let r = match op {
+                    VOp::Madd => b.wrapping_mul(d).wrapping_add(a),
+                    VOp::Nmsub => a.wrapping_sub(b.wrapping_mul(d)),
+                    VOp::Macc => b.wrapping_mul(a).wrapping_add(d),
+                    _ => d.wrapping_sub(b.wrapping_mul(a)),
+                };
+                v.set(vd, i, sew, r & ones(sew));
+            }
+        }
+        VOp::Waddu
+        | VOp::Wadd
+        | VOp::Wsubu
+        | VOp::Wsub
+        | VOp::WadduW
+        | VOp::WaddW
+        | VOp::WsubuW
+        | VOp::WsubW
+        | VOp::Wmulu
+        | VOp::Wmulsu
+        | VOp::Wmul
+        | VOp::Wmaccu
+        | VOp::Wmacc
+        | VOp::Wmaccus
+        | VOp::Wmaccsu => {
+            widens()?;
+            let sw = match op {
+                VOp::WadduW | VOp::WaddW | VOp::WsubuW | VOp::WsubW => wide,
+                _ => sew,
+            };
+            check((vd, lmul + 1, wide), sw)?;
+            for &i in active.iter() {
+                let (a, b, d) = (v.get(vs2, i, sw), operand(v, i, sew), v.get(vd, i, wide));
+                let (u, s) = (|x: u64| x, |x: u64| sext(x, sew) as u64);
+                let r = match op {
+                    VOp::Waddu => a.wrapping_add(b),
+                    VOp::Wadd => s(a).wrapping_add(s(b)),
+                    VOp::Wsubu => a.wrapping_sub(b),
+                    VOp::Wsub => s(a).wrapping_sub(s(b)),
+                    VOp::WadduW => a.wrapping_add(b),
+                    VOp::WaddW => a.wrapping_add(s(b)),
+                    VOp::WsubuW => a.wrapping_sub(b),
+                    VOp::WsubW => a.wrapping_sub(s(b)),
+                    VOp::Wmulu => u(a).wrapping_mul(u(b)),
+                    VOp::Wmulsu => s(a).wrapping_mul(u(b)),
+                    VOp::Wmul => s(a).wrapping_mul(s(b)),
+                    VOp::Wmaccu => d.wrapping_add(u(b).wrapping_mul(u(a))),
+                    VOp::Wmacc => d.wrapping_add(s(b).wrapping_mul(s(a))),
+                    VOp::Wmaccus => d.wrapping_add(u(b).wrapping_mul(s(a))),
+                    _ => d.wrapping_add(s(b).wrapping_mul(u(a))),
+                };
+                v.set(vd, i, wide, r & ones(wide));
+            }
+        }
+        VOp::Nsrl | VOp::Nsra | VOp::Nclipu | VOp::Nclip => {
+            widens()?;
+            check((vd, lmul, sew), wide)?;
+            let (max_s, max_u) = ((1i128 << (sew - 1)) - 1, (1i128 << sew) - 1);
+            for &i in active.iter() {
+                let (a, b) = (v.get(vs2, i, wide), operand(v, i, sew));
+                let shamt = (b & (wide as u64 - 1)) as u32;
+                let signed = sext(a, wide) as i128;
+                let r = match op {
+                    VOp::Nsrl => (a >> shamt) as i128,
+                    VOp::Nsra => signed >> shamt,
+                    VOp::Nclipu => saturate(roundoff(a as i128, shamt, ctx.vxrm), 0, max_u, &mut fx.vxsat),
+                    _ => saturate(roundoff(signed, shamt, ctx.vxrm), -max_s - 1, max_s, &mut fx.vxsat),
+                };
+                v.set(vd, i, sew, r as u64 & ones(sew));
+            }
+        }
+        VOp::Mseq
+        | VOp::Msne
+        | VOp::Msltu
+        | VOp::Mslt
+        | VOp::Msleu
+        | VOp::Msle
+        | VOp::Msgtu
+        | VOp::Msgt
+        | VOp::Madc
+        | VOp::Msbc => {
+            check((vd, 0, 1), sew)?;
+            // the carry-out ones use v0 as the carry-in instead of a mask
+            let carry = matches!(op, VOp::Madc | VOp::Msbc);
+            let indices: Vec<u64> = match carry {
+                true => (ctx.vstart..ctx.vl).collect(),
+                false => active.clone(),
+            };
+            for i in indices {
+                let (a, b) = (v.get(vs2, i, sew), operand(v, i, sew));
+                let (sa, sb) = (sext(a, sew), sext(b, sew));
+                let c = (!vm && v.mask(0, i)) as i128;
+                let bit = match op {
+                    VOp::Mseq => a == b,
+                    VOp::Msne => a != b,
+                    VOp::Msltu => a < b,
+                    VOp::Mslt => sa < sb,
+                    VOp::Msleu => a <= b,
+                    VOp::Msle => sa <= sb,
+                    VOp::Msgtu => a > b,
+                    VOp::Msgt => sa > sb,
+                    VOp::Madc => (a as i128 + b as i128 + c) >> sew != 0,
+                    _ => (a as i128 - b as i128 - c) < 0,
+                };
+                v.set_mask(vd, i, bit);
+            }
+        }
+        VOp::Adc | VOp::Sbc => {
+            check((vd, lmul, sew), sew)?;
+            for i in ctx.vstart..ctx.vl {
+                let (a, b, c) = (v.get(vs2, i, sew), operand(v, i, sew), v.mask(0, i) as u64);
+                let r = match op {
+                    VOp::Adc => a.wrapping_add(b).wrapping_add(c),
+                    _ => a.wrapping_sub(b).wrapping_sub(c),
+                };
+                v.set(vd, i, sew, r & ones(sew));
+            }
+        }
+        VOp::Merge | VOp::Fmerge => {
+            if op == VOp::Fmerge {
+                ctx.config.format(sew)?;
+            }
+            check((vd, lmul, sew), sew)?;
+            for i in ctx.vstart..ctx.vl {
+                let r = match vm || v.mask(0, i) {
+                    true => operand(v, i, sew),
+                    false => v.get(vs2, i, sew),
+                };
+                v.set(vd, i, sew, r);
+            }
+        }
+        VOp::Redsum
+        | VOp::Redand
+        | VOp::Redor
+        | VOp::Redxor
+        | VOp::Redminu
+        | VOp::Redmin
+        | VOp::Redmaxu
+        | VOp::Redmax
+        | VOp::Wredsumu
+        | VOp::Wredsum => {
+            needs_vstart_0()?;
+            let w = match op {
+                VOp::Wredsumu | VOp::Wredsum => {
+                    widens()?;
+                    wide
+                }
+                _ => sew,
+            };
+            check_group(vs2, lmul)?;
+            let start = operand(v, 0, w);
+            let acc = active.iter().fold(start, |acc, &i| {
+                let x = v.get(vs2, i, sew);
+                let (sa, sx) = (sext(acc, sew), sext(x, sew));
+                match op {
+                    VOp::Redsum => acc.wrapping_add(x),
+                    VOp::Redand => acc & x,
+                    VOp::Redor => acc | x,
+                    VOp::Redxor => acc ^ x,
+                    VOp::Redminu => acc.min(x),
+                    VOp::Redmin => sa.min(sx) as u64,
+                    VOp::Redmaxu => acc.max(x),
+                    VOp::Redmax => sa.max(sx) as u64,
+                    VOp::Wredsumu => acc.wrapping_add(x),
+                    _ => acc.wrapping_add(sx as u64),
+                }
+            });
+            if ctx.vl > 0 {
+                v.set(vd, 0, w, acc & ones(w));
+            }
+        }
+        VOp::Slideup | VOp::Slidedown | VOp::Slide1up | VOp::Slide1down | VOp::Fslide1up | VOp::Fslide1down => {
+            if matches!(op, VOp::Fslide1up | VOp::Fslide1down) {
+                ctx.config.format(sew)?;
+            }
+            check((vd, lmul, sew), sew)?;
+            let scalar = operand(v, 0, 64);
+            let up = matches!(op, VOp::Slideup | VOp::Slide1up | VOp::Fslide1up);
+            if up {
+                check_disjoint((vd, lmul, sew), (vs2, lmul, sew))?;
+            }
+            let offset = match op {
+                VOp::Slideup | VOp::Slidedown => scalar & ones(ctx.xlen),
+                _ => 1,
+            };
+            for &i in active.iter() {
+                let r = match op {
+                    VOp::Slideup if i < offset => continue,
+                    VOp::Slideup => v.get(vs2, i - offset, sew),
+                    VOp::Slide1up | VOp::Fslide1up if i == 0 => scalar & ones(sew),
+                    VOp::Slide1up | VOp::Fslide1up => v.get(vs2, i - 1, sew),
+                    VOp::Slide1down | VOp::Fslide1down if i + 1 == ctx.vl => scalar & ones(sew),
+                    _ => match i.checked_add(offset).filter(|&j| j < vlmax) {
+                        Some(j) => v.get(vs2, j, sew),
+                        None => 0,
+                    },
+                };
+                v.set(vd, i, sew, r);
+            }
+        }
+        VOp::Rgather | VOp::Rgatherei16 => {
+            let (iw, iemul) = match op {
+                VOp::Rgatherei16 => (16, lmul + 4 - sew.trailing_zeros() as i32),
+                _ => (sew, lmul),
+            };
+            check_group(vd, lmul)?;
+            check_group(vs2, lmul)?;
+            check_disjoint((vd, lmul, sew), (vs2, lmul, sew))?;
+            if let Some(n) = vs1 {
+                check_group(n, iemul)?;
+                check_disjoint((vd, lmul, sew), (n, iemul, iw))?;
+            }
+            if !vm && vd == 0 {
+                return Err(String::from("Masked vector instruction overwrites v0"));
+            }
+            let snapshot = v.clone();
+            for &i in active.iter() {
+                let index = match src {
+                    Operand::V(n) => snapshot.get(n, i, iw),
+                    Operand::Scalar(x) => x & ones(ctx.xlen),
+                };
+                let r = match index < vlmax {
+                    true => snapshot.get(vs2, index, sew),
+                    false => 0,
+                };
+                v.set(vd, i, sew, r);
+            }
+        }
+        VOp::Compress => {
+            needs_vstart_0()?;
+            let n = vs1.unwrap_or(0);
+            check_group(vd, lmul)?;
+            check_group(vs2, lmul)?;
+            check_disjoint((vd, lmul, sew), (vs2, lmul, sew))?;
+            check_disjoint((vd, lmul, sew), (n, 0, 1))?;
+            let mut j = 0;
+            for i in 0..ctx.vl {
+                if v.mask(n, i) {
+                    let x = v.get(vs2, i, sew);
+                    v.set(vd, j, sew, x);
+                    j += 1;
+                }
+            }
+        }
+        VOp::MvXS => {
+            fx.scalar = Some(sext(v.get(vs2, 0, sew), sew) as u64);
+        }
+        VOp::FmvFS => {
+            ctx.config.format(sew)?;
+            fx.scalar = Some(v.get(vs2, 0, sew));
+        }
+        VOp::MvSX | VOp::FmvSF => {
+            if op == VOp::FmvSF {
+                ctx.config.format(sew)?;
+            }
+            if ctx.vstart < ctx.vl {
+                let x = operand(v, 0, sew);
+                v.set(vd, 0, sew, x);
+            }
+        }
+        VOp::MvWhole(nr) => {
+            let emul = nr.trailing_zeros() as i32;
+            check_group(vd, emul)?;
+            check_group(vs2, emul)?;
+            let eew = match ctx.vtype.vill {
+                true => 8,
+                false => sew,
+            };
+            let evl = nr as u64 * v.vlenb() as u64 * 8 / eew as u64;
+            for i in ctx.vstart..evl {
+                let x = v.get(vs2, i, eew);
+                v.set(vd, i, eew, x);
+            }
+        }
+        VOp::Mandn | VOp::Mand | VOp::Mor | VOp::Mxor | VOp::Morn | VOp::Mnand | VOp::Mnor | VOp::Mxnor => {
+            let n = vs1.unwrap_or(0);
+            for i in ctx.vstart..ctx.vl {
+                let (a, b) = (v.mask(vs2, i), v.mask(n, i));
+                let bit = match op {
+                    VOp::Mandn => a && !b,
+                    VOp::Mand => a && b,
+                    VOp::Mor => a || b,
+                    VOp::Mxor => a != b,
+                    VOp::Morn => a || !b,
+                    VOp::Mnand => !(a && b),
+                    VOp::Mnor => !(a || b),
+                    _ => a == b,
+                };
+                v.set_mask(vd, i, bit);
+            }
+        }
+        VOp::Cpop | VOp::First => {
+            needs_vstart_0()?;
+            let mut set = active.iter().filter(|&&i| v.mask(vs2, i));
+            fx.scalar = Some(match op {
+                VOp::Cpop => set.count() as u64,
+                _ => set.next().copied().unwrap_or(u64::MAX),
+            });
+        }
+        VOp::Msbf | VOp::Msof | VOp::Msif => {
+            needs_vstart_0()?;
+            check_disjoint((vd, 0, 1), (vs2, 0, 1))?;
+            if !vm && vd == 0 {
+                return Err(String::from("Masked vector instruction overwrites v0"));
+            }
+            let mut seen = false;
+            for &i in active.iter() {
+                let first = !seen && v.mask(vs2, i);
+                let bit = match op {
+                    VOp::Msbf => !seen && !first,
+                    VOp::Msof => first,
+                    _ => !seen,
+                };
+                seen |= first;
+                v.set_mask(vd, i, bit);
+            }
+        }
+        VOp::Iota | VOp::Id => {
+            check((vd, lmul, sew), sew)?;
+            if op == VOp::Iota {
+                needs_vstart_0()?;
+                check_disjoint((vd, lmul, sew), (vs2, 0, 1))?;
+            }
+            let mut count = 0;
+            for &i in active.iter() {
+                let r = match op {
+                    VOp::Iota => count,
+                    _ => i,
+                };
+                if op == VOp::Iota && v.mask(vs2, i) {
+                    count += 1;
+                }
+                v.set(vd, i, sew, r & ones(sew));
+            }
+        }
+        VOp::Ext { factor, signed } => {
+            let from = sew / factor;
+            if from < 8 {
+                return Err(String::from("Vector extension source narrower than 8 bits"));
+            }
+            check_group(vd, lmul)?;
+            check((vd, lmul, sew), from)?;
+            for &i in active.iter() {
+                let x = v.get(vs2, i, from);
+                let r = match signed {
+                    true => sext(x, from) as u64,
+                    false => x,
+                };
+                v.set(vd, i, sew, r & ones(sew));
+            }
+        }
+        _ => return exec_fp(v, op, vd, vs2, src, vm, ctx, &active),
+    }
+    Ok(fx)
+}
+
+/// Result of a single-width integer operation on `a` from vs2 and `b`, both `sew` bits.
+fn int_binary(op: VOp, a: u64, b: u64, sew: u32, vxrm: u32, sat: &mut bool) -> u64 {
+    let (ua, ub) = (a as i128, b as i128);
+    let (sa, sb) = (sext(a, sew) as i128, sext(b, sew) as i128);
+    let (min_s, max_s, max_u) = (-(1i128 << (sew - 1)), (1i128 << (sew - 1)) - 1, (1i128 << sew) - 1);
+    let shamt = (b & (sew as u64 - 1)) as u32;
+    let r = match op {
+        VOp::Add => ua + ub,
+        VOp::Sub => ua - ub,
+        VOp::Rsub => ub - ua,
+        VOp::Minu => ua.min(ub),
+        VOp::Min => sa.min(sb),
+        VOp::Maxu => ua.max(ub),
+        VOp::Max => sa.max(sb),
+        VOp::And => ua & ub,
+        VOp::Or => ua | ub,
+        VOp::Xor => ua ^ ub,
+        VOp::Sll => ua << shamt,
+        VOp::Srl => ua >> shamt,
+        VOp::Sra => sa >> shamt,
+        VOp::Ssrl => roundoff(ua, shamt, vxrm),
+        VOp::Ssra => roundoff(sa, shamt, vxrm),
+        VOp::Saddu => saturate(ua + ub, 0, max_u, sat),
+        VOp::Sadd => saturate(sa + sb, min_s, max_s, sat),
+        VOp::Ssubu => saturate(ua - ub, 0, max_u, sat),
+        VOp::Ssub => saturate(sa - sb, min_s, max_s, sat),
+        VOp::Aaddu => roundoff(ua + ub, 1, vxrm),
+        VOp::Aadd => roundoff(sa + sb, 1, vxrm),
+        VOp::Asubu => roundoff(ua - ub, 1, vxrm),
+        VOp::Asub => roundoff(sa - sb, 1, vxrm),
+        VOp::Smul => saturate(roundoff(sa * sb, sew - 1, vxrm), min_s, max_s, sat),
+        VOp::Divu if ub == 0 => max_u,
+        VOp::Divu => ua / ub,
+        VOp::Div if sb == 0 => -1,
+        VOp::Div if sa == min_s && sb == -1 => min_s,
+        VOp::Div => sa / sb,
+        VOp::Remu if ub == 0 => ua,
+        VOp::Remu => ua % ub,
+        VOp::Rem if sb == 0 => sa,
+        VOp::Rem if sa == min_s && sb == -1 => 0,
+        VOp::Rem => sa % sb,
+        VOp::Mulhu => ((a as u128 * b as u128) >> sew) as i128,
+        VOp::Mulhsu => (sa * ub) >> sew,
+        VOp::Mulh => (sa * sb) >> sew,
+        _ => a.wrapping_mul(b) as i128,
+    };
+    r as u64 & ones(sew)
+}
+
+/// Runs the floating-point arithmetic instruction `op`.
+#[allow(clippy::too_many_arguments)]
+fn exec_fp(
+    v: &mut VRegister,
+    op: VOp,
+    vd: usize,
+    vs2: usize,
+    src: Operand,
+    vm: bool,
+    ctx: &Context,
+    active: &[u64],
+) -> Result<Effects, String> {
+    let (sew, lmul) = (ctx.vtype.sew, ctx.vtype.lmul);
+    let wide = 2 * sew;
+    let rounds = !matches!(
+        op,
+        VOp::Fmin
+            | VOp::Fmax
+            | VOp::Fredmin
+            | VOp::Fredmax
+            | VOp::Fsgnj
+            | VOp::Fsgnjn
+            | VOp::Fsgnjx
+            | VOp::Fclass
+            | VOp::Mfeq
+            | VOp::Mfle
+            | VOp::Mflt
+            | VOp::Mfne
+            | VOp::Mfgt
+            | VOp::Mfge
+    );
+    let rm = match (rounds, ctx.frm) {
+        (false, _) => Rounding::Rne,
+        (true, Some(rm)) => rm,
+        (true, None) => return Err(String::from("Invalid rounding mode in frm")),
+    };
+    let mut env = Env::new(rm);
+    let operand = |v: &VRegister, i: u64, eew: u32| match src {
+        Operand::V(n) => v.get(n, i, eew),
+        Operand::Scalar(x) => x & ones(eew),
+    };
+    let vs1 = match src {
+        Operand::V(n) => Some(n),
+        Operand::Scalar(_) => None,
+    };
+    let check = |d: Group, sw: u32| -> Result<(), String> {
+        let s2 = (vs2, lmul + (sw / sew).trailing_zeros() as i32, sw);
+        check_group(d.0, d.1)?;
+        check_group(vs2, s2.1)?;
+        check_overlap(d, s2)?;
+        if let Some(n) = vs1 {
+            check_group(n, lmul)?;
+            check_overlap(d, (n, lmul, sew))?;
+        }
+        match !vm && d.0 == 0 && d.2 != 1 {
+            true => Err(String::from("Masked vector instruction overwrites v0")),
+            false => Ok(()),
+        }
+    };
+    let widening = || match lmul < 3 {
+        true => ctx.config.format(wide),
+        false => Err(String::from("No vector elements twice SEW")),
+    };
+    match op {
+        VOp::Fadd
+        | VOp::Fsub
+        | VOp::Frsub
+        | VOp::Fmin
+        | VOp::Fmax
+        | VOp::Fsgnj
+        | VOp::Fsgnjn
+        | VOp::Fsgnjx
+        | VOp::Fdiv
+        | VOp::Frdiv
+        | VOp::Fmul => {
+            let fmt = ctx.config.format(sew)?;
+            check((vd, lmul, sew), sew)?;
+            for &i in active.iter() {
+                let (a, b) = (v.get(vs2, i, sew), operand(v, i, sew));
+                let r = match op {
+                    VOp::Fadd => fmt.add(a, b, &mut env),
+                    VOp::Fsub => fmt.sub(a, b, &mut env),
+                    VOp::Frsub => fmt.sub(b, a, &mut env),
+                    VOp::Fmin => fmt.min(a, b, &mut env),
+                    VOp::Fmax => fmt.max(a, b, &mut env),
+                    VOp::Fsgnj => fmt.sign_inject(a, b, 0),
+                    VOp::Fsgnjn => fmt.sign_inject(a, b, 1),
+                    VOp::Fsgnjx => fmt.sign_inject(a, b, 2),
+                    VOp::Fdiv => fmt.div(a, b, &mut env),
+                    VOp::Frdiv => fmt.div(b, a, &mut env),
+                    _ => fmt.mul(a, b, &mut env),
+                };
+                v.set(vd, i, sew, r);
+            }
+        }
+        VOp::Fmadd | VOp::Fnmadd | VOp::Fmsub | VOp::Fnmsub | VOp::Fmacc | VOp::Fnmacc | VOp::Fmsac | VOp::Fnmsac => {
+            let fmt = ctx.config.format(sew)?;
+            check((vd, lmul, sew), sew)?;
+            for &i in active.iter() {
+                let (a, b, d) = (v.get(vs2, i, sew), operand(v, i, sew), v.get(vd, i, sew));
+                let r = fused(fmt, op, a, b, d, &mut env);
+                v.set(vd, i, sew, r);
+            }
+        }
+        VOp::Fwadd
+        | VOp::Fwsub
+        | VOp::FwaddW
+        | VOp::FwsubW
+        | VOp::Fwmul
+        | VOp::Fwmacc
+        | VOp::Fwnmacc
+        | VOp::Fwmsac
+        | VOp::Fwnmsac => {
+            let (fmt, wfmt) = (ctx.config.format(sew)?, widening()?);
+            let sw = match op {
+                VOp::FwaddW | VOp::FwsubW => wide,
+                _ => sew,
+            };
+            check((vd, lmul + 1, wide), sw)?;
+            for &i in active.iter() {
+                let (a, b, d) = (v.get(vs2, i, sw), operand(v, i, sew), v.get(vd, i, wide));
+                // widening is exact, only signaling NaNs raise a flag
+                let b = wfmt.convert(fmt, b, &mut env);
+                let a = match sw == wide {
+                    true => a,
+                    false => wfmt.convert(fmt, a, &mut env),
+                };
+                let r = match op {
+                    VOp::Fwadd | VOp::FwaddW => wfmt.add(a, b, &mut env),
+                    VOp::Fwsub | VOp::FwsubW => wfmt.sub(a, b, &mut env),
+                    VOp::Fwmul => wfmt.mul(a, b, &mut env),
+                    VOp::Fwmacc => fused(wfmt, VOp::Fmacc, a, b, d, &mut env),
+                    VOp::Fwnmacc => fused(wfmt, VOp::Fnmacc, a, b, d, &mut env),
+                    VOp::Fwmsac => fused(wfmt, VOp::Fmsac, a, b, d, &mut env),
+                    _ => fused(wfmt, VOp::Fnmsac, a, b, d, &mut env),
+                };
+                v.set(vd, i, wide, r);
+            }
+        }
+        VOp::Mfeq | VOp::Mfle | VOp::Mflt | VOp::Mfne | VOp::Mfgt | VOp::Mfge => {
+            let fmt = ctx.config.format(sew)?;
+            check((vd, 0, 1), sew)?;
+            for &i in active.iter() {
+                let (a, b) = (v.get(vs2, i, sew), operand(v, i, sew));
+                let bit = match op {
+                    VOp::Mfeq => fmt.eq(a, b, &mut env),
+                    VOp::Mfne => !fmt.eq(a, b, &mut env),
+                    VOp::Mflt => fmt.lt(a, b, &mut env),
+                    VOp::Mfle => fmt.le(a, b, &mut env),
+                    VOp::Mfgt => fmt.lt(b, a, &mut env),
+                    _ => fmt.le(b, a, &mut env),
+                };
+                v.set_mask(vd, i, bit);
+            }
+        }
+        VOp::Fsqrt | VOp::Frsqrt7 | VOp::Frec7 | VOp::Fclass => {
+            let fmt = ctx.config.format(sew)?;
+            check((vd, lmul, sew), sew)?;
+            for &i in active.iter() {
+                let a = v.get(vs2, i, sew);
+                let r = match op {
+                    VOp::Fsqrt => fmt.sqrt(a, &mut env),
+                    VOp::Frsqrt7 => fmt.rsqrt7(a, &mut env),
+                    VOp::Frec7 => fmt.rec7(a, &mut env),
+                    _ => fmt.classify(a),
+                };
+                v.set(vd, i, sew, r);
+            }
+        }
+        VOp::Fcvt { from, to, width, rtz } => {
+            let (sw, dw, demul) = match width {
+                1 => (sew, wide, lmul + 1),
+                -1 => (wide, sew, lmul),
+                _ => (sew, sew, lmul),
+            };
+            if width != 0 && (lmul == 3 || wide > ctx.config.elen) {
+                return Err(String::from("No vector elements twice SEW"));
+            }
+            let float = |w: u32, kind: VNum| match kind {
+                VNum::Float => ctx.config.format(w).map(Some),
+                _ => Ok(None),
+            };
+            let (ffrom, fto) = (float(sw, from)?, float(dw, to)?);
+            check((vd, demul, dw), sw)?;
+            if rtz {
+                env.rm = Rounding::Rtz;
+            }
+            for &i in active.iter() {
+                let a = v.get(vs2, i, sw);
+                let r = match (ffrom, fto) {
+                    (Some(f), Some(t)) => t.convert(f, a, &mut env),
+                    (Some(f), None) => f.to_int(a, to == VNum::Signed, dw, &mut env),
+                    (None, Some(t)) => t.from_int(a, from == VNum::Signed, sw, &mut env),
+                    (None, None) => a,
+                };
+                v.set(vd, i, dw, r & ones(dw));
+            }
+        }
+        VOp::FncvtRod => {
+            let (fmt, wfmt) = (ctx.config.format(sew)?, widening()?);
+            check((vd, lmul, sew), wide)?;
+            for &i in active.iter() {
+                // truncated, with the lowest bit set if that was inexact
+                let mut e = Env::new(Rounding::Rtz);
+                let r = fmt.convert(wfmt, v.get(vs2, i, wide), &mut e);
+                let odd = e.flags & flag::NX != 0 && !fmt.is_nan(r);
+                env.flags |= e.flags;
+                v.set(vd, i, sew, r | odd as u64);
+            }
+        }
+        VOp::Fredusum | VOp::Fredosum | VOp::Fredmin | VOp::Fredmax | VOp::Fwredusum | VOp::Fwredosum => {
+            if ctx.vstart != 0 {
+                return Err(String::from("Vector instruction needs vstart 0"));
+            }
+            let fmt = ctx.config.format(sew)?;
+            let wfmt = match op {
+                VOp::Fwredusum | VOp::Fwredosum => widening()?,
+                _ => fmt,
+            };
+            check_group(vs2, lmul)?;
+            let start = operand(v, 0, wfmt.width());
+            let mut acc = start;
+            for &i in active.iter() {
+                let x = v.get(vs2, i, sew);
+                acc = match op {
+                    VOp::Fredmin => fmt.min(acc, x, &mut env),
+                    VOp::Fredmax => fmt.max(acc, x, &mut env),
+                    VOp::Fwredusum | VOp::Fwredosum => {
+                        let x = wfmt.convert(fmt, x, &mut env);
+                        wfmt.add(acc, x, &mut env)
+                    }
+                    _ => fmt.add(acc, x, &mut env),
+                };
+            }
+            if ctx.vl > 0 {
+                v.set(vd, 0, wfmt.width(), acc);
+            }
+        }
+        _ => return Err(format!("No vector operation {:?}", op)),
+    }
+    Ok(Effects {
+        fflags: env.flags,
+        ..Effects::default()
+    })
+}
+
+/// The single-width fused multiply-adds, `a` from vs2, `b` the operand and `d` from vd.
+fn fused(fmt: Format, op: VOp, a: u64, b: u64, d: u64, env: &mut Env) -> u64 {
+    let neg = |x| fmt.negate(x);
+    match op {
+        VOp::Fmacc => fmt.fma(b, a, d, env),
+        VOp::Fnmacc => fmt.fma(neg(b), a, neg(d), env),
+        VOp::Fmsac => fmt.fma(b, a, neg(d), env),
+        VOp::Fnmsac => fmt.fma(neg(b), a, d, env),
+        VOp::Fmadd => fmt.fma(b, d, a, env),
+        VOp::Fnmadd => fmt.fma(neg(b), d, neg(a), env),
+        VOp::Fmsub => fmt.fma(b, d, neg(a), env),
+        _ => fmt.fma(neg(b), d, a, env),
+    }
+}
+
+/// Memory a vector load or store goes through: `mem(i, addr, n, data)` loads `n` bytes at `addr`
+/// for element `i`, or stores the low `n` bytes of `data` if given. It fails with the fault the
+/// access raised.
+pub type Memory<'a> = dyn FnMut(u64, u64, u64, Option<u64>) -> Result<u64, String> + 'a;
+
+/// Runs a vector load or store from `base`; `stride` is rs2's value for strided accesses. An
+/// error from `mem` is returned as it is; any other error is an illegal encoding.
+#[allow(clippy::too_many_arguments)]
+pub fn access(
+    v: &mut VRegister,
+    mem: &mut Memory,
+    store: bool,
+    mode: VMode,
+    eew: u32,
+    nf: u32,
+    vd: usize,
+    base: u64,
+    stride: u64,
+    index: usize,
+    vm: bool,
+    ctx: &Context,
+) -> Result<Effects, String> {
+    let (sew, lmul) = (ctx.vtype.sew, ctx.vtype.lmul);
+    let mut fx = Effects::default();
+    let nf = nf as u64;
+    // data element width, log2 of the registers per field, and elements per field
+    let (width, emul, evl) = match mode {
+        VMode::Whole => (eew, 0, v.vlenb() as u64 * 8 / eew as u64),
+        VMode::Mask => (8, 0, ctx.vl.div_ceil(8)),
+        VMode::Indexed { .. } => (sew, lmul, ctx.vl),
+        _ => (eew, lmul + eew.trailing_zeros() as i32 - sew.trailing_zeros() as i32, ctx.vl),
+    };
+    if eew > ctx.config.elen {
+        return Err(format!("No {}-bit vector elements", eew));
+    }
+    if mode != VMode::Whole && ctx.vtype.vill {
+        return Err(String::from("Vector instruction with vtype.vill set"));
+    }
+    let fields = nf as usize * regs(emul);
+    check_group(vd, emul)?;
+    if mode == VMode::Whole {
+        check_group(vd, nf.trailing_zeros() as i32)?;
+    } else if fields > 8 || vd + fields > 32 {
+        return Err(String::from("Vector segment larger than 8 registers"));
+    }
+    if !store && !vm && vd == 0 {
+        return Err(String::from("Masked vector load overwrites v0"));
+    }
+    if let VMode::Indexed { .. } = mode {
+        let iemul = lmul + eew.trailing_zeros() as i32 - sew.trailing_zeros() as i32;
+        check_group(index, iemul)?;
+        if !store {
+            for f in 0..nf as usize {
+                check_overlap((vd + f * regs(emul), emul, sew), (index, iemul, eew))?;
+            }
+        }
+    }
+    let bytes = width as u64 / 8;
+    let (count, per_field) = match mode {
+        // whole registers are fields laid out one after another
+        VMode::Whole => (nf * evl, 1),
+        _ => (evl, nf),
+    };
+    for i in ctx.vstart..count {
+        if !(vm || matches!(mode, VMode::Whole | VMode::Mask) || v.mask(0, i)) {
+            continue;
+        }
+        let addrs: Vec<u64> = (0..per_field)
+            .map(|f| {
+                let offset = match mode {
+                    VMode::Strided => i.wrapping_mul(stride).wrapping_add(f * bytes),
+                    VMode::Indexed { .. } => v.get(index, i, eew).wrapping_add(f * bytes),
+                    _ => (i * per_field + f) * bytes,
+                };
+                base.wrapping_add(offset) & ones(ctx.xlen)
+            })
+            .collect();
+        for (f, &addr) in addrs.iter().enumerate() {
+            let reg = vd + f * regs(emul);
+            let data = match store {
+                true => Some(v.get(reg, i, width)),
+                false => None,
+            };
+            match mem(i, addr, bytes, data) {
+                Ok(x) if !store => v.set(reg, i, width, x),
+                Ok(_) => {}
+                // past the first element, a fault-only-first load stops there instead
+                Err(_) if mode == VMode::FaultFirst && i > 0 => {
+                    fx.vl = Some(i);
+                    return Ok(fx);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(fx)
+}
+
+#[cfg(test)]
+fn context(vtype: u64, vl: u64) -> Context {
+    let config = VectorConfig::default();
+    Context {
+        config,
+        vtype: Vtype::parse(vtype, &config),
+        vl,
+        vstart: 0,
+        vxrm: 0,
+        frm: Some(Rounding::Rne),
+        xlen: 64,
+    }
+}
+
+#[test]
+fn test_vtype() {
+    let v = VectorConfig::default();
+    // e32, m2, ta, ma
+    let t = Vtype::parse(0xd1, &v);
+    assert!(t.sew == 32 && t.lmul == 1 && t.ta && t.ma && t.vlmax(128) == 8 && t.bits(64) == 0xd1);
+    // e64 at mf8 does not fit, nor e64 on a 32-bit unit
+    assert!(Vtype::parse(0x1d, &v).vill && Vtype::parse(0x18, &VectorConfig::named("zve32x", 32).unwrap()).vill);
+    assert!(Vtype::parse(0x05, &v).vlmax(128) == 2 && Vtype::ILLEGAL.bits(32) == 0x80000000);
+    assert!(VectorConfig::named("v", 64).is_err() && VectorConfig::named("zve64d", 64).is_ok());
+}
+
+#[test]
+fn test_integer() {
+    let mut v = VRegister::new(128);
+    for i in 0..4 {
+        v.set(2, i, 32, [1, 0x7fffffff, 0xffffffff, 6][i as usize]);
+        v.set(4, i, 32, [2, 1, 1, 4][i as usize]);
+    }
+    // e32, m1
+    let ctx = context(0x10, 4);
+    let run = |v: &mut VRegister, op, src| exec(v, op, 8, 2, src, true, &ctx).unwrap();
+    run(&mut v, VOp::Add, Operand::V(4));
+    assert!((0..4).map(|i| v.get(8, i, 32)).collect::<Vec<_>>() == [3, 0x80000000, 0, 10]);
+    assert!(run(&mut v, VOp::Sadd, Operand::V(4)).vxsat && v.get(8, 1, 32) == 0x7fffffff);
+    run(&mut v, VOp::Div, Operand::Scalar(0));
+    assert!(v.get(8, 0, 32) == 0xffffffff);
+    run(&mut v, VOp::Mulh, Operand::Scalar(u64::MAX));
+    assert!(v.get(8, 2, 32) == 0 && v.get(8, 1, 32) == 0xffffffff);
+    // round to nearest up: 6 / 4 is 1.5, to 2
+    run(&mut v, VOp::Ssrl, Operand::Scalar(2));
+    assert!(v.get(8, 3, 32) == 2);
+    // masked by v0 = 0b0101, and widening into e64
+    v.set(0, 0, 8, 0b0101);
+    v.set(8, 1, 32, 0x55);
+    exec(&mut v, VOp::Rsub, 8, 2, Operand::Scalar(10), false, &ctx).unwrap();
+    assert!(v.get(8, 0, 32) == 9 && v.get(8, 1, 32) == 0x55 && v.get(8, 2, 32) == 11);
+    run(&mut v, VOp::Wmulu, Operand::V(4));
+    assert!(v.get(8, 1, 64) == 0x7fffffff && v.get(8, 3, 64) == 24);
+    assert!(exec(&mut v, VOp::Wadd, 9, 2, Operand::V(4), true, &ctx).is_err());
+    // vmslt into a mask, and a sum reduction
+    run(&mut v, VOp::Mslt, Operand::Scalar(2));
+    assert!(v.get(8, 0, 8) == 0b0101);
+    v.set(1, 0, 32, 100);
+    exec(&mut v, VOp::Redsum, 8, 4, Operand::V(1), true, &ctx).unwrap();
+    assert!(v.get(8, 0, 32) == 108);
+}
+
+#[test]
+fn test_permute() {
+    let mut v = VRegister::new(128);
+    for i in 0..4 {
+        v.set(2, i, 32, i + 10);
+    }
+    let ctx = context(0x10, 4);
+    exec(&mut v, VOp::Slideup, 4, 2, Operand::Scalar(1), true, &ctx).unwrap();
+    assert!(v.get(4, 0, 32) == 0 && v.get(4, 3, 32) == 12);
+    exec(&mut v, VOp::Slide1down, 4, 2, Operand::Scalar(99), true, &ctx).unwrap();
+    assert!(v.get(4, 0, 32) == 11 && v.get(4, 3, 32) == 99);
+    // slides up may not overwrite their source
+    assert!(exec(&mut v, VOp::Slideup, 2, 2, Operand::Scalar(1), true, &ctx).is_err());
+    v.set(1, 0, 8, 0b1010);
+    exec(&mut v, VOp::Compress, 6, 2, Operand::V(1), true, &ctx).unwrap();
+    assert!(v.get(6, 0, 32) == 11 && v.get(6, 1, 32) == 13);
+    let fx = exec(&mut v, VOp::Cpop, 10, 1, Operand::V(0), true, &ctx).unwrap();
+    assert!(fx.scalar == Some(2));
+    exec(&mut v, VOp::Iota, 8, 1, Operand::V(0), true, &ctx).unwrap();
+    assert!((0..4).map(|i| v.get(8, i, 32)).collect::<Vec<_>>() == [0, 0, 1, 1]);
+    exec(&mut v, VOp::Msbf, 9, 1, Operand::V(0), true, &ctx).unwrap();
+    assert!(v.get(9, 0, 8) & 0xf == 0b0001);
+}
+
+#[test]
+fn test_fp() {
+    let mut v = VRegister::new(128);
+    v.set(2, 0, 32, 0x3f800000);
+    v.set(2, 1, 32, 0x40000000);
+    let ctx = context(0x10, 2);
+    let fx = exec(&mut v, VOp::Fmul, 4, 2, Operand::Scalar(0x40400000), true, &ctx).unwrap();
+    assert!(v.get(4, 0, 32) == 0x40400000 && v.get(4, 1, 32) == 0x40c00000 && fx.fflags == 0);
+    exec(&mut v, VOp::Fwadd, 6, 2, Operand::V(4), true, &ctx).unwrap();
+    assert!(v.get(6, 1, 64) == 0x4020000000000000);
+    let cvt = VOp::Fcvt {
+        from: VNum::Float,
+        to: VNum::Signed,
+        width: 0,
+        rtz: false,
+    };
+    exec(&mut v, cvt, 8, 4, Operand::V(0), true, &ctx).unwrap();
+    assert!(v.get(8, 1, 32) == 6);
+    v.set(1, 0, 32, 0);
+    exec(&mut v, VOp::Fredosum, 10, 4, Operand::V(1), true, &ctx).unwrap();
+    assert!(v.get(10, 0, 32) == 0x41100000);
+    // no half-precision elements
+    assert!(exec(&mut v, VOp::Fadd, 4, 2, Operand::V(2), true, &context(0x08, 2)).is_err());
+}
+
+#[test]
+fn test_access() {
+    use crate::mmu::Mmu;
+    let mmu = std::cell::RefCell::new(Mmu::new((0..64).collect(), false));
+    let mut mem = |_, addr, n, data: Option<u64>| {
+        let mut mmu = mmu.borrow_mut();
+        if !mmu.contains(addr, n) {
+            return Err(format!("fault at {:#x}", addr));
+        }
+        match data {
+            Some(x) => (0..n).for_each(|i| mmu.write_byte(addr + i, (x >> (8 * i)) as u8)),
+            None => return Ok(mmu.read_nbytes(addr, n)),
+        }
+        Ok(0)
+    };
+    let mut v = VRegister::new(128);
+    let ctx = context(0x00, 4);
+    // vlseg2e8 from 8: fields 8, 10, 12, 14 and 9, 11, 13, 15
+    access(&mut v, &mut mem, false, VMode::Unit, 8, 2, 2, 8, 0, 0, true, &ctx).unwrap();
+    assert!(v.get(2, 3, 8) == 14 && v.get(3, 0, 8) == 9);
+    access(&mut v, &mut mem, false, VMode::Strided, 16, 1, 4, 0, 8, 0, true, &ctx).unwrap();
+    assert!(v.get(4, 1, 16) == 0x0908);
+    v.set(6, 1, 8, 60);
+    access(&mut v, &mut mem, false, VMode::Indexed { ordered: false }, 8, 1, 8, 0, 0, 6, true, &ctx).unwrap();
+    assert!(v.get(8, 1, 8) == 60 && v.get(8, 0, 8) == 0);
+    access(&mut v, &mut mem, true, VMode::Unit, 8, 1, 2, 32, 0, 0, true, &ctx).unwrap();
+    assert!(mmu.borrow().peek(32, 4) == 0x0e0c0a08);
+    // fault-only-first stops at the element past the end of memory
+    let fx = access(&mut v, &mut mem, false, VMode::FaultFirst, 8, 1, 10, 62, 0, 0, true, &ctx).unwrap();
+    assert!(fx.vl == Some(2) && v.get(10, 1, 8) == 63);
+    let e = access(&mut v, &mut mem, false, VMode::Unit, 8, 1, 10, 62, 0, 0, true, &ctx);
+    assert!(e == Err(String::from("fault at 0x40")));
+}